@@ -1,11 +1,14 @@
 //! Macro for generating the rust api for a rust header file.
 #![deny(missing_docs)]
-use ffi_gen::{Abi, FfiGen};
+use ffi_gen::{Abi, FfiGen, InterfaceBuilder};
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 
 /// Selects an abi based on the compile target and uses ffigen to generate the rust api.
+///
+/// With the `ret-area` feature wasm functions return multiple values through
+/// a return area, so the wasm module doesn't need to be post-processed.
 #[proc_macro]
 pub fn ffi_gen(input: TokenStream) -> TokenStream {
     let input: TokenStream2 = input.into();
@@ -19,16 +22,48 @@ pub fn ffi_gen(input: TokenStream) -> TokenStream {
     .into()
 }
 
+/// Marks a function, struct, impl block or enum as part of the interface.
+///
+/// The item is passed through unchanged, unsupported signatures are reported
+/// as compile errors. Items have to be `pub`, private methods of an impl block
+/// are skipped. `FfiGen::from_rust_source` collects the marked items
+/// into an interface.
+#[proc_macro_attribute]
+pub fn export(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let attr: TokenStream2 = attr.into();
+    if !attr.is_empty() {
+        return syn::Error::new_spanned(attr, "`#[export]` doesn't take arguments")
+            .to_compile_error()
+            .into();
+    }
+    let item = syn::parse_macro_input!(input as syn::Item);
+    match InterfaceBuilder::new().rust_item(&item) {
+        Ok(_) => quote!(#item).into(),
+        Err(err) => {
+            let err = err.to_compile_error();
+            quote!(#item #err).into()
+        }
+    }
+}
+
 #[proc_macro_attribute]
 #[doc(hidden)]
 pub fn ffi_gen_wasm_32(input: TokenStream, _: TokenStream) -> TokenStream {
-    inner_ffi_gen(input, Abi::Wasm32)
+    if cfg!(feature = "ret-area") {
+        inner_ffi_gen(input, Abi::Wasm32RetArea)
+    } else {
+        inner_ffi_gen(input, Abi::Wasm32)
+    }
 }
 
 #[proc_macro_attribute]
 #[doc(hidden)]
 pub fn ffi_gen_wasm_64(input: TokenStream, _: TokenStream) -> TokenStream {
-    inner_ffi_gen(input, Abi::Wasm64)
+    if cfg!(feature = "ret-area") {
+        inner_ffi_gen(input, Abi::Wasm64RetArea)
+    } else {
+        inner_ffi_gen(input, Abi::Wasm64)
+    }
 }
 
 #[proc_macro_attribute]
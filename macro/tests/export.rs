@@ -0,0 +1,77 @@
+//! Builds bindings from the items in `export/items.rs`, which are compiled with
+//! `#[ffi_gen_macro::export]` as part of this test.
+use ffi_gen::{Abi, FfiGen};
+use std::process::Command;
+
+include!("export/items.rs");
+
+const ITEMS: &str = include_str!("export/items.rs");
+
+#[test]
+fn exported_items() {
+    let mut counter = Counter::new(40);
+    counter.inc(2);
+    assert_eq!(counter.get(), 42);
+    assert_eq!(greet("world"), "hello world");
+}
+
+#[test]
+fn private_methods_are_skipped() {
+    let rust = FfiGen::from_rust_source(ITEMS)
+        .unwrap()
+        .generate_rust(Abi::Native64)
+        .unwrap();
+    assert!(rust.contains("__Counter_inc"));
+    assert!(!rust.contains("__Counter_added"));
+}
+
+#[test]
+fn python_bindings() {
+    let ffigen = FfiGen::from_rust_source(ITEMS).unwrap();
+    let dir = std::env::temp_dir().join(format!("ffi-gen-export-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // the crate root of a library using the macro, the attributes only validate the items.
+    let library = format!(
+        "{}\n{}",
+        ITEMS.replace("#[ffi_gen_macro::export]", ""),
+        ffigen.generate_rust(Abi::Native64).unwrap()
+    );
+    std::fs::write(dir.join("lib.rs"), library).unwrap();
+    ffigen.generate_python(dir.join("api.py"), "api").unwrap();
+    std::fs::write(
+        dir.join("main.py"),
+        "from api import *\n\
+         api = Api()\n\
+         counter = Counter.new(api, 40)\n\
+         counter.inc(2)\n\
+         assert counter.get() == 42\n\
+         assert api.greet(\"world\") == \"hello world\"\n",
+    )
+    .unwrap();
+
+    let status = Command::new("rustc")
+        .current_dir(&dir)
+        .args([
+            "--edition",
+            "2021",
+            "--crate-type",
+            "cdylib",
+            "--crate-name",
+            "api",
+        ])
+        // provides the stream trait without depending on futures.
+        .args(["--cfg", "feature=\"test_runner\""])
+        .arg("lib.rs")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = Command::new("python3")
+        .current_dir(&dir)
+        .env("LD_LIBRARY_PATH", &dir)
+        .arg("main.py")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
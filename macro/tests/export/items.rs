@@ -0,0 +1,28 @@
+#[ffi_gen_macro::export]
+pub struct Counter {
+    count: u32,
+}
+
+#[ffi_gen_macro::export]
+impl Counter {
+    pub fn new(start: u32) -> Self {
+        Self { count: start }
+    }
+
+    pub fn inc(&mut self, by: u32) {
+        self.count = self.added(by);
+    }
+
+    pub fn get(&self) -> u32 {
+        self.count
+    }
+
+    fn added(&self, by: u32) -> u32 {
+        self.count + by
+    }
+}
+
+#[ffi_gen_macro::export]
+pub fn greet(name: &str) -> String {
+    format!("hello {}", name)
+}
@@ -0,0 +1,8 @@
+//! Compiles the `ffi_gen!` output against implementations that don't match
+//! the interface.
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
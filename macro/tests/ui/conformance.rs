@@ -0,0 +1,45 @@
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+pub trait Stream {
+    type Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>>;
+}
+
+impl<T> Stream for Pin<T>
+where
+    T: core::ops::DerefMut + Unpin,
+    T::Target: Stream,
+{
+    type Item = <T::Target as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.get_mut().as_mut().poll_next(cx)
+    }
+}
+
+// the path is relative to the crate trybuild generates in target/tests/trybuild
+ffi_gen_macro::ffi_gen!("../../../../macro/tests/ui/conformance.rsh");
+
+pub fn add(a: u8, b: String) -> u8 {
+    a + b.len() as u8
+}
+
+pub fn names() -> Vec<u32> {
+    vec![]
+}
+
+pub struct Counter(u64);
+
+impl Counter {
+    pub fn new() -> Self {
+        Self(0)
+    }
+
+    pub fn inc(&mut self, by: u64) {
+        self.0 += by;
+    }
+}
+
+fn main() {}
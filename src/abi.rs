@@ -1,10 +1,10 @@
-use crate::parser::{Interface, Type};
+use crate::parser::{Interface, Runtime, Type};
 use std::collections::HashSet;
 
 pub mod export;
 pub mod import;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum NumType {
     U8,
     U16,
@@ -20,6 +20,21 @@ pub enum NumType {
     UPtr,
 }
 
+pub(crate) const NUM_TYPES: [NumType; 12] = [
+    NumType::U8,
+    NumType::U16,
+    NumType::U32,
+    NumType::U64,
+    NumType::I8,
+    NumType::I16,
+    NumType::I32,
+    NumType::I64,
+    NumType::F32,
+    NumType::F64,
+    NumType::IPtr,
+    NumType::UPtr,
+];
+
 #[derive(Clone, Debug)]
 pub enum AbiType {
     Num(NumType),
@@ -29,6 +44,9 @@ pub enum AbiType {
     RefStr,
     String,
     RefSlice(NumType),
+    RefSliceStr,
+    RefSliceObject(String),
+    RefMutSlice(NumType),
     Vec(NumType),
     RefObject(String),
     Object(String),
@@ -42,6 +60,7 @@ pub enum AbiType {
     Stream(Box<AbiType>),
     Tuple(Vec<AbiType>),
     Buffer(NumType),
+    RefBuffer(NumType),
     List(String),
     RefEnum(String),
 }
@@ -65,8 +84,84 @@ pub enum FunctionType {
     PollStream(String, AbiType),
 }
 
+/// Namespace of the symbols exported by the generated rust library.
+///
+/// All generators derive exported symbol names from here so that several
+/// ffi-gen libraries with different prefixes can be linked into one binary.
+/// The runtime helpers are named after the library exporting them, so only
+/// one of the libraries has to.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Symbols {
+    prefix: Option<String>,
+    runtime: Runtime,
+}
+
+impl Symbols {
+    pub fn new(prefix: Option<String>, runtime: Runtime) -> Self {
+        Self { prefix, runtime }
+    }
+
+    pub fn symbol(&self, name: &str) -> String {
+        prefixed(self.prefix.as_deref(), name)
+    }
+
+    /// Returns `true` if the library exports the runtime helpers.
+    pub fn exports_runtime(&self) -> bool {
+        self.runtime == Runtime::Export
+    }
+
+    fn runtime_symbol(&self, name: &str) -> String {
+        match &self.runtime {
+            Runtime::Export => self.symbol(name),
+            Runtime::Extern(prefix) => prefixed(prefix.as_deref(), name),
+        }
+    }
+
+    pub fn allocate(&self) -> String {
+        self.runtime_symbol("allocate")
+    }
+
+    pub fn deallocate(&self) -> String {
+        self.runtime_symbol("deallocate")
+    }
+
+    pub fn ffi_buffer(&self, op: &str) -> String {
+        self.runtime_symbol(&format!("__ffi_buffer_{}", op))
+    }
+
+    pub fn string_into_parts(&self) -> String {
+        self.runtime_symbol("__ffi_string_into_parts")
+    }
+
+    /// Drop glue of `ty`, buffers and leaked references are dropped by the
+    /// runtime.
+    pub fn destructor(&self, ty: &str) -> String {
+        let name = format!("drop_box_{}", ty);
+        match ty {
+            "FfiBuffer" | "Leak" => self.runtime_symbol(&name),
+            _ => self.symbol(&name),
+        }
+    }
+
+    pub fn destructure_enum(&self, ty: &str) -> String {
+        self.symbol(&format!("destructure_enum_{}", ty))
+    }
+
+    pub fn list(&self, ty: &str, op: &str) -> String {
+        self.symbol(&format!("__FfiList{}{}", ty, op))
+    }
+}
+
+fn prefixed(prefix: Option<&str>, name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}_{}", prefix, name),
+        None => name.to_string(),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct AbiFunction {
+    pub symbols: Symbols,
     pub doc: Vec<String>,
     pub ty: FunctionType,
     pub name: String,
@@ -78,9 +173,9 @@ impl AbiFunction {
     pub fn symbol(&self) -> String {
         match &self.ty {
             FunctionType::Constructor(object) | FunctionType::Method(object) => {
-                format!("__{}_{}", object, &self.name)
+                self.symbols.symbol(&format!("__{}_{}", object, &self.name))
             }
-            FunctionType::Function => format!("__{}", &self.name),
+            FunctionType::Function => self.symbols.symbol(&format!("__{}", &self.name)),
             FunctionType::NextIter(symbol, _) => format!("{}_iter_{}", symbol, &self.name),
             FunctionType::PollFuture(symbol, _) => format!("{}_future_{}", symbol, &self.name),
             FunctionType::PollStream(symbol, _) => format!("{}_stream_{}", symbol, &self.name),
@@ -106,13 +201,17 @@ pub struct AbiObject {
 
 #[derive(Clone, Debug)]
 pub struct AbiIter {
+    pub symbols: Symbols,
     pub ty: AbiType,
     pub symbol: String,
+    /// Borrowed iterators are owned by the object returning them.
+    pub borrowed: bool,
 }
 
 impl AbiIter {
     pub fn next(&self) -> AbiFunction {
         AbiFunction {
+            symbols: self.symbols.clone(),
             ty: FunctionType::NextIter(self.symbol.clone(), self.ty.clone()),
             doc: vec![],
             name: "next".to_string(),
@@ -124,6 +223,7 @@ impl AbiIter {
 
 #[derive(Clone, Debug)]
 pub struct AbiFuture {
+    pub symbols: Symbols,
     pub ty: AbiType,
     pub symbol: String,
 }
@@ -131,6 +231,7 @@ pub struct AbiFuture {
 impl AbiFuture {
     pub fn poll(&self) -> AbiFunction {
         AbiFunction {
+            symbols: self.symbols.clone(),
             ty: FunctionType::PollFuture(self.symbol.clone(), self.ty.clone()),
             doc: vec![],
             name: "poll".to_string(),
@@ -145,6 +246,7 @@ impl AbiFuture {
 
 #[derive(Clone, Debug)]
 pub struct AbiStream {
+    pub symbols: Symbols,
     pub ty: AbiType,
     pub symbol: String,
 }
@@ -152,6 +254,7 @@ pub struct AbiStream {
 impl AbiStream {
     pub fn poll(&self) -> AbiFunction {
         AbiFunction {
+            symbols: self.symbols.clone(),
             ty: FunctionType::PollStream(self.symbol.clone(), self.ty.clone()),
             doc: vec![],
             name: "poll".to_string(),
@@ -210,6 +313,12 @@ pub enum Abi {
     Wasm32,
     /// Wasm 64bit
     Wasm64,
+    /// Wasm 32bit, returning multiple values through a caller-allocated
+    /// return area instead of wasm multi-value.
+    Wasm32RetArea,
+    /// Wasm 64bit, returning multiple values through a caller-allocated
+    /// return area instead of wasm multi-value.
+    Wasm64RetArea,
 }
 
 impl Abi {
@@ -220,6 +329,17 @@ impl Abi {
         return Abi::Native64;
     }
 
+    /// Resolves a pointer-sized type to the fixed-size type of the same width.
+    pub(crate) fn fixed_size(self, ty: NumType) -> NumType {
+        match (self, ty) {
+            (Self::Native32 | Self::Wasm32 | Self::Wasm32RetArea, NumType::IPtr) => NumType::I32,
+            (Self::Native32 | Self::Wasm32 | Self::Wasm32RetArea, NumType::UPtr) => NumType::U32,
+            (Self::Native64 | Self::Wasm64 | Self::Wasm64RetArea, NumType::IPtr) => NumType::I64,
+            (Self::Native64 | Self::Wasm64 | Self::Wasm64RetArea, NumType::UPtr) => NumType::U64,
+            (_, ty) => ty,
+        }
+    }
+
     /// Returns the size and alignment of a primitive type.
     pub(crate) fn layout(self, ty: NumType) -> (usize, usize) {
         let size = match ty {
@@ -227,25 +347,70 @@ impl Abi {
             NumType::U16 | NumType::I16 => 2,
             NumType::U32 | NumType::I32 | NumType::F32 => 4,
             NumType::U64 | NumType::I64 | NumType::F64 => 8,
-            NumType::IPtr => todo!(),
-            NumType::UPtr => todo!(),
+            NumType::IPtr | NumType::UPtr => return self.layout(self.fixed_size(ty)),
         };
+        // the original wasm abis keep widening narrow types to 4 bytes, the return area
+        // abis use the natural layout.
         let size = match self {
-            Self::Native32 | Self::Native64 => size,
             Self::Wasm32 | Self::Wasm64 => core::cmp::max(4, size),
+            _ => size,
         };
         (size, size)
     }
+
+    pub(crate) fn is_wasm(self) -> bool {
+        !matches!(self, Self::Native32 | Self::Native64)
+    }
+
+    /// Returns `true` if 64bit integers are split into two 32bit integers.
+    pub(crate) fn splits_u64(self, ty: NumType) -> bool {
+        matches!(self, Self::Wasm32 | Self::Wasm32RetArea)
+            && matches!(ty, NumType::U64 | NumType::I64)
+    }
+
+    /// Returns `true` if multiple return values are written to a return area
+    /// allocated by the caller.
+    pub(crate) fn has_ret_area(self) -> bool {
+        matches!(self, Self::Wasm32RetArea | Self::Wasm64RetArea)
+    }
+
+    /// Returns the field offsets, size and alignment of the `#[repr(C)]` return
+    /// area holding `fields`.
+    pub(crate) fn ret_area_layout(self, fields: &[Var]) -> (Vec<usize>, usize, usize) {
+        let mut offsets = vec![];
+        let mut offset = 0usize;
+        let mut max_align = 1;
+        for field in fields {
+            let (size, align) = self.layout(field.ty.num());
+            // integers narrower than 32bit are widened to `i32` on wasm
+            let (size, align) = if self.is_wasm() {
+                (core::cmp::max(4, size), core::cmp::max(4, align))
+            } else {
+                (size, align)
+            };
+            offset = offset.next_multiple_of(align);
+            offsets.push(offset);
+            offset += size;
+            max_align = core::cmp::max(max_align, align);
+        }
+        let size = offset.next_multiple_of(max_align);
+        (offsets, size, max_align)
+    }
 }
 
 impl Interface {
-    pub fn objects(&self) -> Vec<AbiObject> {
+    pub(crate) fn symbols(&self) -> Symbols {
+        Symbols::new(self.prefix.clone(), self.runtime.clone())
+    }
+
+    pub(crate) fn objects(&self) -> Vec<AbiObject> {
         let mut objs = vec![];
         for object in &self.objects {
             let mut methods = vec![];
             for method in &object.methods {
                 let obj = object.ident.clone();
                 let func = AbiFunction {
+                    symbols: self.symbols(),
                     doc: method.doc.clone(),
                     name: method.ident.clone(),
                     ty: if method.is_static {
@@ -266,13 +431,13 @@ impl Interface {
                 doc: object.doc.clone(),
                 name: object.ident.clone(),
                 methods,
-                destructor: format!("drop_box_{}", &object.ident),
+                destructor: self.symbols().destructor(&object.ident),
             });
         }
         objs
     }
 
-    pub fn functions(&self) -> Vec<AbiFunction> {
+    pub(crate) fn functions(&self) -> Vec<AbiFunction> {
         let mut funcs = vec![];
         for func in &self.functions {
             assert!(!func.is_static);
@@ -283,6 +448,7 @@ impl Interface {
                 .collect();
             let ret = func.ret.as_ref().map(|ty| self.to_type(ty));
             let func = AbiFunction {
+                symbols: self.symbols(),
                 doc: func.doc.clone(),
                 name: func.ident.clone(),
                 ty: FunctionType::Function,
@@ -294,7 +460,7 @@ impl Interface {
         funcs
     }
 
-    pub fn iterators(&self) -> Vec<AbiIter> {
+    pub(crate) fn iterators(&self) -> Vec<AbiIter> {
         let mut iterators = vec![];
         let mut functions = self.functions();
         for obj in self.objects() {
@@ -315,10 +481,12 @@ impl Interface {
                             symbol.push_str("_stream_poll");
                             p = &**ty
                         }
-                        AbiType::Iter(ty) => {
+                        AbiType::Iter(ty) | AbiType::RefIter(ty) => {
                             iterators.push(AbiIter {
+                                symbols: func.symbols.clone(),
                                 ty: (**ty).clone(),
                                 symbol,
+                                borrowed: matches!(p, AbiType::RefIter(_)),
                             });
                             break;
                         }
@@ -330,7 +498,7 @@ impl Interface {
         iterators
     }
 
-    pub fn futures(&self) -> Vec<AbiFuture> {
+    pub(crate) fn futures(&self) -> Vec<AbiFuture> {
         let mut futures = vec![];
         let mut functions = self.functions();
         for obj in self.objects() {
@@ -345,6 +513,7 @@ impl Interface {
                         AbiType::Future(ty) => {
                             let symbol = func.symbol();
                             futures.push(AbiFuture {
+                                symbols: func.symbols.clone(),
                                 ty: (**ty).clone(),
                                 symbol,
                             });
@@ -358,7 +527,7 @@ impl Interface {
         futures
     }
 
-    pub fn streams(&self) -> Vec<AbiStream> {
+    pub(crate) fn streams(&self) -> Vec<AbiStream> {
         let mut streams = vec![];
         let mut functions = self.functions();
         for obj in self.objects() {
@@ -373,6 +542,7 @@ impl Interface {
                         AbiType::Stream(ty) => {
                             let symbol = func.symbol();
                             streams.push(AbiStream {
+                                symbols: func.symbols.clone(),
                                 ty: (**ty).clone(),
                                 symbol,
                             });
@@ -386,7 +556,7 @@ impl Interface {
         streams
     }
 
-    pub fn listed_types(&self) -> Vec<String> {
+    pub(crate) fn listed_types(&self) -> Vec<String> {
         fn find_inner_listed_types<F: FnMut(String)>(ty: &AbiType, cb: &mut F) {
             use AbiType::*;
             match ty {
@@ -425,7 +595,56 @@ impl Interface {
         fin
     }
 
-    pub fn imports(&self, abi: &Abi) -> Vec<import::Import> {
+    /// Element types of the buffers used in arguments or return values, in
+    /// order of appearance.
+    pub(crate) fn buffers(&self) -> Vec<NumType> {
+        fn find_buffers(ty: &AbiType, res: &mut Vec<NumType>) {
+            use AbiType::*;
+            match ty {
+                Buffer(num) | RefBuffer(num) => {
+                    if !res.contains(num) {
+                        res.push(*num);
+                    }
+                }
+                Option(ty) | Result(ty) | Iter(ty) | Future(ty) | Stream(ty) | RefIter(ty)
+                | RefFuture(ty) | RefStream(ty) => find_buffers(ty, res),
+                Tuple(tys) => tys.iter().for_each(|ty| find_buffers(ty, res)),
+                _ => {}
+            }
+        }
+        let mut res = vec![];
+        let functions = self
+            .functions()
+            .into_iter()
+            .chain(self.objects().into_iter().flat_map(|obj| obj.methods));
+        for func in functions {
+            for (_, ty) in &func.args {
+                find_buffers(ty, &mut res);
+            }
+            if let Some(ty) = &func.ret {
+                find_buffers(ty, &mut res);
+            }
+        }
+        res
+    }
+
+    /// Whether any function takes a result as an argument.
+    pub(crate) fn has_result_args(&self) -> bool {
+        fn is_result(ty: &AbiType) -> bool {
+            match ty {
+                AbiType::Result(_) => true,
+                AbiType::Option(ty) => is_result(ty),
+                AbiType::Tuple(tys) => tys.iter().any(is_result),
+                _ => false,
+            }
+        }
+        self.functions()
+            .into_iter()
+            .chain(self.objects().into_iter().flat_map(|obj| obj.methods))
+            .any(|func| func.args.iter().any(|(_, ty)| is_result(ty)))
+    }
+
+    pub(crate) fn imports(&self, abi: &Abi) -> Vec<import::Import> {
         let mut imports = vec![];
         for function in self.functions() {
             imports.push(abi.import(&function));
@@ -447,7 +666,34 @@ impl Interface {
         imports
     }
 
-    pub fn to_type(&self, ty: &Type) -> AbiType {
+    /// Every destructor a handle can be released with.
+    pub(crate) fn destructors(&self) -> Vec<String> {
+        let symbols = self.symbols();
+        let mut destructors = vec![symbols.destructor("FfiBuffer")];
+        for obj in self.objects() {
+            destructors.push(obj.destructor);
+        }
+        for iter in self.iterators() {
+            if !iter.borrowed {
+                destructors.push(format!("{}_iter_drop", iter.symbol));
+            }
+        }
+        for fut in self.futures() {
+            destructors.push(format!("{}_future_drop", fut.symbol));
+        }
+        for stream in self.streams() {
+            destructors.push(format!("{}_stream_drop", stream.symbol));
+        }
+        for e in &self.enums {
+            destructors.push(symbols.destructor(&e.ident));
+        }
+        for ty in self.listed_types() {
+            destructors.push(symbols.destructor(&format!("FfiList{}", ty)));
+        }
+        destructors
+    }
+
+    pub(crate) fn to_type(&self, ty: &Type) -> AbiType {
         match ty {
             Type::U8 => AbiType::Num(NumType::U8),
             Type::U16 => AbiType::Num(NumType::U16),
@@ -464,12 +710,20 @@ impl Interface {
             Type::Bool => AbiType::Bool,
             Type::Buffer(inner) => match self.to_type(inner) {
                 AbiType::Num(ty) => AbiType::Buffer(ty),
+                AbiType::Isize => AbiType::Buffer(NumType::IPtr),
+                AbiType::Usize => AbiType::Buffer(NumType::UPtr),
                 ty => unimplemented!("Vec<{:?}>", ty),
             },
             Type::Ref(inner) => match &**inner {
                 Type::String => AbiType::RefStr,
                 Type::Slice(inner) => match self.to_type(inner) {
                     AbiType::Num(ty) => AbiType::RefSlice(ty),
+                    AbiType::Isize => AbiType::RefSlice(NumType::IPtr),
+                    AbiType::Usize => AbiType::RefSlice(NumType::UPtr),
+                    AbiType::String => AbiType::RefSliceStr,
+                    AbiType::Object(ident) | AbiType::RefEnum(ident) => {
+                        AbiType::RefSliceObject(ident)
+                    }
                     ty => unimplemented!("&{:?}", ty),
                 },
                 Type::Ident(ident) => {
@@ -481,12 +735,28 @@ impl Interface {
                         panic!("unknown identifier {}", ident)
                     }
                 }
+                Type::Iter(ty) => AbiType::RefIter(Box::new(self.to_type(ty))),
+                Type::Buffer(_) => match self.to_type(inner) {
+                    AbiType::Buffer(ty) => AbiType::RefBuffer(ty),
+                    _ => unreachable!(),
+                },
                 ty => unimplemented!("&{:?}", ty),
             },
+            Type::RefMut(inner) => match &**inner {
+                Type::Slice(inner) => match self.to_type(inner) {
+                    AbiType::Num(ty) => AbiType::RefMutSlice(ty),
+                    AbiType::Isize => AbiType::RefMutSlice(NumType::IPtr),
+                    AbiType::Usize => AbiType::RefMutSlice(NumType::UPtr),
+                    ty => unimplemented!("&mut {:?}", ty),
+                },
+                ty => unimplemented!("&mut {:?}", ty),
+            },
             Type::String => AbiType::String,
             Type::Slice(_) => panic!("slice needs to be passed by reference"),
             Type::Vec(inner) => match self.to_type(inner) {
                 AbiType::Num(ty) => AbiType::Vec(ty),
+                AbiType::Isize => AbiType::Vec(NumType::IPtr),
+                AbiType::Usize => AbiType::Vec(NumType::UPtr),
                 AbiType::Object(ty) => AbiType::List(ty),
                 AbiType::RefEnum(ty) => AbiType::List(ty),
                 AbiType::String => AbiType::List("FfiString".to_string()),
@@ -516,3 +786,80 @@ impl Interface {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RustGenerator;
+    use anyhow::Result;
+
+    #[test]
+    fn test_pointer_layout() {
+        for (abi, size) in [
+            (Abi::Native32, 4),
+            (Abi::Native64, 8),
+            (Abi::Wasm32, 4),
+            (Abi::Wasm64, 8),
+        ] {
+            assert_eq!(abi.layout(NumType::IPtr), (size, size));
+            assert_eq!(abi.layout(NumType::UPtr), (size, size));
+        }
+        assert_eq!(Abi::Native32.fixed_size(NumType::UPtr), NumType::U32);
+        assert_eq!(Abi::Native64.fixed_size(NumType::IPtr), NumType::I64);
+        assert_eq!(Abi::Native64.fixed_size(NumType::U8), NumType::U8);
+    }
+
+    #[test]
+    fn test_ret_area_layout() {
+        let mut gen = VarGen::new();
+        let fields = [NumType::U8, NumType::F64, NumType::IPtr, NumType::U16]
+            .into_iter()
+            .map(|ty| gen.gen_num(ty))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            Abi::Wasm32RetArea.ret_area_layout(&fields),
+            (vec![0, 8, 16, 20], 24, 8)
+        );
+        assert_eq!(
+            Abi::Wasm64RetArea.ret_area_layout(&fields),
+            (vec![0, 8, 16, 24], 32, 8)
+        );
+        assert!(Abi::Wasm32RetArea.has_ret_area());
+        assert!(!Abi::Wasm32.has_ret_area());
+        assert_eq!(Abi::Wasm32.layout(NumType::U8), (4, 4));
+        assert_eq!(Abi::Wasm64.layout(NumType::I16), (4, 4));
+        assert_eq!(Abi::Wasm32RetArea.layout(NumType::U8), (1, 1));
+        assert_eq!(Abi::Wasm64RetArea.layout(NumType::I16), (2, 2));
+    }
+
+    #[test]
+    fn test_pointer_sized_types() -> Result<()> {
+        let iface = Interface::parse(
+            r#"
+            fn split(n: usize) -> (usize, isize);
+            fn negate(b: &[usize]) -> Vec<isize>;
+            fn sum(b: &buffer<usize>) -> usize;
+            fn fill(b: &mut [isize]);
+            "#,
+        )?;
+        for (abi, size) in [(Abi::Native32, 4), (Abi::Native64, 8)] {
+            for func in iface.functions() {
+                abi.export(&func);
+            }
+            let imports = iface.imports(&abi);
+            let split = imports.iter().find(|i| i.symbol == "__split").unwrap();
+            match &split.ffi_ret {
+                Return::Struct(fields, _) => {
+                    let sizes = fields
+                        .iter()
+                        .map(|field| abi.layout(field.ty.num()).0)
+                        .collect::<Vec<_>>();
+                    assert_eq!(sizes, vec![size, size]);
+                }
+                ret => panic!("unexpected return {:?}", ret),
+            }
+            RustGenerator::new(abi).generate(iface.clone());
+        }
+        Ok(())
+    }
+}
@@ -19,9 +19,7 @@ impl Abi {
     ) {
         match &out.ty {
             AbiType::RefEnum(_) => todo!(),
-            AbiType::Num(num)
-                if matches!((self, num), (Abi::Wasm32, NumType::U64 | NumType::I64)) =>
-            {
+            AbiType::Num(num) if self.splits_u64(*num) => {
                 let num_type = *num;
                 let low = gen.gen_num(NumType::U32);
                 let high = gen.gen_num(NumType::U32);
@@ -68,6 +66,26 @@ impl Abi {
                 let ty = *ty;
                 instr.push(Instr::LiftSlice(ptr, len, out, ty));
             }
+            AbiType::RefSliceStr => {
+                let ptr = gen.gen_num(NumType::IPtr);
+                let len = gen.gen_num(NumType::UPtr);
+                ffi_args.extend_from_slice(&[ptr.clone(), len.clone()]);
+                instr.push(Instr::LiftStrSlice(ptr, len, out));
+            }
+            AbiType::RefSliceObject(object) => {
+                let ptr = gen.gen_num(NumType::IPtr);
+                let len = gen.gen_num(NumType::UPtr);
+                ffi_args.extend_from_slice(&[ptr.clone(), len.clone()]);
+                let object = object.clone();
+                instr.push(Instr::LiftObjectSlice(ptr, len, out, object));
+            }
+            AbiType::RefMutSlice(ty) => {
+                let ptr = gen.gen_num(NumType::IPtr);
+                let len = gen.gen_num(NumType::UPtr);
+                ffi_args.extend_from_slice(&[ptr.clone(), len.clone()]);
+                let ty = *ty;
+                instr.push(Instr::LiftMutSlice(ptr, len, out, ty));
+            }
             AbiType::Vec(ty) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 let len = gen.gen_num(NumType::UPtr);
@@ -96,7 +114,17 @@ impl Abi {
                 self.export_arg(some.clone(), gen, &mut some_instr, ffi_args);
                 instr.push(Instr::LiftOption(opt, out, some, some_instr));
             }
-            AbiType::Result(_) => todo!(),
+            AbiType::Result(ty) => {
+                let var = gen.gen_num(NumType::U8);
+                ffi_args.push(var.clone());
+                let err = gen.gen(AbiType::String);
+                let mut err_instr = vec![];
+                self.export_arg(err.clone(), gen, &mut err_instr, ffi_args);
+                let ok = gen.gen((**ty).clone());
+                let mut ok_instr = vec![];
+                self.export_arg(ok.clone(), gen, &mut ok_instr, ffi_args);
+                instr.push(Instr::LiftResult(var, out, ok, ok_instr, err, err_instr));
+            }
             AbiType::RefIter(ty) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 ffi_args.push(ptr.clone());
@@ -142,7 +170,18 @@ impl Abi {
                 }
                 instr.push(Instr::LiftTuple(vars, out));
             }
-            AbiType::Buffer(_) => unimplemented!("\"buffer\" can only be used as return value"),
+            AbiType::Buffer(ty) => {
+                let ptr = gen.gen_num(NumType::IPtr);
+                ffi_args.push(ptr.clone());
+                let ty = *ty;
+                instr.push(Instr::LiftBuffer(ptr, out, ty));
+            }
+            AbiType::RefBuffer(ty) => {
+                let ptr = gen.gen_num(NumType::IPtr);
+                ffi_args.push(ptr.clone());
+                let ty = *ty;
+                instr.push(Instr::LiftRefBuffer(ptr, out, ty));
+            }
             AbiType::List(ty) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 ffi_args.push(ptr.clone());
@@ -163,9 +202,7 @@ impl Abi {
         ffi_rets: &mut Vec<Var>,
     ) {
         match &ret.ty {
-            AbiType::Num(num)
-                if matches!((self, num), (Abi::Wasm32, NumType::U64 | NumType::I64)) =>
-            {
+            AbiType::Num(num) if self.splits_u64(*num) => {
                 let low = gen.gen_num(NumType::UPtr);
                 let high = gen.gen_num(NumType::UPtr);
                 ffi_rets.extend_from_slice(&[low.clone(), high.clone()]);
@@ -212,6 +249,12 @@ impl Abi {
                 ffi_rets.extend_from_slice(&[ptr.clone(), len.clone()]);
                 instr.push(Instr::LowerSlice(ret, ptr, len, ty));
             }
+            AbiType::RefSliceStr | AbiType::RefSliceObject(_) => {
+                unimplemented!("slices of strings and objects can only be used as argument")
+            }
+            AbiType::RefMutSlice(_) => {
+                unimplemented!("\"&mut [T]\" can only be used as argument")
+            }
             AbiType::Vec(ty) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 let len = gen.gen_num(NumType::UPtr);
@@ -301,8 +344,9 @@ impl Abi {
                 ffi_rets.push(ptr.clone());
                 instr.push(Instr::LowerObject(ret, ptr));
             }
+            AbiType::RefBuffer(_) => unimplemented!("\"&buffer\" can only be used as argument"),
             AbiType::List(ty) => {
-                instr.push(Instr::AssertType(ret.clone(), format!("Vec<{}>", ty)));
+                instr.push(Instr::AssertType(ret.clone(), AbiType::List(ty.clone())));
                 let ptr = gen.gen_num(NumType::IPtr);
                 ffi_rets.push(ptr.clone());
                 instr.push(Instr::LowerObject(ret, ptr));
@@ -399,6 +443,9 @@ pub enum Instr {
     LiftString(Var, Var, Var, Var),
     LowerString(Var, Var, Var, Var),
     LiftSlice(Var, Var, Var, NumType),
+    LiftStrSlice(Var, Var, Var),
+    LiftObjectSlice(Var, Var, Var, String),
+    LiftMutSlice(Var, Var, Var, NumType),
     LowerSlice(Var, Var, Var, NumType),
     LiftVec(Var, Var, Var, Var, NumType),
     LowerVec(Var, Var, Var, Var, NumType),
@@ -408,10 +455,13 @@ pub enum Instr {
     LowerObject(Var, Var),
     LiftOption(Var, Var, Var, Vec<Instr>),
     LowerOption(Var, Var, Var, Vec<Instr>),
+    LiftResult(Var, Var, Var, Vec<Instr>, Var, Vec<Instr>),
     LowerResult(Var, Var, Var, Vec<Instr>, Var, Vec<Instr>),
     LiftIter(Var, Var, AbiType),
     LowerIter(Var, Var, AbiType),
     LiftRefIter(Var, Var, AbiType),
+    LiftBuffer(Var, Var, NumType),
+    LiftRefBuffer(Var, Var, NumType),
     LowerRefIter(Var, Var, AbiType),
     LiftFuture(Var, Var, AbiType),
     LowerFuture(Var, Var, AbiType),
@@ -425,5 +475,5 @@ pub enum Instr {
     LowerTuple(Var, Vec<Var>),
     CallAbi(FunctionType, Option<Var>, String, Option<Var>, Vec<Var>),
     DefineRets(Vec<Var>),
-    AssertType(Var, String),
+    AssertType(Var, AbiType),
 }
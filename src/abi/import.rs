@@ -23,9 +23,7 @@ impl Abi {
     ) {
         match &arg.ty {
             AbiType::RefEnum(_) => todo!(),
-            AbiType::Num(num)
-                if matches!((self, num), (Abi::Wasm32, NumType::U64 | NumType::I64)) =>
-            {
+            AbiType::Num(num) if self.splits_u64(*num) => {
                 let low = gen.gen_num(NumType::IPtr);
                 let high = gen.gen_num(NumType::IPtr);
 
@@ -108,6 +106,59 @@ impl Abi {
                 ffi_args.extend_from_slice(&[ptr.clone(), len]);
                 instr_cleanup.push(Instr::Deallocate(ptr, cap, size, align));
             }
+            AbiType::RefSliceStr => {
+                // a single allocation of `cap` bytes: the `u32` byte length of
+                // every string followed by the concatenated utf8 bytes.
+                let ptr = gen.gen_num(NumType::IPtr);
+                let len = gen.gen_num(NumType::UPtr);
+                let cap = gen.gen_num(NumType::UPtr);
+                instr.push(Instr::DefineArgs(vec![cap.clone()]));
+                instr.push(Instr::LowerStrSlice(
+                    arg.clone(),
+                    ptr.clone(),
+                    len.clone(),
+                    cap.clone(),
+                ));
+                ffi_args.extend_from_slice(&[ptr.clone(), len]);
+                instr_cleanup.push(Instr::Deallocate(ptr, cap, 1, 4));
+            }
+            AbiType::RefSliceObject(_) => {
+                let ptr = gen.gen_num(NumType::IPtr);
+                let len = gen.gen_num(NumType::UPtr);
+                let cap = gen.gen_num(NumType::UPtr);
+                let (size, align) = self.layout(NumType::IPtr);
+                instr.push(Instr::DefineArgs(vec![cap.clone()]));
+                instr.push(Instr::LowerObjectSlice(
+                    arg.clone(),
+                    ptr.clone(),
+                    len.clone(),
+                    cap.clone(),
+                    size,
+                    align,
+                ));
+                ffi_args.extend_from_slice(&[ptr.clone(), len]);
+                instr_cleanup.push(Instr::Deallocate(ptr, cap, size, align));
+            }
+            AbiType::RefMutSlice(ty) => {
+                let ty = *ty;
+                let ptr = gen.gen_num(NumType::IPtr);
+                let len = gen.gen_num(NumType::UPtr);
+                let cap = gen.gen_num(NumType::UPtr);
+                let (size, align) = self.layout(ty);
+                instr.push(Instr::DefineArgs(vec![cap.clone()]));
+                instr.push(Instr::LowerMutSlice(
+                    arg.clone(),
+                    ptr.clone(),
+                    len.clone(),
+                    cap.clone(),
+                    ty,
+                    size,
+                    align,
+                ));
+                ffi_args.extend_from_slice(&[ptr.clone(), len.clone()]);
+                instr_cleanup.push(Instr::WriteBack(arg, ptr.clone(), len, cap.clone(), ty));
+                instr_cleanup.push(Instr::Deallocate(ptr, cap, size, align));
+            }
             AbiType::Vec(ty) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 let len = gen.gen_num(NumType::UPtr);
@@ -172,9 +223,29 @@ impl Abi {
                 self.import_arg(some.clone(), gen, ffi_args, &mut some_instr, instr_cleanup);
                 instr.push(Instr::LowerOption(arg, var, some, some_instr));
             }
+            AbiType::Tuple(tys) if tys.is_empty() => {}
             AbiType::Tuple(_) => unreachable!(),
-            AbiType::Result(_) => todo!(),
-            AbiType::Buffer(_) => unimplemented!("\"buffer\" can only be used as return value"),
+            AbiType::Result(ty) => {
+                let var = gen.gen_num(NumType::U8);
+                ffi_args.push(var.clone());
+                let err = gen.gen(AbiType::String);
+                let mut err_instr = vec![];
+                self.import_arg(err.clone(), gen, ffi_args, &mut err_instr, instr_cleanup);
+                let ok = gen.gen((**ty).clone());
+                let mut ok_instr = vec![];
+                self.import_arg(ok.clone(), gen, ffi_args, &mut ok_instr, instr_cleanup);
+                instr.push(Instr::LowerResult(arg, var, ok, ok_instr, err, err_instr));
+            }
+            AbiType::Buffer(_) => {
+                let ptr = gen.gen_num(NumType::IPtr);
+                instr.push(Instr::MoveObject(arg.clone(), ptr.clone()));
+                ffi_args.push(ptr);
+            }
+            AbiType::RefBuffer(_) => {
+                let ptr = gen.gen_num(NumType::IPtr);
+                instr.push(Instr::BorrowObject(arg.clone(), ptr.clone()));
+                ffi_args.push(ptr);
+            }
             AbiType::List(_ty) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 instr.push(Instr::MoveObject(arg.clone(), ptr.clone()));
@@ -185,7 +256,7 @@ impl Abi {
 
     fn import_return(
         self,
-        symbol: &str,
+        func: &AbiFunction,
         ty: &AbiType,
         out: Var,
         gen: &mut VarGen,
@@ -193,9 +264,7 @@ impl Abi {
         instr: &mut Vec<Instr>,
     ) {
         match ty {
-            AbiType::Num(num)
-                if matches!((self, num), (Abi::Wasm32, NumType::U64 | NumType::I64)) =>
-            {
+            AbiType::Num(num) if self.splits_u64(*num) => {
                 let low = gen.gen_num(NumType::U32);
                 let high = gen.gen_num(NumType::U32);
                 ffi_rets.extend_from_slice(&[low.clone(), high.clone()]);
@@ -241,6 +310,12 @@ impl Abi {
                 ffi_rets.extend_from_slice(&[ptr.clone(), len.clone()]);
                 instr.push(Instr::LiftVec(ptr, len, out, *ty));
             }
+            AbiType::RefSliceStr | AbiType::RefSliceObject(_) => {
+                unimplemented!("slices of strings and objects can only be used as argument")
+            }
+            AbiType::RefMutSlice(_) => {
+                unimplemented!("\"&mut [T]\" can only be used as argument")
+            }
             AbiType::Vec(ty) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 let len = gen.gen_num(NumType::UPtr);
@@ -250,18 +325,23 @@ impl Abi {
                 instr.push(Instr::LiftVec(ptr.clone(), len, out, *ty));
                 instr.push(Instr::Deallocate(ptr, cap, size, align));
             }
-            AbiType::RefObject(_) => todo!(),
+            AbiType::RefObject(obj) => {
+                let ptr = gen.gen_num(NumType::IPtr);
+                ffi_rets.push(ptr.clone());
+                let leak = func.symbols.destructor("Leak");
+                instr.push(Instr::LiftRefObject(obj.clone(), ptr, leak, out));
+            }
             AbiType::Object(obj) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 ffi_rets.push(ptr.clone());
-                let destructor = format!("drop_box_{}", obj);
+                let destructor = func.symbols.destructor(obj);
                 instr.push(Instr::LiftObject(obj.clone(), ptr, destructor, out));
             }
             AbiType::Option(ty) => {
                 let var = gen.gen_num(NumType::U8);
                 ffi_rets.push(var.clone());
                 instr.push(Instr::HandleNull(var));
-                self.import_return(symbol, ty, out, gen, ffi_rets, instr);
+                self.import_return(func, ty, out, gen, ffi_rets, instr);
             }
             AbiType::Result(ty) => {
                 let var = gen.gen_num(NumType::U8);
@@ -270,30 +350,36 @@ impl Abi {
                 let cap = gen.gen_num(NumType::UPtr);
                 ffi_rets.extend_from_slice(&[var.clone(), ptr.clone(), len.clone(), cap.clone()]);
                 instr.push(Instr::HandleError(var, ptr, len, cap));
-                self.import_return(symbol, ty, out, gen, ffi_rets, instr);
+                self.import_return(func, ty, out, gen, ffi_rets, instr);
+            }
+            AbiType::RefIter(_) => {
+                let ptr = gen.gen_num(NumType::IPtr);
+                ffi_rets.push(ptr.clone());
+                let next = format!("{}_iter_next", func.symbol());
+                let leak = func.symbols.destructor("Leak");
+                instr.push(Instr::LiftRefIter(ptr, next, leak, out));
             }
-            AbiType::RefIter(_) => todo!(),
             AbiType::Iter(_) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 ffi_rets.push(ptr.clone());
-                let next = format!("{}_iter_next", symbol);
-                let destructor = format!("{}_iter_drop", symbol);
+                let next = format!("{}_iter_next", func.symbol());
+                let destructor = format!("{}_iter_drop", func.symbol());
                 instr.push(Instr::LiftIter(ptr, next, destructor, out));
             }
             AbiType::RefFuture(_) => todo!(),
             AbiType::Future(_) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 ffi_rets.push(ptr.clone());
-                let poll = format!("{}_future_poll", symbol);
-                let destructor = format!("{}_future_drop", symbol);
+                let poll = format!("{}_future_poll", func.symbol());
+                let destructor = format!("{}_future_drop", func.symbol());
                 instr.push(Instr::LiftFuture(ptr, poll, destructor, out));
             }
             AbiType::RefStream(_) => todo!(),
             AbiType::Stream(_) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 ffi_rets.push(ptr.clone());
-                let poll = format!("{}_stream_poll", symbol);
-                let destructor = format!("{}_stream_drop", symbol);
+                let poll = format!("{}_stream_poll", func.symbol());
+                let destructor = format!("{}_stream_drop", func.symbol());
                 instr.push(Instr::LiftStream(ptr, poll, destructor, out));
             }
             AbiType::Tuple(tys) => {
@@ -301,7 +387,7 @@ impl Abi {
                 for ty in tys {
                     let out = gen.gen(ty.clone());
                     vars.push(out.clone());
-                    self.import_return(symbol, ty, out, gen, ffi_rets, instr);
+                    self.import_return(func, ty, out, gen, ffi_rets, instr);
                 }
                 instr.push(Instr::LiftTuple(vars, out));
             }
@@ -312,11 +398,12 @@ impl Abi {
                 instr.push(Instr::LiftObject(
                     ffi_buffer_name_for(*ty).to_string(),
                     buf_ptr,
-                    "drop_box_FfiBuffer".to_string(),
+                    func.symbols.destructor("FfiBuffer"),
                     ffi_buf.clone(),
                 ));
                 instr.push(Instr::LiftNum(ffi_buf, out, NumType::IPtr));
             }
+            AbiType::RefBuffer(_) => unimplemented!("\"&buffer\" can only be used as argument"),
             AbiType::List(ty) => {
                 let buf_ptr = gen.gen_num(NumType::IPtr);
                 ffi_rets.push(buf_ptr.clone());
@@ -324,7 +411,7 @@ impl Abi {
                 instr.push(Instr::LiftObject(
                     format!("FfiList{}", ty),
                     buf_ptr,
-                    format!("drop_box_FfiList{}", ty),
+                    func.symbols.destructor(&format!("FfiList{}", ty)),
                     ffi_buf.clone(),
                 ));
                 instr.push(Instr::LiftNum(ffi_buf, out, NumType::IPtr));
@@ -332,7 +419,7 @@ impl Abi {
             AbiType::RefEnum(obj) => {
                 let ptr = gen.gen_num(NumType::IPtr);
                 ffi_rets.push(ptr.clone());
-                let destructor = format!("drop_box_{}", obj);
+                let destructor = func.symbols.destructor(obj);
                 instr.push(Instr::LiftObject(obj.clone(), ptr, destructor, out));
             }
         }
@@ -394,19 +481,36 @@ impl Abi {
             _ => func.ret.clone(),
         };
         let ret = func.ret.as_ref().map(|ty| gen.gen(ty.clone()));
-        instr.push(Instr::Call(symbol.clone(), ret.clone(), ffi_args.clone()));
         if let Some(ret) = ret {
             let out = gen.gen(ret.ty.clone());
             let mut instr_ret = vec![];
             self.import_return(
-                &symbol,
+                func,
                 &ret.ty,
                 out.clone(),
                 &mut gen,
                 &mut ffi_rets,
                 &mut instr_ret,
             );
-            instr.push(Instr::BindRets(ret.clone(), ffi_rets.clone()));
+            if self.has_ret_area() && ffi_rets.len() > 1 {
+                let (offsets, size, align) = self.ret_area_layout(&ffi_rets);
+                let fields = ffi_rets.iter().cloned().zip(offsets).collect();
+                instr.push(Instr::CallRetArea(
+                    symbol.clone(),
+                    ret,
+                    ffi_args.clone(),
+                    fields,
+                    size,
+                    align,
+                ));
+            } else {
+                instr.push(Instr::Call(
+                    symbol.clone(),
+                    Some(ret.clone()),
+                    ffi_args.clone(),
+                ));
+                instr.push(Instr::BindRets(ret, ffi_rets.clone()));
+            }
             instr.extend(instr_ret);
             instr.extend(instr_cleanup);
             if abi_ret.is_some() {
@@ -415,15 +519,21 @@ impl Abi {
                 instr.push(Instr::ReturnVoid);
             }
         } else {
+            instr.push(Instr::Call(symbol.clone(), None, ffi_args.clone()));
             instr.extend(instr_cleanup);
             instr.push(Instr::ReturnVoid);
         }
+        let ffi_ret = if self.has_ret_area() && ffi_rets.len() > 1 {
+            Return::Void
+        } else {
+            func.ret(ffi_rets)
+        };
         Import {
             symbol,
             abi_args,
             ffi_args,
             instr,
-            ffi_ret: func.ret(ffi_rets),
+            ffi_ret,
             abi_ret,
         }
     }
@@ -443,16 +553,23 @@ pub enum Instr {
     LowerString(Var, Var, Var, Var, usize, usize),
     LiftVec(Var, Var, Var, NumType),
     LowerVec(Var, Var, Var, Var, NumType, usize, usize),
+    LowerMutSlice(Var, Var, Var, Var, NumType, usize, usize),
+    LowerStrSlice(Var, Var, Var, Var),
+    LowerObjectSlice(Var, Var, Var, Var, usize, usize),
+    WriteBack(Var, Var, Var, Var, NumType),
     HandleNull(Var),
     LowerOption(Var, Var, Var, Vec<Instr>),
+    LowerResult(Var, Var, Var, Vec<Instr>, Var, Vec<Instr>),
     HandleError(Var, Var, Var, Var),
     BorrowSelf(Var),
     BorrowObject(Var, Var),
     MoveObject(Var, Var),
     LiftObject(String, Var, String, Var),
+    LiftRefObject(String, Var, String, Var),
     BorrowIter(Var, Var),
     MoveIter(Var, Var),
     LiftIter(Var, String, String, Var),
+    LiftRefIter(Var, String, String, Var),
     BorrowFuture(Var, Var),
     MoveFuture(Var, Var),
     LiftFuture(Var, String, String, Var),
@@ -463,6 +580,7 @@ pub enum Instr {
     DefineArgs(Vec<Var>),
     Call(String, Option<Var>, Vec<Var>),
     BindRets(Var, Vec<Var>),
+    CallRetArea(String, Var, Vec<Var>, Vec<(Var, usize)>, usize, usize),
     ReturnValue(Var),
     ReturnVoid,
 }
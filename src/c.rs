@@ -0,0 +1,306 @@
+use crate::export::Instr;
+use crate::{
+    Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Symbols, Var,
+};
+use genco::prelude::*;
+use genco::tokens::static_literal;
+use std::collections::HashMap;
+
+pub struct CGenerator {
+    abi: Abi,
+}
+
+impl Default for CGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CGenerator {
+    pub fn new() -> Self {
+        Self { abi: Abi::native() }
+    }
+
+    pub fn generate(&self, iface: Interface) -> c::Tokens {
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
+        let guard = symbols.symbol("ffi_gen_h").to_uppercase();
+        let string_parts = symbols.symbol("_FfiStringParts");
+        let enum_wrapper = symbols.symbol("_EnumWrapper");
+        quote! {
+            $(static_literal("//")) AUTO GENERATED FILE, DO NOT EDIT.
+            $(static_literal("//"))
+            $(static_literal("//")) Generated by "ffi-gen".
+
+            #ifndef $(&guard)
+            #define $(&guard)
+
+            #include <stdint.h>
+
+            #ifdef __cplusplus
+            extern "C" {
+            #endif
+
+            $(self.generate_doc(&iface.doc))
+
+            $(for obj in iface.objects() => $(self.generate_handle(&obj))$['\n'])
+
+            uint8_t *$(symbols.allocate())(uintptr_t size, uintptr_t align);
+
+            void $(symbols.deallocate())(uint8_t *ptr, uintptr_t size, uintptr_t align);
+
+            intptr_t $(symbols.ffi_buffer("new"))(uintptr_t size, uintptr_t align);
+
+            uintptr_t $(symbols.ffi_buffer("address"))(void *ptr);
+
+            uintptr_t $(symbols.ffi_buffer("size"))(void *ptr);
+
+            typedef struct $(&string_parts) {
+                intptr_t ptr;
+                uintptr_t len;
+                uintptr_t capacity;
+            } $(&string_parts);
+
+            $(&string_parts) $(symbols.string_into_parts())(void *ptr);
+
+            $(self.generate_destructor(&symbols.destructor("FfiBuffer"), "intptr_t"))
+
+            $(self.generate_destructor(&symbols.destructor("Leak"), "intptr_t"))
+
+            typedef struct $(&enum_wrapper) {
+                uint32_t tag;
+                void *inner;
+            } $(&enum_wrapper);
+
+            $(for func in iface.functions() => $(self.generate_function(&func))$['\n'])
+            $(for obj in iface.objects() => $(self.generate_object(&obj))$['\n'])
+            $(for iter in iface.iterators() =>
+                $(self.generate_function(&iter.next()))$['\n']
+                $(if !iter.borrowed {
+                    $(self.generate_destructor(&format!("{}_iter_drop", iter.symbol), "intptr_t"))$['\n']
+                })
+            )
+            $(for fut in iface.futures() =>
+                $(self.generate_function(&fut.poll()))$['\n']
+                $(self.generate_destructor(&format!("{}_future_drop", fut.symbol), "intptr_t"))$['\n']
+            )
+            $(for stream in iface.streams() =>
+                $(self.generate_function(&stream.poll()))$['\n']
+                $(self.generate_destructor(&format!("{}_stream_drop", stream.symbol), "intptr_t"))$['\n']
+            )
+            $(for ty in iface.listed_types() => $(self.generate_list_type_methods(&symbols, &ty))$['\n'])
+            $(for e in iface.enums.iter() =>
+                $(&enum_wrapper) $(symbols.destructure_enum(&e.ident))(void *ptr);$['\n']
+                $(self.generate_destructor(&symbols.destructor(&e.ident), "intptr_t"))$['\n']
+            )
+
+            #ifdef __cplusplus
+            }
+            #endif
+
+            #endif
+        }
+    }
+
+    fn generate_handle(&self, obj: &AbiObject) -> c::Tokens {
+        quote! {
+            $(self.generate_doc(&obj.doc))
+            typedef struct $(&obj.name) $(&obj.name);
+        }
+    }
+
+    fn generate_object(&self, obj: &AbiObject) -> c::Tokens {
+        let boxed = format!("{} *", obj.name);
+        quote! {
+            $(for method in &obj.methods => $(self.generate_function(method))$['\n'])
+            $(self.generate_destructor(&obj.destructor, &boxed))
+        }
+    }
+
+    /// Destructors take an unused `isolate_callback_data` as first argument
+    /// to be compatible with dart finalizers.
+    fn generate_destructor(&self, name: &str, boxed: &str) -> c::Tokens {
+        quote!(void $name(intptr_t isolate_callback_data, $(decl(boxed, "boxed")));)
+    }
+
+    fn generate_function(&self, func: &AbiFunction) -> c::Tokens {
+        let ffi = self.abi.export(func);
+        let args: Vec<String> = ffi
+            .ffi_args
+            .iter()
+            .zip(self.handle_args(func))
+            .map(|(var, handle)| match handle {
+                Some(object) => decl(&format!("{} *", object), &self.var(var)),
+                None => decl(self.ty(var), &self.var(var)),
+            })
+            .collect();
+        let args = if args.is_empty() {
+            "void".to_string()
+        } else {
+            args.join(", ")
+        };
+        let (return_struct, ret) = match &ffi.ffi_ret {
+            Return::Void => (quote!(), "void".to_string()),
+            Return::Num(var) => match self.handle_ret(func) {
+                Some(object) => (quote!(), format!("{} *", object)),
+                None => (quote!(), self.ty(var).to_string()),
+            },
+            Return::Struct(vars, name) => (
+                quote! {
+                    typedef struct $name {
+                        $(for (i, var) in vars.iter().enumerate() => $(self.ty(var)) $(format!("ret{}", i));$['\r'])
+                    } $name;
+                    $['\n']
+                },
+                name.clone(),
+            ),
+        };
+        quote! {
+            $return_struct
+            $(self.generate_doc(&func.doc))
+            $(decl(&ret, &ffi.symbol))($args);
+        }
+    }
+
+    /// Object of every ffi argument of `func` that is declared as a typed
+    /// handle, lists are passed as untyped handles.
+    pub(crate) fn handle_args(&self, func: &AbiFunction) -> Vec<Option<String>> {
+        let ffi = self.abi.export(func);
+        let mut objects: Vec<&str> = func
+            .args
+            .iter()
+            .filter_map(|(_, ty)| match ty {
+                AbiType::Object(object) | AbiType::RefObject(object) => Some(object.as_str()),
+                _ => None,
+            })
+            .collect();
+        if let FunctionType::Method(object) = &func.ty {
+            objects.push(object);
+        }
+        let handles: HashMap<u32, String> = ffi
+            .instr
+            .iter()
+            .filter_map(|instr| match instr {
+                Instr::LiftObject(var, _, object) | Instr::LiftRefObject(var, _, object)
+                    if objects.contains(&object.as_str()) =>
+                {
+                    Some((var.binding, object.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        ffi.ffi_args
+            .iter()
+            .map(|var| handles.get(&var.binding).cloned())
+            .collect()
+    }
+
+    /// Object of the return value of `func` if it is declared as a typed
+    /// handle.
+    pub(crate) fn handle_ret(&self, func: &AbiFunction) -> Option<String> {
+        match &func.ret {
+            Some(AbiType::Object(object) | AbiType::RefObject(object)) => Some(object.clone()),
+            _ => None,
+        }
+    }
+
+    fn generate_list_type_methods(&self, symbols: &Symbols, ty: &str) -> c::Tokens {
+        let name = format!("FfiList{}", ty);
+        quote! {
+            uintptr_t $(symbols.list(ty, "Create"))(void);
+
+            $(self.generate_destructor(&symbols.destructor(&name), "intptr_t"))
+
+            uint32_t $(symbols.list(ty, "Len"))(uintptr_t boxed);
+
+            uintptr_t $(symbols.list(ty, "ElementAt"))(uintptr_t boxed, uint32_t index);
+
+            uintptr_t $(symbols.list(ty, "Remove"))(uintptr_t boxed, uint32_t index);
+
+            void $(symbols.list(ty, "Add"))(uintptr_t boxed, uintptr_t element);
+
+            void $(symbols.list(ty, "Insert"))(uintptr_t boxed, uint32_t index, uintptr_t element);
+        }
+    }
+
+    fn generate_doc(&self, doc: &[String]) -> Vec<c::Tokens> {
+        doc.iter()
+            .enumerate()
+            .map(|(index, line)| {
+                if index < doc.len() - 1 {
+                    quote!($(format!("/// {}\n", line)))
+                } else {
+                    quote!($(format!("/// {}", line)))
+                }
+            })
+            .collect()
+    }
+
+    fn var(&self, var: &Var) -> String {
+        format!("tmp{}", var.binding)
+    }
+
+    fn ty(&self, var: &Var) -> &'static str {
+        match &var.ty {
+            AbiType::Num(num) => self.num_type(*num),
+            ty => unreachable!("{:?} is not an ffi type", ty),
+        }
+    }
+
+    fn num_type(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::U8 => "uint8_t",
+            NumType::U16 => "uint16_t",
+            NumType::U32 => "uint32_t",
+            NumType::U64 => "uint64_t",
+            NumType::I8 => "int8_t",
+            NumType::I16 => "int16_t",
+            NumType::I32 => "int32_t",
+            NumType::I64 => "int64_t",
+            NumType::F32 => "float",
+            NumType::F64 => "double",
+            NumType::IPtr => "intptr_t",
+            NumType::UPtr => "uintptr_t",
+        }
+    }
+}
+
+/// Declares `name` with type `ty`, pointers are written as `T *name`.
+fn decl(ty: &str, name: &str) -> String {
+    if ty.ends_with('*') {
+        format!("{}{}", ty, name)
+    } else {
+        format!("{} {}", ty, name)
+    }
+}
+
+#[cfg(feature = "test_runner")]
+#[doc(hidden)]
+pub mod test_runner {
+    use super::*;
+    use anyhow::Result;
+    use std::process::Command;
+
+    /// Checks that the generated header compiles with the system C compiler.
+    pub fn compile_pass(iface: &str) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let header = CGenerator::new().generate(iface).to_file_string()?;
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("api.h"), header)?;
+        std::fs::write(
+            dir.path().join("main.c"),
+            "#include \"api.h\"\n#include \"api.h\"\n\nint main(void) {\n    return 0;\n}\n",
+        )?;
+        let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let status = Command::new(cc)
+            .args(["-std=c11", "-Wall", "-Wextra", "-Werror", "-pedantic"])
+            .arg("-fsyntax-only")
+            .arg("main.c")
+            .current_dir(dir.path())
+            .status()?;
+        if !status.success() {
+            anyhow::bail!("compiling the c header failed");
+        }
+        Ok(())
+    }
+}
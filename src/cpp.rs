@@ -0,0 +1,1390 @@
+use crate::c::CGenerator;
+use crate::dart::ffi_buffer_name_for;
+use crate::import::{Import, Instr};
+use crate::parser::{Enum, Type};
+use crate::{
+    Abi, AbiFunction, AbiType, FunctionType, Interface, NumType, Return, Symbols, Var, NUM_TYPES,
+};
+use genco::prelude::*;
+use genco::tokens::static_literal;
+use heck::*;
+
+pub struct CppGenerator {
+    abi: Abi,
+    c: CGenerator,
+    header: String,
+    namespace: String,
+}
+
+/// How the function a body is generated for returns.
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Ret {
+    Void,
+    Value,
+    /// Functions returning `Option<()>`, `Some(())` is returned as
+    /// `std::tuple<>`.
+    Unit,
+}
+
+/// State shared by the instructions of a single function body.
+struct Body<'a> {
+    objects: &'a [String],
+    handle_args: Vec<Option<String>>,
+    handle_ret: bool,
+    void_call: bool,
+    ret: Ret,
+}
+
+impl CppGenerator {
+    /// Generates a header that includes the c header `header` and wraps the
+    /// api in namespace `namespace`.
+    pub fn new(header: String, namespace: String) -> Self {
+        Self {
+            abi: Abi::native(),
+            c: CGenerator::new(),
+            header,
+            namespace,
+        }
+    }
+
+    pub fn generate(&self, iface: Interface) -> c::Tokens {
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
+        let guard = symbols.symbol("ffi_gen_hpp").to_uppercase();
+        let objects: Vec<String> = iface.objects().into_iter().map(|obj| obj.name).collect();
+        let lists = iface.listed_types();
+        let mut functions = vec![];
+        for iter in iface.iterators() {
+            functions.push(iter.next());
+        }
+        for fut in iface.futures() {
+            functions.push(fut.poll());
+        }
+        for stream in iface.streams() {
+            functions.push(stream.poll());
+        }
+        quote! {
+            $(static_literal("//")) AUTO GENERATED FILE, DO NOT EDIT.
+            $(static_literal("//"))
+            $(static_literal("//")) Generated by "ffi-gen".
+
+            #ifndef $(&guard)
+            #define $(&guard)
+
+            #include $(quoted(&self.header))
+
+            #include <atomic>
+            #include <cstdint>
+            #include <cstring>
+            #include <exception>
+            #include <functional>
+            #include <future>
+            #include <memory>
+            #include <mutex>
+            #include <optional>
+            #include <stdexcept>
+            #include <string>
+            #include <string_view>
+            #include <tuple>
+            #include <unordered_map>
+            #include <utility>
+            #include <vector>
+
+            $(self.generate_doc(&iface.doc))
+            namespace $(&self.namespace) {
+
+            $(self.generate_runtime(&iface))
+
+            $(static_literal("///")) A rust String owned by a list.
+            class FfiString {
+            public:
+                explicit FfiString(detail::Box box) : box_(std::move(box)) {}
+
+                std::string to_string() const {
+                    auto parts = ::$(symbols.string_into_parts())(reinterpret_cast<void *>(box_.borrow()));
+                    return std::string(reinterpret_cast<const char *>(parts.ptr), parts.len);
+                }
+
+                detail::Box box_;
+            };
+
+            $(for obj in &objects => class $(self.type_ident(obj));$['\r'])
+            $(for e in &iface.enums => class $(self.type_ident(&e.ident));$['\r'])
+            $(for ty in NUM_TYPES => class $(ffi_buffer_name_for(ty));$['\r'])
+            $(for ty in &lists => class $(format!("FfiList{}", ty));$['\r'])
+
+            $(for obj in iface.objects() =>
+                $(self.generate_doc(&obj.doc))
+                class $(self.type_ident(&obj.name)) {
+                public:
+                    explicit $(self.type_ident(&obj.name))(detail::Box box) : box_(std::move(box)) {}
+
+                    $(for method in &obj.methods => $(self.generate_declaration(method))$['\r'])
+
+                    detail::Box box_;
+                };
+                $['\n']
+            )
+            $(for e in &iface.enums => $(self.generate_enum(e))$['\n'])
+            $(for ty in NUM_TYPES => $(self.generate_ffi_buffer(ty))$['\n'])
+            $(for ty in &lists => $(self.generate_list_type(&objects, ty))$['\n'])
+
+            namespace detail {
+
+            $(for func in &functions => $(self.generate_prototype(func))$['\r'])
+
+            $(for func in &functions => $(self.generate_function(&objects, func))$['\n'])
+
+            }
+
+            $(for func in iface.functions() => $(self.generate_function(&objects, &func))$['\n'])
+            $(for obj in iface.objects() =>
+                $(for method in &obj.methods => $(self.generate_function(&objects, method))$['\n'])
+            )
+            $(for e in &iface.enums => $(self.generate_enum_constructor(&symbols, e))$['\n'])
+            $(for ty in NUM_TYPES => $(self.generate_ffi_buffer_methods(&symbols, ty))$['\n'])
+            $(for ty in &lists => $(self.generate_list_type_methods(&symbols, &objects, ty))$['\n'])
+            }
+
+            #endif
+        }
+    }
+
+    fn generate_runtime(&self, iface: &Interface) -> c::Tokens {
+        let symbols = iface.symbols();
+        quote! {
+            namespace detail {
+
+            $(static_literal("///")) Owns a handle to a rust object and drops it when going out of scope.
+            $(static_literal("///")) Boxes without a drop function are borrowed from another object.
+            class Box {
+            public:
+                using Drop = void (*)(intptr_t);
+
+                Box(intptr_t ptr, Drop drop) : ptr_(ptr), drop_(drop) {}
+
+                Box(const Box &) = delete;
+
+                Box &operator=(const Box &) = delete;
+
+                Box(Box &&other) noexcept
+                    : ptr_(std::exchange(other.ptr_, 0)), drop_(std::exchange(other.drop_, nullptr)) {}
+
+                Box &operator=(Box &&other) noexcept {
+                    if (this != &other) {
+                        reset();
+                        ptr_ = std::exchange(other.ptr_, 0);
+                        drop_ = std::exchange(other.drop_, nullptr);
+                    }
+                    return *this;
+                }
+
+                ~Box() {
+                    reset();
+                }
+
+                intptr_t borrow() const {
+                    if (ptr_ == 0) {
+                        throw std::logic_error("use after move");
+                    }
+                    return ptr_;
+                }
+
+                intptr_t release() {
+                    if (drop_ == nullptr) {
+                        throw std::logic_error("can't move borrowed value");
+                    }
+                    intptr_t ptr = borrow();
+                    ptr_ = 0;
+                    drop_ = nullptr;
+                    return ptr;
+                }
+
+                void reset() {
+                    if (ptr_ != 0 && drop_ != nullptr) {
+                        drop_(ptr_);
+                    }
+                    ptr_ = 0;
+                    drop_ = nullptr;
+                }
+
+            private:
+                intptr_t ptr_;
+                Drop drop_;
+            };
+
+            $(static_literal("///")) Copies size bytes into memory owned by rust.
+            inline intptr_t lower_bytes(const void *data, size_t size, size_t align) {
+                if (size == 0) {
+                    return static_cast<intptr_t>(align);
+                }
+                uint8_t *ptr = ::$(symbols.allocate())(size, align);
+                std::memcpy(ptr, data, size);
+                return reinterpret_cast<intptr_t>(ptr);
+            }
+
+            inline void free_bytes(intptr_t ptr, size_t size, size_t align) {
+                if (size > 0) {
+                    ::$(symbols.deallocate())(reinterpret_cast<uint8_t *>(ptr), size, align);
+                }
+            }
+
+            $(static_literal("///")) Packs the u32 byte length of every string followed by the utf8 bytes.
+            template <typename S>
+            std::vector<uint8_t> pack_strs(const std::vector<S> &strs) {
+                std::vector<uint8_t> bytes(strs.size() * 4);
+                for (size_t i = 0; i < strs.size(); i++) {
+                    uint32_t len = static_cast<uint32_t>(strs[i].size());
+                    std::memcpy(bytes.data() + i * 4, &len, 4);
+                    bytes.insert(bytes.end(), strs[i].begin(), strs[i].end());
+                }
+                return bytes;
+            }
+
+            inline std::mutex &wakers_mutex() {
+                static std::mutex mutex;
+                return mutex;
+            }
+
+            inline std::unordered_map<int64_t, std::function<void()>> &wakers() {
+                static std::unordered_map<int64_t, std::function<void()>> wakers;
+                return wakers;
+            }
+
+            inline int64_t register_waker(std::function<void()> waker) {
+                static int64_t next_port = 0;
+                std::lock_guard<std::mutex> lock(wakers_mutex());
+                int64_t port = ++next_port;
+                wakers().emplace(port, std::move(waker));
+                return port;
+            }
+
+            inline void unregister_waker(int64_t port) {
+                std::lock_guard<std::mutex> lock(wakers_mutex());
+                wakers().erase(port);
+            }
+
+            $(static_literal("///")) Passed to rust as the post_cobject argument of the poll functions. The waker
+            $(static_literal("///")) is copied out of the map, so it can poll again without holding the lock.
+            inline void post_cobject(int64_t port, const void *) {
+                std::function<void()> waker;
+                {
+                    std::lock_guard<std::mutex> lock(wakers_mutex());
+                    auto it = wakers().find(port);
+                    if (it == wakers().end()) {
+                        return;
+                    }
+                    waker = it->second;
+                }
+                waker();
+            }
+
+            $(static_literal("///")) Polls a rust future or stream whenever it is woken.
+            class Task : public std::enable_shared_from_this<Task> {
+            public:
+                virtual ~Task() = default;
+
+                void wake() {
+                    {
+                        std::lock_guard<std::mutex> lock(mutex_);
+                        if (finished_) {
+                            return;
+                        }
+                        if (polling_) {
+                            woken_ = true;
+                            return;
+                        }
+                        polling_ = true;
+                    }
+                    auto self = shared_from_this();
+                    for (;;) {
+                        bool finished = poll();
+                        std::lock_guard<std::mutex> lock(mutex_);
+                        if (finished || !woken_) {
+                            finished_ = finished;
+                            polling_ = false;
+                            return;
+                        }
+                        woken_ = false;
+                    }
+                }
+
+            protected:
+                $(static_literal("///")) Returns true once the task is finished.
+                virtual bool poll() = 0;
+
+            private:
+                std::mutex mutex_;
+                bool polling_ = false;
+                bool woken_ = false;
+                bool finished_ = false;
+            };
+
+            template <typename T>
+            class FutureTask : public Task {
+            public:
+                using Poll = std::optional<T> (*)(intptr_t, intptr_t, int64_t);
+
+                FutureTask(Box box, Poll poll) : box_(std::move(box)), poll_(poll) {}
+
+                std::future<T> start() {
+                    auto future = promise_.get_future();
+                    auto self = shared_from_this();
+                    port_ = register_waker([self] { self->wake(); });
+                    wake();
+                    return future;
+                }
+
+            protected:
+                bool poll() override {
+                    try {
+                        auto ret = poll_(box_.borrow(), reinterpret_cast<intptr_t>(&post_cobject), port_);
+                        if (!ret) {
+                            return false;
+                        }
+                        promise_.set_value(std::move(*ret));
+                    } catch (...) {
+                        promise_.set_exception(std::current_exception());
+                    }
+                    unregister_waker(port_);
+                    box_.reset();
+                    return true;
+                }
+
+            private:
+                Box box_;
+                Poll poll_;
+                int64_t port_ = 0;
+                std::promise<T> promise_;
+            };
+
+            template <typename T>
+            std::future<T> spawn_future(Box box, std::optional<T> (*poll)(intptr_t, intptr_t, int64_t)) {
+                auto task = std::make_shared<FutureTask<T>>(std::move(box), poll);
+                return task->start();
+            }
+
+            template <typename T>
+            class StreamTask : public Task {
+            public:
+                using Poll = std::optional<T> (*)(intptr_t, intptr_t, int64_t, int64_t);
+
+                StreamTask(
+                    Box box,
+                    Poll poll,
+                    std::function<void(T)> on_item,
+                    std::function<void(std::exception_ptr)> on_error,
+                    std::function<void()> on_done)
+                    : box_(std::move(box)),
+                      poll_(poll),
+                      on_item_(std::move(on_item)),
+                      on_error_(std::move(on_error)),
+                      on_done_(std::move(on_done)) {}
+
+                void start() {
+                    auto self = shared_from_this();
+                    port_ = register_waker([self] { self->wake(); });
+                    done_ = register_waker([this, self] {
+                        ended_ = true;
+                        wake();
+                    });
+                    wake();
+                }
+
+            protected:
+                bool poll() override {
+                    if (!ended_) {
+                        try {
+                            auto ret = poll_(box_.borrow(), reinterpret_cast<intptr_t>(&post_cobject), port_, done_);
+                            if (ret) {
+                                on_item_(std::move(*ret));
+                            }
+                        } catch (...) {
+                            if (on_error_) {
+                                on_error_(std::current_exception());
+                            }
+                        }
+                        return false;
+                    }
+                    unregister_waker(port_);
+                    unregister_waker(done_);
+                    box_.reset();
+                    if (on_done_) {
+                        on_done_();
+                    }
+                    return true;
+                }
+
+            private:
+                Box box_;
+                Poll poll_;
+                std::function<void(T)> on_item_;
+                std::function<void(std::exception_ptr)> on_error_;
+                std::function<void()> on_done_;
+                int64_t port_ = 0;
+                int64_t done_ = 0;
+                std::atomic<bool> ended_{false};
+            };
+
+            }
+
+            $(static_literal("///")) Result passed as an argument to rust.
+            template <typename T>
+            class Result {
+            public:
+                static Result ok(T value) {
+                    return Result(std::optional<T>(std::move(value)), "");
+                }
+
+                static Result err(std::string error) {
+                    return Result(std::nullopt, std::move(error));
+                }
+
+                bool is_ok() const {
+                    return value_.has_value();
+                }
+
+                T &value() {
+                    if (!value_) {
+                        throw std::logic_error("result is an error");
+                    }
+                    return *value_;
+                }
+
+                const std::string &error() const {
+                    if (value_) {
+                        throw std::logic_error("result is not an error");
+                    }
+                    return error_;
+                }
+
+            private:
+                Result(std::optional<T> value, std::string error)
+                    : value_(std::move(value)), error_(std::move(error)) {}
+
+                std::optional<T> value_;
+                std::string error_;
+            };
+
+            $(static_literal("///")) A rust iterator.
+            template <typename T>
+            class Iter {
+            public:
+                using Next = std::optional<T> (*)(intptr_t);
+
+                Iter(detail::Box box, Next next) : box_(std::move(box)), next_(next) {}
+
+                std::optional<T> next() {
+                    return next_(box_.borrow());
+                }
+
+                std::vector<T> collect() {
+                    std::vector<T> items;
+                    while (auto item = next()) {
+                        items.push_back(std::move(*item));
+                    }
+                    return items;
+                }
+
+                detail::Box box_;
+
+            private:
+                Next next_;
+            };
+
+            $(static_literal("///")) A rust stream, items are delivered to the callbacks passed to listen
+            $(static_literal("///")) on the thread that wakes the stream.
+            template <typename T>
+            class Stream {
+            public:
+                using Poll = std::optional<T> (*)(intptr_t, intptr_t, int64_t, int64_t);
+
+                Stream(detail::Box box, Poll poll) : box_(std::move(box)), poll_(poll) {}
+
+                void listen(
+                    std::function<void(T)> on_item,
+                    std::function<void(std::exception_ptr)> on_error = nullptr,
+                    std::function<void()> on_done = nullptr) {
+                    auto task = std::make_shared<detail::StreamTask<T>>(
+                        std::move(box_), poll_, std::move(on_item), std::move(on_error), std::move(on_done));
+                    task->start();
+                }
+
+                detail::Box box_;
+
+            private:
+                Poll poll_;
+            };
+        }
+    }
+
+    fn generate_enum(&self, e: &Enum) -> c::Tokens {
+        let name = self.type_ident(&e.ident);
+        let tag = format!("{}Tag", name);
+        quote! {
+            enum class $(&tag) {
+                $(for entry in &e.entries => $(self.type_ident(&entry.name)),$['\r'])
+            };
+
+            $(self.generate_doc(&e.doc))
+            class $(&name) {
+            public:
+                explicit $(&name)(detail::Box box);
+
+                $(&tag) tag() const {
+                    return tag_;
+                }
+                $(for (entry, inner) in inner_objects(e) =>
+                    $['\n']
+                    const std::optional<$(self.type_ident(&inner))> &$(self.ident(&entry))() const {
+                        return $(&entry)_;
+                    }
+                )
+
+                detail::Box box_;
+
+            private:
+                $(&tag) tag_;
+                $(for (entry, inner) in inner_objects(e) =>
+                    std::optional<$(self.type_ident(&inner))> $(&entry)_;$['\r'])
+            };
+        }
+    }
+
+    fn generate_enum_constructor(&self, symbols: &Symbols, e: &Enum) -> c::Tokens {
+        let name = self.type_ident(&e.ident);
+        let inners = inner_objects(e);
+        quote! {
+            inline $(&name)::$(&name)(detail::Box box) : box_(std::move(box)) {
+                auto parts = ::$(symbols.destructure_enum(&e.ident))(reinterpret_cast<void *>(box_.borrow()));
+                switch (parts.tag) {
+                $(for (i, entry) in e.entries.iter().enumerate() =>
+                    case $(i):$['\r']
+                        tag_ = $(format!("{}Tag", name))::$(self.type_ident(&entry.name));
+                        $(for (field, inner) in inners.iter().filter(|(field, _)| *field == entry.name.to_snake_case()) =>
+                            $(field)_.emplace(detail::Box(
+                                reinterpret_cast<intptr_t>(parts.inner),
+                                $(self.drop_fn(&symbols.destructor(inner), Some(inner)))));
+                        )
+                        break;
+                )
+                default:
+                    throw std::runtime_error("destructuring enum gave back an invalid tag");
+                }
+            }
+        }
+    }
+
+    fn generate_ffi_buffer(&self, ty: NumType) -> c::Tokens {
+        let name = ffi_buffer_name_for(ty);
+        let num = self.num_type(ty);
+        quote! {
+            $(static_literal("///")) A buffer that can be filled through data and passed to rust without copying.
+            class $name {
+            public:
+                explicit $name(detail::Box box) : box_(std::move(box)) {}
+
+                $(static_literal("///")) Allocates a zeroed buffer of len elements.
+                static $name allocate(size_t len);
+
+                $num *data() const;
+
+                size_t size() const;
+
+                detail::Box box_;
+            };
+        }
+    }
+
+    fn generate_ffi_buffer_methods(&self, symbols: &Symbols, ty: NumType) -> c::Tokens {
+        let name = ffi_buffer_name_for(ty);
+        let num = self.num_type(ty);
+        quote! {
+            inline $name $name::allocate(size_t len) {
+                intptr_t ptr = ::$(symbols.ffi_buffer("new"))(len * sizeof($num), alignof($num));
+                return $name(detail::Box(ptr, $(self.drop_fn(&symbols.destructor("FfiBuffer"), None))));
+            }
+
+            inline $num *$name::data() const {
+                return reinterpret_cast<$num *>(::$(symbols.ffi_buffer("address"))(reinterpret_cast<void *>(box_.borrow())));
+            }
+
+            inline size_t $name::size() const {
+                return ::$(symbols.ffi_buffer("size"))(reinterpret_cast<void *>(box_.borrow())) / sizeof($num);
+            }
+        }
+    }
+
+    /// Strings can't be moved in or out of a list, rust doesn't export a
+    /// destructor for them.
+    fn generate_list_type(&self, objects: &[String], ty: &str) -> c::Tokens {
+        let name = format!("FfiList{}", ty);
+        let elem = self.type_ident(ty);
+        let owned = objects.iter().any(|obj| obj == ty);
+        quote! {
+            $(static_literal("///")) A rust Vec owning its elements.
+            class $(&name) {
+            public:
+                explicit $(&name)(detail::Box box) : box_(std::move(box)) {}
+
+                static $(&name) create();
+
+                uint32_t size() const;
+
+                $(static_literal("///")) The returned element is borrowed from the list.
+                $(&elem) at(uint32_t index) const;
+                $(if owned {
+                    $['\n']
+                    $(static_literal("///")) Moves the element out of the list.
+                    $(&elem) remove(uint32_t index);
+
+                    $(static_literal("///")) Moves the element into the list.
+                    void push_back($(&elem) element);
+
+                    $(static_literal("///")) Moves the element into the list.
+                    void insert(uint32_t index, $(&elem) element);
+                })
+
+                detail::Box box_;
+            };
+        }
+    }
+
+    fn generate_list_type_methods(
+        &self,
+        symbols: &Symbols,
+        objects: &[String],
+        ty: &str,
+    ) -> c::Tokens {
+        let name = format!("FfiList{}", ty);
+        let elem = self.type_ident(ty);
+        let owned = objects.iter().any(|obj| obj == ty);
+        let boxed = quote!(static_cast<uintptr_t>(box_.borrow()));
+        quote! {
+            inline $(&name) $(&name)::create() {
+                intptr_t ptr = static_cast<intptr_t>(::$(symbols.list(ty, "Create"))());
+                return $(&name)(detail::Box(ptr, $(self.drop_fn(&symbols.destructor(&name), None))));
+            }
+
+            inline uint32_t $(&name)::size() const {
+                return ::$(symbols.list(ty, "Len"))($(&boxed));
+            }
+
+            inline $(&elem) $(&name)::at(uint32_t index) const {
+                intptr_t ptr = static_cast<intptr_t>(::$(symbols.list(ty, "ElementAt"))($(&boxed), index));
+                return $(&elem)(detail::Box(ptr, nullptr));
+            }
+            $(if owned {
+                $['\n']
+                inline $(&elem) $(&name)::remove(uint32_t index) {
+                    intptr_t ptr = static_cast<intptr_t>(::$(symbols.list(ty, "Remove"))($(&boxed), index));
+                    return $(&elem)(detail::Box(ptr, $(self.drop_fn(&symbols.destructor(ty), Some(ty)))));
+                }
+
+                inline void $(&name)::push_back($(&elem) element) {
+                    ::$(symbols.list(ty, "Add"))($(&boxed), static_cast<uintptr_t>(element.box_.release()));
+                }
+
+                inline void $(&name)::insert(uint32_t index, $(&elem) element) {
+                    ::$(symbols.list(ty, "Insert"))($(&boxed), index, static_cast<uintptr_t>(element.box_.release()));
+                }
+            })
+        }
+    }
+
+    /// Declaration of a method inside of its class.
+    fn generate_declaration(&self, func: &AbiFunction) -> c::Tokens {
+        let ffi = self.abi.import(func);
+        let (ret, args) = self.signature(func, &ffi);
+        let name = self.ident(&func.name);
+        quote! {
+            $(self.generate_doc(&func.doc))
+            $(match &func.ty {
+                FunctionType::Constructor(_) => { static $ret $name($args); }
+                _ => { $ret $name($args) const; }
+            })
+        }
+    }
+
+    /// Declaration of a wrapper around a `next` or `poll` function.
+    fn generate_prototype(&self, func: &AbiFunction) -> c::Tokens {
+        let ffi = self.abi.import(func);
+        let (ret, args) = self.signature(func, &ffi);
+        quote!(inline $ret $(wrapper(&ffi.symbol))($args);)
+    }
+
+    fn generate_function(&self, objects: &[String], func: &AbiFunction) -> c::Tokens {
+        let ffi = self.abi.import(func);
+        let (ret, args) = self.signature(func, &ffi);
+        let body = Body {
+            objects,
+            handle_args: self.c.handle_args(func),
+            handle_ret: self.c.handle_ret(func).is_some(),
+            void_call: matches!(ffi.ffi_ret, Return::Void),
+            ret: match (&ffi.abi_ret, &func.ret) {
+                (Some(_), _) => Ret::Value,
+                (None, Some(AbiType::Option(_))) => Ret::Unit,
+                (None, _) => Ret::Void,
+            },
+        };
+        let body = self.generate_body(&body, &ffi.instr);
+        let name = self.ident(&func.name);
+        match &func.ty {
+            FunctionType::Function => quote! {
+                $(self.generate_doc(&func.doc))
+                inline $ret $name($args) {
+                    $body
+                }
+            },
+            FunctionType::Constructor(object) => quote! {
+                inline $ret $(self.type_ident(object))::$name($args) {
+                    $body
+                }
+            },
+            FunctionType::Method(object) => quote! {
+                inline $ret $(self.type_ident(object))::$name($args) const {
+                    $body
+                }
+            },
+            FunctionType::NextIter(_, _)
+            | FunctionType::PollFuture(_, _)
+            | FunctionType::PollStream(_, _) => quote! {
+                inline $ret $(wrapper(&ffi.symbol))($args) {
+                    $body
+                }
+            },
+        }
+    }
+
+    fn signature(&self, func: &AbiFunction, ffi: &Import) -> (String, String) {
+        let ret = match &ffi.abi_ret {
+            Some(ty) => self.ret_type(ty),
+            // `Option<()>` has no abi return value.
+            None if matches!(func.ret, Some(AbiType::Option(_))) => {
+                "std::optional<std::tuple<>>".to_string()
+            }
+            None => "void".to_string(),
+        };
+        let args = ffi
+            .abi_args
+            .iter()
+            .map(|(name, ty)| decl(&self.arg_type(ty), &self.ident(name)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        (ret, args)
+    }
+
+    fn generate_body(&self, body: &Body, instrs: &[Instr]) -> c::Tokens {
+        // variables defined inside of an option or result need to outlive
+        // the branch for the cleanup instructions.
+        let mut nested = vec![];
+        for instr in instrs {
+            nested_defines(instr, &mut nested);
+        }
+        quote! {
+            $(for var in &nested => $(self.ffi_type(var)) $(self.var(var)) = 0;$['\r'])
+            $(for instr in instrs => $(self.generate_instr(body, instr, false))$['\r'])
+        }
+    }
+
+    fn generate_instr(&self, body: &Body, instr: &Instr, nested: bool) -> c::Tokens {
+        match instr {
+            Instr::BindArg(arg, out) => quote!(auto &&$(self.var(out)) = $(self.ident(arg));),
+            Instr::DefineArgs(_) if nested => quote!(),
+            Instr::DefineArgs(vars) => quote! {
+                $(for var in vars => $(self.ffi_type(var)) $(self.var(var)) = 0;$['\r'])
+            },
+            Instr::BorrowSelf(out) => quote!($(self.var(out)) = box_.borrow();),
+            Instr::BorrowObject(in_, out)
+            | Instr::BorrowIter(in_, out)
+            | Instr::BorrowFuture(in_, out)
+            | Instr::BorrowStream(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).box_.borrow();)
+            }
+            Instr::MoveObject(in_, out)
+            | Instr::MoveIter(in_, out)
+            | Instr::MoveStream(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).box_.release();)
+            }
+            Instr::MoveFuture(_, _) => unimplemented!("futures can only be returned"),
+            Instr::LowerNum(in_, out) => {
+                quote!($(self.var(out)) = static_cast<$(self.ffi_type(out))>($(self.var(in_)));)
+            }
+            Instr::LiftNum(in_, out, _) => {
+                quote!(auto $(self.var(out)) = std::move($(self.var(in_)));)
+            }
+            Instr::LowerBool(in_, out) => quote!($(self.var(out)) = $(self.var(in_)) ? 1 : 0;),
+            Instr::LiftBool(in_, out) => quote!(bool $(self.var(out)) = $(self.var(in_)) != 0;),
+            Instr::LowerString(in_, ptr, len, cap, size, align)
+            | Instr::LowerVec(in_, ptr, len, cap, _, size, align)
+            | Instr::LowerMutSlice(in_, ptr, len, cap, _, size, align) => quote! {
+                $(self.var(len)) = $(self.var(in_)).size();
+                $(self.var(ptr)) = detail::lower_bytes($(self.var(in_)).data(), $(self.var(len)) * $(*size), $(*align));
+                $(self.var(cap)) = $(self.var(len));
+            },
+            Instr::LowerStrSlice(in_, ptr, len, cap) => quote! {
+                auto $(self.var(ptr))_0 = detail::pack_strs($(self.var(in_)));
+                $(self.var(len)) = $(self.var(in_)).size();
+                $(self.var(cap)) = $(self.var(ptr))_0.size();
+                $(self.var(ptr)) = detail::lower_bytes($(self.var(ptr))_0.data(), $(self.var(cap)), 4);
+            },
+            Instr::LowerObjectSlice(in_, ptr, len, cap, size, align) => quote! {
+                std::vector<intptr_t> $(self.var(ptr))_0;
+                for (const auto *obj : $(self.var(in_))) {
+                    $(self.var(ptr))_0.push_back(obj->box_.borrow());
+                }
+                $(self.var(len)) = $(self.var(in_)).size();
+                $(self.var(ptr)) = detail::lower_bytes($(self.var(ptr))_0.data(), $(self.var(len)) * $(*size), $(*align));
+                $(self.var(cap)) = $(self.var(len));
+            },
+            Instr::WriteBack(in_, ptr, len, cap, ty) => quote! {
+                if ($(self.var(cap)) > 0) {
+                    std::memcpy(
+                        $(self.var(in_)).data(),
+                        reinterpret_cast<const void *>($(self.var(ptr))),
+                        $(self.var(len)) * sizeof($(self.num_type(*ty))));
+                }
+            },
+            Instr::Deallocate(ptr, len, size, align) => {
+                quote!(detail::free_bytes($(self.var(ptr)), $(self.var(len)) * $(*size), $(*align));)
+            }
+            Instr::LiftString(ptr, len, out) => quote! {
+                std::string $(self.var(out))(reinterpret_cast<const char *>($(self.var(ptr))), $(self.var(len)));
+            },
+            Instr::LiftVec(ptr, len, out, ty) => {
+                let num = self.num_type(*ty);
+                quote! {
+                    std::vector<$num> $(self.var(out))(
+                        reinterpret_cast<const $num *>($(self.var(ptr))),
+                        reinterpret_cast<const $num *>($(self.var(ptr))) + $(self.var(len)));
+                }
+            }
+            Instr::HandleNull(var) => quote! {
+                if ($(self.var(var)) == 0) {
+                    $(match body.ret {
+                        Ret::Void => { return; }
+                        Ret::Value | Ret::Unit => { return std::nullopt; }
+                    })
+                }
+            },
+            Instr::LowerOption(arg, var, some, some_instr) => quote! {
+                if (!$(self.var(arg))) {
+                    $(self.var(var)) = 0;
+                } else {
+                    $(self.var(var)) = 1;
+                    [[maybe_unused]] auto &&$(self.var(some)) = *$(self.var(arg));
+                    $(for instr in some_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                }
+            },
+            Instr::LowerResult(arg, var, ok, ok_instr, err, err_instr) => quote! {
+                if ($(self.var(arg)).is_ok()) {
+                    $(self.var(var)) = 1;
+                    [[maybe_unused]] auto &&$(self.var(ok)) = $(self.var(arg)).value();
+                    $(for instr in ok_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                } else {
+                    $(self.var(var)) = 0;
+                    [[maybe_unused]] auto &&$(self.var(err)) = $(self.var(arg)).error();
+                    $(for instr in err_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                }
+            },
+            Instr::HandleError(var, ptr, len, cap) => quote! {
+                if ($(self.var(var)) == 0) {
+                    std::string $(self.var(var))_0(reinterpret_cast<const char *>($(self.var(ptr))), $(self.var(len)));
+                    detail::free_bytes($(self.var(ptr)), $(self.var(cap)), 1);
+                    throw std::runtime_error($(self.var(var))_0);
+                }
+            },
+            Instr::LiftObject(obj, box_, drop, out) => {
+                let object = body.objects.iter().find(|o| *o == obj).map(|o| o.as_str());
+                quote! {
+                    auto $(self.var(out)) = $(self.type_ident(obj))(detail::Box($(self.var(box_)), $(self.drop_fn(drop, object))));
+                }
+            }
+            Instr::LiftRefObject(obj, box_, _leak, out) => quote! {
+                auto $(self.var(out)) = $(self.type_ident(obj))(detail::Box($(self.var(box_)), nullptr));
+            },
+            Instr::LiftIter(box_, next, drop, out) => quote! {
+                auto $(self.var(out)) = $(self.ret_type(lifted(&out.ty)))(
+                    detail::Box($(self.var(box_)), $(self.drop_fn(drop, None))),
+                    &detail::$(wrapper(next)));
+            },
+            Instr::LiftRefIter(box_, next, _leak, out) => quote! {
+                auto $(self.var(out)) = $(self.ret_type(lifted(&out.ty)))(detail::Box($(self.var(box_)), nullptr), &detail::$(wrapper(next)));
+            },
+            Instr::LiftFuture(box_, poll, drop, out) => quote! {
+                auto $(self.var(out)) = detail::spawn_future(
+                    detail::Box($(self.var(box_)), $(self.drop_fn(drop, None))),
+                    &detail::$(wrapper(poll)));
+            },
+            Instr::LiftStream(box_, poll, drop, out) => quote! {
+                auto $(self.var(out)) = $(self.ret_type(lifted(&out.ty)))(
+                    detail::Box($(self.var(box_)), $(self.drop_fn(drop, None))),
+                    &detail::$(wrapper(poll)));
+            },
+            Instr::LiftTuple(vars, out) => match vars.len() {
+                0 => quote!([[maybe_unused]] auto $(self.var(out)) = std::tuple<>();),
+                1 => quote!(auto $(self.var(out)) = std::move($(self.var(&vars[0])));),
+                _ => quote! {
+                    auto $(self.var(out)) = std::make_tuple($(for var in vars join (, ) => std::move($(self.var(var)))));
+                },
+            },
+            Instr::Call(symbol, ret, args) => {
+                let args = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| match body.handle_args.get(i) {
+                        Some(Some(object)) => {
+                            format!("reinterpret_cast<::{} *>({})", object, self.var(arg))
+                        }
+                        _ => self.var(arg),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                // parenthesized to avoid expanding function-like macros
+                // like glibc's `__f32`.
+                let call = format!("(::{})({})", symbol, args);
+                match ret {
+                    Some(ret) if !body.void_call => {
+                        if body.handle_ret {
+                            quote!(auto $(self.var(ret)) = reinterpret_cast<intptr_t>($call);)
+                        } else {
+                            quote!(auto $(self.var(ret)) = $call;)
+                        }
+                    }
+                    _ => quote!($call;),
+                }
+            }
+            Instr::BindRets(ret, vars) => match vars.len() {
+                0 => quote!(),
+                1 => quote!(auto $(self.var(&vars[0])) = $(self.var(ret));),
+                _ => quote! {
+                    $(for (i, var) in vars.iter().enumerate() =>
+                        auto $(self.var(var)) = $(self.var(ret)).$(format!("ret{}", i));$['\r'])
+                },
+            },
+            Instr::ReturnValue(ret) => quote!(return $(self.var(ret));),
+            Instr::ReturnVoid => match body.ret {
+                Ret::Unit => quote!(return std::tuple<>();),
+                Ret::Void | Ret::Value => quote!(return;),
+            },
+            Instr::LiftNumFromU32Tuple(..)
+            | Instr::LowerNumFromU32Tuple(..)
+            | Instr::CallRetArea(..) => unreachable!(),
+        }
+    }
+
+    /// Captureless lambda calling the destructor `drop`, object destructors
+    /// take a typed handle.
+    fn drop_fn(&self, drop: &str, object: Option<&str>) -> String {
+        match object {
+            Some(object) => format!(
+                "[](intptr_t ptr) {{ ::{}(0, reinterpret_cast<::{} *>(ptr)); }}",
+                drop, object
+            ),
+            None => format!("[](intptr_t ptr) {{ ::{}(0, ptr); }}", drop),
+        }
+    }
+
+    fn arg_type(&self, ty: &AbiType) -> String {
+        match ty {
+            AbiType::RefStr => "std::string_view".to_string(),
+            AbiType::RefSlice(ty) => format!("const std::vector<{}> &", self.num_type(*ty)),
+            AbiType::RefMutSlice(ty) => format!("std::vector<{}> &", self.num_type(*ty)),
+            AbiType::RefSliceStr => "const std::vector<std::string> &".to_string(),
+            AbiType::RefSliceObject(ty) => {
+                format!("const std::vector<const {} *> &", self.type_ident(ty))
+            }
+            AbiType::RefObject(ty) => format!("const {} &", self.type_ident(ty)),
+            AbiType::RefBuffer(ty) => format!("const {} &", ffi_buffer_name_for(*ty)),
+            AbiType::Option(ty) => format!("std::optional<{}>", self.value_type(ty, true)),
+            AbiType::Result(ty) => format!("Result<{}>", self.value_type(ty, true)),
+            AbiType::Future(_) | AbiType::RefFuture(_) => {
+                unimplemented!("futures can only be returned")
+            }
+            ty => self.value_type(ty, true),
+        }
+    }
+
+    fn ret_type(&self, ty: &AbiType) -> String {
+        self.value_type(ty, false)
+    }
+
+    /// Type of a value, results passed as arguments are wrapped in a
+    /// `Result`, returned errors are thrown.
+    fn value_type(&self, ty: &AbiType, arg: bool) -> String {
+        match ty {
+            AbiType::Num(ty) => self.num_type(*ty).to_string(),
+            AbiType::Isize => "intptr_t".to_string(),
+            AbiType::Usize => "size_t".to_string(),
+            AbiType::Bool => "bool".to_string(),
+            AbiType::RefStr | AbiType::String => "std::string".to_string(),
+            AbiType::RefSlice(ty) | AbiType::RefMutSlice(ty) | AbiType::Vec(ty) => {
+                format!("std::vector<{}>", self.num_type(*ty))
+            }
+            AbiType::RefSliceStr => "std::vector<std::string>".to_string(),
+            AbiType::RefSliceObject(ty) => format!("std::vector<const {} *>", self.type_ident(ty)),
+            AbiType::RefObject(ty) | AbiType::Object(ty) | AbiType::RefEnum(ty) => {
+                self.type_ident(ty)
+            }
+            AbiType::Option(ty) => format!("std::optional<{}>", self.value_type(ty, arg)),
+            AbiType::Result(ty) if arg => format!("Result<{}>", self.value_type(ty, arg)),
+            AbiType::Result(ty) => self.value_type(ty, arg),
+            AbiType::Tuple(tys) => match tys.len() {
+                1 => self.value_type(&tys[0], arg),
+                _ => format!(
+                    "std::tuple<{}>",
+                    tys.iter()
+                        .map(|ty| self.value_type(ty, arg))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            },
+            AbiType::Iter(ty) | AbiType::RefIter(ty) => {
+                format!("Iter<{}>", self.value_type(ty, false))
+            }
+            AbiType::Future(ty) | AbiType::RefFuture(ty) => {
+                format!("std::future<{}>", self.value_type(ty, false))
+            }
+            AbiType::Stream(ty) | AbiType::RefStream(ty) => {
+                format!("Stream<{}>", self.value_type(ty, false))
+            }
+            AbiType::Buffer(ty) | AbiType::RefBuffer(ty) => ffi_buffer_name_for(*ty).to_string(),
+            AbiType::List(ty) => format!("FfiList{}", ty),
+        }
+    }
+
+    fn ffi_type(&self, var: &Var) -> &'static str {
+        match &var.ty {
+            AbiType::Num(num) => self.num_type(*num),
+            ty => unreachable!("{:?} is not an ffi type", ty),
+        }
+    }
+
+    fn num_type(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::U8 => "uint8_t",
+            NumType::U16 => "uint16_t",
+            NumType::U32 => "uint32_t",
+            NumType::U64 => "uint64_t",
+            NumType::I8 => "int8_t",
+            NumType::I16 => "int16_t",
+            NumType::I32 => "int32_t",
+            NumType::I64 => "int64_t",
+            NumType::F32 => "float",
+            NumType::F64 => "double",
+            NumType::IPtr => "intptr_t",
+            NumType::UPtr => "uintptr_t",
+        }
+    }
+
+    fn generate_doc(&self, doc: &[String]) -> Vec<c::Tokens> {
+        doc.iter()
+            .enumerate()
+            .map(|(index, line)| {
+                if index < doc.len() - 1 {
+                    quote!($(format!("/// {}\n", line)))
+                } else {
+                    quote!($(format!("/// {}", line)))
+                }
+            })
+            .collect()
+    }
+
+    fn var(&self, var: &Var) -> String {
+        format!("tmp{}", var.binding)
+    }
+
+    fn type_ident(&self, s: &str) -> String {
+        sanitize_identifier(s)
+    }
+
+    fn ident(&self, s: &str) -> String {
+        sanitize_identifier(s)
+    }
+}
+
+/// Declares `name` with type `ty`, references are written as `T &name`.
+fn decl(ty: &str, name: &str) -> String {
+    if ty.ends_with('&') || ty.ends_with('*') {
+        format!("{}{}", ty, name)
+    } else {
+        format!("{} {}", ty, name)
+    }
+}
+
+/// Type lifted by the last instruction of an optional or fallible return.
+fn lifted(ty: &AbiType) -> &AbiType {
+    match ty {
+        AbiType::Option(ty) | AbiType::Result(ty) => lifted(ty),
+        ty => ty,
+    }
+}
+
+/// Name of the wrapper around the `next` or `poll` function `symbol`.
+fn wrapper(symbol: &str) -> String {
+    format!("wrap_{}", symbol.trim_start_matches('_'))
+}
+
+/// Field name and object of every enum entry wrapping an object.
+fn inner_objects(e: &Enum) -> Vec<(String, String)> {
+    e.entries
+        .iter()
+        .filter_map(|entry| match &entry.inner {
+            Some(Type::Ident(name)) => Some((entry.name.to_snake_case(), name.clone())),
+            Some(_) => unimplemented!("Enums can only wrap objects"),
+            None => None,
+        })
+        .collect()
+}
+
+/// Collects the variables defined inside of `LowerOption` and `LowerResult`.
+fn nested_defines(instr: &Instr, vars: &mut Vec<Var>) {
+    fn collect(instrs: &[Instr], vars: &mut Vec<Var>) {
+        for instr in instrs {
+            if let Instr::DefineArgs(defines) = instr {
+                vars.extend(defines.iter().cloned());
+            }
+            nested_defines(instr, vars);
+        }
+    }
+    match instr {
+        Instr::LowerOption(_, _, _, some) => collect(some, vars),
+        Instr::LowerResult(_, _, _, ok, _, err) => {
+            collect(ok, vars);
+            collect(err, vars);
+        }
+        _ => {}
+    }
+}
+
+fn sanitize_identifier(id: &str) -> String {
+    if RESERVED_IDENTIFIERS.contains(&id) {
+        format!("{}_", id)
+    } else {
+        id.to_string()
+    }
+}
+
+// https://en.cppreference.com/w/cpp/keyword
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 92] = [
+    "alignas",
+    "alignof",
+    "and",
+    "and_eq",
+    "asm",
+    "auto",
+    "bitand",
+    "bitor",
+    "bool",
+    "break",
+    "case",
+    "catch",
+    "char",
+    "char8_t",
+    "char16_t",
+    "char32_t",
+    "class",
+    "compl",
+    "concept",
+    "const",
+    "consteval",
+    "constexpr",
+    "constinit",
+    "const_cast",
+    "continue",
+    "co_await",
+    "co_return",
+    "co_yield",
+    "decltype",
+    "default",
+    "delete",
+    "do",
+    "double",
+    "dynamic_cast",
+    "else",
+    "enum",
+    "explicit",
+    "export",
+    "extern",
+    "false",
+    "float",
+    "for",
+    "friend",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "mutable",
+    "namespace",
+    "new",
+    "noexcept",
+    "not",
+    "not_eq",
+    "nullptr",
+    "operator",
+    "or",
+    "or_eq",
+    "private",
+    "protected",
+    "public",
+    "register",
+    "reinterpret_cast",
+    "requires",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "static_assert",
+    "static_cast",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "thread_local",
+    "throw",
+    "true",
+    "try",
+    "typedef",
+    "typeid",
+    "typename",
+    "union",
+    "unsigned",
+    "using",
+    "virtual",
+    "void",
+    "volatile",
+    "wchar_t",
+    "while",
+    "xor",
+    "xor_eq",
+];
+
+#[cfg(feature = "test_runner")]
+#[doc(hidden)]
+pub mod test_runner {
+    use super::*;
+    use crate::RustGenerator;
+    use anyhow::Result;
+    use std::io::Write;
+    use std::process::Command;
+    use tempfile::{NamedTempFile, TempDir};
+    use trybuild::TestCases;
+
+    pub fn compile_pass(iface: &str, rust: rust::Tokens, cpp: c::Tokens) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let (mut rust_file, rust_file_path) = NamedTempFile::new()?.keep()?;
+        writeln!(rust_file, "#![feature(vec_into_raw_parts)]")?;
+        writeln!(rust_file, "#![feature(lazy_cell)]")?;
+        let rust_gen = RustGenerator::new(Abi::native());
+        let rust_tokens = rust_gen.generate(iface.clone());
+        let header = CGenerator::new().generate(iface.clone()).to_file_string()?;
+        let api = CppGenerator::new("api.h".to_string(), "api".to_string())
+            .generate(iface)
+            .to_file_string()?;
+
+        let library_tokens: genco::Tokens<genco::lang::Rust> = quote! {
+            $rust_tokens
+            $rust
+        };
+
+        let bin_tokens: c::Tokens = quote! {
+            #include <cassert>
+            #include "api.hpp"
+
+            using namespace api;
+
+            int main() {
+                $cpp
+                return 0;
+            }
+        };
+
+        let library_dir = TempDir::new()?.keep();
+        let library_file = library_dir.join("libcompile_pass.so");
+        let bin_file = library_dir.join("main");
+        std::fs::write(library_dir.join("api.h"), header)?;
+        std::fs::write(library_dir.join("api.hpp"), api)?;
+        std::fs::write(library_dir.join("main.cpp"), bin_tokens.to_file_string()?)?;
+
+        let library = library_tokens.to_file_string()?;
+        rust_file.write_all(library.as_bytes())?;
+
+        let cxx = std::env::var("CXX").unwrap_or_else(|_| "c++".to_string());
+        let runner_tokens: rust::Tokens = quote! {
+            fn main() {
+                use std::process::Command;
+                let ret = Command::new("rustc")
+                    .arg("--edition")
+                    .arg("2021")
+                    .arg("--crate-name")
+                    .arg("compile_pass")
+                    .arg("--crate-type")
+                    .arg("cdylib")
+                    .arg("--cfg")
+                    .arg("feature=\"test_runner\"")
+                    .arg("-o")
+                    .arg($(quoted(library_file.as_path().to_str().unwrap())))
+                    .arg($(quoted(rust_file_path.as_path().to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+                let ret = Command::new($(quoted(&cxx)))
+                    .current_dir($(quoted(library_dir.as_path().to_str().unwrap())))
+                    .args(["-std=c++17", "-Wall", "-Wextra", "-Werror", "-pedantic"])
+                    .arg("main.cpp")
+                    .arg("-L.")
+                    .arg("-lcompile_pass")
+                    .arg("-pthread")
+                    .arg("-o")
+                    .arg($(quoted(bin_file.as_path().to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+                let ret = Command::new($(quoted(bin_file.as_path().to_str().unwrap())))
+                    .env("LD_LIBRARY_PATH", $(quoted(library_dir.as_path().to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+            }
+        };
+        let (mut runner_file, runner_file_path) = NamedTempFile::new()?.keep()?;
+        let runner = runner_tokens.to_file_string()?;
+        runner_file.write_all(runner.as_bytes())?;
+
+        let test = TestCases::new();
+        test.pass(runner_file_path.as_path().to_str().unwrap());
+        Ok(())
+    }
+
+    /// Checks that the generated header compiles with the system C++ compiler.
+    pub fn compile_pass_header(iface: &str) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let header = CGenerator::new().generate(iface.clone()).to_file_string()?;
+        let cpp = CppGenerator::new("api.h".to_string(), "api".to_string())
+            .generate(iface)
+            .to_file_string()?;
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("api.h"), header)?;
+        std::fs::write(dir.path().join("api.hpp"), cpp)?;
+        std::fs::write(
+            dir.path().join("main.cpp"),
+            "#include \"api.hpp\"\n#include \"api.hpp\"\n\nint main() {\n    return 0;\n}\n",
+        )?;
+        let cxx = std::env::var("CXX").unwrap_or_else(|_| "c++".to_string());
+        let status = Command::new(cxx)
+            .args(["-std=c++17", "-Wall", "-Wextra", "-Werror", "-pedantic"])
+            .arg("-fsyntax-only")
+            .arg("main.cpp")
+            .current_dir(dir.path())
+            .status()?;
+        if !status.success() {
+            anyhow::bail!("compiling the c++ header failed");
+        }
+        Ok(())
+    }
+}
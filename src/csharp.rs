@@ -0,0 +1,1415 @@
+use crate::dart::ffi_buffer_name_for;
+use crate::import::{Import, Instr};
+use crate::parser::{Enum, Type};
+use crate::{
+    Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Symbols, Var,
+    NUM_TYPES,
+};
+use genco::prelude::*;
+use genco::tokens::static_literal;
+use heck::*;
+
+pub struct CSharpGenerator {
+    abi: Abi,
+    namespace: String,
+    cdylib_name: String,
+}
+
+/// State shared by the instructions of a single function body.
+struct Body {
+    void_call: bool,
+    /// `Some(())` is returned as `true` to tell it apart from `None`.
+    unit: bool,
+    /// `next` and `poll` wrappers return whether there is a value and pass
+    /// it through an `out` argument.
+    wrapper: bool,
+    /// Item type of the iterator, future or stream returned by the function.
+    item: Option<String>,
+    /// Values whose handles are borrowed by the call and need to stay
+    /// reachable until it returns, the finalizer would drop them otherwise.
+    fences: Vec<String>,
+}
+
+impl CSharpGenerator {
+    /// Generates a c# file in `namespace` importing the functions from the
+    /// library `cdylib_name`.
+    pub fn new(namespace: String, cdylib_name: String) -> Self {
+        Self {
+            abi: Abi::native(),
+            namespace,
+            cdylib_name,
+        }
+    }
+
+    pub fn generate(&self, iface: Interface) -> csharp::Tokens {
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
+        let objects: Vec<String> = iface.objects().into_iter().map(|obj| obj.name).collect();
+        let imports = iface.imports(&self.abi);
+        let destructors = self.destructors(&iface, &symbols);
+        let mut wrappers = vec![];
+        for iter in iface.iterators() {
+            wrappers.push(iter.next());
+        }
+        for fut in iface.futures() {
+            wrappers.push(fut.poll());
+        }
+        for stream in iface.streams() {
+            wrappers.push(stream.poll());
+        }
+        quote! {
+            $(static_literal("//")) AUTO GENERATED FILE, DO NOT EDIT.
+            $(static_literal("//"))
+            $(static_literal("//")) Generated by "ffi-gen".
+            $(for line in &iface.doc => $(format!("// {}", line).trim_end().to_string())$['\r'])
+            $(static_literal("#nullable enable"))
+
+            using System;
+            using System.Collections;
+            using System.Collections.Concurrent;
+            using System.Collections.Generic;
+            using System.Linq;
+            using System.Runtime.CompilerServices;
+            using System.Runtime.InteropServices;
+            using System.Text;
+            using System.Threading;
+            using System.Threading.Channels;
+            using System.Threading.Tasks;
+
+            namespace $(&self.namespace);
+
+            $(self.generate_native(&iface, &symbols, &destructors, &imports))
+
+            $(for func in &imports join ($['\n']) => $(self.generate_return_struct(&func.ffi_ret)))
+
+            $(self.generate_runtime(&symbols))
+
+            $(for drop in &destructors join ($['\n']) => $(self.generate_handle(drop)))
+
+            $(for obj in iface.objects() join ($['\n']) => $(self.generate_object(&obj)))
+
+            $(for e in &iface.enums join ($['\n']) => $(self.generate_enum(&symbols, e)))
+
+            $(for ty in NUM_TYPES join ($['\n']) => $(self.generate_ffi_buffer(&symbols, ty)))
+
+            $(for ty in iface.listed_types() join ($['\n']) => $(self.generate_list_type(&symbols, &objects, &ty)))
+
+            internal static class FfiWrappers {
+                $(for func in &wrappers join ($['\n']) => $(self.generate_function(func)))
+            }
+
+            $(static_literal("/// <summary>"))
+            $(static_literal("/// Functions exported by the library."))
+            $(static_literal("/// </summary>"))
+            public static class Api {
+                $(for func in iface.functions() join ($['\n']) => $(self.generate_function(&func)))
+            }
+        }
+    }
+
+    /// Every destructor a handle can be released with, the leak destructor
+    /// isn't needed as borrowed handles aren't released.
+    fn destructors(&self, iface: &Interface, symbols: &Symbols) -> Vec<String> {
+        let mut destructors = vec![symbols.destructor("FfiBuffer")];
+        for obj in iface.objects() {
+            destructors.push(obj.destructor);
+        }
+        for iter in iface.iterators() {
+            if !iter.borrowed {
+                destructors.push(format!("{}_iter_drop", iter.symbol));
+            }
+        }
+        for fut in iface.futures() {
+            destructors.push(format!("{}_future_drop", fut.symbol));
+        }
+        for stream in iface.streams() {
+            destructors.push(format!("{}_stream_drop", stream.symbol));
+        }
+        for e in &iface.enums {
+            destructors.push(symbols.destructor(&e.ident));
+        }
+        for ty in iface.listed_types() {
+            destructors.push(symbols.destructor(&format!("FfiList{}", ty)));
+        }
+        destructors
+    }
+
+    /// `DllImport` declarations of every exported symbol, handles are passed
+    /// as `nint`.
+    fn generate_native(
+        &self,
+        iface: &Interface,
+        symbols: &Symbols,
+        destructors: &[String],
+        imports: &[Import],
+    ) -> csharp::Tokens {
+        quote! {
+            internal static class Native {
+                private const string Library = $(quoted(&self.cdylib_name));
+
+                $(self.declare("nint", &symbols.allocate(), "nuint size, nuint align"))
+                $(self.declare("void", &symbols.deallocate(), "nint ptr, nuint size, nuint align"))
+                $(self.declare("nint", &symbols.ffi_buffer("new"), "nuint size, nuint align"))
+                $(self.declare("nint", &symbols.ffi_buffer("address"), "nint ptr"))
+                $(self.declare("nuint", &symbols.ffi_buffer("size"), "nint ptr"))
+                $(self.declare("FfiStringParts", &symbols.string_into_parts(), "nint ptr"))
+                $(for e in &iface.enums =>
+                    $(self.declare("FfiEnumWrapper", &symbols.destructure_enum(&e.ident), "nint ptr"))$['\r'])
+                $(for drop in destructors =>
+                    $(self.declare("void", drop, "nint isolate, nint boxed"))$['\r'])
+                $(for ty in iface.listed_types() => $(self.declare_list(symbols, &ty))$['\r'])
+                $(for func in imports => $(self.declare_import(func))$['\r'])
+            }
+        }
+    }
+
+    fn declare(&self, ret: &str, symbol: &str, args: &str) -> csharp::Tokens {
+        quote! {
+            [DllImport(Library)]
+            internal static extern $ret $symbol($args);
+        }
+    }
+
+    fn declare_list(&self, symbols: &Symbols, ty: &str) -> csharp::Tokens {
+        quote! {
+            $(self.declare("nint", &symbols.list(ty, "Create"), ""))
+            $(self.declare("uint", &symbols.list(ty, "Len"), "nint boxed"))
+            $(self.declare("nint", &symbols.list(ty, "ElementAt"), "nint boxed, uint index"))
+            $(self.declare("nint", &symbols.list(ty, "Remove"), "nint boxed, uint index"))
+            $(self.declare("void", &symbols.list(ty, "Add"), "nint boxed, nint element"))
+            $(self.declare("void", &symbols.list(ty, "Insert"), "nint boxed, uint index, nint element"))
+        }
+    }
+
+    fn declare_import(&self, func: &Import) -> csharp::Tokens {
+        let args = func
+            .ffi_args
+            .iter()
+            .map(|var| format!("{} {}", self.ffi_type(var), self.var(var)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let ret = match &func.ffi_ret {
+            Return::Void => "void",
+            Return::Num(var) => self.ffi_type(var),
+            Return::Struct(_, name) => name,
+        };
+        self.declare(ret, &func.symbol, &args)
+    }
+
+    fn generate_return_struct(&self, ret: &Return) -> csharp::Tokens {
+        if let Return::Struct(vars, name) = ret {
+            quote! {
+                [StructLayout(LayoutKind.Sequential)]
+                internal struct $name {
+                    $(for (i, var) in vars.iter().enumerate() =>
+                        public $(self.ffi_type(var)) $(format!("Ret{}", i));$['\r'])
+                }
+            }
+        } else {
+            quote!()
+        }
+    }
+
+    fn generate_runtime(&self, symbols: &Symbols) -> csharp::Tokens {
+        quote! {
+            [StructLayout(LayoutKind.Sequential)]
+            internal struct FfiStringParts {
+                public nint Ptr;
+                public nuint Len;
+                public nuint Capacity;
+            }
+
+            [StructLayout(LayoutKind.Sequential)]
+            internal struct FfiEnumWrapper {
+                public uint Tag;
+                public nint Inner;
+            }
+
+            $(static_literal("/// <summary>"))
+            $(static_literal("/// Error returned by rust."))
+            $(static_literal("/// </summary>"))
+            public sealed class FfiException : Exception {
+                public FfiException(string message) : base(message) {}
+            }
+
+            $(static_literal("/// <summary>"))
+            $(static_literal("/// Result passed to rust, errors are passed as strings."))
+            $(static_literal("/// </summary>"))
+            public readonly struct FfiResult<T> {
+                private FfiResult(bool isOk, T value, string error) {
+                    IsOk = isOk;
+                    Value = value;
+                    Error = error;
+                }
+
+                public bool IsOk { get; }
+
+                public T Value { get; }
+
+                public string Error { get; }
+
+                public static FfiResult<T> Ok(T value) => new FfiResult<T>(true, value, "");
+
+                public static FfiResult<T> Err(string error) => new FfiResult<T>(false, default!, error);
+            }
+
+            $(static_literal("/// <summary>"))
+            $(static_literal("/// Handle to a rust object, handles that are not owned are borrowed from"))
+            $(static_literal("/// another object which is kept alive as parent."))
+            $(static_literal("/// </summary>"))
+            internal abstract class FfiHandle : SafeHandle {
+                private readonly bool _owned;
+                private readonly object? _parent;
+
+                protected FfiHandle(nint ptr, bool owned, object? parent) : base(IntPtr.Zero, owned) {
+                    SetHandle(ptr);
+                    _owned = owned;
+                    _parent = parent;
+                }
+
+                public override bool IsInvalid => handle == IntPtr.Zero;
+
+                internal nint Borrow() {
+                    if (IsClosed || IsInvalid) {
+                        throw new ObjectDisposedException(GetType().Name, "use after move");
+                    }
+                    return handle;
+                }
+
+                internal nint Release() {
+                    if (!_owned) {
+                        throw new InvalidOperationException("borrowed values can not be moved");
+                    }
+                    var ptr = Borrow();
+                    SetHandleAsInvalid();
+                    return ptr;
+                }
+            }
+
+            internal sealed class FfiBorrowedHandle : FfiHandle {
+                internal FfiBorrowedHandle(nint ptr, object? parent) : base(ptr, false, parent) {}
+
+                protected override bool ReleaseHandle() => true;
+            }
+
+            internal static class Ffi {
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// Copies the items into memory owned by rust."))
+                $(static_literal("/// </summary>"))
+                internal static unsafe nint LowerSpan<T>(ReadOnlySpan<T> items, nuint align) where T : unmanaged {
+                    var bytes = MemoryMarshal.AsBytes(items);
+                    if (bytes.Length == 0) {
+                        return (nint)align;
+                    }
+                    var ptr = Native.$(symbols.allocate())((nuint)bytes.Length, align);
+                    bytes.CopyTo(new Span<byte>((void*)ptr, bytes.Length));
+                    return ptr;
+                }
+
+                internal static void FreeBytes(nint ptr, nuint size, nuint align) {
+                    if (size > 0) {
+                        Native.$(symbols.deallocate())(ptr, size, align);
+                    }
+                }
+
+                internal static unsafe T[] LiftArray<T>(nint ptr, nuint len) where T : unmanaged {
+                    if (len == 0) {
+                        return Array.Empty<T>();
+                    }
+                    return new ReadOnlySpan<T>((void*)ptr, (int)len).ToArray();
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// Copies the items rust wrote to a mutable slice back."))
+                $(static_literal("/// </summary>"))
+                internal static unsafe void WriteBack<T>(Span<T> items, nint ptr) where T : unmanaged {
+                    new ReadOnlySpan<T>((void*)ptr, items.Length).CopyTo(items);
+                }
+
+                internal static unsafe string LiftString(nint ptr, nuint len) {
+                    if (len == 0) {
+                        return "";
+                    }
+                    return Encoding.UTF8.GetString((byte*)ptr, (int)len);
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// Packs the u32 byte length of every string followed by the utf8 bytes."))
+                $(static_literal("/// </summary>"))
+                internal static byte[] PackStrs(string[] strs) {
+                    var encoded = Array.ConvertAll(strs, str => Encoding.UTF8.GetBytes(str));
+                    var offset = strs.Length * 4;
+                    var bytes = new byte[offset + encoded.Sum(str => str.Length)];
+                    var lens = MemoryMarshal.Cast<byte, uint>(bytes.AsSpan(0, offset));
+                    for (var i = 0; i < encoded.Length; i++) {
+                        lens[i] = (uint)encoded[i].Length;
+                        encoded[i].CopyTo(bytes, offset);
+                        offset += encoded[i].Length;
+                    }
+                    return bytes;
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// Completes once the rust future is ready."))
+                $(static_literal("/// </summary>"))
+                internal static Task<T> Await<T>(FfiHandle handle, FfiPollFuture<T> poll) {
+                    return new FfiFutureTask<T>(handle, poll).Start();
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// The rust stream is polled once it is enumerated, so it can only be enumerated once."))
+                $(static_literal("/// </summary>"))
+                internal static async IAsyncEnumerable<T> Stream<T>(
+                    FfiHandle handle,
+                    FfiPollStream<T> poll,
+                    [EnumeratorCancellation] CancellationToken cancellationToken = default
+                ) {
+                    var channel = Channel.CreateUnbounded<T>();
+                    var task = new FfiStreamTask<T>(handle, poll, channel.Writer);
+                    task.Start();
+                    try {
+                        await foreach (var item in channel.Reader.ReadAllAsync(cancellationToken)) {
+                            yield return item;
+                        }
+                    } finally {
+                        task.End();
+                    }
+                }
+            }
+
+            internal delegate bool FfiNext<T>(nint boxed, out T value);
+
+            internal delegate bool FfiPollFuture<T>(nint boxed, nint postCobject, long port, out T value);
+
+            internal delegate bool FfiPollStream<T>(nint boxed, nint postCobject, long port, long done, out T value);
+
+            internal static class FfiWakers {
+                private static long _nextPort;
+                private static readonly ConcurrentDictionary<long, Action> Wakers = new ConcurrentDictionary<long, Action>();
+
+                internal static long Register(Action waker) {
+                    var port = Interlocked.Increment(ref _nextPort);
+                    Wakers[port] = waker;
+                    return port;
+                }
+
+                internal static void Unregister(long port) {
+                    Wakers.TryRemove(port, out _);
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// Unmanaged entry point passed to the poll functions, wakers of ended tasks are ignored."))
+                $(static_literal("/// </summary>"))
+                [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+                private static void PostCObject(long port, nint obj) {
+                    if (Wakers.TryGetValue(port, out var waker)) {
+                        waker();
+                    }
+                }
+
+                internal static unsafe nint PostCObjectAddress => (nint)(delegate* unmanaged[Cdecl]<long, nint, void>)&PostCObject;
+            }
+
+            $(static_literal("/// <summary>"))
+            $(static_literal("/// Polls a rust future or stream whenever it is woken."))
+            $(static_literal("/// </summary>"))
+            internal abstract class FfiTask {
+                private readonly object _lock = new object();
+                private bool _polling;
+                private bool _woken;
+                private bool _finished;
+
+                internal void Wake() {
+                    lock (_lock) {
+                        if (_finished) {
+                            return;
+                        }
+                        if (_polling) {
+                            _woken = true;
+                            return;
+                        }
+                        _polling = true;
+                    }
+                    while (true) {
+                        var finished = Poll();
+                        lock (_lock) {
+                            if (finished || !_woken) {
+                                _finished = finished;
+                                _polling = false;
+                                return;
+                            }
+                            _woken = false;
+                        }
+                    }
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// Returns true once the task is finished."))
+                $(static_literal("/// </summary>"))
+                protected abstract bool Poll();
+            }
+
+            internal sealed class FfiFutureTask<T> : FfiTask {
+                private readonly FfiHandle _handle;
+                private readonly FfiPollFuture<T> _poll;
+                private readonly TaskCompletionSource<T> _source = new TaskCompletionSource<T>(TaskCreationOptions.RunContinuationsAsynchronously);
+                private long _port;
+
+                internal FfiFutureTask(FfiHandle handle, FfiPollFuture<T> poll) {
+                    _handle = handle;
+                    _poll = poll;
+                }
+
+                internal Task<T> Start() {
+                    _port = FfiWakers.Register(Wake);
+                    Wake();
+                    return _source.Task;
+                }
+
+                protected override bool Poll() {
+                    try {
+                        if (!_poll(_handle.Borrow(), FfiWakers.PostCObjectAddress, _port, out var value)) {
+                            return false;
+                        }
+                        _source.SetResult(value);
+                    } catch (Exception error) {
+                        _source.SetException(error);
+                    }
+                    FfiWakers.Unregister(_port);
+                    _handle.Dispose();
+                    return true;
+                }
+            }
+
+            internal sealed class FfiStreamTask<T> : FfiTask {
+                private readonly FfiHandle _handle;
+                private readonly FfiPollStream<T> _poll;
+                private readonly ChannelWriter<T> _writer;
+                private long _port;
+                private long _done;
+                private volatile bool _ended;
+
+                internal FfiStreamTask(FfiHandle handle, FfiPollStream<T> poll, ChannelWriter<T> writer) {
+                    _handle = handle;
+                    _poll = poll;
+                    _writer = writer;
+                }
+
+                internal void Start() {
+                    _port = FfiWakers.Register(Wake);
+                    _done = FfiWakers.Register(End);
+                    Wake();
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// Stops polling, called once rust is done or the stream is no longer enumerated."))
+                $(static_literal("/// </summary>"))
+                internal void End() {
+                    _ended = true;
+                    Wake();
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// Writes items to the channel until rust is pending, errors complete the channel."))
+                $(static_literal("/// </summary>"))
+                protected override bool Poll() {
+                    try {
+                        while (!_ended) {
+                            if (!_poll(_handle.Borrow(), FfiWakers.PostCObjectAddress, _port, _done, out var item)) {
+                                return false;
+                            }
+                            _writer.TryWrite(item);
+                        }
+                        _writer.TryComplete();
+                    } catch (Exception error) {
+                        _writer.TryComplete(error);
+                    }
+                    FfiWakers.Unregister(_port);
+                    FfiWakers.Unregister(_done);
+                    _handle.Dispose();
+                    return true;
+                }
+            }
+
+            $(static_literal("/// <summary>"))
+            $(static_literal("/// A rust iterator, it can only be enumerated once."))
+            $(static_literal("/// </summary>"))
+            public sealed class FfiIter<T> : IEnumerable<T>, IDisposable {
+                internal readonly FfiHandle Handle;
+                private readonly FfiNext<T> _next;
+
+                internal FfiIter(FfiHandle handle, FfiNext<T> next) {
+                    Handle = handle;
+                    _next = next;
+                }
+
+                public void Dispose() => Handle.Dispose();
+
+                public IEnumerator<T> GetEnumerator() {
+                    while (_next(Handle.Borrow(), out var item)) {
+                        yield return item;
+                    }
+                }
+
+                IEnumerator IEnumerable.GetEnumerator() => GetEnumerator();
+            }
+
+            $(static_literal("/// <summary>"))
+            $(static_literal("/// A rust String owned by a list."))
+            $(static_literal("/// </summary>"))
+            public sealed class FfiString : IDisposable {
+                internal readonly FfiHandle Handle;
+
+                internal FfiString(FfiHandle handle) {
+                    Handle = handle;
+                }
+
+                public void Dispose() => Handle.Dispose();
+
+                public override string ToString() {
+                    var parts = Native.$(symbols.string_into_parts())(Handle.Borrow());
+                    GC.KeepAlive(this);
+                    return Ffi.LiftString(parts.Ptr, parts.Len);
+                }
+            }
+        }
+    }
+
+    /// `SafeHandle` releasing the box with the destructor `drop`.
+    fn generate_handle(&self, drop: &str) -> csharp::Tokens {
+        let name = handle_name(drop);
+        quote! {
+            internal sealed class $(&name) : FfiHandle {
+                internal $(&name)(nint ptr) : base(ptr, true, null) {}
+
+                protected override bool ReleaseHandle() {
+                    Native.$drop(0, handle);
+                    return true;
+                }
+            }
+        }
+    }
+
+    fn generate_object(&self, obj: &AbiObject) -> csharp::Tokens {
+        let name = self.type_ident(&obj.name);
+        quote! {
+            $(self.generate_doc(&obj.doc))
+            public sealed class $(&name) : IDisposable {
+                internal readonly FfiHandle Handle;
+
+                internal $(&name)(FfiHandle handle) {
+                    Handle = handle;
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// Drops the rust object, otherwise it is dropped once this object is finalized."))
+                $(static_literal("/// </summary>"))
+                public void Dispose() => Handle.Dispose();
+                $(for method in &obj.methods =>
+                    $['\n']
+                    $(self.generate_function(method))
+                )
+            }
+        }
+    }
+
+    fn generate_enum(&self, symbols: &Symbols, e: &Enum) -> csharp::Tokens {
+        let name = self.type_ident(&e.ident);
+        let tag = format!("{}Tag", name);
+        quote! {
+            public enum $(&tag) : uint {
+                $(for entry in &e.entries join (,$['\r']) => $(entry.name.to_upper_camel_case()))
+            }
+
+            $(self.generate_doc(&e.doc))
+            public sealed class $(&name) : IDisposable {
+                internal readonly FfiHandle Handle;
+
+                internal $(&name)(FfiHandle handle) {
+                    Handle = handle;
+                    var parts = Native.$(symbols.destructure_enum(&e.ident))(handle.Borrow());
+                    Tag = ($(&tag))parts.Tag;
+                    $(for (entry, inner) in inner_objects(e) =>
+                        $(entry.to_upper_camel_case()) = Tag == $(&tag).$(entry.to_upper_camel_case())
+                            ? new $(self.type_ident(&inner))(new $(handle_name(&symbols.destructor(&inner)))(parts.Inner))
+                            : null;$['\r'])
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// The tag of this enum object."))
+                $(static_literal("/// </summary>"))
+                public $(&tag) Tag { get; }
+                $(for (entry, inner) in inner_objects(e) =>
+                    $['\r']public $(self.type_ident(&inner))? $(entry.to_upper_camel_case()) { get; })
+
+                public void Dispose() => Handle.Dispose();
+            }
+        }
+    }
+
+    fn generate_ffi_buffer(&self, symbols: &Symbols, ty: NumType) -> csharp::Tokens {
+        let name = ffi_buffer_name_for(ty);
+        let elem = self.num_type(ty);
+        let (size, _) = self.abi.layout(ty);
+        quote! {
+            $(static_literal("/// <summary>"))
+            $(static_literal("/// A buffer that can be filled through its elements and passed to rust without copying."))
+            $(static_literal("/// </summary>"))
+            public sealed class $name : IDisposable {
+                internal readonly FfiHandle Handle;
+
+                internal $name(FfiHandle handle) {
+                    Handle = handle;
+                }
+
+                public void Dispose() => Handle.Dispose();
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// The elements of the buffer, only valid while the buffer is alive."))
+                $(static_literal("/// </summary>"))
+                public unsafe Span<$elem> Elements {
+                    get {
+                        var ptr = Handle.Borrow();
+                        var address = Native.$(symbols.ffi_buffer("address"))(ptr);
+                        var size = Native.$(symbols.ffi_buffer("size"))(ptr);
+                        GC.KeepAlive(this);
+                        return new Span<$elem>((void*)address, (int)(size / $size));
+                    }
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// Allocates a zeroed buffer of count elements."))
+                $(static_literal("/// </summary>"))
+                public static $name Allocate(int count) {
+                    var ptr = Native.$(symbols.ffi_buffer("new"))((nuint)count * $size, $size);
+                    return new $name(new $(handle_name(&symbols.destructor("FfiBuffer")))(ptr));
+                }
+            }
+        }
+    }
+
+    /// Strings can't be moved in or out of a list, rust doesn't export a
+    /// destructor for them.
+    fn generate_list_type(
+        &self,
+        symbols: &Symbols,
+        objects: &[String],
+        ty: &str,
+    ) -> csharp::Tokens {
+        let name = format!("FfiList{}", ty);
+        let elem = self.type_ident(ty);
+        let owned = objects.iter().any(|obj| obj == ty);
+        quote! {
+            $(static_literal("/// <summary>"))
+            $(static_literal("/// A rust Vec owning its elements."))
+            $(static_literal("/// </summary>"))
+            public sealed class $(&name) : IReadOnlyList<$(&elem)>, IDisposable {
+                internal readonly FfiHandle Handle;
+
+                internal $(&name)(FfiHandle handle) {
+                    Handle = handle;
+                }
+
+                public void Dispose() => Handle.Dispose();
+
+                public int Count {
+                    get {
+                        var len = Native.$(symbols.list(ty, "Len"))(Handle.Borrow());
+                        GC.KeepAlive(this);
+                        return (int)len;
+                    }
+                }
+
+                $(static_literal("/// <summary>"))
+                $(static_literal("/// The returned element is borrowed from the list and keeps it alive."))
+                $(static_literal("/// </summary>"))
+                public $(&elem) this[int index] {
+                    get {
+                        CheckIndex(index, Count);
+                        var ptr = Native.$(symbols.list(ty, "ElementAt"))(Handle.Borrow(), (uint)index);
+                        GC.KeepAlive(this);
+                        return new $(&elem)(new FfiBorrowedHandle(ptr, this));
+                    }
+                }
+
+                public IEnumerator<$(&elem)> GetEnumerator() {
+                    for (var i = 0; i < Count; i++) {
+                        yield return this[i];
+                    }
+                }
+
+                IEnumerator IEnumerable.GetEnumerator() => GetEnumerator();
+                $(if owned {
+                    $['\n']
+                    $(static_literal("/// <summary>"))
+                    $(static_literal("/// Moves the element out of the list."))
+                    $(static_literal("/// </summary>"))
+                    public $(&elem) RemoveAt(int index) {
+                        CheckIndex(index, Count);
+                        var ptr = Native.$(symbols.list(ty, "Remove"))(Handle.Borrow(), (uint)index);
+                        GC.KeepAlive(this);
+                        return new $(&elem)(new $(handle_name(&symbols.destructor(ty)))(ptr));
+                    }
+
+                    $(static_literal("/// <summary>"))
+                    $(static_literal("/// Moves the element into the list."))
+                    $(static_literal("/// </summary>"))
+                    public void Add($(&elem) element) {
+                        Native.$(symbols.list(ty, "Add"))(Handle.Borrow(), element.Handle.Release());
+                        GC.KeepAlive(this);
+                    }
+
+                    $(static_literal("/// <summary>"))
+                    $(static_literal("/// Moves the element into the list."))
+                    $(static_literal("/// </summary>"))
+                    public void Insert(int index, $(&elem) element) {
+                        CheckIndex(index, Count + 1);
+                        Native.$(symbols.list(ty, "Insert"))(Handle.Borrow(), (uint)index, element.Handle.Release());
+                        GC.KeepAlive(this);
+                    }
+                })
+
+                private static void CheckIndex(int index, int count) {
+                    if (index < 0 || index >= count) {
+                        throw new ArgumentOutOfRangeException(nameof(index));
+                    }
+                }
+
+                public static $(&name) Create() {
+                    var ptr = Native.$(symbols.list(ty, "Create"))();
+                    return new $(&name)(new $(handle_name(&symbols.destructor(&name)))(ptr));
+                }
+            }
+        }
+    }
+
+    fn generate_function(&self, func: &AbiFunction) -> csharp::Tokens {
+        let ffi = self.abi.import(func);
+        let unit = ffi.abi_ret.is_none() && matches!(func.ret, Some(AbiType::Option(_)));
+        let wrapper = matches!(
+            func.ty,
+            FunctionType::NextIter(_, _)
+                | FunctionType::PollFuture(_, _)
+                | FunctionType::PollStream(_, _)
+        );
+        let mut fences = vec![];
+        if let FunctionType::Method(_) = &func.ty {
+            fences.push("this".to_string());
+        }
+        for instr in &ffi.instr {
+            if let Instr::BindArg(_, var) = instr {
+                if borrows(&var.ty) {
+                    fences.push(self.var(var));
+                }
+            }
+        }
+        let body = Body {
+            void_call: matches!(ffi.ffi_ret, Return::Void),
+            unit,
+            wrapper,
+            item: func.ret.as_ref().and_then(|ty| self.item_type(ty)),
+            fences,
+        };
+        // variables defined inside of an option or result need to outlive
+        // the branch for the cleanup instructions.
+        let mut nested = vec![];
+        for instr in &ffi.instr {
+            nested_defines(instr, &mut nested);
+        }
+        quote! {
+            $(self.generate_doc(&func.doc))
+            $(self.signature(func, &ffi, &body)) {
+                $(for var in &nested => $(self.ffi_type(var)) $(self.var(var)) = 0;$['\r'])
+                $(for instr in &ffi.instr => $(self.generate_instr(&body, instr, false))$['\r'])
+            }
+        }
+    }
+
+    /// Modifiers, return type, name and arguments of `func`.
+    fn signature(&self, func: &AbiFunction, ffi: &Import, body: &Body) -> String {
+        let mut args = ffi
+            .abi_args
+            .iter()
+            .map(|(name, ty)| format!("{} {}", self.arg_type(ty), self.ident(name)))
+            .collect::<Vec<_>>();
+        if body.wrapper {
+            let value = match &ffi.abi_ret {
+                Some(AbiType::Option(ty)) => self.value_type(ty, false),
+                Some(ty) => self.value_type(ty, false),
+                None => "ValueTuple".to_string(),
+            };
+            args.push(format!("out {} value", value));
+            return format!(
+                "internal static bool {}({})",
+                wrapper_name(&ffi.symbol),
+                args.join(", ")
+            );
+        }
+        let modifiers = match &func.ty {
+            FunctionType::Method(_) => "public",
+            _ => "public static",
+        };
+        let ret = match &ffi.abi_ret {
+            Some(ty) => self.value_type(ty, false),
+            None if body.unit => "bool".to_string(),
+            None => "void".to_string(),
+        };
+        format!(
+            "{} {} {}({})",
+            modifiers,
+            ret,
+            func.name.to_upper_camel_case(),
+            args.join(", ")
+        )
+    }
+
+    fn generate_instr(&self, body: &Body, instr: &Instr, nested: bool) -> csharp::Tokens {
+        match instr {
+            Instr::BindArg(arg, out) => quote!(var $(self.var(out)) = $(self.ident(arg));),
+            Instr::DefineArgs(_) if nested => quote!(),
+            Instr::DefineArgs(vars) => quote! {
+                $(for var in vars => $(self.ffi_type(var)) $(self.var(var)) = 0;$['\r'])
+            },
+            Instr::BorrowSelf(out) => quote!($(self.var(out)) = Handle.Borrow();),
+            Instr::BorrowObject(in_, out) | Instr::BorrowIter(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).Handle.Borrow();)
+            }
+            Instr::MoveObject(in_, out) | Instr::MoveIter(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).Handle.Release();)
+            }
+            Instr::BorrowFuture(_, _)
+            | Instr::MoveFuture(_, _)
+            | Instr::BorrowStream(_, _)
+            | Instr::MoveStream(_, _) => unimplemented!("futures and streams can only be returned"),
+            Instr::LowerNum(in_, out) => {
+                let value = format!("({}){}", self.ffi_type(out), self.var(in_));
+                quote!($(self.var(out)) = $value;)
+            }
+            Instr::LiftNum(in_, out, _) => quote!(var $(self.var(out)) = $(self.var(in_));),
+            Instr::LowerBool(in_, out) => {
+                quote!($(self.var(out)) = (byte)($(self.var(in_)) ? 1 : 0);)
+            }
+            Instr::LiftBool(in_, out) => quote!(var $(self.var(out)) = $(self.var(in_)) != 0;),
+            Instr::LowerString(in_, ptr, len, cap, _, _) => quote! {
+                var $(self.var(ptr))_0 = Encoding.UTF8.GetBytes($(self.var(in_)));
+                $(self.var(len)) = (nuint)$(self.var(ptr))_0.Length;
+                $(self.var(ptr)) = Ffi.LowerSpan<byte>($(self.var(ptr))_0, 1);
+                $(self.var(cap)) = $(self.var(len));
+            },
+            Instr::LowerVec(in_, ptr, len, cap, ty, _, align)
+            | Instr::LowerMutSlice(in_, ptr, len, cap, ty, _, align) => quote! {
+                $(self.var(len)) = (nuint)$(self.var(in_)).Length;
+                $(self.var(ptr)) = Ffi.LowerSpan<$(self.num_type(*ty))>($(self.var(in_)), $(*align));
+                $(self.var(cap)) = $(self.var(len));
+            },
+            Instr::LowerStrSlice(in_, ptr, len, cap) => quote! {
+                var $(self.var(ptr))_0 = Ffi.PackStrs($(self.var(in_)));
+                $(self.var(len)) = (nuint)$(self.var(in_)).Length;
+                $(self.var(cap)) = (nuint)$(self.var(ptr))_0.Length;
+                $(self.var(ptr)) = Ffi.LowerSpan<byte>($(self.var(ptr))_0, 4);
+            },
+            Instr::LowerObjectSlice(in_, ptr, len, cap, _, align) => quote! {
+                var $(self.var(ptr))_0 = Array.ConvertAll($(self.var(in_)), obj => obj.Handle.Borrow());
+                $(self.var(len)) = (nuint)$(self.var(in_)).Length;
+                $(self.var(ptr)) = Ffi.LowerSpan<nint>($(self.var(ptr))_0, $(*align));
+                $(self.var(cap)) = $(self.var(len));
+            },
+            Instr::WriteBack(in_, ptr, _, cap, ty) => quote! {
+                if ($(self.var(cap)) > 0) {
+                    Ffi.WriteBack<$(self.num_type(*ty))>($(self.var(in_)), $(self.var(ptr)));
+                }
+            },
+            Instr::Deallocate(ptr, len, size, align) => quote! {
+                Ffi.FreeBytes($(self.var(ptr)), $(self.var(len)) * $(*size), $(*align));
+            },
+            Instr::LiftString(ptr, len, out) => quote! {
+                var $(self.var(out)) = Ffi.LiftString($(self.var(ptr)), $(self.var(len)));
+            },
+            Instr::LiftVec(ptr, len, out, ty) => quote! {
+                var $(self.var(out)) = Ffi.LiftArray<$(self.num_type(*ty))>($(self.var(ptr)), $(self.var(len)));
+            },
+            Instr::HandleNull(var) => quote! {
+                if ($(self.var(var)) == 0) {
+                    $(match (body.wrapper, body.unit) {
+                        (true, _) => {
+                            value = default!;
+                            return false;
+                        }
+                        (false, true) => return false;,
+                        (false, false) => return null;,
+                    })
+                }
+            },
+            Instr::LowerOption(arg, var, some, some_instr) => quote! {
+                if ($(self.var(arg)) is { } $(self.var(some))) {
+                    $(self.var(var)) = 1;
+                    $(for instr in some_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                } else {
+                    $(self.var(var)) = 0;
+                }
+            },
+            Instr::LowerResult(arg, var, ok, ok_instr, err, err_instr) => quote! {
+                if ($(self.var(arg)).IsOk) {
+                    var $(self.var(ok)) = $(self.var(arg)).Value;
+                    $(self.var(var)) = 1;
+                    $(for instr in ok_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                } else {
+                    var $(self.var(err)) = $(self.var(arg)).Error;
+                    $(self.var(var)) = 0;
+                    $(for instr in err_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                }
+            },
+            Instr::HandleError(var, ptr, len, cap) => quote! {
+                if ($(self.var(var)) == 0) {
+                    var $(self.var(var))_0 = Ffi.LiftString($(self.var(ptr)), $(self.var(len)));
+                    Ffi.FreeBytes($(self.var(ptr)), $(self.var(cap)), 1);
+                    throw new FfiException($(self.var(var))_0);
+                }
+            },
+            Instr::LiftObject(obj, box_, drop, out) => quote! {
+                var $(self.var(out)) = new $(self.type_ident(obj))(new $(handle_name(drop))($(self.var(box_))));
+            },
+            Instr::LiftRefObject(obj, box_, _leak, out) => quote! {
+                var $(self.var(out)) = new $(self.type_ident(obj))(new FfiBorrowedHandle($(self.var(box_)), null));
+            },
+            Instr::LiftIter(box_, next, drop, out) => quote! {
+                var $(self.var(out)) = new FfiIter<$(self.item(body))>(new $(handle_name(drop))($(self.var(box_))), FfiWrappers.$(wrapper_name(next)));
+            },
+            Instr::LiftRefIter(box_, next, _leak, out) => quote! {
+                var $(self.var(out)) = new FfiIter<$(self.item(body))>(new FfiBorrowedHandle($(self.var(box_)), null), FfiWrappers.$(wrapper_name(next)));
+            },
+            Instr::LiftFuture(box_, poll, drop, out) => quote! {
+                var $(self.var(out)) = Ffi.Await<$(self.item(body))>(new $(handle_name(drop))($(self.var(box_))), FfiWrappers.$(wrapper_name(poll)));
+            },
+            Instr::LiftStream(box_, poll, drop, out) => quote! {
+                var $(self.var(out)) = Ffi.Stream<$(self.item(body))>(new $(handle_name(drop))($(self.var(box_))), FfiWrappers.$(wrapper_name(poll)));
+            },
+            Instr::LiftTuple(vars, out) => match vars.len() {
+                0 => quote!(var $(self.var(out)) = default(ValueTuple);),
+                1 => quote!(var $(self.var(out)) = $(self.var(&vars[0]));),
+                _ => {
+                    let values = vars
+                        .iter()
+                        .map(|var| self.var(var))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    quote!(var $(self.var(out)) = ($values);)
+                }
+            },
+            Instr::Call(symbol, ret, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.var(arg))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let call = format!("Native.{}({});", symbol, args);
+                quote! {
+                    $(match ret {
+                        Some(ret) if !body.void_call => var $(self.var(ret)) = $call,
+                        _ => $call,
+                    })
+                    $(for value in &body.fences => $['\r']GC.KeepAlive($value);)
+                }
+            }
+            Instr::BindRets(ret, vars) => match vars.len() {
+                0 => quote!(),
+                1 => quote!(var $(self.var(&vars[0])) = $(self.var(ret));),
+                _ => quote! {
+                    $(for (i, var) in vars.iter().enumerate() =>
+                        var $(self.var(var)) = $(self.var(ret)).$(format!("Ret{}", i));$['\r'])
+                },
+            },
+            Instr::ReturnValue(ret) if body.wrapper => quote! {
+                value = $(self.var(ret));
+                return true;
+            },
+            Instr::ReturnValue(ret) => quote!(return $(self.var(ret));),
+            Instr::ReturnVoid if body.wrapper => quote! {
+                value = default;
+                return true;
+            },
+            Instr::ReturnVoid if body.unit => quote!(return true;),
+            Instr::ReturnVoid => quote!(return;),
+            Instr::LiftNumFromU32Tuple(..)
+            | Instr::LowerNumFromU32Tuple(..)
+            | Instr::CallRetArea(..) => unreachable!(),
+        }
+    }
+
+    fn item<'a>(&self, body: &'a Body) -> &'a str {
+        body.item
+            .as_deref()
+            .expect("iterators, futures and streams have an item type")
+    }
+
+    /// Item type of the iterator, future or stream in `ty`.
+    fn item_type(&self, ty: &AbiType) -> Option<String> {
+        match ty {
+            AbiType::Iter(ty)
+            | AbiType::RefIter(ty)
+            | AbiType::Future(ty)
+            | AbiType::RefFuture(ty)
+            | AbiType::Stream(ty)
+            | AbiType::RefStream(ty) => Some(self.value_type(ty, false)),
+            AbiType::Option(ty) | AbiType::Result(ty) => self.item_type(ty),
+            AbiType::Tuple(tys) => tys.iter().find_map(|ty| self.item_type(ty)),
+            _ => None,
+        }
+    }
+
+    fn arg_type(&self, ty: &AbiType) -> String {
+        match ty {
+            AbiType::Future(_)
+            | AbiType::RefFuture(_)
+            | AbiType::Stream(_)
+            | AbiType::RefStream(_) => unimplemented!("futures and streams can only be returned"),
+            ty => self.value_type(ty, true),
+        }
+    }
+
+    /// Type of a value, results passed as arguments are wrapped in a
+    /// `FfiResult`, returned errors are thrown.
+    fn value_type(&self, ty: &AbiType, arg: bool) -> String {
+        match ty {
+            AbiType::Num(ty) => self.num_type(*ty).to_string(),
+            AbiType::Isize => "nint".to_string(),
+            AbiType::Usize => "nuint".to_string(),
+            AbiType::Bool => "bool".to_string(),
+            AbiType::RefStr | AbiType::String => "string".to_string(),
+            AbiType::RefSlice(ty) | AbiType::RefMutSlice(ty) | AbiType::Vec(ty) => {
+                format!("{}[]", self.num_type(*ty))
+            }
+            AbiType::RefSliceStr => "string[]".to_string(),
+            AbiType::RefSliceObject(ty) => format!("{}[]", self.type_ident(ty)),
+            AbiType::RefObject(ty) | AbiType::Object(ty) | AbiType::RefEnum(ty) => {
+                self.type_ident(ty)
+            }
+            AbiType::Option(ty) => format!("{}?", self.value_type(ty, arg)),
+            AbiType::Result(ty) if arg => format!("FfiResult<{}>", self.value_type(ty, arg)),
+            AbiType::Result(ty) => self.value_type(ty, arg),
+            AbiType::Tuple(tys) => {
+                let tys = tys
+                    .iter()
+                    .map(|ty| self.value_type(ty, arg))
+                    .collect::<Vec<_>>();
+                match tys.len() {
+                    0 => "ValueTuple".to_string(),
+                    1 => tys[0].clone(),
+                    _ => format!("({})", tys.join(", ")),
+                }
+            }
+            AbiType::Iter(ty) | AbiType::RefIter(ty) => {
+                format!("FfiIter<{}>", self.value_type(ty, false))
+            }
+            AbiType::Future(ty) | AbiType::RefFuture(ty) => {
+                format!("Task<{}>", self.value_type(ty, false))
+            }
+            AbiType::Stream(ty) | AbiType::RefStream(ty) => {
+                format!("IAsyncEnumerable<{}>", self.value_type(ty, false))
+            }
+            AbiType::Buffer(ty) | AbiType::RefBuffer(ty) => ffi_buffer_name_for(*ty).to_string(),
+            AbiType::List(ty) => format!("FfiList{}", ty),
+        }
+    }
+
+    fn ffi_type(&self, var: &Var) -> &'static str {
+        match &var.ty {
+            AbiType::Num(num) => self.num_type(*num),
+            ty => unreachable!("{:?} is not an ffi type", ty),
+        }
+    }
+
+    fn num_type(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::U8 => "byte",
+            NumType::U16 => "ushort",
+            NumType::U32 => "uint",
+            NumType::U64 => "ulong",
+            NumType::I8 => "sbyte",
+            NumType::I16 => "short",
+            NumType::I32 => "int",
+            NumType::I64 => "long",
+            NumType::F32 => "float",
+            NumType::F64 => "double",
+            NumType::IPtr => "nint",
+            NumType::UPtr => "nuint",
+        }
+    }
+
+    fn generate_doc(&self, doc: &[String]) -> csharp::Tokens {
+        if doc.is_empty() {
+            return quote!();
+        }
+        quote! {
+            $(static_literal("/// <summary>"))
+            $(for line in doc => $(format!("/// {}", escape_xml(line)).trim_end().to_string())$['\r'])
+            $(static_literal("/// </summary>"))
+        }
+    }
+
+    fn var(&self, var: &Var) -> String {
+        format!("tmp{}", var.binding)
+    }
+
+    fn type_ident(&self, s: &str) -> String {
+        sanitize_identifier(s)
+    }
+
+    fn ident(&self, s: &str) -> String {
+        sanitize_identifier(&s.to_lower_camel_case())
+    }
+}
+
+/// Whether a value of type `ty` is borrowed by rust for the duration of a
+/// call.
+fn borrows(ty: &AbiType) -> bool {
+    match ty {
+        AbiType::RefObject(_)
+        | AbiType::RefSliceObject(_)
+        | AbiType::RefIter(_)
+        | AbiType::RefBuffer(_) => true,
+        AbiType::Option(ty) | AbiType::Result(ty) => borrows(ty),
+        _ => false,
+    }
+}
+
+/// Name of the `SafeHandle` releasing boxes with the destructor `drop`.
+fn handle_name(drop: &str) -> String {
+    format!("{}Handle", drop.to_upper_camel_case())
+}
+
+/// Name of the wrapper around the `next` or `poll` function `symbol`.
+fn wrapper_name(symbol: &str) -> String {
+    format!("wrap_{}", symbol.trim_start_matches('_'))
+}
+
+/// Entry name and object of every enum entry wrapping an object.
+fn inner_objects(e: &Enum) -> Vec<(String, String)> {
+    e.entries
+        .iter()
+        .filter_map(|entry| match &entry.inner {
+            Some(Type::Ident(name)) => Some((entry.name.clone(), name.clone())),
+            Some(_) => unimplemented!("Enums can only wrap objects"),
+            None => None,
+        })
+        .collect()
+}
+
+/// Collects the variables defined inside of `LowerOption` and `LowerResult`.
+fn nested_defines(instr: &Instr, vars: &mut Vec<Var>) {
+    fn collect(instrs: &[Instr], vars: &mut Vec<Var>) {
+        for instr in instrs {
+            if let Instr::DefineArgs(defines) = instr {
+                vars.extend(defines.iter().cloned());
+            }
+            nested_defines(instr, vars);
+        }
+    }
+    match instr {
+        Instr::LowerOption(_, _, _, some) => collect(some, vars),
+        Instr::LowerResult(_, _, _, ok, _, err) => {
+            collect(ok, vars);
+            collect(err, vars);
+        }
+        _ => {}
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Keywords are escaped as verbatim identifiers.
+fn sanitize_identifier(id: &str) -> String {
+    if RESERVED_IDENTIFIERS.contains(&id) {
+        format!("@{}", id)
+    } else {
+        id.to_string()
+    }
+}
+
+// https://learn.microsoft.com/en-us/dotnet/csharp/language-reference/keywords/
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 77] = [
+    "abstract",
+    "as",
+    "base",
+    "bool",
+    "break",
+    "byte",
+    "case",
+    "catch",
+    "char",
+    "checked",
+    "class",
+    "const",
+    "continue",
+    "decimal",
+    "default",
+    "delegate",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "event",
+    "explicit",
+    "extern",
+    "false",
+    "finally",
+    "fixed",
+    "float",
+    "for",
+    "foreach",
+    "goto",
+    "if",
+    "implicit",
+    "in",
+    "int",
+    "interface",
+    "internal",
+    "is",
+    "lock",
+    "long",
+    "namespace",
+    "new",
+    "null",
+    "object",
+    "operator",
+    "out",
+    "override",
+    "params",
+    "private",
+    "protected",
+    "public",
+    "readonly",
+    "ref",
+    "return",
+    "sbyte",
+    "sealed",
+    "short",
+    "sizeof",
+    "stackalloc",
+    "static",
+    "string",
+    "struct",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "uint",
+    "ulong",
+    "unchecked",
+    "unsafe",
+    "ushort",
+    "using",
+    "virtual",
+    "void",
+    "volatile",
+    "while",
+];
+
+#[cfg(feature = "test_runner")]
+#[doc(hidden)]
+pub mod test_runner {
+    use super::*;
+    use crate::RustGenerator;
+    use anyhow::Result;
+    use std::io::Write;
+    use tempfile::{NamedTempFile, TempDir};
+    use trybuild::TestCases;
+
+    static PROJECT: &str = r#"<Project Sdk="Microsoft.NET.Sdk">
+  <PropertyGroup>
+    <OutputType>Exe</OutputType>
+    <TargetFramework>net8.0</TargetFramework>
+    <Nullable>enable</Nullable>
+    <AllowUnsafeBlocks>true</AllowUnsafeBlocks>
+  </PropertyGroup>
+</Project>
+"#;
+
+    pub fn compile_pass(iface: &str, rust: rust::Tokens, csharp: csharp::Tokens) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let (mut rust_file, rust_file_path) = NamedTempFile::new()?.keep()?;
+        writeln!(rust_file, "#![feature(vec_into_raw_parts)]")?;
+        writeln!(rust_file, "#![feature(lazy_cell)]")?;
+        let rust_gen = RustGenerator::new(Abi::native());
+        let rust_tokens = rust_gen.generate(iface.clone());
+        let csharp_gen =
+            CSharpGenerator::new("CompilePass".to_string(), "compile_pass".to_string());
+        let module = csharp_gen.generate(iface).to_file_string()?;
+
+        let library_tokens: genco::Tokens<genco::lang::Rust> = quote! {
+            $rust_tokens
+            $rust
+        };
+
+        let main_tokens: csharp::Tokens = quote! {
+            using System;
+            using System.Collections.Generic;
+            using System.Linq;
+            using System.Threading.Tasks;
+            using CompilePass;
+
+            $csharp
+
+            static void Check(bool condition) {
+                if (!condition) {
+                    throw new Exception("check failed");
+                }
+            }
+        };
+
+        let library_dir = TempDir::new()?.keep();
+        let library_file = library_dir.join("libcompile_pass.so");
+        std::fs::write(library_dir.join("compile_pass.csproj"), PROJECT)?;
+        std::fs::write(library_dir.join("Api.cs"), module)?;
+        std::fs::write(
+            library_dir.join("Program.cs"),
+            main_tokens.to_file_string()?,
+        )?;
+
+        let library = library_tokens.to_file_string()?;
+        rust_file.write_all(library.as_bytes())?;
+
+        let runner_tokens: rust::Tokens = quote! {
+            fn main() {
+                use std::process::Command;
+                let ret = Command::new("rustc")
+                    .arg("--edition")
+                    .arg("2021")
+                    .arg("--crate-name")
+                    .arg("compile_pass")
+                    .arg("--crate-type")
+                    .arg("cdylib")
+                    .arg("--cfg")
+                    .arg("feature=\"test_runner\"")
+                    .arg("-o")
+                    .arg($(quoted(library_file.as_path().to_str().unwrap())))
+                    .arg($(quoted(rust_file_path.as_path().to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+                let ret = Command::new("dotnet")
+                    .current_dir($(quoted(library_dir.as_path().to_str().unwrap())))
+                    .env("LD_LIBRARY_PATH", $(quoted(library_dir.as_path().to_str().unwrap())))
+                    .arg("run")
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+            }
+        };
+        let (mut runner_file, runner_file_path) = NamedTempFile::new()?.keep()?;
+        let runner = runner_tokens.to_file_string()?;
+        runner_file.write_all(runner.as_bytes())?;
+
+        let test = TestCases::new();
+        test.pass(runner_file_path.as_path().to_str().unwrap());
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
 use crate::import::{Import, Instr};
 use crate::parser::{Enum, Type};
-use crate::{Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Var};
+use crate::{
+    Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Symbols, Var,
+    NUM_TYPES,
+};
 use genco::prelude::*;
 use genco::tokens::static_literal;
 use heck::*;
@@ -21,6 +24,8 @@ impl DartGenerator {
     }
 
     pub fn generate(&self, iface: Interface) -> dart::Tokens {
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
         quote! {
             $(static_literal("//")) AUTO GENERATED FILE, DO NOT EDIT.
             $(static_literal("//"))
@@ -78,8 +83,9 @@ impl DartGenerator {
                 bool _moved;
                 ffi.Pointer<ffi.Void> _finalizer = ffi.Pointer.fromAddress(0);
                 final Object? _context;
+                final _Box? _parent;
 
-                _Box(this._api, this._ptr, this._dropSymbol, {Object? context}) : _dropped = false, _moved = false, _context = context;
+                _Box(this._api, this._ptr, this._dropSymbol, {Object? context, _Box? parent}) : _dropped = false, _moved = false, _context = context, _parent = parent;
 
                 late final _dropPtr = _api._lookup<
                     ffi.NativeFunction<
@@ -95,6 +101,11 @@ impl DartGenerator {
                     if (_moved) {
                         throw StateError("use after move");
                     }
+                    for (var parent = _parent; parent != null; parent = parent._parent) {
+                        if (parent._dropped || parent._moved) {
+                            throw StateError("use of borrowed value after its owner was dropped");
+                        }
+                    }
                     return _ptr.address;
                 }
 
@@ -105,6 +116,9 @@ impl DartGenerator {
                     if (_moved) {
                         throw StateError("can't move value twice");
                     }
+                    if (_parent != null) {
+                        throw StateError("can't move borrowed value");
+                    }
                     _moved = true;
                     _api._unregisterFinalizer(this);
                     return _ptr.address;
@@ -124,11 +138,36 @@ impl DartGenerator {
                 }
             }
 
+            $(static_literal("///")) Result passed as an argument to rust.
+            class FfiResult<T> {
+                final bool isOk;
+                final T? _value;
+                final String? _error;
+
+                const FfiResult.ok(T value) : isOk = true, _value = value, _error = null;
+
+                const FfiResult.err(String error) : isOk = false, _value = null, _error = error;
+
+                T get value {
+                    if (!isOk) {
+                        throw StateError("result is an error");
+                    }
+                    return _value as T;
+                }
+
+                String get error {
+                    if (isOk) {
+                        throw StateError("result is not an error");
+                    }
+                    return _error!;
+                }
+            }
+
             void debugAllocation(String name, int address, int len) {
                 print($(static_literal("\"$name - address: $address ; len: $len\"")));
             }
 
-            $(for ty in "Int8 Uint8 Int16 Uint16 Int32 Uint32 Int64 Uint64 Float32 Float64".split(' ') => $(self.generate_ffi_buffer(ty)))
+            $(for ty in NUM_TYPES => $(self.generate_ffi_buffer(&symbols, ty)))
 
             $(static_literal("///")) Implements Iterable and Iterator for a rust iterator.
             class Iter<T> extends Iterable<T> implements Iterator<T> {
@@ -234,6 +273,11 @@ impl DartGenerator {
                 return controller.stream;
             }
 
+            $(static_literal("///")) Buffers owning the views returned by asTypedList, so they can be passed
+            $(static_literal("///")) to rust as mutable slices without a temporary copy. Passing a view of a
+            $(static_literal("///")) dropped buffer throws instead of handing rust a dangling address.
+            final _nativeViews = Expando<_Box>();
+
             class FfiString {
                 final Api _api;
                 final _Box _box;
@@ -351,34 +395,41 @@ impl DartGenerator {
 
                 late final _allocatePtr = _lookup<
                     ffi.NativeFunction<
-                        ffi.Pointer<ffi.Uint8> Function(ffi.IntPtr, ffi.IntPtr)>>("allocate");
+                        ffi.Pointer<ffi.Uint8> Function(ffi.IntPtr, ffi.IntPtr)>>($(quoted(symbols.allocate())));
 
                 late final _allocate = _allocatePtr.asFunction<
                     ffi.Pointer<ffi.Uint8> Function(int, int)>();
 
                 late final _deallocatePtr = _lookup<
                     ffi.NativeFunction<
-                        ffi.Void Function(ffi.Pointer<ffi.Uint8>, ffi.IntPtr, ffi.IntPtr)>>("deallocate");
+                        ffi.Void Function(ffi.Pointer<ffi.Uint8>, ffi.IntPtr, ffi.IntPtr)>>($(quoted(symbols.deallocate())));
 
                 late final _deallocate = _deallocatePtr.asFunction<
                     void Function(ffi.Pointer<ffi.Uint8>, int, int)>();
 
+                late final _ffiBufferNewPtr = _lookup<
+                    ffi.NativeFunction<
+                        ffi.IntPtr Function(ffi.UintPtr, ffi.UintPtr)>>($(quoted(symbols.ffi_buffer("new"))));
+
+                late final _ffiBufferNew = _ffiBufferNewPtr.asFunction<
+                    int Function(int, int)>();
+
                 late final _ffiBufferAddressPtr = _lookup<
                     ffi.NativeFunction<
-                        ffi.UintPtr Function(ffi.IntPtr)>>("__ffi_buffer_address");
+                        ffi.UintPtr Function(ffi.IntPtr)>>($(quoted(symbols.ffi_buffer("address"))));
 
                 late final _ffiBufferAddress = _ffiBufferAddressPtr.asFunction<
                     int Function(int)>();
 
                 late final _ffiBufferSizePtr = _lookup<
                     ffi.NativeFunction<
-                        ffi.UintPtr Function(ffi.IntPtr)>>("__ffi_buffer_size");
+                        ffi.UintPtr Function(ffi.IntPtr)>>($(quoted(symbols.ffi_buffer("size"))));
 
                 late final _ffiBufferSize = _ffiBufferSizePtr.asFunction<
                     int Function(int)>();
 
                 late final _ffiStringIntoPartsPtr =
-                    _lookup<ffi.NativeFunction<_FfiStringParts Function(ffi.IntPtr)>>("__ffi_string_into_parts");
+                    _lookup<ffi.NativeFunction<_FfiStringParts Function(ffi.IntPtr)>>($(quoted(symbols.string_into_parts())));
 
                 late final _ffiStringIntoParts = _ffiStringIntoPartsPtr.asFunction<_FfiStringParts Function(int)>();
 
@@ -387,24 +438,24 @@ impl DartGenerator {
                 $(for stream in iface.streams() => $(self.generate_function(&stream.poll())))
 
                 $(for func in iface.imports(&self.abi) => $(self.generate_wrapper(func)))
-                $(for ty in iface.listed_types() => $(self.generate_list_methods(ty.as_str())))
-                $(for e in iface.enums.iter() => $(self.generate_enum_helpers(e)))
+                $(for ty in iface.listed_types() => $(self.generate_list_methods(&symbols, ty.as_str())))
+                $(for e in iface.enums.iter() => $(self.generate_enum_helpers(&symbols, e)))
             }
 
             $(for obj in iface.objects() => $(self.generate_object(obj)))
 
-            $(for e in iface.enums.iter() => $(self.generate_enum(e)))
+            $(for e in iface.enums.iter() => $(self.generate_enum(&symbols, e)))
 
             $(for func in iface.imports(&self.abi) => $(self.generate_return_struct(&func.ffi_ret)))
 
-            $(for ty in iface.listed_types() => $(self.generate_list_type(ty.as_str())))
+            $(for ty in iface.listed_types() => $(self.generate_list_type(&symbols, ty.as_str())))
         }
     }
 
-    fn generate_enum_helpers(&self, e: &Enum) -> dart::Tokens {
+    fn generate_enum_helpers(&self, symbols: &Symbols, e: &Enum) -> dart::Tokens {
         let ptr_name = format!("_destructure{}Ptr", e.ident);
         let func_name = format!("_destructure{}", e.ident);
-        let symbol_name = format!("destructure_enum_{}", e.ident);
+        let symbol_name = symbols.destructure_enum(&e.ident);
         quote!(
             late final $(&ptr_name) = _lookup<
                 ffi.NativeFunction<
@@ -416,7 +467,7 @@ impl DartGenerator {
         )
     }
 
-    fn generate_enum(&self, e: &Enum) -> dart::Tokens {
+    fn generate_enum(&self, symbols: &Symbols, e: &Enum) -> dart::Tokens {
         let enum_tag_name = format!("{}Tag", e.ident);
         let destructure_function_name = format!("_destructure{}", e.ident);
         let mut destructure_switch_index = -1;
@@ -443,7 +494,7 @@ impl DartGenerator {
                                     let inner_name = if let Type::Ident(name) = entry.inner.as_ref().unwrap() { name } else { unimplemented!("Enums can only wrap objects") };
                                     quote!(
                                         final ffi.Pointer<ffi.Void> innerPtr = ffi.Pointer.fromAddress(parts.inner);
-                                        final innerBox = _Box(this._api, innerPtr, $(quoted(format!("drop_box_{}", inner_name))));
+                                        final innerBox = _Box(this._api, innerPtr, $(quoted(symbols.destructor(inner_name))));
                                         innerBox._finalizer = this._api._registerFinalizer(innerBox);
                                         this._inner = $(inner_name)._(this._api, innerBox);
                                     )
@@ -477,62 +528,62 @@ impl DartGenerator {
         )
     }
 
-    fn generate_list_methods(&self, ty: &str) -> dart::Tokens {
+    fn generate_list_methods(&self, symbols: &Symbols, ty: &str) -> dart::Tokens {
         let list_name_s = format!("FfiList{}", ty);
         let list_name = list_name_s.as_str();
         quote!(
 
             $list_name $(format!("create{}", list_name))() {
                 final ffi.Pointer<ffi.Void> list_ptr = ffi.Pointer.fromAddress($(format!("_ffiList{}Create", ty))());
-                final list_box = _Box(this, list_ptr, $(quoted(format!("drop_box_{}", list_name))));
+                final list_box = _Box(this, list_ptr, $(quoted(symbols.destructor(list_name))));
                 return $list_name._(this, list_box);
             }
 
             late final $(format!("_ffiList{}CreatePtr", ty)) = _lookup<
                 ffi.NativeFunction<
-                    ffi.IntPtr Function()>>($(quoted(format!("__{}Create", list_name))));
+                    ffi.IntPtr Function()>>($(quoted(symbols.list(ty, "Create"))));
 
             late final $(format!("_ffiList{}Create", ty)) = $(format!("_ffiList{}CreatePtr", ty)).asFunction<
                 int Function()>();
 
             late final $(format!("_ffiList{}LenPtr", ty)) = _lookup<
                 ffi.NativeFunction<
-                    ffi.Uint32 Function(ffi.IntPtr)>>($(quoted(format!("__{}Len", list_name))));
+                    ffi.Uint32 Function(ffi.IntPtr)>>($(quoted(symbols.list(ty, "Len"))));
 
             late final $(format!("_ffiList{}Len", ty)) = $(format!("_ffiList{}LenPtr", ty)).asFunction<
                 int Function(int)>();
 
             late final $(format!("_ffiList{}ElementAtPtr", ty)) = _lookup<
                 ffi.NativeFunction<
-                    ffi.IntPtr Function(ffi.IntPtr, ffi.Uint32)>>($(quoted(format!("__{}ElementAt", list_name))));
+                    ffi.IntPtr Function(ffi.IntPtr, ffi.Uint32)>>($(quoted(symbols.list(ty, "ElementAt"))));
 
             late final $(format!("_ffiList{}ElementAt", ty)) = $(format!("_ffiList{}ElementAtPtr", ty)).asFunction<
                 int Function(int, int)>();
 
             late final $(format!("_ffiList{}RemovePtr", ty)) = _lookup<
                 ffi.NativeFunction<
-                    ffi.IntPtr Function(ffi.IntPtr, ffi.Uint32)>>($(quoted(format!("__{}Remove", list_name))));
+                    ffi.IntPtr Function(ffi.IntPtr, ffi.Uint32)>>($(quoted(symbols.list(ty, "Remove"))));
 
             late final $(format!("_ffiList{}Remove", ty)) = $(format!("_ffiList{}RemovePtr", ty)).asFunction<
                 int Function(int, int)>();
 
             late final $(format!("_ffiList{}AddPtr", ty)) = _lookup<
                 ffi.NativeFunction<
-                    ffi.Void Function(ffi.IntPtr, ffi.IntPtr)>>($(quoted(format!("__{}Add", list_name))));
+                    ffi.Void Function(ffi.IntPtr, ffi.IntPtr)>>($(quoted(symbols.list(ty, "Add"))));
 
             late final $(format!("_ffiList{}Add", ty)) = $(format!("_ffiList{}AddPtr", ty)).asFunction<
                 void Function(int, int)>();
 
             late final $(format!("_ffiList{}InsertPtr", ty)) = _lookup<
                 ffi.NativeFunction<
-                    ffi.Void Function(ffi.IntPtr, ffi.Uint32, ffi.IntPtr)>>($(quoted(format!("__{}Insert", list_name))));
+                    ffi.Void Function(ffi.IntPtr, ffi.Uint32, ffi.IntPtr)>>($(quoted(symbols.list(ty, "Insert"))));
 
             late final $(format!("_ffiList{}Insert", ty)) = $(format!("_ffiList{}InsertPtr", ty)).asFunction<
                 void Function(int, int, int)>();
         )
     }
 
-    fn generate_list_type(&self, ty: &str) -> dart::Tokens {
+    fn generate_list_type(&self, symbols: &Symbols, ty: &str) -> dart::Tokens {
         let list_name_s = format!("FfiList{}", ty);
         let list_name = list_name_s.as_str();
         quote!(
@@ -554,7 +605,7 @@ impl DartGenerator {
                 @override
                 $ty elementAt(int index) {
                     final address = _api.$(format!("_ffiList{}ElementAt", ty))(_box.borrow(), index);
-                    final reference = _Box(_api, ffi.Pointer.fromAddress(address), "drop_box_Leak", context: this,);
+                    final reference = _Box(_api, ffi.Pointer.fromAddress(address), $(quoted(symbols.destructor("Leak"))), context: this,);
                     return $ty._(_api, reference);
                 }
 
@@ -565,7 +616,7 @@ impl DartGenerator {
                 $(static_literal("///")) Moves the element out of this list and returns it
                 $ty remove(int index) {
                     final address = _api.$(format!("_ffiList{}Remove", ty))(_box.borrow(), index);
-                    final reference = _Box(_api, ffi.Pointer.fromAddress(address), $(quoted(format!("drop_box_{}", ty))));
+                    final reference = _Box(_api, ffi.Pointer.fromAddress(address), $(quoted(symbols.destructor(ty))));
                     reference._finalizer = _api._registerFinalizer(reference);
                     return $ty._(_api, reference);
                 }
@@ -628,7 +679,7 @@ impl DartGenerator {
             }
             _ => self.ident(&func.name),
         };
-        let args: genco::Tokens<genco::lang::Dart> = quote!($(for (name, ty) in &ffi.abi_args => $(self.generate_type(ty)) $(self.ident(name)),));
+        let args: genco::Tokens<genco::lang::Dart> = quote!($(for (name, ty) in &ffi.abi_args => $(self.generate_arg_type(ty)) $(self.ident(name)),));
         let body: genco::Tokens<genco::lang::Dart> =
             quote!($(for instr in &ffi.instr => $(self.generate_instr(api, instr))));
         let ret = if let Some(ret) = ffi.abi_ret.as_ref() {
@@ -655,38 +706,40 @@ impl DartGenerator {
         }
     }
 
-    fn generate_ffi_buffer(&self, ty: &str) -> dart::Tokens {
-        let bytes = ty
-            .chars()
-            .skip_while(|&c| !c.is_ascii_digit())
-            .collect::<String>()
-            .parse::<u32>()
-            .unwrap()
-            / 8;
-        let pointer_name = match ty {
-            "Float32" => "Float",
-            "Float64" => "Double",
-            _ => ty,
-        };
-        let class_name = format!("FfiBuffer{}", ty);
+    fn generate_ffi_buffer(&self, symbols: &Symbols, ty: NumType) -> dart::Tokens {
+        let fixed = self.abi.fixed_size(ty);
+        let (bytes, _) = self.abi.layout(fixed);
+        let list_name = typed_list_name_for(fixed);
+        let class_name = ffi_buffer_name_for(ty);
         quote! {
-            class $(&class_name) {
+            class $(class_name) {
                 final Api _api;
                 final _Box _box;
 
-                $(&class_name)._(this._api, this._box);
+                $(class_name)._(this._api, this._box);
+
+                $(static_literal("///")) Allocates a zeroed buffer of length elements that can be filled
+                $(static_literal("///")) through asTypedList and passed to rust without copying.
+                static $(class_name) allocate(Api api, int length) {
+                    final ptr = api._ffiBufferNew(length * $(bytes), $(bytes));
+                    final box = _Box(api, ffi.Pointer.fromAddress(ptr), $(quoted(symbols.destructor("FfiBuffer"))));
+                    box._finalizer = api._registerFinalizer(box);
+                    return $(class_name)._(api, box);
+                }
 
                 void drop() {
                     _box.drop();
                 }
 
                 $(static_literal("///")) Returns a typed view of this buffer.
-                $(static_literal("///")) Note: The lifetime of this view is tied to the lifetime of this $(class_name.to_string()). This $(ty)List must not live longer than the creating $(class_name.to_string())
-                $(ty)List asTypedList() {
+                $(static_literal("///")) Note: The lifetime of this view is tied to the lifetime of this $(class_name). This $(list_name) must not live longer than the creating $(class_name)
+                $(list_name) asTypedList() {
                     final buffer = _box.borrow();
                     final addressRaw = _api._ffiBufferAddress(buffer);
                     final size = _api._ffiBufferSize(buffer) ~/ $(bytes);
-                    return ffi.Pointer<ffi.$(pointer_name)>.fromAddress(addressRaw).asTypedList(size);
+                    final view = ffi.Pointer<$(self.generate_native_num_type(fixed))>.fromAddress(addressRaw).asTypedList(size);
+                    _nativeViews[view] = _box;
+                    return view;
                 }
             }
         }
@@ -713,6 +766,11 @@ impl DartGenerator {
                 $(self.var(box_))_1._finalizer = $api._registerFinalizer($(self.var(box_))_1);
                 final $(self.var(out)) = $obj._($api, $(self.var(box_))_1);
             },
+            Instr::LiftRefObject(obj, box_, leak, out) => quote! {
+                final ffi.Pointer<ffi.Void> $(self.var(box_))_0 = ffi.Pointer.fromAddress($(self.var(box_)));
+                final $(self.var(box_))_1 = _Box($api, $(self.var(box_))_0, $(quoted(leak)), parent: _box);
+                final $(self.var(out)) = $obj._($api, $(self.var(box_))_1);
+            },
             Instr::BindArg(arg, out) => quote!(final $(self.var(out)) = $(self.ident(arg));),
             Instr::BindRets(ret, vars) => match vars.len() {
                 0 => quote!(),
@@ -768,15 +826,60 @@ impl DartGenerator {
             },
             Instr::LowerVec(in_, ptr, len, cap, ty, size, align) => quote! {
                 $(self.var(len)) = $(self.var(in_)).length;
-                final ffi.Pointer<$(self.generate_native_num_type(*ty))> $(self.var(ptr))_0 =
+                final ffi.Pointer<$(self.generate_native_num_type(self.abi.fixed_size(*ty)))> $(self.var(ptr))_0 =
                     $api.__allocate($(self.var(len)) * $(*size), $(*align));
                 final $(self.var(ptr))_1 = $(self.var(ptr))_0.asTypedList($(self.var(len)));
                 $(self.var(ptr))_1.setAll(0, $(self.var(in_)));
                 $(self.var(ptr)) = $(self.var(ptr))_0.address;
                 $(self.var(cap)) = $(self.var(len));
             },
+            Instr::LowerStrSlice(in_, ptr, len, cap) => quote! {
+                final $(self.var(in_))_0 = $(self.var(in_)).map((s) => utf8.encode(s)).toList();
+                $(self.var(len)) = $(self.var(in_))_0.length;
+                $(self.var(cap)) = $(self.var(in_))_0.fold($(self.var(len)) * 4, (int n, s) => n + s.length);
+                final ffi.Pointer<ffi.Uint8> $(self.var(ptr))_0 = $api.__allocate($(self.var(cap)), 4);
+                final $(self.var(ptr))_1 = $(self.var(ptr))_0.cast<ffi.Uint32>().asTypedList($(self.var(len)));
+                final $(self.var(ptr))_2 = $(self.var(ptr))_0.asTypedList($(self.var(cap)));
+                var $(self.var(ptr))_3 = $(self.var(len)) * 4;
+                for (var i = 0; i < $(self.var(len)); i++) {
+                    $(self.var(ptr))_1[i] = $(self.var(in_))_0[i].length;
+                    $(self.var(ptr))_2.setAll($(self.var(ptr))_3, $(self.var(in_))_0[i]);
+                    $(self.var(ptr))_3 += $(self.var(in_))_0[i].length;
+                }
+                $(self.var(ptr)) = $(self.var(ptr))_0.address;
+            },
+            Instr::LowerObjectSlice(in_, ptr, len, cap, size, align) => quote! {
+                $(self.var(len)) = $(self.var(in_)).length;
+                final ffi.Pointer<ffi.IntPtr> $(self.var(ptr))_0 =
+                    $api.__allocate($(self.var(len)) * $(*size), $(*align));
+                for (var i = 0; i < $(self.var(len)); i++) {
+                    $(self.var(ptr))_0[i] = $(self.var(in_))[i]._box.borrow();
+                }
+                $(self.var(ptr)) = $(self.var(ptr))_0.address;
+                $(self.var(cap)) = $(self.var(len));
+            },
+            Instr::LowerMutSlice(in_, ptr, len, cap, ty, size, align) => quote! {
+                $(self.var(len)) = $(self.var(in_)).length;
+                final $(self.var(ptr))_box = _nativeViews[$(self.var(in_))];
+                if ($(self.var(ptr))_box != null) {
+                    $(self.var(ptr)) = $api._ffiBufferAddress($(self.var(ptr))_box.borrow());
+                } else {
+                    final ffi.Pointer<$(self.generate_native_num_type(self.abi.fixed_size(*ty)))> $(self.var(ptr))_0 =
+                        $api.__allocate($(self.var(len)) * $(*size), $(*align));
+                    $(self.var(ptr))_0.asTypedList($(self.var(len))).setAll(0, $(self.var(in_)));
+                    $(self.var(ptr)) = $(self.var(ptr))_0.address;
+                    $(self.var(cap)) = $(self.var(len));
+                }
+            },
+            Instr::WriteBack(in_, ptr, len, cap, ty) => quote! {
+                if ($(self.var(cap)) > 0) {
+                    final ffi.Pointer<$(self.generate_native_num_type(self.abi.fixed_size(*ty)))> $(self.var(ptr))_2 =
+                        ffi.Pointer.fromAddress($(self.var(ptr)));
+                    $(self.var(in_)).setAll(0, $(self.var(ptr))_2.asTypedList($(self.var(len))));
+                }
+            },
             Instr::LiftVec(ptr, len, out, ty) => quote! {
-                final ffi.Pointer<$(self.generate_native_num_type(*ty))> $(self.var(ptr))_0 =
+                final ffi.Pointer<$(self.generate_native_num_type(self.abi.fixed_size(*ty)))> $(self.var(ptr))_0 =
                     ffi.Pointer.fromAddress($(self.var(ptr)));
                 final $(self.var(out)) = $(self.var(ptr))_0.asTypedList($(self.var(len))).toList();
             },
@@ -812,6 +915,17 @@ impl DartGenerator {
                     $(for inst in some_instr => $(self.generate_instr(api, inst)))
                 }
             },
+            Instr::LowerResult(arg, var, ok, ok_instr, err, err_instr) => quote! {
+                if ($(self.var(arg)).isOk) {
+                    $(self.var(var)) = 1;
+                    final $(self.var(ok)) = $(self.var(arg)).value;
+                    $(for inst in ok_instr => $(self.generate_instr(api, inst)))
+                } else {
+                    $(self.var(var)) = 0;
+                    final $(self.var(err)) = $(self.var(arg)).error;
+                    $(for inst in err_instr => $(self.generate_instr(api, inst)))
+                }
+            },
             Instr::HandleError(var, ptr, len, cap) => quote! {
                 if ($(self.var(var)) == 0) {
                     debugAllocation("handle error", $(self.var(ptr)), $(self.var(len)));
@@ -831,6 +945,11 @@ impl DartGenerator {
                 $(self.var(box_))_1._finalizer = $api._registerFinalizer($(self.var(box_))_1);
                 final $(self.var(out)) = Iter._($(self.var(box_))_1, $api.$(format!("__{}", self.ident(next))));
             },
+            Instr::LiftRefIter(box_, next, leak, out) => quote! {
+                final ffi.Pointer<ffi.Void> $(self.var(box_))_0 = ffi.Pointer.fromAddress($(self.var(box_)));
+                final $(self.var(box_))_1 = _Box($api, $(self.var(box_))_0, $(quoted(leak)), parent: _box);
+                final $(self.var(out)) = Iter._($(self.var(box_))_1, $api.$(format!("__{}", self.ident(next))));
+            },
             Instr::LiftFuture(box_, poll, drop, out) => quote! {
                 final ffi.Pointer<ffi.Void> $(self.var(box_))_0 = ffi.Pointer.fromAddress($(self.var(box_)));
                 final $(self.var(box_))_1 = _Box($api, $(self.var(box_))_0, $(quoted(drop)));
@@ -851,7 +970,9 @@ impl DartGenerator {
                     $(for var in vars => $(self.var(out)).add($(self.var(var)));)
                 },
             },
-            Instr::LiftNumFromU32Tuple(..) | Instr::LowerNumFromU32Tuple(..) => unreachable!(),
+            Instr::LiftNumFromU32Tuple(..)
+            | Instr::LowerNumFromU32Tuple(..)
+            | Instr::CallRetArea(..) => unreachable!(),
         }
     }
 
@@ -882,6 +1003,15 @@ impl DartGenerator {
         }
     }
 
+    /// Like `generate_type`, but results are passed wrapped in a `FfiResult`.
+    fn generate_arg_type(&self, ty: &AbiType) -> dart::Tokens {
+        match ty {
+            AbiType::Option(ty) => quote!($(self.generate_arg_type(ty))?),
+            AbiType::Result(ty) => quote!(FfiResult<$(self.generate_arg_type(ty))>),
+            _ => self.generate_type(ty),
+        }
+    }
+
     fn generate_type(&self, ty: &AbiType) -> dart::Tokens {
         match ty {
             AbiType::Num(ty) => self.generate_wrapped_num_type(*ty),
@@ -891,6 +1021,9 @@ impl DartGenerator {
             AbiType::RefSlice(ty) | AbiType::Vec(ty) => {
                 quote!(List<$(self.generate_wrapped_num_type(*ty))>)
             }
+            AbiType::RefMutSlice(ty) => quote!($(typed_list_name_for(self.abi.fixed_size(*ty)))),
+            AbiType::RefSliceStr => quote!(List<String>),
+            AbiType::RefSliceObject(ty) => quote!(List<$ty>),
             AbiType::Option(ty) => quote!($(self.generate_type(ty))?),
             AbiType::Result(ty) => self.generate_type(ty),
             AbiType::Tuple(tuple) => match tuple.len() {
@@ -906,7 +1039,7 @@ impl DartGenerator {
             AbiType::RefStream(ty) | AbiType::Stream(ty) => {
                 quote!(Stream<$(self.generate_type(ty))>)
             }
-            AbiType::Buffer(ty) => quote!($(ffi_buffer_name_for(*ty))),
+            AbiType::Buffer(ty) | AbiType::RefBuffer(ty) => quote!($(ffi_buffer_name_for(*ty))),
             AbiType::List(ty) => quote!($(format!("FfiList{}", ty))),
             AbiType::RefEnum(ty) => quote!($(ty)),
         }
@@ -1002,7 +1135,7 @@ fn sanitize_identifier(id: &str) -> String {
 }
 
 // https://dart.dev/guides/language/language-tour#keywords
-static RESERVED_IDENTIFIERS: [&str; 63] = [
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 63] = [
     "abstract",
     "as",
     "assert",
@@ -1179,6 +1312,22 @@ pub mod test_runner {
     }
 }
 
+fn typed_list_name_for(ty: NumType) -> &'static str {
+    match ty {
+        NumType::U8 => "Uint8List",
+        NumType::U16 => "Uint16List",
+        NumType::U32 => "Uint32List",
+        NumType::U64 => "Uint64List",
+        NumType::I8 => "Int8List",
+        NumType::I16 => "Int16List",
+        NumType::I32 => "Int32List",
+        NumType::I64 => "Int64List",
+        NumType::F32 => "Float32List",
+        NumType::F64 => "Float64List",
+        NumType::IPtr | NumType::UPtr => unreachable!("pointer-sized types need to be resolved"),
+    }
+}
+
 pub fn ffi_buffer_name_for(ty: NumType) -> &'static str {
     match ty {
         NumType::U8 => "FfiBufferUint8",
@@ -1191,7 +1340,7 @@ pub fn ffi_buffer_name_for(ty: NumType) -> &'static str {
         NumType::I64 => "FfiBufferInt64",
         NumType::F32 => "FfiBufferFloat32",
         NumType::F64 => "FfiBufferFloat64",
-        NumType::IPtr => todo!(),
-        NumType::UPtr => todo!(),
+        NumType::IPtr => "FfiBufferIntPtr",
+        NumType::UPtr => "FfiBufferUintPtr",
     }
 }
@@ -0,0 +1,472 @@
+use crate::parser::{Enum, Function, InterfaceBuilder, Type};
+use anyhow::Result;
+use quote::ToTokens;
+use syn::ext::IdentExt;
+use syn::{
+    Attribute, Expr, ExprLit, FnArg, GenericArgument, Ident, ImplItem, Item, Lit, Meta, Pat,
+    PathArguments, PathSegment, ReturnType, Signature, TypeParamBound, Visibility,
+};
+
+fn is_export(attr: &Attribute) -> bool {
+    attr.path()
+        .segments
+        .last()
+        .map(|seg| seg.ident == "export")
+        .unwrap_or_default()
+}
+
+fn is_pub(vis: &Visibility) -> bool {
+    !matches!(vis, Visibility::Inherited)
+}
+
+fn require_pub(vis: &Visibility, ident: &Ident) -> syn::Result<()> {
+    if is_pub(vis) {
+        Ok(())
+    } else {
+        Err(syn::Error::new_spanned(
+            ident,
+            format!("`{}` must be `pub` to be exported", ident),
+        ))
+    }
+}
+
+fn docs(attrs: &[Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(nv) if nv.path.is_ident("doc") => match &nv.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(s), ..
+                }) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn unsupported<T: ToTokens>(tokens: T, what: &str) -> syn::Error {
+    let s = tokens.to_token_stream().to_string();
+    syn::Error::new_spanned(tokens, format!("unsupported {} `{}`", what, s))
+}
+
+fn type_args(seg: &PathSegment) -> Vec<&syn::Type> {
+    match &seg.arguments {
+        PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn assoc_type<'a>(seg: &'a PathSegment, name: &str) -> Option<&'a syn::Type> {
+    match &seg.arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            GenericArgument::AssocType(assoc) if assoc.ident == name => Some(&assoc.ty),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+fn to_type(ty: &syn::Type, self_ty: Option<&str>) -> syn::Result<Type> {
+    Ok(match ty {
+        syn::Type::Paren(paren) => to_type(&paren.elem, self_ty)?,
+        syn::Type::Group(group) => to_type(&group.elem, self_ty)?,
+        syn::Type::Tuple(tuple) => Type::Tuple(
+            tuple
+                .elems
+                .iter()
+                .map(|ty| to_type(ty, self_ty))
+                .collect::<syn::Result<_>>()?,
+        ),
+        syn::Type::Reference(r) if r.mutability.is_none() => {
+            let inner = match &*r.elem {
+                syn::Type::Slice(slice) => match to_type(&slice.elem, self_ty)? {
+                    Type::Ref(elem) if matches!(*elem, Type::String | Type::Ident(_)) => {
+                        Type::Slice(elem)
+                    }
+                    elem => Type::Slice(Box::new(elem)),
+                },
+                syn::Type::Path(path) if path.qself.is_none() && path.path.is_ident("str") => {
+                    Type::String
+                }
+                ty => to_type(ty, self_ty)?,
+            };
+            Type::Ref(Box::new(inner))
+        }
+        syn::Type::Reference(r) => match &*r.elem {
+            syn::Type::Slice(slice) => Type::RefMut(Box::new(Type::Slice(Box::new(to_type(
+                &slice.elem,
+                self_ty,
+            )?)))),
+            ty => match to_type(ty, self_ty)? {
+                ty @ (Type::Iter(_) | Type::Buffer(_)) => Type::Ref(Box::new(ty)),
+                _ => return Err(unsupported(r, "type")),
+            },
+        },
+        syn::Type::ImplTrait(bounds) => {
+            let ty = bounds.bounds.iter().find_map(|bound| match bound {
+                TypeParamBound::Trait(bound) => {
+                    let seg = bound.path.segments.last()?;
+                    let (assoc, wrap): (_, fn(Box<Type>) -> Type) =
+                        match seg.ident.to_string().as_str() {
+                            "Iterator" | "IntoIterator" => ("Item", Type::Iter),
+                            "Stream" => ("Item", Type::Stream),
+                            "Future" => ("Output", Type::Future),
+                            _ => return None,
+                        };
+                    Some(assoc_type(seg, assoc).map(|inner| (inner, wrap)))
+                }
+                _ => None,
+            });
+            match ty {
+                Some(Some((inner, wrap))) => wrap(Box::new(to_type(inner, self_ty)?)),
+                _ => return Err(unsupported(bounds, "type")),
+            }
+        }
+        syn::Type::Path(path) if path.qself.is_none() => {
+            let seg = path.path.segments.last().unwrap();
+            let args = type_args(seg);
+            let name = seg.ident.unraw().to_string();
+            let inner = |i: usize| -> syn::Result<Box<Type>> {
+                match args.get(i) {
+                    Some(ty) => Ok(Box::new(to_type(ty, self_ty)?)),
+                    None => Err(unsupported(path, "type")),
+                }
+            };
+            match name.as_str() {
+                "u8" => Type::U8,
+                "u16" => Type::U16,
+                "u32" => Type::U32,
+                "u64" => Type::U64,
+                "usize" => Type::Usize,
+                "i8" => Type::I8,
+                "i16" => Type::I16,
+                "i32" => Type::I32,
+                "i64" => Type::I64,
+                "isize" => Type::Isize,
+                "bool" => Type::Bool,
+                "f32" => Type::F32,
+                "f64" => Type::F64,
+                "String" => Type::String,
+                "Vec" => Type::Vec(inner(0)?),
+                "Option" => Type::Option(inner(0)?),
+                "Result" => Type::Result(inner(0)?),
+                "FfiIter" => Type::Iter(inner(0)?),
+                "FfiBuffer" => {
+                    let inner = inner(0)?;
+                    match &*inner {
+                        Type::U8
+                        | Type::U16
+                        | Type::U32
+                        | Type::U64
+                        | Type::I8
+                        | Type::I16
+                        | Type::I32
+                        | Type::I64
+                        | Type::F32
+                        | Type::F64
+                        | Type::Usize
+                        | Type::Isize => Type::Buffer(inner),
+                        _ => return Err(unsupported(path, "type")),
+                    }
+                }
+                "Self" => match self_ty {
+                    Some(ident) => Type::Ident(ident.to_string()),
+                    None => return Err(unsupported(path, "type")),
+                },
+                _ if args.is_empty() && matches!(seg.arguments, PathArguments::None) => {
+                    Type::Ident(name)
+                }
+                _ => return Err(unsupported(path, "type")),
+            }
+        }
+        ty => return Err(unsupported(ty, "type")),
+    })
+}
+
+fn to_function(
+    sig: &Signature,
+    attrs: &[Attribute],
+    self_ty: Option<&str>,
+) -> syn::Result<Function> {
+    if let Some(param) = sig.generics.type_params().next() {
+        return Err(syn::Error::new_spanned(
+            param,
+            "generic functions can't be exported",
+        ));
+    }
+    let mut func = Function::new(sig.ident.unraw().to_string());
+    func.doc = docs(attrs);
+    func.is_static = self_ty.is_some();
+    for arg in &sig.inputs {
+        match arg {
+            FnArg::Receiver(receiver) => {
+                if receiver.reference.is_none() || receiver.colon_token.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        receiver,
+                        format!(
+                            "unsupported receiver `{}`, methods must take `&self` or `&mut self`",
+                            receiver.to_token_stream()
+                        ),
+                    ));
+                }
+                func.is_static = false;
+            }
+            FnArg::Typed(arg) => match &*arg.pat {
+                Pat::Ident(pat) => {
+                    func = func.arg(pat.ident.unraw().to_string(), to_type(&arg.ty, self_ty)?);
+                }
+                pat => return Err(unsupported(pat, "argument pattern")),
+            },
+        }
+    }
+    let ret = match &sig.output {
+        ReturnType::Default => None,
+        ReturnType::Type(_, ty) => Some(to_type(ty, self_ty)?),
+    };
+    func.ret = match (sig.asyncness.is_some(), ret) {
+        (true, ret) => Some(Type::Future(Box::new(
+            ret.unwrap_or_else(|| Type::Tuple(vec![])),
+        ))),
+        (false, ret) => ret,
+    };
+    Ok(func)
+}
+
+fn self_ident(ty: &syn::Type) -> syn::Result<String> {
+    match ty {
+        syn::Type::Path(path) if path.qself.is_none() => match path.path.get_ident() {
+            Some(ident) => Ok(ident.unraw().to_string()),
+            None => Err(unsupported(path, "object")),
+        },
+        ty => Err(unsupported(ty, "object")),
+    }
+}
+
+impl InterfaceBuilder {
+    /// Adds an item annotated with `#[export]`.
+    ///
+    /// Free standing functions become functions, structs and inherent impl
+    /// blocks become objects and enums become enums. Fails with a spanned
+    /// error if the item uses types that can't be passed across the ffi.
+    pub fn rust_item(mut self, item: &Item) -> syn::Result<Self> {
+        match item {
+            Item::Fn(func) => {
+                require_pub(&func.vis, &func.sig.ident)?;
+                self = self.function(to_function(&func.sig, &func.attrs, None)?);
+            }
+            Item::Struct(s) => {
+                require_pub(&s.vis, &s.ident)?;
+                let doc = docs(&s.attrs);
+                self.object_mut(s.ident.unraw().to_string()).doc.extend(doc);
+            }
+            Item::Impl(imp) => {
+                if let Some((_, path, _)) = &imp.trait_ {
+                    return Err(syn::Error::new_spanned(
+                        path,
+                        "only inherent impl blocks can be exported",
+                    ));
+                }
+                let ident = self_ident(&imp.self_ty)?;
+                let mut methods = vec![];
+                for item in &imp.items {
+                    // private methods are helpers of the exported ones.
+                    match item {
+                        ImplItem::Fn(method) if is_pub(&method.vis) => {
+                            methods.push(to_function(&method.sig, &method.attrs, Some(&ident))?);
+                        }
+                        _ => {}
+                    }
+                }
+                self.object_mut(ident).methods.extend(methods);
+            }
+            Item::Enum(e) => {
+                require_pub(&e.vis, &e.ident)?;
+                let mut en = Enum::new(e.ident.unraw().to_string());
+                en.doc = docs(&e.attrs);
+                for variant in &e.variants {
+                    let name = variant.ident.unraw().to_string();
+                    en = match &variant.fields {
+                        syn::Fields::Unit => en.entry(name),
+                        syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                            let field = &fields.unnamed[0].ty;
+                            match to_type(field, None)? {
+                                Type::Ident(object) => en.entry_with(name, object),
+                                _ => {
+                                    return Err(syn::Error::new_spanned(
+                                        field,
+                                        "enums can only wrap objects",
+                                    ))
+                                }
+                            }
+                        }
+                        fields => return Err(unsupported(fields, "enum variant")),
+                    };
+                }
+                self = self.enum_(en);
+            }
+            item => {
+                return Err(syn::Error::new_spanned(
+                    item,
+                    "only functions, structs, impl blocks and enums can be exported",
+                ))
+            }
+        }
+        Ok(self)
+    }
+
+    /// Adds all items annotated with `#[export]` in a rust source file,
+    /// including the ones in inline modules.
+    pub fn rust_source(self, source: &str) -> Result<Self> {
+        fn add_items(
+            mut builder: InterfaceBuilder,
+            items: &[Item],
+        ) -> syn::Result<InterfaceBuilder> {
+            for item in items {
+                if let Item::Mod(module) = item {
+                    if let Some((_, items)) = &module.content {
+                        builder = add_items(builder, items)?;
+                    }
+                    continue;
+                }
+                let attrs = match item {
+                    Item::Fn(item) => &item.attrs,
+                    Item::Struct(item) => &item.attrs,
+                    Item::Impl(item) => &item.attrs,
+                    Item::Enum(item) => &item.attrs,
+                    _ => continue,
+                };
+                if attrs.iter().any(is_export) {
+                    builder = builder.rust_item(item)?;
+                }
+            }
+            Ok(builder)
+        }
+        let file = syn::parse_file(source)?;
+        add_items(self, &file.items).map_err(|err| {
+            let start = err.span().start();
+            anyhow::anyhow!("{}:{}: {}", start.line, start.column + 1, err)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{Interface, InterfaceBuilder};
+    use anyhow::Result;
+
+    #[test]
+    fn test_rust_source() -> Result<()> {
+        let res = InterfaceBuilder::new()
+            .rust_source(
+                r#"
+                /// The main entry point of this example.
+                #[ffi_gen_macro::export]
+                pub struct Greeter {
+                    name: String,
+                }
+
+                #[export]
+                impl Greeter {
+                    pub fn new(name: &str) -> Self {
+                        Self { name: name.into() }
+                    }
+
+                    pub fn greet(&self) -> String {
+                        format!("Hello {}", self.name)
+                    }
+
+                    pub async fn names(&mut self) -> Vec<String> {
+                        vec![]
+                    }
+
+                    pub fn letters(&mut self) -> &mut FfiIter<u32> {
+                        todo!()
+                    }
+
+                    fn helper(&self, map: HashMap<String, u8>) {}
+                }
+
+                #[export]
+                pub enum Greeting {
+                    None,
+                    Some(Greeter),
+                }
+
+                mod api {
+                    #[export]
+                    pub fn parse(s: &str, bytes: &[u8]) -> Result<Option<(u8, i64)>, String> {
+                        todo!()
+                    }
+
+                    #[export]
+                    pub fn chars(s: &str) -> impl Iterator<Item = u32> {
+                        s.chars().map(|c| c as u32)
+                    }
+
+                    #[export]
+                    pub fn greet_all(names: &[&str], greeters: &[&Greeter]) {}
+
+                    #[export]
+                    pub fn offsets(buf: FfiBuffer<usize>) -> FfiBuffer<isize> {
+                        todo!()
+                    }
+                }
+
+                pub fn private() {}
+                "#,
+            )?
+            .build()?;
+        let parsed = Interface::parse(
+            r#"
+            /// The main entry point of this example.
+            object Greeter {
+                static fn new(name: &string) -> Greeter;
+                fn greet() -> string;
+                fn names() -> Future<Vec<string>>;
+                fn letters() -> &Iterator<u32>;
+            }
+
+            enum Greeting {
+                None,
+                Some(Greeter)
+            }
+
+            fn parse(s: &string, bytes: &[u8]) -> Result<Option<(u8, i64)>>;
+            fn chars(s: &string) -> Iterator<u32>;
+            fn greet_all(names: &[string], greeters: &[Greeter]);
+            fn offsets(buf: buffer<usize>) -> buffer<isize>;
+            "#,
+        )?;
+        assert_eq!(res, parsed.without_lines());
+
+        let err = InterfaceBuilder::new()
+            .rust_source("#[export]\npub fn hello(\n    map: HashMap<String, u8>) {}")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:10: unsupported type `HashMap < String , u8 >`"
+        );
+
+        let err = InterfaceBuilder::new()
+            .rust_source("#[export]\nimpl Greeter {\n    pub fn boxed(self: Box<Self>) {}\n}")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:18: unsupported receiver `self : Box < Self >`, methods must take `&self` or `&mut self`"
+        );
+
+        let err = InterfaceBuilder::new()
+            .rust_source("#[export]\nfn hello() {}")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "2:4: `hello` must be `pub` to be exported");
+        Ok(())
+    }
+}
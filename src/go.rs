@@ -0,0 +1,1296 @@
+use crate::c::CGenerator;
+use crate::dart::ffi_buffer_name_for;
+use crate::import::{Import, Instr};
+use crate::parser::{Enum, Type};
+use crate::{
+    Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Symbols, Var,
+    NUM_TYPES,
+};
+use genco::prelude::*;
+use genco::tokens::static_literal;
+use heck::*;
+
+pub struct GoGenerator {
+    abi: Abi,
+    c: CGenerator,
+    package: String,
+    c_header: String,
+    cdylib_name: String,
+}
+
+/// How the value of a function is returned, errors are returned as an
+/// additional `error`.
+enum Ret {
+    /// Nothing, or `true` for `Some(())`.
+    Void,
+    /// A single value, `Some` of a value that isn't nullable is returned by
+    /// reference.
+    Value { by_ref: bool },
+    /// Tuples are returned as multiple values.
+    Spread(usize),
+}
+
+/// State shared by the instructions of a single function body.
+struct Body<'a> {
+    objects: &'a [String],
+    handle_args: Vec<Option<String>>,
+    handle_ret: bool,
+    ffi_ret: &'a Return,
+    /// `Some(())` is returned as `true` to tell it apart from `None`.
+    unit: bool,
+    ret: Ret,
+    throws: bool,
+    /// Zero values of the returned values used when returning an error.
+    zeros: Vec<String>,
+    /// `next` and `poll` wrappers return the element of the channel and
+    /// whether it is ready. Errors are returned as `FfiResult` elements.
+    wrapper: Option<Wrapper>,
+    /// Values whose handles are borrowed by the call and need to stay
+    /// reachable until it returns, the finalizer would drop them otherwise.
+    fences: Vec<String>,
+}
+
+struct Wrapper {
+    elem: String,
+    result: bool,
+}
+
+impl GoGenerator {
+    /// Generates a go file in `package` using cgo to call the functions
+    /// declared in `c_header` and exported by the library `cdylib_name`.
+    pub fn new(package: String, c_header: String, cdylib_name: String) -> Self {
+        Self {
+            abi: Abi::native(),
+            c: CGenerator::new(),
+            package,
+            c_header,
+            cdylib_name,
+        }
+    }
+
+    pub fn generate(&self, iface: Interface) -> go::Tokens {
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
+        let objects: Vec<String> = iface.objects().into_iter().map(|obj| obj.name).collect();
+        let mut wrappers = vec![];
+        for iter in iface.iterators() {
+            wrappers.push(iter.next());
+        }
+        for fut in iface.futures() {
+            wrappers.push(fut.poll());
+        }
+        for stream in iface.streams() {
+            wrappers.push(stream.poll());
+        }
+        quote! {
+            $(static_literal("//")) AUTO GENERATED FILE, DO NOT EDIT.
+            $(static_literal("//"))
+            $(static_literal("//")) Generated by "ffi-gen".
+
+            $(self.generate_doc(&iface.doc))
+            package $(&self.package)
+
+            $(static_literal("/*"))
+            $(format!("#cgo LDFLAGS: -l{}", self.cdylib_name))
+            $(format!("#include \"{}\"", self.c_header))
+
+            $(static_literal("extern void ffiPostCObject(int64_t port, void *obj);"))
+            $(static_literal("*/"))
+            import "C"
+
+            import (
+                "runtime"
+                "sync"
+                "unsafe"
+            )
+
+            $(self.generate_runtime(&symbols))
+
+            $(for obj in iface.objects() join ($['\n']) => $(self.generate_object(&objects, &obj)))
+
+            $(for e in &iface.enums join ($['\n']) => $(self.generate_enum(&symbols, e)))
+
+            $(for ty in NUM_TYPES join ($['\n']) => $(self.generate_ffi_buffer(&symbols, ty)))
+
+            $(for ty in iface.listed_types() join ($['\n']) => $(self.generate_list_type(&symbols, &objects, &ty)))
+
+            $(for func in &wrappers join ($['\n']) => $(self.generate_function(&objects, func)))
+
+            $(for func in iface.functions() join ($['\n']) => $(self.generate_function(&objects, &func)))
+        }
+    }
+
+    fn generate_runtime(&self, symbols: &Symbols) -> go::Tokens {
+        quote! {
+            $(static_literal("//")) FfiError is an error returned by rust.
+            type FfiError struct {
+                Message string
+            }
+
+            func (err *FfiError) Error() string {
+                return err.Message
+            }
+
+            $(static_literal("//")) FfiResult is a result passed to rust or received from a channel.
+            type FfiResult[T any] struct {
+                Value T
+                Err   error
+            }
+
+            $(static_literal("//")) ffiBox is a handle to a rust object, boxes without a drop function are
+            $(static_literal("//")) borrowed from another object which is kept alive as parent.
+            type ffiBox struct {
+                mu     sync.Mutex
+                ptr    int
+                drop   func(int)
+                parent any
+            }
+
+            func newFfiBox(ptr int, drop func(int), parent any) *ffiBox {
+                box := &ffiBox{ptr: ptr, drop: drop, parent: parent}
+                if drop != nil {
+                    runtime.SetFinalizer(box, (*ffiBox).free)
+                }
+                return box
+            }
+
+            func (box *ffiBox) borrow() int {
+                box.mu.Lock()
+                defer box.mu.Unlock()
+                if box.ptr == 0 {
+                    panic("use after move")
+                }
+                return box.ptr
+            }
+
+            func (box *ffiBox) release() int {
+                box.mu.Lock()
+                defer box.mu.Unlock()
+                if box.drop == nil {
+                    panic("borrowed values can not be moved")
+                }
+                if box.ptr == 0 {
+                    panic("use after move")
+                }
+                ptr := box.ptr
+                box.ptr = 0
+                box.drop = nil
+                runtime.SetFinalizer(box, nil)
+                return ptr
+            }
+
+            func (box *ffiBox) free() {
+                box.mu.Lock()
+                defer box.mu.Unlock()
+                if box.ptr != 0 && box.drop != nil {
+                    box.drop(box.ptr)
+                }
+                box.ptr = 0
+                box.drop = nil
+            }
+
+            $(static_literal("//")) ffiLowerSlice copies the items into memory owned by rust.
+            func ffiLowerSlice[T any](items []T, align uint) int {
+                size := uintptr(len(items)) * unsafe.Sizeof(*new(T))
+                if size == 0 {
+                    return int(align)
+                }
+                ptr := C.$(symbols.allocate())(C.uintptr_t(size), C.uintptr_t(align))
+                copy(unsafe.Slice((*T)(unsafe.Pointer(ptr)), len(items)), items)
+                return int(uintptr(unsafe.Pointer(ptr)))
+            }
+
+            func ffiFreeBytes(ptr int, size uint, align uint) {
+                if size > 0 {
+                    C.$(symbols.deallocate())((*C.uint8_t)(unsafe.Pointer(uintptr(ptr))), C.uintptr_t(size), C.uintptr_t(align))
+                }
+            }
+
+            func ffiLiftSlice[T any](ptr int, count uint) []T {
+                items := make([]T, count)
+                if count > 0 {
+                    copy(items, unsafe.Slice((*T)(unsafe.Pointer(uintptr(ptr))), count))
+                }
+                return items
+            }
+
+            $(static_literal("//")) ffiWriteBack copies the items rust wrote to a mutable slice back.
+            func ffiWriteBack[T any](items []T, ptr int) {
+                copy(items, unsafe.Slice((*T)(unsafe.Pointer(uintptr(ptr))), len(items)))
+            }
+
+            func ffiLiftString(ptr int, count uint) string {
+                if count == 0 {
+                    return ""
+                }
+                return C.GoStringN((*C.char)(unsafe.Pointer(uintptr(ptr))), C.int(count))
+            }
+
+            $(static_literal("//")) ffiPackStrs packs the u32 byte length of every string followed by the
+            $(static_literal("//")) utf8 bytes.
+            func ffiPackStrs(strs []string) []byte {
+                size := len(strs) * 4
+                for _, str := range strs {
+                    size += len(str)
+                }
+                bytes := make([]byte, size)
+                offset := len(strs) * 4
+                for i, str := range strs {
+                    *(*uint32)(unsafe.Pointer(&bytes[i*4])) = uint32(len(str))
+                    offset += copy(bytes[offset:], str)
+                }
+                return bytes
+            }
+
+            func ffiLowerBool(value bool) uint8 {
+                if value {
+                    return 1
+                }
+                return 0
+            }
+
+            var ffiWakers = struct {
+                sync.Mutex
+                next   int64
+                wakers map[int64]chan struct{}
+            }{wakers: map[int64]chan struct{}{}}
+
+            func ffiRegisterWaker(wake chan struct{}) int64 {
+                ffiWakers.Lock()
+                defer ffiWakers.Unlock()
+                ffiWakers.next++
+                ffiWakers.wakers[ffiWakers.next] = wake
+                return ffiWakers.next
+            }
+
+            func ffiUnregisterWaker(port int64) {
+                ffiWakers.Lock()
+                defer ffiWakers.Unlock()
+                delete(ffiWakers.wakers, port)
+            }
+
+            $(static_literal("//")) ffiPostCObject is exported so its address can be passed to the poll functions.
+            $(static_literal("//")) The send never blocks, a wake that is already pending is enough.
+            $(static_literal("//"))
+            $(static_literal("//export ffiPostCObject"))
+            func ffiPostCObject(port C.int64_t, obj unsafe.Pointer) {
+                ffiWakers.Lock()
+                wake := ffiWakers.wakers[int64(port)]
+                ffiWakers.Unlock()
+                if wake != nil {
+                    select {
+                    case wake <- struct{}{}:
+                    default:
+                    }
+                }
+            }
+
+            var ffiPostCObjectAddress = int(uintptr(unsafe.Pointer(C.ffiPostCObject)))
+
+            $(static_literal("//")) ffiAwait polls the rust future whenever it is woken and sends the value
+            $(static_literal("//")) once it is ready.
+            func ffiAwait[T any](box *ffiBox, poll func(int, int, int64) (T, bool)) <-chan T {
+                ch := make(chan T, 1)
+                go func() {
+                    defer box.free()
+                    wake := make(chan struct{}, 1)
+                    port := ffiRegisterWaker(wake)
+                    defer ffiUnregisterWaker(port)
+                    for {
+                        if value, ok := poll(box.borrow(), ffiPostCObjectAddress, port); ok {
+                            ch <- value
+                            return
+                        }
+                        <-wake
+                    }
+                }()
+                return ch
+            }
+
+            $(static_literal("//")) ffiStream sends the items of the rust stream and closes the channel once
+            $(static_literal("//")) rust wakes the done port.
+            func ffiStream[T any](box *ffiBox, poll func(int, int, int64, int64) (T, bool)) <-chan T {
+                ch := make(chan T)
+                go func() {
+                    defer close(ch)
+                    defer box.free()
+                    wake := make(chan struct{}, 1)
+                    done := make(chan struct{}, 1)
+                    port := ffiRegisterWaker(wake)
+                    defer ffiUnregisterWaker(port)
+                    donePort := ffiRegisterWaker(done)
+                    defer ffiUnregisterWaker(donePort)
+                    for {
+                        select {
+                        case <-done:
+                            return
+                        default:
+                        }
+                        if item, ok := poll(box.borrow(), ffiPostCObjectAddress, port, donePort); ok {
+                            ch <- item
+                            continue
+                        }
+                        select {
+                        case <-wake:
+                        case <-done:
+                            return
+                        }
+                    }
+                }()
+                return ch
+            }
+
+            $(static_literal("//")) ffiIter sends the items of the rust iterator and closes the channel once
+            $(static_literal("//")) it is exhausted.
+            func ffiIter[T any](box *ffiBox, next func(int) (T, bool)) <-chan T {
+                ch := make(chan T)
+                go func() {
+                    defer close(ch)
+                    defer box.free()
+                    for {
+                        item, ok := next(box.borrow())
+                        if !ok {
+                            return
+                        }
+                        ch <- item
+                    }
+                }()
+                return ch
+            }
+
+            $(static_literal("//")) FfiString is a rust String owned by a list.
+            type FfiString struct {
+                box *ffiBox
+            }
+
+            func newFfiString(box *ffiBox) *FfiString {
+                return &FfiString{box: box}
+            }
+
+            func (str *FfiString) Close() {
+                str.box.free()
+            }
+
+            func (str *FfiString) String() string {
+                parts := C.$(symbols.string_into_parts())(unsafe.Pointer(uintptr(str.box.borrow())))
+                runtime.KeepAlive(str)
+                return ffiLiftString(int(parts.ptr), uint(parts.len))
+            }
+        }
+    }
+
+    fn generate_object(&self, objects: &[String], obj: &AbiObject) -> go::Tokens {
+        let name = self.type_ident(&obj.name);
+        quote! {
+            $(self.generate_doc(&obj.doc))
+            type $(&name) struct {
+                box *ffiBox
+            }
+
+            func $(format!("new{}", name))(box *ffiBox) *$(&name) {
+                return &$(&name){box: box}
+            }
+
+            $(static_literal("//")) Close drops the rust object, otherwise it is dropped once the object is
+            $(static_literal("//")) finalized.
+            func (self *$(&name)) Close() {
+                self.box.free()
+            }
+            $(for method in &obj.methods =>
+                $['\n']
+                $(self.generate_function(objects, method))
+            )
+        }
+    }
+
+    fn generate_enum(&self, symbols: &Symbols, e: &Enum) -> go::Tokens {
+        let name = self.type_ident(&e.ident);
+        let tag = format!("{}Tag", name);
+        quote! {
+            $(static_literal("//")) $(&tag) is the tag of $(&name).
+            type $(&tag) uint32
+
+            const (
+                $(for (i, entry) in e.entries.iter().enumerate() =>
+                    $(if i == 0 {
+                        $(&tag)$(entry.name.to_upper_camel_case()) $(&tag) = iota
+                    } else {
+                        $(&tag)$(entry.name.to_upper_camel_case())
+                    })$['\r'])
+            )
+
+            $(self.generate_doc(&e.doc))
+            type $(&name) struct {
+                box *ffiBox
+                $(static_literal("//")) Tag is the tag of this enum object.
+                Tag $(&tag)
+                $(for (entry, inner) in inner_objects(e) =>
+                    $(entry.to_upper_camel_case()) *$(self.type_ident(&inner))$['\r'])
+            }
+
+            func $(format!("new{}", name))(box *ffiBox) *$(&name) {
+                parts := C.$(symbols.destructure_enum(&e.ident))(unsafe.Pointer(uintptr(box.borrow())))
+                e := &$(&name){box: box, Tag: $(&tag)(parts.tag)}
+                $(for (entry, inner) in inner_objects(e) =>
+                    if e.Tag == $(&tag)$(entry.to_upper_camel_case()) {
+                        e.$(entry.to_upper_camel_case()) = $(format!("new{}", self.type_ident(&inner)))(newFfiBox(int(uintptr(parts.inner)), $(self.drop_fn(&symbols.destructor(&inner), Some(&inner))), nil))
+                    }$['\r'])
+                return e
+            }
+
+            func (e *$(&name)) Close() {
+                e.box.free()
+            }
+        }
+    }
+
+    fn generate_ffi_buffer(&self, symbols: &Symbols, ty: NumType) -> go::Tokens {
+        let name = ffi_buffer_name_for(ty);
+        let num = self.num_type(ty);
+        let (size, _) = self.abi.layout(ty);
+        quote! {
+            $(static_literal("//")) $name is a buffer that can be filled through its elements and passed to
+            $(static_literal("//")) rust without copying.
+            type $name struct {
+                box *ffiBox
+            }
+
+            func $(format!("new{}", name))(box *ffiBox) *$name {
+                return &$name{box: box}
+            }
+
+            $(static_literal("//")) $(format!("New{}", name)) allocates a zeroed buffer of count elements.
+            func $(format!("New{}", name))(count int) *$name {
+                ptr := C.$(symbols.ffi_buffer("new"))(C.uintptr_t(count*$size), $size)
+                return $(format!("new{}", name))(newFfiBox(int(ptr), $(self.drop_fn(&symbols.destructor("FfiBuffer"), None)), nil))
+            }
+
+            func (buf *$name) Close() {
+                buf.box.free()
+            }
+
+            $(static_literal("//")) Elements of the buffer, only valid while the buffer is alive.
+            func (buf *$name) Elements() []$num {
+                ptr := unsafe.Pointer(uintptr(buf.box.borrow()))
+                address := C.$(symbols.ffi_buffer("address"))(ptr)
+                size := C.$(symbols.ffi_buffer("size"))(ptr)
+                runtime.KeepAlive(buf)
+                return unsafe.Slice((*$num)(unsafe.Pointer(uintptr(address))), int(size)/$size)
+            }
+        }
+    }
+
+    /// Strings can't be moved in or out of a list, rust doesn't export a
+    /// destructor for them.
+    fn generate_list_type(&self, symbols: &Symbols, objects: &[String], ty: &str) -> go::Tokens {
+        let name = format!("FfiList{}", ty);
+        let elem = self.type_ident(ty);
+        let owned = objects.iter().any(|obj| obj == ty);
+        let boxed = quote!(C.uintptr_t(list.box.borrow()));
+        quote! {
+            $(static_literal("//")) $(&name) is a rust Vec owning its elements.
+            type $(&name) struct {
+                box *ffiBox
+            }
+
+            func $(format!("new{}", name))(box *ffiBox) *$(&name) {
+                return &$(&name){box: box}
+            }
+
+            func $(format!("New{}", name))() *$(&name) {
+                ptr := C.$(symbols.list(ty, "Create"))()
+                return $(format!("new{}", name))(newFfiBox(int(ptr), $(self.drop_fn(&symbols.destructor(&name), None)), nil))
+            }
+
+            func (list *$(&name)) Close() {
+                list.box.free()
+            }
+
+            func (list *$(&name)) Len() int {
+                len := C.$(symbols.list(ty, "Len"))($(&boxed))
+                runtime.KeepAlive(list)
+                return int(len)
+            }
+
+            $(static_literal("//")) Get returns an element borrowed from the list, it keeps the list alive.
+            func (list *$(&name)) Get(index int) *$(&elem) {
+                list.checkIndex(index, list.Len())
+                ptr := C.$(symbols.list(ty, "ElementAt"))($(&boxed), C.uint32_t(index))
+                return $(format!("new{}", elem))(newFfiBox(int(ptr), nil, list))
+            }
+            $(if owned {
+                $['\n']
+                $(static_literal("//")) Remove moves the element out of the list.
+                func (list *$(&name)) Remove(index int) *$(&elem) {
+                    list.checkIndex(index, list.Len())
+                    ptr := C.$(symbols.list(ty, "Remove"))($(&boxed), C.uint32_t(index))
+                    runtime.KeepAlive(list)
+                    return $(format!("new{}", elem))(newFfiBox(int(ptr), $(self.drop_fn(&symbols.destructor(ty), Some(ty))), nil))
+                }
+
+                $(static_literal("//")) Add moves the element into the list.
+                func (list *$(&name)) Add(element *$(&elem)) {
+                    C.$(symbols.list(ty, "Add"))($(&boxed), C.uintptr_t(element.box.release()))
+                    runtime.KeepAlive(list)
+                }
+
+                $(static_literal("//")) Insert moves the element into the list.
+                func (list *$(&name)) Insert(index int, element *$(&elem)) {
+                    list.checkIndex(index, list.Len()+1)
+                    C.$(symbols.list(ty, "Insert"))($(&boxed), C.uint32_t(index), C.uintptr_t(element.box.release()))
+                    runtime.KeepAlive(list)
+                }
+            })
+
+            func (list *$(&name)) checkIndex(index int, len int) {
+                if index < 0 || index >= len {
+                    panic("index out of range")
+                }
+            }
+        }
+    }
+
+    fn generate_function(&self, objects: &[String], func: &AbiFunction) -> go::Tokens {
+        let ffi = self.abi.import(func);
+        let unit = ffi.abi_ret.is_none() && matches!(func.ret, Some(AbiType::Option(_)));
+        let throws = matches!(func.ret, Some(AbiType::Result(_)));
+        let wrapper = match &func.ty {
+            FunctionType::NextIter(_, ty)
+            | FunctionType::PollFuture(_, ty)
+            | FunctionType::PollStream(_, ty) => Some(Wrapper {
+                elem: self.elem_type(ty),
+                result: matches!(ty, AbiType::Result(_)),
+            }),
+            _ => None,
+        };
+        let (ret, results) = match ffi.abi_ret.as_ref().map(strip_result) {
+            None if unit => (Ret::Void, vec!["bool".to_string()]),
+            None => (Ret::Void, vec![]),
+            Some(AbiType::Tuple(tys)) if tys.len() != 1 => (
+                Ret::Spread(tys.len()),
+                tys.iter().map(|ty| self.value_type(ty, false)).collect(),
+            ),
+            Some(ty) => (
+                Ret::Value {
+                    by_ref: matches!(ty, AbiType::Option(inner) if !nullable(inner)),
+                },
+                vec![self.value_type(ty, false)],
+            ),
+        };
+        let mut fences = vec![];
+        if let FunctionType::Method(_) = &func.ty {
+            fences.push("self".to_string());
+        }
+        for instr in &ffi.instr {
+            if let Instr::BindArg(_, var) = instr {
+                if borrows(&var.ty) {
+                    fences.push(self.var(var));
+                }
+            }
+        }
+        let body = Body {
+            objects,
+            handle_args: self.c.handle_args(func),
+            handle_ret: self.c.handle_ret(func).is_some(),
+            ffi_ret: &ffi.ffi_ret,
+            unit,
+            ret,
+            throws,
+            zeros: results.iter().map(|ty| format!("*new({})", ty)).collect(),
+            wrapper,
+            fences,
+        };
+        let mut results = results;
+        if body.throws {
+            results.push("error".to_string());
+        }
+        if let Some(wrapper) = &body.wrapper {
+            results = vec![wrapper.elem.clone(), "bool".to_string()];
+        }
+        // variables defined inside of an option or result need to outlive
+        // the branch for the cleanup instructions.
+        let mut nested = vec![];
+        for instr in &ffi.instr {
+            nested_defines(instr, &mut nested);
+        }
+        quote! {
+            $(self.generate_doc(&func.doc))
+            $(self.signature(func, &ffi, &results)) {
+                $(for var in &nested => var $(self.var(var)) $(self.ffi_type(var))$['\r'])
+                $(for instr in &ffi.instr => $(self.generate_instr(&body, instr, false))$['\r'])
+            }
+        }
+    }
+
+    /// Receiver, name, arguments and results of `func`.
+    fn signature(&self, func: &AbiFunction, ffi: &Import, results: &[String]) -> String {
+        let name = match &func.ty {
+            FunctionType::Function => func.name.to_upper_camel_case(),
+            FunctionType::Method(object) => format!(
+                "(self *{}) {}",
+                self.type_ident(object),
+                func.name.to_upper_camel_case()
+            ),
+            FunctionType::Constructor(object) if func.name == "new" => {
+                format!("New{}", self.type_ident(object))
+            }
+            FunctionType::Constructor(object) => format!(
+                "{}{}",
+                self.type_ident(object),
+                func.name.to_upper_camel_case()
+            ),
+            FunctionType::NextIter(_, _)
+            | FunctionType::PollFuture(_, _)
+            | FunctionType::PollStream(_, _) => wrapper_name(&ffi.symbol),
+        };
+        let args = ffi
+            .abi_args
+            .iter()
+            .map(|(name, ty)| format!("{} {}", self.ident(name), self.arg_type(ty)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let results = match results.len() {
+            0 => "".to_string(),
+            1 => format!(" {}", results[0]),
+            _ => format!(" ({})", results.join(", ")),
+        };
+        format!("func {}({}){}", name, args, results)
+    }
+
+    fn generate_instr(&self, body: &Body, instr: &Instr, nested: bool) -> go::Tokens {
+        match instr {
+            Instr::BindArg(arg, out) => quote!($(self.var(out)) := $(self.ident(arg))),
+            Instr::DefineArgs(_) if nested => quote!(),
+            Instr::DefineArgs(vars) => quote! {
+                $(for var in vars => var $(self.var(var)) $(self.ffi_type(var))$['\r'])
+            },
+            Instr::BorrowSelf(out) => quote!($(self.var(out)) = self.box.borrow()),
+            Instr::BorrowObject(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).box.borrow())
+            }
+            Instr::MoveObject(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).box.release())
+            }
+            Instr::BorrowIter(_, _)
+            | Instr::MoveIter(_, _)
+            | Instr::BorrowFuture(_, _)
+            | Instr::MoveFuture(_, _)
+            | Instr::BorrowStream(_, _)
+            | Instr::MoveStream(_, _) => {
+                unimplemented!("iterators, futures and streams can only be returned")
+            }
+            Instr::LowerNum(in_, out) => {
+                quote!($(self.var(out)) = $(self.ffi_type(out))($(self.var(in_))))
+            }
+            Instr::LiftNum(in_, out, _) => quote!($(self.var(out)) := $(self.var(in_))),
+            Instr::LowerBool(in_, out) => {
+                quote!($(self.var(out)) = ffiLowerBool($(self.var(in_))))
+            }
+            Instr::LiftBool(in_, out) => quote!($(self.var(out)) := $(self.var(in_)) != 0),
+            Instr::LowerString(in_, ptr, len, cap, _, _) => quote! {
+                $(self.var(ptr))_0 := []byte($(self.var(in_)))
+                $(self.var(len)) = uint(len($(self.var(ptr))_0))
+                $(self.var(ptr)) = ffiLowerSlice($(self.var(ptr))_0, 1)
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::LowerVec(in_, ptr, len, cap, _, _, align)
+            | Instr::LowerMutSlice(in_, ptr, len, cap, _, _, align) => quote! {
+                $(self.var(len)) = uint(len($(self.var(in_))))
+                $(self.var(ptr)) = ffiLowerSlice($(self.var(in_)), $(*align))
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::LowerStrSlice(in_, ptr, len, cap) => quote! {
+                $(self.var(ptr))_0 := ffiPackStrs($(self.var(in_)))
+                $(self.var(len)) = uint(len($(self.var(in_))))
+                $(self.var(cap)) = uint(len($(self.var(ptr))_0))
+                $(self.var(ptr)) = ffiLowerSlice($(self.var(ptr))_0, 4)
+            },
+            Instr::LowerObjectSlice(in_, ptr, len, cap, _, align) => quote! {
+                $(self.var(ptr))_0 := make([]int, len($(self.var(in_))))
+                for i, obj := range $(self.var(in_)) {
+                    $(self.var(ptr))_0[i] = obj.box.borrow()
+                }
+                $(self.var(len)) = uint(len($(self.var(in_))))
+                $(self.var(ptr)) = ffiLowerSlice($(self.var(ptr))_0, $(*align))
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::WriteBack(in_, ptr, _, cap, _) => quote! {
+                if $(self.var(cap)) > 0 {
+                    ffiWriteBack($(self.var(in_)), $(self.var(ptr)))
+                }
+            },
+            Instr::Deallocate(ptr, len, size, align) => quote! {
+                ffiFreeBytes($(self.var(ptr)), $(self.var(len))*$(*size), $(*align))
+            },
+            Instr::LiftString(ptr, len, out) => quote! {
+                $(self.var(out)) := ffiLiftString($(self.var(ptr)), $(self.var(len)))
+            },
+            Instr::LiftVec(ptr, len, out, ty) => quote! {
+                $(self.var(out)) := ffiLiftSlice[$(self.num_type(*ty))]($(self.var(ptr)), $(self.var(len)))
+            },
+            Instr::HandleNull(var) => quote! {
+                if $(self.var(var)) == 0 {
+                    $(self.return_none(body))
+                }
+            },
+            Instr::LowerOption(arg, var, some, some_instr) => {
+                let some_value = match &arg.ty {
+                    AbiType::Option(inner) if !nullable(inner) => format!("*{}", self.var(arg)),
+                    _ => self.var(arg),
+                };
+                quote! {
+                    if $(self.var(arg)) != nil {
+                        $(self.var(some)) := $some_value
+                        $(self.var(var)) = 1
+                        $(for instr in some_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                    } else {
+                        $(self.var(var)) = 0
+                    }
+                }
+            }
+            Instr::LowerResult(arg, var, ok, ok_instr, err, err_instr) => quote! {
+                if $(self.var(arg)).Err == nil {
+                    $(self.var(ok)) := $(self.var(arg)).Value
+                    $(self.var(var)) = 1
+                    $(for instr in ok_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                } else {
+                    $(self.var(err)) := $(self.var(arg)).Err.Error()
+                    $(self.var(var)) = 0
+                    $(for instr in err_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                }
+            },
+            Instr::HandleError(var, ptr, len, cap) => {
+                let err = format!("&FfiError{{{}_0}}", self.var(var));
+                quote! {
+                    if $(self.var(var)) == 0 {
+                        $(self.var(var))_0 := ffiLiftString($(self.var(ptr)), $(self.var(len)))
+                        ffiFreeBytes($(self.var(ptr)), $(self.var(cap)), 1)
+                        $(self.return_error(body, &err))
+                    }
+                }
+            }
+            Instr::LiftObject(obj, box_, drop, out) => {
+                let typed = body.objects.iter().any(|o| o == obj);
+                let drop = self.drop_fn(drop, if typed { Some(obj) } else { None });
+                quote! {
+                    $(self.var(out)) := $(format!("new{}", self.type_ident(obj)))(newFfiBox($(self.var(box_)), $drop, nil))
+                }
+            }
+            Instr::LiftRefObject(obj, box_, _leak, out) => quote! {
+                $(self.var(out)) := $(format!("new{}", self.type_ident(obj)))(newFfiBox($(self.var(box_)), nil, nil))
+            },
+            Instr::LiftIter(box_, next, drop, out) => quote! {
+                $(self.var(out)) := ffiIter(newFfiBox($(self.var(box_)), $(self.drop_fn(drop, None)), nil), $(wrapper_name(next)))
+            },
+            Instr::LiftRefIter(box_, next, _leak, out) => quote! {
+                $(self.var(out)) := ffiIter(newFfiBox($(self.var(box_)), nil, nil), $(wrapper_name(next)))
+            },
+            Instr::LiftFuture(box_, poll, drop, out) => quote! {
+                $(self.var(out)) := ffiAwait(newFfiBox($(self.var(box_)), $(self.drop_fn(drop, None)), nil), $(wrapper_name(poll)))
+            },
+            Instr::LiftStream(box_, poll, drop, out) => quote! {
+                $(self.var(out)) := ffiStream(newFfiBox($(self.var(box_)), $(self.drop_fn(drop, None)), nil), $(wrapper_name(poll)))
+            },
+            Instr::LiftTuple(vars, out) => match vars.len() {
+                1 => quote!($(self.var(out)) := $(self.var(&vars[0]))),
+                _ => {
+                    let tys = vars.iter().map(|var| var.ty.clone()).collect();
+                    let values = vars
+                        .iter()
+                        .map(|var| self.var(var))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    quote! {
+                        $(self.var(out)) := $(self.value_type(&AbiType::Tuple(tys), false)){$values}
+                    }
+                }
+            },
+            Instr::Call(symbol, ret, args) => {
+                let args = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| match body.handle_args.get(i) {
+                        Some(Some(object)) => {
+                            format!(
+                                "(*C.{})(unsafe.Pointer(uintptr({})))",
+                                object,
+                                self.var(arg)
+                            )
+                        }
+                        _ => format!("C.{}({})", self.c_type(arg), self.var(arg)),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let call = format!("C.{}({})", symbol, args);
+                let call = match (ret, body.ffi_ret) {
+                    (Some(ret), Return::Struct(_, _)) => format!("{} := {}", self.var(ret), call),
+                    (Some(ret), Return::Num(_)) if body.handle_ret => format!(
+                        "{} := int(uintptr(unsafe.Pointer({})))",
+                        self.var(ret),
+                        call
+                    ),
+                    (Some(ret), Return::Num(var)) => {
+                        format!("{} := {}({})", self.var(ret), self.ffi_type(var), call)
+                    }
+                    _ => call,
+                };
+                quote! {
+                    $call
+                    $(for value in &body.fences => $['\r']runtime.KeepAlive($value))
+                }
+            }
+            Instr::BindRets(ret, vars) => match vars.len() {
+                0 => quote!(),
+                1 => quote!($(self.var(&vars[0])) := $(self.var(ret))),
+                _ => quote! {
+                    $(for (i, var) in vars.iter().enumerate() =>
+                        $(self.var(var)) := $(self.ffi_type(var))($(self.var(ret)).$(format!("ret{}", i)))$['\r'])
+                },
+            },
+            Instr::ReturnValue(ret) => self.return_value(body, &self.var(ret)),
+            Instr::ReturnVoid => self.return_void(body),
+            Instr::LiftNumFromU32Tuple(..)
+            | Instr::LowerNumFromU32Tuple(..)
+            | Instr::CallRetArea(..) => unreachable!(),
+        }
+    }
+
+    fn return_none(&self, body: &Body) -> go::Tokens {
+        if let Some(wrapper) = &body.wrapper {
+            return quote!(return *new($(&wrapper.elem)), false);
+        }
+        let none = if body.unit { "false" } else { "nil" };
+        if body.throws {
+            quote!(return $none, nil)
+        } else {
+            quote!(return $none)
+        }
+    }
+
+    fn return_error(&self, body: &Body, err: &str) -> go::Tokens {
+        match &body.wrapper {
+            Some(wrapper) if wrapper.result => {
+                quote!(return $(&wrapper.elem){Err: $err}, true)
+            }
+            Some(_) => unreachable!("only results can return errors"),
+            None => {
+                let mut values = body.zeros.clone();
+                values.push(err.to_string());
+                quote!(return $(values.join(", ")))
+            }
+        }
+    }
+
+    fn return_value(&self, body: &Body, value: &str) -> go::Tokens {
+        if let Some(wrapper) = &body.wrapper {
+            return if wrapper.result {
+                quote!(return $(&wrapper.elem){Value: $value}, true)
+            } else {
+                quote!(return $value, true)
+            };
+        }
+        let mut values = match body.ret {
+            Ret::Void => vec![],
+            Ret::Value { by_ref: true } => vec![format!("&{}", value)],
+            Ret::Value { by_ref: false } => vec![value.to_string()],
+            Ret::Spread(len) => (0..len).map(|i| format!("{}.V{}", value, i)).collect(),
+        };
+        if body.throws {
+            values.push("nil".to_string());
+        }
+        quote!(return $(values.join(", ")))
+    }
+
+    fn return_void(&self, body: &Body) -> go::Tokens {
+        match (&body.wrapper, body.unit, body.throws) {
+            (Some(wrapper), _, _) => quote!(return *new($(&wrapper.elem)), true),
+            (None, true, true) => quote!(return true, nil),
+            (None, true, false) => quote!(return true),
+            (None, false, true) => quote!(return nil),
+            (None, false, false) => quote!(return),
+        }
+    }
+
+    /// Function calling the destructor `drop`, object destructors take a
+    /// typed handle.
+    fn drop_fn(&self, drop: &str, object: Option<&str>) -> String {
+        match object {
+            Some(object) => format!(
+                "func(ptr int) {{ C.{}(0, (*C.{})(unsafe.Pointer(uintptr(ptr)))) }}",
+                drop, object
+            ),
+            None => format!("func(ptr int) {{ C.{}(0, C.intptr_t(ptr)) }}", drop),
+        }
+    }
+
+    fn arg_type(&self, ty: &AbiType) -> String {
+        match ty {
+            AbiType::Iter(_)
+            | AbiType::RefIter(_)
+            | AbiType::Future(_)
+            | AbiType::RefFuture(_)
+            | AbiType::Stream(_)
+            | AbiType::RefStream(_) => {
+                unimplemented!("iterators, futures and streams can only be returned")
+            }
+            ty => self.value_type(ty, true),
+        }
+    }
+
+    /// Element of the channel an iterator, future or stream of `ty` is
+    /// received from.
+    fn elem_type(&self, ty: &AbiType) -> String {
+        match ty {
+            AbiType::Result(ty) => format!("FfiResult[{}]", self.value_type(ty, false)),
+            ty => self.value_type(ty, false),
+        }
+    }
+
+    /// Type of a value, results passed as arguments are wrapped in a
+    /// `FfiResult`, returned errors are returned as an `error`.
+    fn value_type(&self, ty: &AbiType, arg: bool) -> String {
+        match ty {
+            AbiType::Num(ty) => self.num_type(*ty).to_string(),
+            AbiType::Isize => "int".to_string(),
+            AbiType::Usize => "uint".to_string(),
+            AbiType::Bool => "bool".to_string(),
+            AbiType::RefStr | AbiType::String => "string".to_string(),
+            AbiType::RefSlice(ty) | AbiType::RefMutSlice(ty) | AbiType::Vec(ty) => {
+                format!("[]{}", self.num_type(*ty))
+            }
+            AbiType::RefSliceStr => "[]string".to_string(),
+            AbiType::RefSliceObject(ty) => format!("[]*{}", self.type_ident(ty)),
+            AbiType::RefObject(ty) | AbiType::Object(ty) | AbiType::RefEnum(ty) => {
+                format!("*{}", self.type_ident(ty))
+            }
+            AbiType::Option(ty) if nullable(ty) => self.value_type(ty, arg),
+            AbiType::Option(ty) => format!("*{}", self.value_type(ty, arg)),
+            AbiType::Result(ty) if arg => format!("FfiResult[{}]", self.value_type(ty, arg)),
+            AbiType::Result(ty) => self.value_type(ty, arg),
+            AbiType::Tuple(tys) => match tys.len() {
+                1 => self.value_type(&tys[0], arg),
+                _ => format!(
+                    "struct{{{}}}",
+                    tys.iter()
+                        .enumerate()
+                        .map(|(i, ty)| format!(" V{} {}", i, self.value_type(ty, arg)))
+                        .collect::<Vec<_>>()
+                        .join(";")
+                        + if tys.is_empty() { "" } else { " " }
+                ),
+            },
+            AbiType::Iter(ty)
+            | AbiType::RefIter(ty)
+            | AbiType::Future(ty)
+            | AbiType::RefFuture(ty)
+            | AbiType::Stream(ty)
+            | AbiType::RefStream(ty) => format!("<-chan {}", self.elem_type(ty)),
+            AbiType::Buffer(ty) | AbiType::RefBuffer(ty) => {
+                format!("*{}", ffi_buffer_name_for(*ty))
+            }
+            AbiType::List(ty) => format!("*FfiList{}", ty),
+        }
+    }
+
+    fn ffi_type(&self, var: &Var) -> &'static str {
+        match &var.ty {
+            AbiType::Num(num) => self.num_type(*num),
+            ty => unreachable!("{:?} is not an ffi type", ty),
+        }
+    }
+
+    fn c_type(&self, var: &Var) -> &'static str {
+        match &var.ty {
+            AbiType::Num(num) => match num {
+                NumType::U8 => "uint8_t",
+                NumType::U16 => "uint16_t",
+                NumType::U32 => "uint32_t",
+                NumType::U64 => "uint64_t",
+                NumType::I8 => "int8_t",
+                NumType::I16 => "int16_t",
+                NumType::I32 => "int32_t",
+                NumType::I64 => "int64_t",
+                NumType::F32 => "float",
+                NumType::F64 => "double",
+                NumType::IPtr => "intptr_t",
+                NumType::UPtr => "uintptr_t",
+            },
+            ty => unreachable!("{:?} is not an ffi type", ty),
+        }
+    }
+
+    fn num_type(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::U8 => "uint8",
+            NumType::U16 => "uint16",
+            NumType::U32 => "uint32",
+            NumType::U64 => "uint64",
+            NumType::I8 => "int8",
+            NumType::I16 => "int16",
+            NumType::I32 => "int32",
+            NumType::I64 => "int64",
+            NumType::F32 => "float32",
+            NumType::F64 => "float64",
+            NumType::IPtr => "int",
+            NumType::UPtr => "uint",
+        }
+    }
+
+    fn generate_doc(&self, doc: &[String]) -> go::Tokens {
+        quote! {
+            $(for line in doc => $(format!("// {}", line).trim_end().to_string())$['\r'])
+        }
+    }
+
+    fn var(&self, var: &Var) -> String {
+        format!("tmp{}", var.binding)
+    }
+
+    fn type_ident(&self, s: &str) -> String {
+        sanitize_identifier(s)
+    }
+
+    fn ident(&self, s: &str) -> String {
+        sanitize_identifier(&s.to_lower_camel_case())
+    }
+}
+
+/// Whether a value of type `ty` can already be `nil`, so an option of it
+/// doesn't need to be a pointer.
+fn nullable(ty: &AbiType) -> bool {
+    matches!(
+        ty,
+        AbiType::Object(_)
+            | AbiType::RefObject(_)
+            | AbiType::RefEnum(_)
+            | AbiType::Iter(_)
+            | AbiType::RefIter(_)
+            | AbiType::Future(_)
+            | AbiType::RefFuture(_)
+            | AbiType::Stream(_)
+            | AbiType::RefStream(_)
+            | AbiType::Buffer(_)
+            | AbiType::RefBuffer(_)
+            | AbiType::List(_)
+    )
+}
+
+fn strip_result(ty: &AbiType) -> &AbiType {
+    match ty {
+        AbiType::Result(ty) => ty,
+        ty => ty,
+    }
+}
+
+/// Whether a value of type `ty` is borrowed by rust for the duration of a
+/// call.
+fn borrows(ty: &AbiType) -> bool {
+    match ty {
+        AbiType::RefObject(_) | AbiType::RefSliceObject(_) | AbiType::RefBuffer(_) => true,
+        AbiType::Option(ty) | AbiType::Result(ty) => borrows(ty),
+        _ => false,
+    }
+}
+
+/// Name of the wrapper around the `next` or `poll` function `symbol`.
+fn wrapper_name(symbol: &str) -> String {
+    format!("wrap_{}", symbol.trim_start_matches('_'))
+}
+
+/// Entry name and object of every enum entry wrapping an object.
+fn inner_objects(e: &Enum) -> Vec<(String, String)> {
+    e.entries
+        .iter()
+        .filter_map(|entry| match &entry.inner {
+            Some(Type::Ident(name)) => Some((entry.name.clone(), name.clone())),
+            Some(_) => unimplemented!("Enums can only wrap objects"),
+            None => None,
+        })
+        .collect()
+}
+
+/// Collects the variables defined inside of `LowerOption` and `LowerResult`.
+fn nested_defines(instr: &Instr, vars: &mut Vec<Var>) {
+    fn collect(instrs: &[Instr], vars: &mut Vec<Var>) {
+        for instr in instrs {
+            if let Instr::DefineArgs(defines) = instr {
+                vars.extend(defines.iter().cloned());
+            }
+            nested_defines(instr, vars);
+        }
+    }
+    match instr {
+        Instr::LowerOption(_, _, _, some) => collect(some, vars),
+        Instr::LowerResult(_, _, _, ok, _, err) => {
+            collect(ok, vars);
+            collect(err, vars);
+        }
+        _ => {}
+    }
+}
+
+fn sanitize_identifier(id: &str) -> String {
+    if RESERVED_IDENTIFIERS.contains(&id) {
+        format!("{}_", id)
+    } else {
+        id.to_string()
+    }
+}
+
+// https://go.dev/ref/spec#Keywords, and the builtins and packages used by the
+// generated code.
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 37] = [
+    "C",
+    "append",
+    "break",
+    "case",
+    "chan",
+    "close",
+    "const",
+    "continue",
+    "copy",
+    "default",
+    "defer",
+    "else",
+    "fallthrough",
+    "for",
+    "func",
+    "go",
+    "goto",
+    "if",
+    "import",
+    "interface",
+    "len",
+    "make",
+    "map",
+    "new",
+    "package",
+    "panic",
+    "range",
+    "return",
+    "runtime",
+    "select",
+    "self",
+    "struct",
+    "switch",
+    "sync",
+    "type",
+    "unsafe",
+    "var",
+];
+
+#[cfg(feature = "test_runner")]
+#[doc(hidden)]
+pub mod test_runner {
+    use super::*;
+    use crate::RustGenerator;
+    use anyhow::Result;
+    use std::io::Write;
+    use tempfile::{NamedTempFile, TempDir};
+    use trybuild::TestCases;
+
+    pub fn compile_pass(iface: &str, rust: rust::Tokens, go: go::Tokens) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let (mut rust_file, rust_file_path) = NamedTempFile::new()?.keep()?;
+        writeln!(rust_file, "#![feature(vec_into_raw_parts)]")?;
+        writeln!(rust_file, "#![feature(lazy_cell)]")?;
+        let rust_gen = RustGenerator::new(Abi::native());
+        let rust_tokens = rust_gen.generate(iface.clone());
+        let header = CGenerator::new().generate(iface.clone()).to_file_string()?;
+        let go_gen = GoGenerator::new(
+            "api".to_string(),
+            "api.h".to_string(),
+            "compile_pass".to_string(),
+        );
+        let package = go_gen.generate(iface).to_file_string()?;
+
+        let library_tokens: genco::Tokens<genco::lang::Rust> = quote! {
+            $rust_tokens
+            $rust
+        };
+
+        let main_tokens: go::Tokens = quote! {
+            package main
+
+            import "compile_pass/api"
+
+            func check(condition bool) {
+                if !condition {
+                    panic("check failed")
+                }
+            }
+
+            func equal[T comparable](a []T, b []T) bool {
+                if len(a) != len(b) {
+                    return false
+                }
+                for i := range a {
+                    if a[i] != b[i] {
+                        return false
+                    }
+                }
+                return true
+            }
+
+            func main() {
+                $go
+            }
+        };
+
+        let library_dir = TempDir::new()?.keep();
+        let library_file = library_dir.join("libcompile_pass.so");
+        let package_dir = library_dir.join("api");
+        std::fs::create_dir(&package_dir)?;
+        std::fs::write(
+            library_dir.join("go.mod"),
+            "module compile_pass\n\ngo 1.21\n",
+        )?;
+        std::fs::write(package_dir.join("api.h"), header)?;
+        std::fs::write(package_dir.join("api.go"), package)?;
+        std::fs::write(library_dir.join("main.go"), main_tokens.to_file_string()?)?;
+
+        let library = library_tokens.to_file_string()?;
+        rust_file.write_all(library.as_bytes())?;
+
+        let library_path = library_dir.as_path().to_str().unwrap();
+        let runner_tokens: rust::Tokens = quote! {
+            fn main() {
+                use std::process::Command;
+                let ret = Command::new("rustc")
+                    .arg("--edition")
+                    .arg("2021")
+                    .arg("--crate-name")
+                    .arg("compile_pass")
+                    .arg("--crate-type")
+                    .arg("cdylib")
+                    .arg("--cfg")
+                    .arg("feature=\"test_runner\"")
+                    .arg("-o")
+                    .arg($(quoted(library_file.as_path().to_str().unwrap())))
+                    .arg($(quoted(rust_file_path.as_path().to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+                let ret = Command::new("go")
+                    .current_dir($(quoted(library_path)))
+                    .env("CGO_ENABLED", "1")
+                    .env("CGO_LDFLAGS", $(quoted(format!("-L{}", library_path))))
+                    .env("LD_LIBRARY_PATH", $(quoted(library_path)))
+                    .arg("run")
+                    .arg(".")
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+            }
+        };
+        let (mut runner_file, runner_file_path) = NamedTempFile::new()?.keep()?;
+        let runner = runner_tokens.to_file_string()?;
+        runner_file.write_all(runner.as_bytes())?;
+
+        let test = TestCases::new();
+        test.pass(runner_file_path.as_path().to_str().unwrap());
+        Ok(())
+    }
+}
@@ -1,33 +1,38 @@
+use crate::dart::ffi_buffer_name_for;
 use crate::import::Instr;
-use crate::{Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Var};
-use anyhow::Result;
+use crate::wasm::{self, MultiValueExport, ValType};
+use crate::{
+    Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Var, NUM_TYPES,
+};
+use anyhow::{Context, Result};
 use genco::prelude::*;
 use heck::*;
 use std::path::Path;
-use std::process::Command;
 
 pub struct JsGenerator {
     abi: Abi,
+    napi: bool,
 }
 
 impl Default for JsGenerator {
     fn default() -> Self {
-        Self { abi: Abi::Wasm32 }
+        Self::new(Abi::Wasm32)
     }
 }
 
 pub struct TsGenerator {
+    abi: Abi,
     docs: bool,
 }
 
 impl Default for TsGenerator {
     fn default() -> Self {
-        Self { docs: true }
+        Self::new(Abi::Wasm32)
     }
 }
 
 // https://developer.mozilla.org/en-US/docs/Web/JavaScript/Reference/Lexical_grammar#keywords
-static RESERVED_IDENTIFIERS: [&str; 64] = [
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 64] = [
     "abstract",
     "arguments",
     "await",
@@ -94,6 +99,55 @@ static RESERVED_IDENTIFIERS: [&str; 64] = [
     "yield",
 ];
 
+// Type names that can't be used for classes in the typescript definitions, either because
+// they are predefined types or because the generated definitions refer to them.
+pub(crate) static TS_RESERVED_TYPE_IDENTIFIERS: [&str; 17] = [
+    "any",
+    "bigint",
+    "boolean",
+    "never",
+    "number",
+    "object",
+    "string",
+    "symbol",
+    "undefined",
+    "unknown",
+    "void",
+    "Api",
+    "Array",
+    "BigInt",
+    "Iterable",
+    "Promise",
+    "ReadableStream",
+];
+
+fn typed_array(abi: Abi, ty: NumType) -> js::Tokens {
+    match abi.fixed_size(ty) {
+        NumType::U8 => quote!(Uint8Array),
+        NumType::U16 => quote!(Uint16Array),
+        NumType::U32 => quote!(Uint32Array),
+        NumType::U64 => quote!(BigUint64Array),
+        NumType::I8 => quote!(Int8Array),
+        NumType::I16 => quote!(Int16Array),
+        NumType::I32 => quote!(Int32Array),
+        NumType::I64 => quote!(BigInt64Array),
+        NumType::F32 => quote!(Float32Array),
+        NumType::F64 => quote!(Float64Array),
+        NumType::IPtr | NumType::UPtr => unreachable!(),
+    }
+}
+
+/// Returns the `DataView` getter reading a value the same way wasm passes it
+/// to js.
+fn data_view_getter(abi: Abi, ty: NumType) -> &'static str {
+    match abi.fixed_size(ty) {
+        NumType::F32 => "getFloat32",
+        NumType::F64 => "getFloat64",
+        NumType::U64 | NumType::I64 => "getBigInt64",
+        _ => "getInt32",
+    }
+}
+
 fn sanitize_identifier(id: &str) -> String {
     if RESERVED_IDENTIFIERS.contains(&id) {
         format!("_{}", id)
@@ -103,6 +157,10 @@ fn sanitize_identifier(id: &str) -> String {
 }
 
 impl TsGenerator {
+    pub fn new(abi: Abi) -> Self {
+        Self { abi, docs: true }
+    }
+
     fn gen_doc(
         &self,
         items: impl IntoIterator<Item = impl Into<genco::tokens::ItemStr>>,
@@ -123,6 +181,22 @@ impl TsGenerator {
         })
     }
     pub fn generate(&self, iface: Interface) -> js::Tokens {
+        let iface = iface.renamed();
+        let buffers = iface.buffers();
+        let results = if iface.has_result_args() {
+            quote! {
+                $(self.gen_doc(&["Result passed as an argument to rust."]))
+                export type FfiResult<T> = { ok: true, value: T } | { ok: false, error: string };
+
+                $(self.gen_doc(&["Constructs a successful result."]))
+                export function ok<T>(value: T): FfiResult<T>;
+
+                $(self.gen_doc(&["Constructs a failed result from an error or message."]))
+                export function err<T>(error: Error | string): FfiResult<T>;
+            }
+        } else {
+            quote!()
+        };
         quote! {
             $("// AUTO GENERATED FILE, DO NOT EDIT.")
             $("//")
@@ -130,6 +204,10 @@ impl TsGenerator {
             $("/* tslint:disable */")
             $("/* eslint:disable */")
 
+            $results
+
+            $(for ty in buffers join ($['\n']) => $(self.generate_buffer(ty)))
+
             $(self.gen_doc(&["Main entry point to the library."]))
             export class Api {
               $(self.gen_doc(&["API constructor.","","@returns an `Api` instance."]))
@@ -141,12 +219,28 @@ impl TsGenerator {
               $(for func in iface.functions() join ($['\n']$['\n']) => $(self.generate_function(func)))
             }
 
-            $(for obj in iface.objects() => $(self.generate_object(obj)))
+            $(for obj in iface.objects() join ($['\n']) => $(self.generate_object(obj)))
+        }
+    }
+
+    fn generate_buffer(&self, ty: NumType) -> js::Tokens {
+        let name = ffi_buffer_name_for(ty);
+        quote! {
+            $(self.gen_doc(&["Buffer in wasm memory that can be passed to rust without copying."]))
+            export class $name {
+                $(self.gen_doc(&["Allocates a zeroed buffer of `length` elements."]))
+                static allocate(api: Api, length: number): $name;
+
+                $(self.gen_doc(&["Returns a view of the buffer, which is invalidated when the buffer is dropped."]))
+                asTypedArray(): $(typed_array(self.abi, ty));
+
+                drop(): void;
+            }
         }
     }
 
     fn generate_function(&self, func: AbiFunction) -> js::Tokens {
-        let ffi = Abi::Wasm32.import(&func);
+        let ffi = self.abi.import(&func);
         let args = self.generate_args(&ffi.abi_args);
         let ret = self.generate_return_type(ffi.abi_ret.as_ref());
         let name = self.ident(&func.name);
@@ -168,13 +262,21 @@ impl TsGenerator {
         let len = abi_args.len();
         let args = quote!($(for (idx, (name, ty)) in abi_args.iter().enumerate() join (, ) =>
             $(match ty {
-                AbiType::Option(inner) if idx < len - 1 => $(self.ident(name)): $(self.generate_return_type(Some(inner))) $("| null"),
-                _ => $(self.ident(name)): $(self.generate_return_type(Some(ty)))
+                AbiType::Option(inner) if idx < len - 1 => $(self.ident(name)): $(self.generate_arg_type(inner)) $("| null"),
+                _ => $(self.ident(name)): $(self.generate_arg_type(ty))
             })
         ));
         args
     }
 
+    fn generate_arg_type(&self, ty: &AbiType) -> js::Tokens {
+        match ty {
+            AbiType::Option(inner) => quote!($(self.generate_arg_type(inner))?),
+            AbiType::Result(inner) => quote!(FfiResult<$(self.generate_arg_type(inner))>),
+            _ => self.generate_return_type(Some(ty)),
+        }
+    }
+
     fn generate_return_type(&self, ret: Option<&AbiType>) -> js::Tokens {
         if let Some(ret) = ret {
             match ret {
@@ -186,14 +288,17 @@ impl TsGenerator {
                     | NumType::I16
                     | NumType::I32
                     | NumType::F32
-                    | NumType::F64 => quote!(number),
+                    | NumType::F64
+                    | NumType::IPtr
+                    | NumType::UPtr => quote!(number),
                     NumType::U64 | NumType::I64 => quote!(BigInt),
-                    NumType::IPtr => todo!(),
-                    NumType::UPtr => todo!(),
                 },
                 AbiType::Isize | AbiType::Usize => quote!(number),
                 AbiType::Bool => quote!(boolean),
                 AbiType::RefStr | AbiType::String => quote!(string),
+                AbiType::RefMutSlice(prim) => typed_array(self.abi, *prim),
+                AbiType::RefSliceStr => quote!(Array<string>),
+                AbiType::RefSliceObject(i) => quote!(Array<$(self.type_ident(i))>),
                 AbiType::RefSlice(prim) | AbiType::Vec(prim) => {
                     let inner = self.generate_return_type(Some(&AbiType::Num(*prim)));
                     quote!(Array<$inner>)
@@ -225,7 +330,7 @@ impl TsGenerator {
                         quote!([$(for ty in tys join (, ) => $(self.generate_return_type(Some(ty))))])
                     }
                 },
-                AbiType::Buffer(_) => unimplemented!("\"FfiBuffer\" type for javascript"),
+                AbiType::Buffer(ty) | AbiType::RefBuffer(ty) => quote!($(ffi_buffer_name_for(*ty))),
                 AbiType::List(_) => unimplemented!("\"FfiList\" type for javascript"),
                 AbiType::RefEnum(_) => unimplemented!("enums type for javascript"),
             }
@@ -254,7 +359,22 @@ impl TsGenerator {
 }
 
 impl JsGenerator {
+    pub fn new(abi: Abi) -> Self {
+        Self { abi, napi: false }
+    }
+
+    /// Generates bindings loading the native cdylib as a Node-API addon
+    /// instead of instantiating a wasm module.
+    pub fn napi() -> Self {
+        Self {
+            abi: Abi::native(),
+            napi: true,
+        }
+    }
+
     pub fn generate(&self, iface: Interface) -> js::Tokens {
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
         quote! {
             $("// AUTO GENERATED FILE, DO NOT EDIT.")
             $("//")
@@ -262,74 +382,16 @@ impl JsGenerator {
             $("/* tslint:disable */")
             $("/* eslint:disable */")
 
-            // a node fetch polyfill that won't trigger webpack or other bundlers
-            // idea borrowed from:
-            // https://github.com/dcodeIO/webassembly/blob/master/src/index.js#L223
-            let fs;
-            const fetch_polyfill = async (file) => {
-                const readFile = await eval("mport('fs')".replace(/^/, 'i'))
-                    .then(({ readFile }) => readFile);
-                return new Promise((resolve, reject) => {
-                    readFile(
-                        file,
-                        function(err, data) {
-                            return (err)
-                                ? reject(err)
-                                : resolve({
-                                    arrayBuffer: () => Promise.resolve(data),
-                                    ok: true,
-                                });
-                        }
-                        );
-                });
-            }
-
-            let ReadableStream;
-            if (typeof window == "object") {
-                ReadableStream = window.ReadableStream;
-                $("// Workaround for combined use with `wasm-bindgen`, so we don't have to")
-                $("// patch the `importObject` while loading the WASM module.")
-                window.__notifier_callback = (idx) => notifierRegistry.callbacks[idx]();
-            } else {
-                eval("mport('node:stream/web')".replace(/^/, 'i')).then(pkg => {
-                    ReadableStream = pkg.ReadableStream;
-                });
-                $("// Workaround for combined use with `wasm-bindgen`, so we don't have to")
-                $("// patch the `importObject` while loading the WASM module.")
-                global.__notifier_callback = (idx) => notifierRegistry.callbacks[idx]();
-            };
-
-            const fetchFn = (typeof fetch === "function" && fetch) || fetch_polyfill;
-
-            // gets the wasm at a url and instantiates it.
-            // checks if streaming instantiation is available and uses that
-            function fetchAndInstantiate(url, imports) {
-                const env = imports.env || {};
-                env.__notifier_callback = (idx) => notifierRegistry.callbacks[idx]();
-                imports.env = env;
-                return fetchFn(url)
-                    .then((resp) => {
-                        if (!resp.ok) {
-                            throw new Error("Got a ${resp.status} fetching wasm @ ${url}");
-                        }
-
-                        const wasm = "application/wasm";
-                        const type = resp.headers && resp.headers.get("content-type");
-
-                        return (WebAssembly.instantiateStreaming && type === wasm)
-                            ? WebAssembly.instantiateStreaming(resp, imports)
-                            : resp.arrayBuffer().then(buf => WebAssembly.instantiate(buf, imports));
-                        })
-                        .then(result => result.instance);
-            }
+            $(self.generate_loader())
 
             const dropRegistry = new FinalizationRegistry(drop => drop());
 
             class Box {
-                constructor(ptr, destructor) {
+                constructor(ptr, destructor, parent) {
                     this.ptr = ptr;
                     this.dropped = false;
                     this.moved = false;
+                    this.parent = parent;
                     dropRegistry.register(this, destructor, this);
                     this.destructor = destructor;
                 }
@@ -341,6 +403,11 @@ impl JsGenerator {
                     if (this.moved) {
                         throw new Error("use after move");
                     }
+                    for (let parent = this.parent; parent; parent = parent.parent) {
+                        if (parent.dropped || parent.moved) {
+                            throw new Error("use of borrowed value after its owner was dropped");
+                        }
+                    }
                     return this.ptr;
                 }
 
@@ -351,6 +418,9 @@ impl JsGenerator {
                     if (this.moved) {
                         throw new Error("can't move value twice");
                     }
+                    if (this.parent) {
+                        throw new Error("can't move borrowed value");
+                    }
                     this.moved = true;
                     dropRegistry.unregister(this);
                     return this.ptr;
@@ -369,12 +439,64 @@ impl JsGenerator {
                 }
             }
 
+            class FfiBuffer {
+                constructor(api, box) {
+                    this.api = api;
+                    this.box = box;
+                }
+
+                static allocate(api, length) {
+                    const bytes = this.array.BYTES_PER_ELEMENT;
+                    const ptr = $(self.lift_ptr(quote!(api.instance.exports.$(symbols.ffi_buffer("new"))($(self.lower_ptr(quote!(length * bytes))), $(self.lower_ptr(quote!(bytes)))))));
+                    const box = new Box(ptr, () => { api.drop($(quoted(symbols.destructor("FfiBuffer"))), ptr); });
+                    return new this(api, box);
+                }
+
+                asTypedArray() {
+                    const ptr = this.box.borrow();
+                    const exports = this.api.instance.exports;
+                    const addr = $(self.lift_ptr(quote!(exports.$(symbols.ffi_buffer("address"))($(self.lower_ptr(quote!(ptr)))))));
+                    const size = $(self.lift_ptr(quote!(exports.$(symbols.ffi_buffer("size"))($(self.lower_ptr(quote!(ptr)))))));
+                    const array = this.constructor.array;
+                    $(if self.napi {
+                        return new array(exports.__napi_memory(addr, size));
+                    } else {
+                        return new array(exports.memory.buffer, addr, size / array.BYTES_PER_ELEMENT);
+                    })
+                }
+
+                drop() {
+                    this.box.drop();
+                }
+            }
+
+            $(for ty in NUM_TYPES => $(self.generate_buffer(ty)))
+
             class NotifierRegistry {
                 constructor() {
                     this.counter = 0;
                     this.callbacks = {};
+                    $(if self.napi {
+                        this.pending = 0;
+                        this.addons = [];
+                    })
                 }
 
+                $(if self.napi {
+                    addAddon(exports) {
+                        exports.__napi_set_notifier((idx) => {
+                            const notifier = this.callbacks[idx];
+                            if (notifier) {
+                                notifier();
+                            }
+                        });
+                        if (this.pending > 0) {
+                            exports.__napi_ref();
+                        }
+                        this.addons.push(exports);
+                    }
+                })
+
                 reserveSlot() {
                     const idx = this.counter;
                     this.counter += 1;
@@ -382,10 +504,27 @@ impl JsGenerator {
                 }
 
                 registerNotifier(idx, notifier) {
+                    $(if self.napi {
+                        // keeps node alive until the notifier is called
+                        if (!(idx in this.callbacks)) {
+                            this.pending += 1;
+                            if (this.pending === 1) {
+                                this.addons.forEach((addon) => addon.__napi_ref());
+                            }
+                        }
+                    })
                     this.callbacks[idx] = notifier;
                 }
 
                 unregisterNotifier(idx) {
+                    $(if self.napi {
+                        if (idx in this.callbacks) {
+                            this.pending -= 1;
+                            if (this.pending === 0) {
+                                this.addons.forEach((addon) => addon.__napi_unref());
+                            }
+                        }
+                    })
                     delete this.callbacks[idx];
                 }
             }
@@ -453,25 +592,46 @@ impl JsGenerator {
                 });
             };
 
+            $(if iface.has_result_args() {
+                export const ok = (value) => ({ ok: true, value });
+
+                export const err = (error) => ({
+                    ok: false,
+                    error: error instanceof Error ? error.message : String(error),
+                });
+            })
+
             export class Api {
-                async fetch(url, imports) {
-                    this.instance = await fetchAndInstantiate(url, imports);
-                }
+                $(if self.napi {
+                    async fetch(url, imports) {
+                        await streamsLoaded;
+                        this.initWithInstance(loadAddon(url));
+                    }
 
-                initWithInstance(instance) {
-                    this.instance = instance;
-                }
+                    initWithInstance(instance) {
+                        this.instance = instance;
+                        notifierRegistry.addAddon(instance.exports);
+                    }
+                } else {
+                    async fetch(url, imports) {
+                        this.instance = await fetchAndInstantiate(url, imports);
+                    }
+
+                    initWithInstance(instance) {
+                        this.instance = instance;
+                    }
+                })
 
                 allocate(size, align) {
-                    return this.instance.exports.allocate(size, align);
+                    return $(self.lift_ptr(quote!(this.instance.exports.$(symbols.allocate())($(self.lower_ptr(quote!(size))), $(self.lower_ptr(quote!(align)))))));
                 }
 
                 deallocate(ptr, size, align) {
-                    this.instance.exports.deallocate(ptr, size, align);
+                    this.instance.exports.$(symbols.deallocate())($(self.lower_ptr(quote!(ptr))), $(self.lower_ptr(quote!(size))), $(self.lower_ptr(quote!(align))));
                 }
 
                 drop(symbol, ptr) {
-                    this.instance.exports[symbol](0, ptr);
+                    this.instance.exports[symbol]($(self.lower_ptr(quote!(0))), $(self.lower_ptr(quote!(ptr))));
                 }
 
                 $(for func in iface.functions() => $(self.generate_function(&func)))
@@ -486,6 +646,86 @@ impl JsGenerator {
         }
     }
 
+    fn generate_loader(&self) -> js::Tokens {
+        if self.napi {
+            return quote! {
+                let ReadableStream;
+                const streamsLoaded = eval("mport('node:stream/web')".replace(/^/, 'i')).then(pkg => {
+                    ReadableStream = pkg.ReadableStream;
+                });
+
+                // loads the native library at a path as a node addon.
+                function loadAddon(path) {
+                    const module = { exports: {} };
+                    process.dlopen(module, path);
+                    return { exports: module.exports };
+                }
+            };
+        }
+        quote! {
+        // a node fetch polyfill that won't trigger webpack or other bundlers
+        // idea borrowed from:
+        // https://github.com/dcodeIO/webassembly/blob/master/src/index.js#L223
+        let fs;
+        const fetch_polyfill = async (file) => {
+            const readFile = await eval("mport('fs')".replace(/^/, 'i'))
+                .then(({ readFile }) => readFile);
+            return new Promise((resolve, reject) => {
+                readFile(
+                    file,
+                    function(err, data) {
+                        return (err)
+                            ? reject(err)
+                            : resolve({
+                                arrayBuffer: () => Promise.resolve(data),
+                                ok: true,
+                            });
+                    }
+                    );
+            });
+        }
+
+        let ReadableStream;
+        if (typeof window == "object") {
+            ReadableStream = window.ReadableStream;
+            $("// Workaround for combined use with `wasm-bindgen`, so we don't have to")
+            $("// patch the `importObject` while loading the WASM module.")
+            window.__notifier_callback = (idx) => notifierRegistry.callbacks[idx]();
+        } else {
+            eval("mport('node:stream/web')".replace(/^/, 'i')).then(pkg => {
+                ReadableStream = pkg.ReadableStream;
+            });
+            $("// Workaround for combined use with `wasm-bindgen`, so we don't have to")
+            $("// patch the `importObject` while loading the WASM module.")
+            global.__notifier_callback = (idx) => notifierRegistry.callbacks[idx]();
+        };
+
+        const fetchFn = (typeof fetch === "function" && fetch) || fetch_polyfill;
+
+        // gets the wasm at a url and instantiates it.
+        // checks if streaming instantiation is available and uses that
+        function fetchAndInstantiate(url, imports) {
+            const env = imports.env || {};
+            env.__notifier_callback = (idx) => notifierRegistry.callbacks[idx]();
+            imports.env = env;
+            return fetchFn(url)
+                .then((resp) => {
+                    if (!resp.ok) {
+                        throw new Error("Got a ${resp.status} fetching wasm @ ${url}");
+                    }
+
+                    const wasm = "application/wasm";
+                    const type = resp.headers && resp.headers.get("content-type");
+
+                    return (WebAssembly.instantiateStreaming && type === wasm)
+                        ? WebAssembly.instantiateStreaming(resp, imports)
+                        : resp.arrayBuffer().then(buf => WebAssembly.instantiate(buf, imports));
+                    })
+                    .then(result => result.instance);
+        }
+        }
+    }
+
     fn generate_object(&self, obj: AbiObject) -> js::Tokens {
         quote! {
             export class $(self.type_ident(&obj.name)) {
@@ -557,13 +797,19 @@ impl JsGenerator {
                 const $(self.var(box_))_1 = new Box($(self.var(box_)), $(self.var(box_))_0);
                 const $(self.var(out)) = new $obj($api, $(self.var(box_))_1);
             },
+            Instr::LiftRefObject(obj, box_, _, out) => quote! {
+                const $(self.var(box_))_0 = new Box($(self.var(box_)), () => {}, this.box);
+                const $(self.var(out)) = new $obj($api, $(self.var(box_))_0);
+            },
             Instr::BindArg(arg, out) => quote!(const $(self.var(out)) = $(self.ident(arg));),
             Instr::BindRets(ret, vars) => match vars.len() {
                 0 => quote!(),
-                1 => quote!(const $(self.var(&vars[0])) = $(self.var(ret));),
+                1 => {
+                    quote!(const $(self.var(&vars[0])) = $(self.lift_num(&vars[0], self.var(ret)));)
+                }
                 _ => quote! {
                     $(for (idx, var) in vars.iter().enumerate() =>
-                        const $(self.var(var)) = $(self.var(ret))[$(idx)];)
+                        const $(self.var(var)) = $(self.lift_num(var, quote!($(self.var(ret))[$(idx)])));)
                 },
             },
             Instr::LiftNumFromU32Tuple(low, high, out, num_type) => {
@@ -617,40 +863,118 @@ impl JsGenerator {
                 $(self.var(len)) = $(self.var(in_))_1.length;
                 $(self.var(ptr)) = $api.allocate($(self.var(len)) * $(*size), $(*align));
                 const $(self.var(ptr))_0 =
-                    new Uint8Array($api.instance.exports.memory.buffer, $(self.var(ptr)), $(self.var(len)));
+                    $(self.memory(api, quote!(Uint8Array), self.var(ptr), self.var(len)));
                 $(self.var(ptr))_0.set($(self.var(in_))_1, 0);
                 $(self.var(cap)) = $(self.var(len));
             },
             Instr::LiftString(ptr, len, out) => quote! {
                 const $(self.var(out))_0 =
-                    new Uint8Array($api.instance.exports.memory.buffer, $(self.var(ptr)), $(self.var(len)));
+                    $(self.memory(api, quote!(Uint8Array), self.var(ptr), self.var(len)));
                 const $(self.var(out))_1 = new TextDecoder();
                 const $(self.var(out)) = $(self.var(out))_1.decode($(self.var(out))_0);
             },
-            Instr::LowerVec(in_, ptr, len, cap, ty, size, align) => quote! {
+            Instr::LowerVec(in_, ptr, len, cap, ty, size, align) => {
+                let elems = if self.is_bigint_ptr(*ty) {
+                    quote!($(self.var(in_)).map(BigInt))
+                } else {
+                    self.var(in_)
+                };
+                quote! {
+                    $(self.var(len)) = $(self.var(in_)).length;
+                    $(self.var(ptr)) = $api.allocate($(self.var(len)) * $(*size), $(*align));
+                    const $(self.var(ptr))_0 =
+                        $(self.memory(api, typed_array(self.abi, *ty), self.var(ptr), self.var(len)));
+                    $(self.var(ptr))_0.set($elems, 0);
+                    $(self.var(cap)) = $(self.var(len));
+                }
+            }
+            Instr::LowerStrSlice(in_, ptr, len, cap) => quote! {
+                const $(self.var(in_))_0 = new TextEncoder();
+                const $(self.var(in_))_1 = $(self.var(in_)).map((s) => $(self.var(in_))_0.encode(s));
+                $(self.var(len)) = $(self.var(in_))_1.length;
+                $(self.var(cap)) = $(self.var(in_))_1.reduce((n, s) => n + s.length, $(self.var(len)) * 4);
+                $(self.var(ptr)) = $api.allocate($(self.var(cap)), 4);
+                const $(self.var(ptr))_0 =
+                    $(self.memory(api, quote!(Uint32Array), self.var(ptr), self.var(len)));
+                const $(self.var(ptr))_1 =
+                    $(self.memory(api, quote!(Uint8Array), self.var(ptr), self.var(cap)));
+                let $(self.var(ptr))_2 = $(self.var(len)) * 4;
+                $(self.var(in_))_1.forEach((s, i) => {
+                    $(self.var(ptr))_0[i] = s.length;
+                    $(self.var(ptr))_1.set(s, $(self.var(ptr))_2);
+                    $(self.var(ptr))_2 += s.length;
+                });
+            },
+            Instr::LowerObjectSlice(in_, ptr, len, cap, size, align) => quote! {
                 $(self.var(len)) = $(self.var(in_)).length;
                 $(self.var(ptr)) = $api.allocate($(self.var(len)) * $(*size), $(*align));
                 const $(self.var(ptr))_0 =
-                    new $(self.generate_array(*ty))(
-                        $api.instance.exports.memory.buffer, $(self.var(ptr)), $(self.var(len)));
-                $(self.var(ptr))_0.set($(self.var(in_)), 0);
+                    $(self.memory(api, typed_array(self.abi, NumType::IPtr), self.var(ptr), self.var(len)));
+                $(self.var(in_)).forEach((obj, i) => {
+                    $(self.var(ptr))_0[i] = $(self.lower_ptr(quote!(obj.box.borrow())));
+                });
                 $(self.var(cap)) = $(self.var(len));
             },
-            Instr::LiftVec(ptr, len, out, ty) => quote! {
-                const $(self.var(out))_0 =
-                    new $(self.generate_array(*ty))(
-                        $api.instance.exports.memory.buffer, $(self.var(ptr)), $(self.var(len)));
-                const $(self.var(out)) = Array.from($(self.var(out))_0);
+            Instr::LowerMutSlice(in_, ptr, len, cap, ty, size, align) => {
+                let copy = quote! {
+                    $(self.var(ptr)) = $api.allocate($(self.var(len)) * $(*size), $(*align));
+                    const $(self.var(ptr))_0 =
+                        $(self.memory(api, typed_array(self.abi, *ty), self.var(ptr), self.var(len)));
+                    $(self.var(ptr))_0.set($(self.var(in_)), 0);
+                    $(self.var(cap)) = $(self.var(len));
+                };
+                // views into the native memory can't be told apart from other
+                // arrays, so node addons always copy
+                if self.napi {
+                    return quote! {
+                        $(self.var(len)) = $(self.var(in_)).length;
+                        $copy
+                    };
+                }
+                quote! {
+                    $(self.var(len)) = $(self.var(in_)).length;
+                    if ($(self.var(in_)).buffer === $api.instance.exports.memory.buffer) {
+                        $(self.var(ptr)) = $(self.var(in_)).byteOffset;
+                    } else {
+                        $copy
+                    }
+                }
+            }
+            Instr::WriteBack(in_, ptr, len, cap, ty) => quote! {
+                if ($(self.var(cap)) > 0) {
+                    $(self.var(in_)).set($(self.memory(api, typed_array(self.abi, *ty), self.var(ptr), self.var(len))));
+                }
             },
+            Instr::LiftVec(ptr, len, out, ty) => {
+                let map = if self.is_bigint_ptr(*ty) {
+                    quote!(, Number)
+                } else {
+                    quote!()
+                };
+                quote! {
+                    const $(self.var(out))_0 =
+                        $(self.memory(api, typed_array(self.abi, *ty), self.var(ptr), self.var(len)));
+                    const $(self.var(out)) = Array.from($(self.var(out))_0$map);
+                }
+            }
             Instr::Call(symbol, ret, args) => {
-                let invoke =
-                    quote!($api.instance.exports.$symbol($(for arg in args => $(self.var(arg)),)););
+                let invoke = quote! {
+                    $api.instance.exports.$symbol($(for arg in args => $(self.lower_num(arg)),));
+                };
                 if let Some(ret) = ret {
                     quote!(const $(self.var(ret)) = $invoke)
                 } else {
                     invoke
                 }
             }
+            Instr::CallRetArea(symbol, ret, args, fields, size, align) => quote! {
+                const $(self.var(ret)) = $api.allocate($(*size), $(*align));
+                $api.instance.exports.$symbol($(self.lower_ptr(self.var(ret))), $(for arg in args => $(self.lower_num(arg)),));
+                const $(self.var(ret))_0 = new DataView($api.instance.exports.memory.buffer);
+                $(for (var, offset) in fields =>
+                    const $(self.var(var)) = $(self.lift_num(var, quote!($(self.var(ret))_0.$(data_view_getter(self.abi, var.ty.num()))($(self.var(ret)) + $(*offset), true))));)
+                $api.deallocate($(self.var(ret)), $(*size), $(*align));
+            },
             Instr::DefineArgs(vars) => quote! {
                 $(for var in vars => let $(self.var(var)) = 0;)
             },
@@ -670,10 +994,21 @@ impl JsGenerator {
                     $(for inst in some_instr => $(self.generate_instr(api, inst)))
                 }
             },
+            Instr::LowerResult(arg, var, ok, ok_instr, err, err_instr) => quote! {
+                if ($(self.var(arg)).ok) {
+                    $(self.var(var)) = 1;
+                    const $(self.var(ok)) = $(self.var(arg)).value;
+                    $(for inst in ok_instr => $(self.generate_instr(api, inst)))
+                } else {
+                    $(self.var(var)) = 0;
+                    const $(self.var(err)) = $(self.var(arg)).error;
+                    $(for inst in err_instr => $(self.generate_instr(api, inst)))
+                }
+            },
             Instr::HandleError(var, ptr, len, cap) => quote! {
                 if ($(self.var(var)) === 0) {
                     const $(self.var(var))_0 =
-                        new Uint8Array($api.instance.exports.memory.buffer, $(self.var(ptr)), $(self.var(len)));
+                        $(self.memory(api, quote!(Uint8Array), self.var(ptr), self.var(len)));
                     const $(self.var(var))_1 = new TextDecoder();
                     const $(self.var(var))_2 = $(self.var(var))_1.decode($(self.var(var))_0);
                     if ($(self.var(len)) > 0) {
@@ -689,18 +1024,24 @@ impl JsGenerator {
                     return $api.$(self.ident(next))(a);
                 });
             },
+            Instr::LiftRefIter(box_, next, _, out) => quote! {
+                const $(self.var(box_))_0 = new Box($(self.var(box_)), () => {}, this.box);
+                const $(self.var(out)) = nativeIter($(self.var(box_))_0, (a) => {
+                    return $api.$(self.ident(next))(a);
+                });
+            },
             Instr::LiftFuture(box_, poll, drop, out) => quote! {
                 const $(self.var(box_))_0 = () => { $api.drop($[str]($[const](drop)), $(self.var(box_))); };
                 const $(self.var(box_))_1 = new Box($(self.var(box_)), $(self.var(box_))_0);
                 const $(self.var(out)) = nativeFuture($(self.var(box_))_1, (a, b, c) => {
-                    return $api.$(self.ident(poll))(a, b, c);
+                    return $api.$(self.ident(poll))(a, $(self.post_cobject(api)), c);
                 });
             },
             Instr::LiftStream(box_, poll, drop, out) => quote! {
                 const $(self.var(box_))_0 = () => { $api.drop($[str]($[const](drop)), $(self.var(box_))); };
                 const $(self.var(box_))_1 = new Box($(self.var(box_)), $(self.var(box_))_0);
                 const $(self.var(out)) = nativeStream($(self.var(box_))_1, (a, b, c, d) => {
-                    return $api.$(self.ident(poll))(a, b, c, d);
+                    return $api.$(self.ident(poll))(a, $(self.post_cobject(api)), c, d);
                 });
             },
             Instr::LiftTuple(vars, out) => match vars.len() {
@@ -718,20 +1059,76 @@ impl JsGenerator {
         quote!($(format!("tmp{}", var.binding)))
     }
 
-    fn generate_array(&self, ty: NumType) -> js::Tokens {
-        match ty {
-            NumType::U8 => quote!(Uint8Array),
-            NumType::U16 => quote!(Uint16Array),
-            NumType::U32 => quote!(Uint32Array),
-            NumType::U64 => quote!(BigUint64Array),
-            NumType::I8 => quote!(Int8Array),
-            NumType::I16 => quote!(Int16Array),
-            NumType::I32 => quote!(Int32Array),
-            NumType::I64 => quote!(BigInt64Array),
-            NumType::F32 => quote!(Float32Array),
-            NumType::F64 => quote!(Float64Array),
-            NumType::IPtr => todo!(),
-            NumType::UPtr => todo!(),
+    /// Views `len` elements at `ptr` in the wasm memory, or the native memory
+    /// of a node addon.
+    fn memory(
+        &self,
+        api: &js::Tokens,
+        array: js::Tokens,
+        ptr: js::Tokens,
+        len: js::Tokens,
+    ) -> js::Tokens {
+        if self.napi {
+            quote!(new $(&array)($api.instance.exports.__napi_memory($ptr, $len * $array.BYTES_PER_ELEMENT)))
+        } else {
+            quote!(new $array($api.instance.exports.memory.buffer, $ptr, $len))
+        }
+    }
+
+    /// Pointers are `i64` in wasm64 and on 64bit hosts, which javascript
+    /// represents as a `BigInt`. They are kept as numbers in javascript so that
+    /// they can be used to index the memory.
+    fn is_bigint_ptr(&self, ty: NumType) -> bool {
+        matches!(ty, NumType::IPtr | NumType::UPtr)
+            && matches!(self.abi.fixed_size(ty), NumType::I64 | NumType::U64)
+    }
+
+    /// The `post_cobject` argument of a poll function. Wasm calls the
+    /// `__notifier_callback` import instead, node addons post to a threadsafe
+    /// function.
+    fn post_cobject(&self, api: &js::Tokens) -> js::Tokens {
+        if self.napi {
+            quote!($api.instance.exports.__napi_post_cobject)
+        } else {
+            quote!(b)
+        }
+    }
+
+    fn lower_ptr(&self, value: js::Tokens) -> js::Tokens {
+        if self.is_bigint_ptr(NumType::IPtr) {
+            quote!(BigInt($value))
+        } else {
+            value
+        }
+    }
+
+    fn lift_ptr(&self, value: js::Tokens) -> js::Tokens {
+        if self.is_bigint_ptr(NumType::IPtr) {
+            quote!(Number($value))
+        } else {
+            value
+        }
+    }
+
+    fn lower_num(&self, var: &Var) -> js::Tokens {
+        match &var.ty {
+            AbiType::Num(ty) if self.is_bigint_ptr(*ty) => self.lower_ptr(self.var(var)),
+            _ => self.var(var),
+        }
+    }
+
+    fn lift_num(&self, var: &Var, value: js::Tokens) -> js::Tokens {
+        match &var.ty {
+            AbiType::Num(ty) if self.is_bigint_ptr(*ty) => self.lift_ptr(value),
+            _ => value,
+        }
+    }
+
+    fn generate_buffer(&self, ty: NumType) -> js::Tokens {
+        quote! {
+            export class $(ffi_buffer_name_for(ty)) extends FfiBuffer {
+                static array = $(typed_array(self.abi, ty));
+            }
         }
     }
 
@@ -750,86 +1147,68 @@ pub struct WasmMultiValueShim {
 
 impl Default for WasmMultiValueShim {
     fn default() -> Self {
-        Self::new()
+        Self::new(Abi::Wasm32)
     }
 }
 
 impl WasmMultiValueShim {
-    pub fn new() -> Self {
-        Self { abi: Abi::Wasm32 }
+    pub fn new(abi: Abi) -> Self {
+        Self { abi }
     }
 
     #[cfg(feature = "test_runner")]
-    #[allow(unused_variables)]
-    pub fn generate(&self, path: &str, iface: Interface) -> rust::Tokens {
-        let args = self.generate_args(iface);
-        if !args.is_empty() {
-            quote! {
-                let ret = Command::new("multi-value-reverse-polyfill")
-                    .arg($(quoted(path)))
-                    $(for arg in args => .arg($(quoted(arg))))
-                    .status()
-                    .unwrap()
-                    .success();
-                assert!(ret);
-            }
-        } else {
-            quote! {
-                let ret = Command::new("cp")
-                    .arg($(quoted(path)))
-                    .arg($(quoted(format!("{}.multivalue.wasm", path))))
-                    .status()
-                    .unwrap()
-                    .success();
-                assert!(ret);
-            }
+    pub fn generate(&self, path: &str, iface: &str) -> rust::Tokens {
+        let abi = format!("{:?}", self.abi);
+        quote! {
+            let ffigen: ffi_gen::FfiGen = $(quoted(iface)).parse().unwrap();
+            ffigen
+                .wasm_multi_value_shim_with_abi(ffi_gen::Abi::$abi, $(quoted(path)))
+                .expect("Multi value shim");
         }
     }
 
     pub fn run<P: AsRef<Path>>(&self, path: P, iface: Interface) -> Result<()> {
-        let args = self.generate_args(iface);
-        let path = path.as_ref().to_str().unwrap();
-        if !args.is_empty() {
-            let mut cmd = Command::new("multi-value-reverse-polyfill");
-            cmd.arg(path);
-            for arg in args {
-                cmd.arg(arg);
-            }
-            let status = cmd.status()?;
-            if !status.success() {
-                anyhow::bail!("multi-value-reverse-polyfill failed for: {:?}", cmd);
-            }
-        } else {
-            let status = Command::new("cp")
-                .arg(path)
-                .arg(format!("{}.multivalue.wasm", path))
-                .status()?;
-            if !status.success() {
-                anyhow::bail!("cp failed");
-            }
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
+        let exports = self.generate_args(iface);
+        let path = path.as_ref();
+        let mut module = std::fs::read(path)?;
+        if !exports.is_empty() {
+            module = wasm::multi_value_xform(
+                &module,
+                &exports,
+                self.abi.fixed_size(NumType::UPtr) == NumType::U64,
+                &symbols.allocate(),
+                &symbols.deallocate(),
+            )
+            .with_context(|| format!("multi value shim failed for {}", path.display()))?;
         }
+        std::fs::write(format!("{}.multivalue.wasm", path.display()), module)?;
         Ok(())
     }
 
-    fn generate_args(&self, iface: Interface) -> Vec<String> {
+    fn generate_args(&self, iface: Interface) -> Vec<MultiValueExport> {
         iface
             .imports(&self.abi)
             .into_iter()
             .filter_map(|import| match &import.ffi_ret {
                 Return::Struct(fields, _) => {
-                    let mut ret = String::new();
-                    for field in fields {
-                        let (size, _) = self.abi.layout(field.ty.num());
-                        let r = match field.ty.num() {
-                            NumType::F32 => "f32 ",
-                            NumType::F64 => "f64 ",
-                            _ if size > 4 => "i64 ",
-                            _ => "i32 ",
-                        };
-                        ret.push_str(r);
-                    }
-
-                    Some(format!("{} {}", import.symbol, ret))
+                    let results = fields
+                        .iter()
+                        .map(|field| {
+                            let (size, _) = self.abi.layout(field.ty.num());
+                            match field.ty.num() {
+                                NumType::F32 => ValType::F32,
+                                NumType::F64 => ValType::F64,
+                                _ if size > 4 => ValType::I64,
+                                _ => ValType::I32,
+                            }
+                        })
+                        .collect();
+                    Some(MultiValueExport {
+                        symbol: import.symbol,
+                        results,
+                    })
                 }
                 _ => None,
             })
@@ -848,13 +1227,109 @@ pub mod test_runner {
     use trybuild::TestCases;
 
     pub fn compile_pass(iface: &str, rust: rust::Tokens, js: js::Tokens) -> Result<()> {
+        compile_pass_abi(Abi::Wasm32, iface, rust, js)
+    }
+
+    pub fn compile_pass_ret_area(iface: &str, rust: rust::Tokens, js: js::Tokens) -> Result<()> {
+        compile_pass_abi(Abi::Wasm32RetArea, iface, rust, js)
+    }
+
+    pub fn compile_pass_wasm64(iface: &str, rust: rust::Tokens, js: js::Tokens) -> Result<()> {
+        compile_pass_abi(Abi::Wasm64, iface, rust, js)
+    }
+
+    /// Builds the rust api for wasm64 and checks that the generated js and ts
+    /// bindings contain each of `expected`, without running them.
+    pub fn build_wasm64(iface: &str, rust: rust::Tokens, expected: &[&str]) -> Result<()> {
         let iface = Interface::parse(iface)?;
+        let rust_tokens = RustGenerator::new(Abi::Wasm64).generate(iface.clone());
+        let js = JsGenerator::new(Abi::Wasm64)
+            .generate(iface.clone())
+            .to_file_string()?;
+        let ts = TsGenerator::new(Abi::Wasm64)
+            .generate(iface)
+            .to_file_string()?;
+        for expected in expected {
+            anyhow::ensure!(
+                js.contains(expected) || ts.contains(expected),
+                "the bindings don't contain `{}`:\n{}\n{}",
+                expected,
+                js,
+                ts
+            );
+        }
+
+        let library_tokens: rust::Tokens = quote! {
+            $rust_tokens
+            $rust
+        };
         let mut rust_file = NamedTempFile::new()?;
-        let rust_gen = RustGenerator::new(Abi::Wasm32);
+        writeln!(rust_file, "#![feature(vec_into_raw_parts)]")?;
+        rust_file.write_all(library_tokens.to_file_string()?.as_bytes())?;
+        let project = tempfile::tempdir()?;
+        let wasm = wasm64_project(rust_file.as_ref(), project.path())?;
+        let status = std::process::Command::new("cargo")
+            .args(WASM64_BUILD)
+            .current_dir(project.path())
+            .status()?;
+        anyhow::ensure!(status.success(), "building the wasm64 module failed");
+        anyhow::ensure!(wasm.exists(), "{} wasn't built", wasm.display());
+        Ok(())
+    }
+
+    /// Returns `true` if node can load modules using 64bit memories and
+    /// tables, which the wasm64 target emits. Checked with a module that only
+    /// declares one of each.
+    pub fn node_supports_wasm64() -> bool {
+        let script = "process.exit(WebAssembly.validate(new Uint8Array([\
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, \
+            0x04, 0x04, 0x01, 0x70, 0x04, 0x00, \
+            0x05, 0x03, 0x01, 0x04, 0x00])) ? 0 : 1)";
+        std::process::Command::new("node")
+            .arg("--experimental-wasm-memory64")
+            .arg("-e")
+            .arg(script)
+            .status()
+            .map(|status| status.success())
+            .unwrap_or_default()
+    }
+
+    /// Arguments building a cargo project for wasm64.
+    const WASM64_BUILD: &[&str] = &[
+        "build",
+        "-Zbuild-std=std,panic_abort",
+        "--features",
+        "test_runner",
+        "--target",
+        "wasm64-unknown-unknown",
+        "--target-dir",
+        "target",
+    ];
+
+    /// Writes a throwaway cargo project to `dir` that builds `lib` as a
+    /// cdylib, as wasm64-unknown-unknown is a tier 3 target without a prebuilt
+    /// std and needs `-Zbuild-std`. Returns the path of the built module.
+    fn wasm64_project(lib: &std::path::Path, dir: &std::path::Path) -> Result<std::path::PathBuf> {
+        let manifest = format!(
+            "[package]\nname = \"compile_pass\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n\
+             [lib]\npath = {:?}\ncrate-type = [\"cdylib\"]\n\n\
+             [features]\ntest_runner = []\n\n[workspace]\n",
+            lib,
+        );
+        std::fs::write(dir.join("Cargo.toml"), manifest)?;
+        Ok(dir.join("target/wasm64-unknown-unknown/debug/compile_pass.wasm"))
+    }
+
+    fn compile_pass_abi(abi: Abi, iface: &str, rust: rust::Tokens, js: js::Tokens) -> Result<()> {
+        let source = iface;
+        let iface = Interface::parse(iface)?;
+        let mut rust_file = NamedTempFile::new()?;
+        let rust_gen = RustGenerator::new(abi);
         let rust_tokens = rust_gen.generate(iface.clone());
         let mut js_file = tempfile::Builder::new().suffix(".mjs").tempfile()?;
-        let js_gen = JsGenerator::default();
+        let js_gen = JsGenerator::new(abi);
         let js_tokens = js_gen.generate(iface.clone());
+        let ptr = |value: &str| js_gen.lift_ptr(quote!($value));
 
         let library_tokens: genco::Tokens<genco::lang::Rust> = quote! {
             $rust_tokens
@@ -875,21 +1350,28 @@ pub mod test_runner {
         };
 
         let library_file = NamedTempFile::new()?;
+        let library_path = library_file.as_ref().to_str().unwrap();
+        // return area abis load the module built by rustc as is
+        let wasm = if abi.has_ret_area() {
+            library_path.to_string()
+        } else {
+            format!("{}.multivalue.wasm", library_path)
+        };
         let bin_tokens: genco::Tokens<genco::lang::JavaScript> = quote! {
             import assert from "assert";
             $js_tokens
 
             async function main() {
                 const api = new Api();
-                await api.fetch($[str]($[const](library_file.as_ref().to_str().unwrap()).multivalue.wasm), {
+                await api.fetch($(quoted(wasm)), {
                     env: {
                         __panic: (ptr, len) => {
-                            const buf = new Uint8Array(api.instance.exports.memory.buffer, ptr, len);
+                            const buf = new Uint8Array(api.instance.exports.memory.buffer, $(ptr("ptr")), $(ptr("len")));
                             const decoder = new TextDecoder();
                             throw decoder.decode(buf);
                         },
                         __log: (ptr, len) => {
-                            const buf = new Uint8Array(api.instance.exports.memory.buffer, ptr, len);
+                            const buf = new Uint8Array(api.instance.exports.memory.buffer, $(ptr("ptr")), $(ptr("len")));
                             const decoder = new TextDecoder();
                             console.log(decoder.decode(buf));
                         },
@@ -906,12 +1388,32 @@ pub mod test_runner {
         let bin = bin_tokens.to_file_string()?;
         js_file.write_all(bin.as_bytes())?;
 
-        let wasm_multi_value =
-            WasmMultiValueShim::new().generate(library_file.as_ref().to_str().unwrap(), iface);
+        let wasm_multi_value = if abi.has_ret_area() {
+            quote!()
+        } else {
+            WasmMultiValueShim::new(abi).generate(library_path, source)
+        };
 
-        let runner_tokens: rust::Tokens = quote! {
-            fn main() {
-                use std::process::Command;
+        let target_dir = tempfile::tempdir()?;
+        let compile_lib: rust::Tokens = match abi {
+            Abi::Wasm64 | Abi::Wasm64RetArea => {
+                let wasm = wasm64_project(rust_file.as_ref(), target_dir.path())?;
+                quote! {
+                    let ret = Command::new("cargo")
+                        $(for arg in WASM64_BUILD => .arg($(quoted(*arg))))
+                        .current_dir($(quoted(target_dir.path().to_str().unwrap())))
+                        .status()
+                        .expect("Compiling lib")
+                        .success();
+                    assert!(ret);
+                    std::fs::copy(
+                        $(quoted(wasm.to_str().unwrap())),
+                        $(quoted(library_file.as_ref().to_str().unwrap())),
+                    )
+                    .expect("Copying lib");
+                }
+            }
+            _ => quote! {
                 let ret = Command::new("rustc")
                     .arg("--edition")
                     .arg("2021")
@@ -930,9 +1432,21 @@ pub mod test_runner {
                     .expect("Compiling lib")
                     .success();
                 assert!(ret);
+            },
+        };
+        let node_flags: &[&str] = match abi {
+            Abi::Wasm64 | Abi::Wasm64RetArea => &["--experimental-wasm-memory64"],
+            _ => &[],
+        };
+
+        let runner_tokens: rust::Tokens = quote! {
+            fn main() {
+                use std::process::Command;
+                $compile_lib
                 //println!("{}", #_($bin));
                 $wasm_multi_value
                 let ret = Command::new("node")
+                    $(for flag in node_flags => .arg($(quoted(*flag))))
                     .arg("--expose-gc")
                     .arg("--unhandled-rejections=strict")
                     .arg($(quoted(js_file.as_ref().to_str().unwrap())))
@@ -957,9 +1471,92 @@ pub mod test_runner {
         Ok(())
     }
 
+    pub fn compile_pass_napi(iface: &str, rust: rust::Tokens, js: js::Tokens) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let mut rust_file = NamedTempFile::new()?;
+        let rust_gen = RustGenerator::new(Abi::native()).napi(true);
+        let rust_tokens = rust_gen.generate(iface.clone());
+        let mut js_file = tempfile::Builder::new().suffix(".mjs").tempfile()?;
+        let js_gen = JsGenerator::napi();
+        let js_tokens = js_gen.generate(iface);
+
+        let library_tokens: genco::Tokens<genco::lang::Rust> = quote! {
+            $rust_tokens
+            $rust
+
+            pub fn panic(msg: &str) {
+                panic!("{}", msg);
+            }
+
+            pub fn log(msg: &str) {
+                println!("{}", msg);
+            }
+        };
+
+        let library_file = tempfile::Builder::new().suffix(".node").tempfile()?;
+        let bin_tokens: genco::Tokens<genco::lang::JavaScript> = quote! {
+            import assert from "assert";
+            $js_tokens
+
+            async function main() {
+                const api = new Api();
+                await api.fetch($(quoted(library_file.as_ref().to_str().unwrap())));
+                $js
+            }
+            main();
+        };
+
+        let library = library_tokens.to_file_string()?;
+        writeln!(rust_file, "#![feature(vec_into_raw_parts)]")?;
+        rust_file.write_all(library.as_bytes())?;
+        let bin = bin_tokens.to_file_string()?;
+        js_file.write_all(bin.as_bytes())?;
+
+        let runner_tokens: rust::Tokens = quote! {
+            fn main() {
+                use std::process::Command;
+                let ret = Command::new("rustc")
+                    .arg("--edition")
+                    .arg("2021")
+                    .arg("--crate-name")
+                    .arg("compile_pass")
+                    .arg("--crate-type")
+                    .arg("cdylib")
+                    .arg("-o")
+                    .arg($(quoted(library_file.as_ref().to_str().unwrap())))
+                    .arg("--cfg")
+                    .arg("feature=\"test_runner\"")
+                    .arg($(quoted(rust_file.as_ref().to_str().unwrap())))
+                    .status()
+                    .expect("Compiling lib")
+                    .success();
+                assert!(ret);
+                let ret = Command::new("node")
+                    .arg("--expose-gc")
+                    .arg("--unhandled-rejections=strict")
+                    .arg($(quoted(js_file.as_ref().to_str().unwrap())))
+                    .status()
+                    .expect("Running node")
+                    .success();
+                assert!(ret);
+            }
+        };
+
+        let mut runner_file = NamedTempFile::new()?;
+        let runner = runner_tokens.to_file_string()?;
+        runner_file.write_all(runner.as_bytes())?;
+
+        let test = TestCases::new();
+        test.pass(runner_file.as_ref());
+        Ok(())
+    }
+
     pub fn compile_pass_ts(iface: &str, ts_tokens: js::Tokens) -> Result<()> {
         let iface = Interface::parse(iface)?;
-        let ts_gen = TsGenerator { docs: false };
+        let ts_gen = TsGenerator {
+            abi: Abi::Wasm32,
+            docs: false,
+        };
         let js_tokens = ts_gen.generate(iface);
         // remove static header to no bloat the tests
         let left = js_tokens.to_file_string().unwrap().replace(
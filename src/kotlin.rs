@@ -0,0 +1,1350 @@
+use crate::dart::ffi_buffer_name_for;
+use crate::import::{Import, Instr};
+use crate::parser::{Enum, Type};
+use crate::{
+    Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Symbols, Var,
+    NUM_TYPES,
+};
+use genco::prelude::*;
+use genco::tokens::static_literal;
+use heck::*;
+use std::collections::BTreeSet;
+
+/// Kotlin isn't supported by genco, the java formatter is close enough as
+/// long as the strings don't contain `$`.
+pub type Tokens = java::Tokens;
+
+pub struct KotlinGenerator {
+    abi: Abi,
+    package: String,
+    cdylib_name: String,
+}
+
+/// State shared by the instructions of a single function body.
+struct Body {
+    void_call: bool,
+    /// `Some(())` is returned as `Unit` to tell it apart from `null`.
+    unit: bool,
+    /// Values whose handles are borrowed by the call and need to stay
+    /// reachable until it returns, the cleaner would drop them otherwise.
+    fences: Vec<String>,
+}
+
+impl KotlinGenerator {
+    /// Generates a kotlin file in `package` loading the library `cdylib_name`
+    /// through JNA.
+    pub fn new(package: String, cdylib_name: String) -> Self {
+        Self {
+            abi: Abi::native(),
+            package,
+            cdylib_name,
+        }
+    }
+
+    pub fn generate(&self, iface: Interface) -> Tokens {
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
+        let objects: Vec<String> = iface.objects().into_iter().map(|obj| obj.name).collect();
+        let imports = iface.imports(&self.abi);
+        let mut wrappers = vec![];
+        for iter in iface.iterators() {
+            wrappers.push(iter.next());
+        }
+        for fut in iface.futures() {
+            wrappers.push(fut.poll());
+        }
+        for stream in iface.streams() {
+            wrappers.push(stream.poll());
+        }
+        quote! {
+            $(static_literal("//")) AUTO GENERATED FILE, DO NOT EDIT.
+            $(static_literal("//"))
+            $(static_literal("//")) Generated by "ffi-gen".
+
+            $(self.generate_doc(&iface.doc))
+            package $(&self.package)
+
+            import com.sun.jna.Callback
+            import com.sun.jna.CallbackReference
+            import com.sun.jna.Library
+            import com.sun.jna.Native
+            import com.sun.jna.Pointer
+            import com.sun.jna.Structure
+            import java.lang.ref.Cleaner
+            import java.lang.ref.Reference
+            import java.nio.ByteBuffer
+            import java.nio.ByteOrder
+            import java.util.concurrent.ConcurrentHashMap
+            import java.util.concurrent.atomic.AtomicLong
+            import kotlin.coroutines.resume
+            import kotlin.coroutines.resumeWithException
+            import kotlinx.coroutines.CancellableContinuation
+            import kotlinx.coroutines.channels.Channel
+            import kotlinx.coroutines.channels.ProducerScope
+            import kotlinx.coroutines.channels.awaitClose
+            import kotlinx.coroutines.flow.Flow
+            import kotlinx.coroutines.flow.buffer
+            import kotlinx.coroutines.flow.callbackFlow
+            import kotlinx.coroutines.suspendCancellableCoroutine
+
+            $(self.generate_library(&iface, &symbols, &imports))
+
+            $(for func in &imports join ($['\n']) => $(self.generate_return_struct(&func.ffi_ret)))
+
+            $(self.generate_runtime(&symbols))
+
+            $(for size in tuple_sizes(&iface) join ($['\n']) => $(self.generate_tuple(size)))
+
+            $(for obj in iface.objects() join ($['\n']) => $(self.generate_object(&obj)))
+
+            $(for e in &iface.enums join ($['\n']) => $(self.generate_enum(&symbols, e)))
+
+            $(for ty in NUM_TYPES join ($['\n']) => $(self.generate_ffi_buffer(&symbols, ty)))
+
+            $(for ty in iface.listed_types() join ($['\n']) => $(self.generate_list_type(&symbols, &objects, &ty)))
+
+            $(for func in &wrappers join ($['\n']) => $(self.generate_function(func)))
+
+            $(for func in iface.functions() join ($['\n']) => $(self.generate_function(&func)))
+        }
+    }
+
+    /// Data class for tuples with more elements than `Triple`.
+    fn generate_tuple(&self, size: usize) -> Tokens {
+        let params = (1..=size)
+            .map(|i| format!("out T{}", i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let fields = (1..=size)
+            .map(|i| format!("val {}: T{}", tuple_field(i - 1), i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        quote! {
+            $(static_literal("/**"))
+            $(static_literal(" * A rust tuple with more elements than a Triple."))
+            $(static_literal(" */"))
+            data class $(tuple_name(size))<$params>($fields)
+        }
+    }
+
+    /// JNA interface declaring every exported symbol, handles are passed as
+    /// `Long`.
+    fn generate_library(&self, iface: &Interface, symbols: &Symbols, imports: &[Import]) -> Tokens {
+        let mut destructors = vec![symbols.destructor("FfiBuffer"), symbols.destructor("Leak")];
+        for obj in iface.objects() {
+            destructors.push(obj.destructor);
+        }
+        for iter in iface.iterators() {
+            if !iter.borrowed {
+                destructors.push(format!("{}_iter_drop", iter.symbol));
+            }
+        }
+        for fut in iface.futures() {
+            destructors.push(format!("{}_future_drop", fut.symbol));
+        }
+        for stream in iface.streams() {
+            destructors.push(format!("{}_stream_drop", stream.symbol));
+        }
+        for e in &iface.enums {
+            destructors.push(symbols.destructor(&e.ident));
+        }
+        quote! {
+            internal interface FfiLib : Library {
+                fun $(symbols.allocate())(size: Long, align: Long): Long
+                fun $(symbols.deallocate())(ptr: Long, size: Long, align: Long)
+                fun $(symbols.ffi_buffer("new"))(size: Long, align: Long): Long
+                fun $(symbols.ffi_buffer("address"))(ptr: Long): Long
+                fun $(symbols.ffi_buffer("size"))(ptr: Long): Long
+                fun $(symbols.string_into_parts())(ptr: Long): FfiStringParts
+                $(for e in &iface.enums => fun $(symbols.destructure_enum(&e.ident))(ptr: Long): FfiEnumWrapper$['\r'])
+                $(for drop in &destructors => fun $drop(isolate: Long, boxed: Long)$['\r'])
+                $(for ty in iface.listed_types() => $(self.declare_list(symbols, &ty))$['\r'])
+                $(for func in imports => $(self.declare_import(func))$['\r'])
+            }
+
+            internal val ffiLib: FfiLib = Native.load($(quoted(&self.cdylib_name)), FfiLib::class.java)
+        }
+    }
+
+    fn declare_list(&self, symbols: &Symbols, ty: &str) -> Tokens {
+        quote! {
+            fun $(symbols.list(ty, "Create"))(): Long
+            fun $(symbols.destructor(&format!("FfiList{}", ty)))(isolate: Long, boxed: Long)
+            fun $(symbols.list(ty, "Len"))(boxed: Long): Int
+            fun $(symbols.list(ty, "ElementAt"))(boxed: Long, index: Int): Long
+            fun $(symbols.list(ty, "Remove"))(boxed: Long, index: Int): Long
+            fun $(symbols.list(ty, "Add"))(boxed: Long, element: Long)
+            fun $(symbols.list(ty, "Insert"))(boxed: Long, index: Int, element: Long)
+        }
+    }
+
+    fn declare_import(&self, func: &Import) -> Tokens {
+        let args = func
+            .ffi_args
+            .iter()
+            .map(|var| format!("{}: {}", self.var(var), self.ffi_type(var)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let ret = match &func.ffi_ret {
+            Return::Void => "".to_string(),
+            Return::Num(var) => format!(": {}", self.ffi_type(var)),
+            Return::Struct(_, name) => format!(": {}", name),
+        };
+        quote!(fun $(&func.symbol)($args)$ret)
+    }
+
+    fn generate_return_struct(&self, ret: &Return) -> Tokens {
+        if let Return::Struct(vars, name) = ret {
+            let fields: Vec<(String, &Var)> = vars
+                .iter()
+                .enumerate()
+                .map(|(i, var)| (format!("ret{}", i), var))
+                .collect();
+            quote! {
+                @Structure.FieldOrder($(for (field, _) in &fields join (, ) => $(quoted(field))))
+                internal class $name : Structure(), Structure.ByValue {
+                    $(for (field, var) in &fields =>
+                        @JvmField var $field: $(self.ffi_type(var)) = $(self.zero(var))$['\r'])
+                }
+            }
+        } else {
+            quote!()
+        }
+    }
+
+    fn generate_runtime(&self, symbols: &Symbols) -> Tokens {
+        quote! {
+            @Structure.FieldOrder("ptr", "len", "capacity")
+            internal class FfiStringParts : Structure(), Structure.ByValue {
+                @JvmField var ptr: Long = 0
+                @JvmField var len: Long = 0
+                @JvmField var capacity: Long = 0
+            }
+
+            @Structure.FieldOrder("tag", "inner")
+            internal class FfiEnumWrapper : Structure(), Structure.ByValue {
+                @JvmField var tag: Int = 0
+                @JvmField var inner: Long = 0
+            }
+
+            $(static_literal("/**"))
+            $(static_literal(" * Error returned by rust."))
+            $(static_literal(" */"))
+            class FfiError(message: String) : Exception(message)
+
+            internal val ffiCleaner: Cleaner = Cleaner.create()
+
+            $(static_literal("/**"))
+            $(static_literal(" * Handle to a rust object, boxes without a drop function are borrowed"))
+            $(static_literal(" * from another object which is kept alive as [parent]."))
+            $(static_literal(" */"))
+            internal class FfiBox(ptr: Long, drop: ((Long) -> Unit)?, @Suppress("unused") private val parent: Any? = null) : AutoCloseable {
+                private val state = State(ptr, drop)
+                private val cleanable = if (drop != null) ffiCleaner.register(this, state) else null
+
+                $(static_literal("/**"))
+                $(static_literal(" * Registered with the cleaner, so it must not reference the box."))
+                $(static_literal(" */"))
+                private class State(var ptr: Long, var drop: ((Long) -> Unit)?) : Runnable {
+                    @Synchronized
+                    override fun run() {
+                        val drop = drop
+                        if (ptr != 0L && drop != null) {
+                            drop(ptr)
+                        }
+                        ptr = 0
+                        this.drop = null
+                    }
+                }
+
+                fun borrow(): Long = synchronized(state) {
+                    check(state.ptr != 0L) { "use after move" }
+                    state.ptr
+                }
+
+                fun release(): Long = synchronized(state) {
+                    check(state.drop != null) { "borrowed values can not be moved" }
+                    val ptr = borrow()
+                    state.ptr = 0
+                    state.drop = null
+                    ptr
+                }
+
+                override fun close() {
+                    if (cleanable != null) {
+                        cleanable.clean()
+                    } else {
+                        synchronized(state) { state.ptr = 0 }
+                    }
+                }
+            }
+
+            internal fun ffiBuffer(size: Int): ByteBuffer = ByteBuffer.allocate(size).order(ByteOrder.nativeOrder())
+
+            $(static_literal("/**"))
+            $(static_literal(" * View of memory owned by rust."))
+            $(static_literal(" */"))
+            internal fun ffiRead(ptr: Long, size: Long): ByteBuffer {
+                if (size == 0L) {
+                    return ffiBuffer(0)
+                }
+                return Pointer(ptr).getByteBuffer(0, size).order(ByteOrder.nativeOrder())
+            }
+
+            $(static_literal("/**"))
+            $(static_literal(" * Copies the bytes into memory owned by rust."))
+            $(static_literal(" */"))
+            internal fun ffiLowerBytes(bytes: ByteArray, align: Long): Long {
+                if (bytes.isEmpty()) {
+                    return align
+                }
+                val ptr = ffiLib.$(symbols.allocate())(bytes.size.toLong(), align)
+                Pointer(ptr).write(0, bytes, 0, bytes.size)
+                return ptr
+            }
+
+            internal fun ffiFreeBytes(ptr: Long, size: Long, align: Long) {
+                if (size > 0) {
+                    ffiLib.$(symbols.deallocate())(ptr, size, align)
+                }
+            }
+
+            $(static_literal("/**"))
+            $(static_literal(" * Packs the u32 byte length of every string followed by the utf8 bytes."))
+            $(static_literal(" */"))
+            internal fun ffiPackStrs(strs: List<String>): ByteArray {
+                val encoded = strs.map { str -> str.toByteArray(Charsets.UTF_8) }
+                val bytes = ffiBuffer(strs.size * 4 + encoded.sumOf { str -> str.size })
+                for (str in encoded) {
+                    bytes.putInt(str.size)
+                }
+                for (str in encoded) {
+                    bytes.put(str)
+                }
+                return bytes.array()
+            }
+
+            internal fun ffiLiftString(ptr: Long, len: Long): String {
+                if (len == 0L) {
+                    return ""
+                }
+                return String(Pointer(ptr).getByteArray(0, len.toInt()), Charsets.UTF_8)
+            }
+
+            internal object FfiWakers {
+                private val nextPort = AtomicLong()
+                private val wakers = ConcurrentHashMap<Long, () -> Unit>()
+
+                fun register(waker: () -> Unit): Long {
+                    val port = nextPort.incrementAndGet()
+                    wakers[port] = waker
+                    return port
+                }
+
+                fun unregister(port: Long) {
+                    wakers.remove(port)
+                }
+
+                fun wake(port: Long) {
+                    wakers[port]?.invoke()
+                }
+            }
+
+            internal fun interface FfiPostCObject : Callback {
+                fun invoke(port: Long, obj: Pointer?)
+            }
+
+            $(static_literal("/**"))
+            $(static_literal(" * Kept in a top level property, jna doesn't keep callbacks alive while rust holds the pointer."))
+            $(static_literal(" */"))
+            private val ffiPostCObject = FfiPostCObject { port, _ -> FfiWakers.wake(port) }
+
+            internal val ffiPostCObjectAddress: Long = Pointer.nativeValue(CallbackReference.getFunctionPointer(ffiPostCObject))
+
+            $(static_literal("/**"))
+            $(static_literal(" * Polls a rust future or stream whenever it is woken."))
+            $(static_literal(" */"))
+            internal abstract class FfiTask {
+                private var polling = false
+                private var woken = false
+                private var finished = false
+
+                fun wake() {
+                    synchronized(this) {
+                        if (finished) {
+                            return
+                        }
+                        if (polling) {
+                            woken = true
+                            return
+                        }
+                        polling = true
+                    }
+                    while (true) {
+                        val finished = poll()
+                        synchronized(this) {
+                            if (finished || !woken) {
+                                this.finished = finished
+                                polling = false
+                                return
+                            }
+                            woken = false
+                        }
+                    }
+                }
+
+                $(static_literal("/**"))
+                $(static_literal(" * Returns true once the task is finished."))
+                $(static_literal(" */"))
+                abstract fun poll(): Boolean
+            }
+
+            internal class FfiFutureTask<T>(
+                private val box: FfiBox,
+                private val pollFn: (Long, Long, Long) -> T?,
+                private val continuation: CancellableContinuation<T>,
+            ) : FfiTask() {
+                private var port = 0L
+                @Volatile private var cancelled = false
+
+                fun start() {
+                    port = FfiWakers.register { wake() }
+                    wake()
+                }
+
+                fun cancel() {
+                    cancelled = true
+                    wake()
+                }
+
+                override fun poll(): Boolean {
+                    if (!cancelled) {
+                        try {
+                            val value = pollFn(box.borrow(), ffiPostCObjectAddress, port) ?: return false
+                            continuation.resume(value)
+                        } catch (error: Exception) {
+                            continuation.resumeWithException(error)
+                        }
+                    }
+                    FfiWakers.unregister(port)
+                    box.close()
+                    return true
+                }
+            }
+
+            $(static_literal("/**"))
+            $(static_literal(" * Suspends until the rust future is ready, cancelling drops the future."))
+            $(static_literal(" */"))
+            internal suspend fun <T> ffiAwait(box: FfiBox, poll: (Long, Long, Long) -> T?): T =
+                suspendCancellableCoroutine { continuation ->
+                    val task = FfiFutureTask(box, poll, continuation)
+                    continuation.invokeOnCancellation { task.cancel() }
+                    task.start()
+                }
+
+            internal class FfiStreamTask<T>(
+                private val box: FfiBox,
+                private val pollFn: (Long, Long, Long, Long) -> T?,
+                private val scope: ProducerScope<T>,
+            ) : FfiTask() {
+                private var port = 0L
+                private var done = 0L
+                @Volatile private var ended = false
+
+                fun start() {
+                    port = FfiWakers.register { wake() }
+                    done = FfiWakers.register { end() }
+                    wake()
+                }
+
+                $(static_literal("/**"))
+                $(static_literal(" * Stops polling, called once rust is done or the flow is no longer collected."))
+                $(static_literal(" */"))
+                fun end() {
+                    ended = true
+                    wake()
+                }
+
+                $(static_literal("/**"))
+                $(static_literal(" * Sends items to the flow until rust is pending, errors close the flow right away."))
+                $(static_literal(" */"))
+                override fun poll(): Boolean {
+                    try {
+                        while (!ended) {
+                            val item = pollFn(box.borrow(), ffiPostCObjectAddress, port, done) ?: return false
+                            scope.trySend(item)
+                        }
+                    } catch (error: Exception) {
+                        scope.close(error)
+                    }
+                    FfiWakers.unregister(port)
+                    FfiWakers.unregister(done)
+                    box.close()
+                    scope.close()
+                    return true
+                }
+            }
+
+            $(static_literal("/**"))
+            $(static_literal(" * The rust stream is polled once the flow is collected, so it can only be collected once."))
+            $(static_literal(" */"))
+            internal fun <T> ffiStream(box: FfiBox, poll: (Long, Long, Long, Long) -> T?): Flow<T> =
+                callbackFlow {
+                    val task = FfiStreamTask(box, poll, this)
+                    task.start()
+                    awaitClose { task.end() }
+                }.buffer(Channel.UNLIMITED)
+
+            $(static_literal("/**"))
+            $(static_literal(" * A rust iterator."))
+            $(static_literal(" */"))
+            class FfiIter<T> internal constructor(
+                internal val box: FfiBox,
+                private val nextFn: (Long) -> T?,
+            ) : Iterator<T>, AutoCloseable {
+                private var item: T? = null
+
+                override fun hasNext(): Boolean {
+                    if (item == null) {
+                        item = nextFn(box.borrow())
+                    }
+                    return item != null
+                }
+
+                override fun next(): T {
+                    val item = item ?: nextFn(box.borrow()) ?: throw NoSuchElementException()
+                    this.item = null
+                    return item
+                }
+
+                override fun close() = box.close()
+            }
+
+            $(static_literal("/**"))
+            $(static_literal(" * A rust String owned by a list."))
+            $(static_literal(" */"))
+            class FfiString internal constructor(internal val box: FfiBox) : AutoCloseable {
+                override fun close() = box.close()
+
+                override fun toString(): String {
+                    val parts = ffiLib.$(symbols.string_into_parts())(box.borrow())
+                    Reference.reachabilityFence(this)
+                    return ffiLiftString(parts.ptr, parts.len)
+                }
+            }
+        }
+    }
+
+    fn generate_object(&self, obj: &AbiObject) -> Tokens {
+        let (constructors, methods): (Vec<&AbiFunction>, Vec<&AbiFunction>) = obj
+            .methods
+            .iter()
+            .partition(|method| matches!(method.ty, FunctionType::Constructor(_)));
+        quote! {
+            $(self.generate_doc(&obj.doc))
+            class $(self.type_ident(&obj.name)) internal constructor(internal val box: FfiBox) : AutoCloseable {
+                $(static_literal("/**"))
+                $(static_literal(" * Drops the rust object, otherwise it is dropped once this object is unreachable."))
+                $(static_literal(" */"))
+                override fun close() = box.close()
+                $(for method in methods =>
+                    $['\n']
+                    $(self.generate_function(method))
+                )
+                $(if !constructors.is_empty() {
+                    $['\n']
+                    companion object {
+                        $(for method in constructors join ($['\n']) => $(self.generate_function(method)))
+                    }
+                })
+            }
+        }
+    }
+
+    fn generate_enum(&self, symbols: &Symbols, e: &Enum) -> Tokens {
+        let name = self.type_ident(&e.ident);
+        let tag = format!("{}Tag", name);
+        quote! {
+            enum class $(&tag) {
+                $(for entry in &e.entries join (,$['\r']) => $(entry.name.to_shouty_snake_case()))
+            }
+
+            $(self.generate_doc(&e.doc))
+            class $(&name) internal constructor(internal val box: FfiBox) : AutoCloseable {
+                $(static_literal("/**"))
+                $(static_literal(" * The tag of this enum object."))
+                $(static_literal(" */"))
+                val tag: $(&tag)
+                $(for (entry, inner) in inner_objects(e) =>
+                    val $(self.ident(&entry)): $(self.type_ident(&inner))?$['\r'])
+
+                init {
+                    val parts = ffiLib.$(symbols.destructure_enum(&e.ident))(box.borrow())
+                    tag = $(&tag).values()[parts.tag]
+                    $(for (entry, inner) in inner_objects(e) =>
+                        $(self.ident(&entry)) = if (tag == $(&tag).$(entry.to_shouty_snake_case())) {
+                            $(self.type_ident(&inner))(FfiBox(parts.inner, $(self.drop_fn(&symbols.destructor(&inner)))))
+                        } else {
+                            null
+                        }$['\r'])
+                }
+
+                override fun close() = box.close()
+            }
+        }
+    }
+
+    fn generate_ffi_buffer(&self, symbols: &Symbols, ty: NumType) -> Tokens {
+        let name = ffi_buffer_name_for(ty);
+        let (size, _) = self.abi.layout(ty);
+        let (elements, view) = match self.ffi_num_type(ty) {
+            "Byte" => ("ByteBuffer", ""),
+            "Short" => ("java.nio.ShortBuffer", ".asShortBuffer()"),
+            "Int" => ("java.nio.IntBuffer", ".asIntBuffer()"),
+            "Long" => ("java.nio.LongBuffer", ".asLongBuffer()"),
+            "Float" => ("java.nio.FloatBuffer", ".asFloatBuffer()"),
+            _ => ("java.nio.DoubleBuffer", ".asDoubleBuffer()"),
+        };
+        quote! {
+            $(static_literal("/**"))
+            $(static_literal(" * A buffer that can be filled through [elements] and passed to rust without copying."))
+            $(static_literal(" */"))
+            class $name internal constructor(internal val box: FfiBox) : AutoCloseable {
+                override fun close() = box.close()
+
+                $(static_literal("/**"))
+                $(static_literal(" * The elements of the buffer, only valid while the buffer is alive."))
+                $(static_literal(" */"))
+                val elements: $elements
+                    get() {
+                        val ptr = box.borrow()
+                        val address = ffiLib.$(symbols.ffi_buffer("address"))(ptr)
+                        val size = ffiLib.$(symbols.ffi_buffer("size"))(ptr)
+                        return ffiRead(address, size)$view
+                    }
+
+                companion object {
+                    $(static_literal("/**"))
+                    $(static_literal(" * Allocates a zeroed buffer of [count] elements."))
+                    $(static_literal(" */"))
+                    fun allocate(count: Int): $name {
+                        val ptr = ffiLib.$(symbols.ffi_buffer("new"))(count.toLong() * $size, $size)
+                        return $name(FfiBox(ptr, $(self.drop_fn(&symbols.destructor("FfiBuffer")))))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Strings can't be moved in or out of a list, rust doesn't export a
+    /// destructor for them.
+    fn generate_list_type(&self, symbols: &Symbols, objects: &[String], ty: &str) -> Tokens {
+        let name = format!("FfiList{}", ty);
+        let elem = self.type_ident(ty);
+        let owned = objects.iter().any(|obj| obj == ty);
+        quote! {
+            $(static_literal("/**"))
+            $(static_literal(" * A rust Vec owning its elements."))
+            $(static_literal(" */"))
+            class $(&name) internal constructor(internal val box: FfiBox) : Iterable<$(&elem)>, AutoCloseable {
+                override fun close() = box.close()
+
+                val size: Int
+                    get() = ffiLib.$(symbols.list(ty, "Len"))(box.borrow())
+
+                $(static_literal("/**"))
+                $(static_literal(" * The returned element is borrowed from the list and keeps it alive."))
+                $(static_literal(" */"))
+                operator fun get(index: Int): $(&elem) {
+                    checkIndex(index, size)
+                    val ptr = ffiLib.$(symbols.list(ty, "ElementAt"))(box.borrow(), index)
+                    return $(&elem)(FfiBox(ptr, null, this))
+                }
+
+                override fun iterator(): Iterator<$(&elem)> = List(size) { index -> get(index) }.iterator()
+                $(if owned {
+                    $['\n']
+                    $(static_literal("/**"))
+                    $(static_literal(" * Moves the element out of the list."))
+                    $(static_literal(" */"))
+                    fun removeAt(index: Int): $(&elem) {
+                        checkIndex(index, size)
+                        val ptr = ffiLib.$(symbols.list(ty, "Remove"))(box.borrow(), index)
+                        return $(&elem)(FfiBox(ptr, $(self.drop_fn(&symbols.destructor(ty)))))
+                    }
+
+                    $(static_literal("/**"))
+                    $(static_literal(" * Moves the element into the list."))
+                    $(static_literal(" */"))
+                    fun add(element: $(&elem)) {
+                        ffiLib.$(symbols.list(ty, "Add"))(box.borrow(), element.box.release())
+                    }
+
+                    $(static_literal("/**"))
+                    $(static_literal(" * Moves the element into the list."))
+                    $(static_literal(" */"))
+                    fun add(index: Int, element: $(&elem)) {
+                        checkIndex(index, size + 1)
+                        ffiLib.$(symbols.list(ty, "Insert"))(box.borrow(), index, element.box.release())
+                    }
+                })
+
+                private fun checkIndex(index: Int, size: Int) {
+                    if (index < 0 || index >= size) {
+                        throw IndexOutOfBoundsException("index out of bounds")
+                    }
+                }
+
+                companion object {
+                    fun create(): $(&name) {
+                        val ptr = ffiLib.$(symbols.list(ty, "Create"))()
+                        return $(&name)(FfiBox(ptr, $(self.drop_fn(&symbols.destructor(&name)))))
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_function(&self, func: &AbiFunction) -> Tokens {
+        let ffi = self.abi.import(func);
+        let unit = ffi.abi_ret.is_none() && matches!(func.ret, Some(AbiType::Option(_)));
+        let mut fences = vec![];
+        if let FunctionType::Method(_) = &func.ty {
+            fences.push("this".to_string());
+        }
+        for instr in &ffi.instr {
+            if let Instr::BindArg(_, var) = instr {
+                if borrows(&var.ty) {
+                    fences.push(self.var(var));
+                }
+            }
+        }
+        let body = Body {
+            void_call: matches!(ffi.ffi_ret, Return::Void),
+            unit,
+            fences,
+        };
+        // variables defined inside of an option or result need to outlive
+        // the branch for the cleanup instructions.
+        let mut nested = vec![];
+        for instr in &ffi.instr {
+            nested_defines(instr, &mut nested);
+        }
+        quote! {
+            $(self.generate_doc(&func.doc))
+            $(self.signature(func, &ffi, unit)) {
+                $(for var in &nested => var $(self.var(var)): $(self.ffi_type(var)) = $(self.zero(var))$['\r'])
+                $(for instr in &ffi.instr => $(self.generate_instr(&body, instr, false))$['\r'])
+            }
+        }
+    }
+
+    /// Modifiers, name, arguments and return type of `func`.
+    fn signature(&self, func: &AbiFunction, ffi: &Import, unit: bool) -> String {
+        let wrapper = matches!(
+            func.ty,
+            FunctionType::NextIter(_, _)
+                | FunctionType::PollFuture(_, _)
+                | FunctionType::PollStream(_, _)
+        );
+        let name = if wrapper {
+            wrapper_name(&ffi.symbol)
+        } else {
+            self.ident(&func.name)
+        };
+        let modifiers = if wrapper {
+            "private fun"
+        } else if matches!(func.ret, Some(AbiType::Future(_))) {
+            "suspend fun"
+        } else {
+            "fun"
+        };
+        let args = ffi
+            .abi_args
+            .iter()
+            .map(|(name, ty)| format!("{}: {}", self.ident(name), self.arg_type(ty)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let ret = match &ffi.abi_ret {
+            Some(ty) => format!(": {}", self.value_type(ty, false)),
+            None if unit => ": Unit?".to_string(),
+            None => "".to_string(),
+        };
+        format!("{} {}({}){}", modifiers, name, args, ret)
+    }
+
+    fn generate_instr(&self, body: &Body, instr: &Instr, nested: bool) -> Tokens {
+        match instr {
+            Instr::BindArg(arg, out) => quote!(val $(self.var(out)) = $(self.ident(arg))),
+            Instr::DefineArgs(_) if nested => quote!(),
+            Instr::DefineArgs(vars) => quote! {
+                $(for var in vars => var $(self.var(var)): $(self.ffi_type(var)) = $(self.zero(var))$['\r'])
+            },
+            Instr::BorrowSelf(out) => quote!($(self.var(out)) = box.borrow()),
+            Instr::BorrowObject(in_, out) | Instr::BorrowIter(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).box.borrow())
+            }
+            Instr::MoveObject(in_, out) | Instr::MoveIter(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).box.release())
+            }
+            Instr::BorrowFuture(_, _)
+            | Instr::MoveFuture(_, _)
+            | Instr::BorrowStream(_, _)
+            | Instr::MoveStream(_, _) => unimplemented!("futures and streams can only be returned"),
+            Instr::LowerNum(in_, out) => {
+                let value = format!("{}.to{}()", self.var(in_), self.ffi_type(out));
+                quote!($(self.var(out)) = $value)
+            }
+            Instr::LiftNum(in_, out, _) => {
+                quote!(val $(self.var(out)) = $(self.var(in_))$(self.lift_num(&out.ty)))
+            }
+            Instr::LowerBool(in_, out) => {
+                quote!($(self.var(out)) = (if ($(self.var(in_))) 1 else 0).toByte())
+            }
+            Instr::LiftBool(in_, out) => {
+                quote!(val $(self.var(out)) = $(self.var(in_)).toInt() != 0)
+            }
+            Instr::LowerString(in_, ptr, len, cap, _, _) => quote! {
+                val $(self.var(ptr))_0 = $(self.var(in_)).toByteArray(Charsets.UTF_8)
+                $(self.var(len)) = $(self.var(ptr))_0.size.toLong()
+                $(self.var(ptr)) = ffiLowerBytes($(self.var(ptr))_0, 1)
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::LowerVec(in_, ptr, len, cap, ty, size, align)
+            | Instr::LowerMutSlice(in_, ptr, len, cap, ty, size, align) => quote! {
+                val $(self.var(ptr))_0 = ffiBuffer($(self.var(in_)).size * $(*size))
+                for (item in $(self.var(in_))) $(self.var(ptr))_0.$(self.put_num(*ty, "item"))
+                $(self.var(len)) = $(self.var(in_)).size.toLong()
+                $(self.var(ptr)) = ffiLowerBytes($(self.var(ptr))_0.array(), $(*align))
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::LowerStrSlice(in_, ptr, len, cap) => quote! {
+                val $(self.var(ptr))_0 = ffiPackStrs($(self.var(in_)))
+                $(self.var(len)) = $(self.var(in_)).size.toLong()
+                $(self.var(cap)) = $(self.var(ptr))_0.size.toLong()
+                $(self.var(ptr)) = ffiLowerBytes($(self.var(ptr))_0, 4)
+            },
+            Instr::LowerObjectSlice(in_, ptr, len, cap, size, align) => quote! {
+                val $(self.var(ptr))_0 = ffiBuffer($(self.var(in_)).size * $(*size))
+                for (obj in $(self.var(in_))) $(self.var(ptr))_0.putLong(obj.box.borrow())
+                $(self.var(len)) = $(self.var(in_)).size.toLong()
+                $(self.var(ptr)) = ffiLowerBytes($(self.var(ptr))_0.array(), $(*align))
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::WriteBack(in_, ptr, _, cap, ty) => {
+                let (size, _) = self.abi.layout(*ty);
+                quote! {
+                    if ($(self.var(cap)) > 0) {
+                        val $(self.var(ptr))_1 = ffiRead($(self.var(ptr)), $(self.var(cap)) * $size)
+                        for (i in $(self.var(in_)).indices) $(self.var(in_))[i] = $(self.var(ptr))_1.$(self.get_num(*ty))
+                    }
+                }
+            }
+            Instr::Deallocate(ptr, len, size, align) => quote! {
+                ffiFreeBytes($(self.var(ptr)), $(self.var(len)) * $(*size), $(*align))
+            },
+            Instr::LiftString(ptr, len, out) => quote! {
+                val $(self.var(out)) = ffiLiftString($(self.var(ptr)), $(self.var(len)))
+            },
+            Instr::LiftVec(ptr, len, out, ty) => {
+                let (size, _) = self.abi.layout(*ty);
+                quote! {
+                    val $(self.var(out))_0 = ffiRead($(self.var(ptr)), $(self.var(len)) * $size)
+                    val $(self.var(out)) = List($(self.var(len)).toInt()) { $(self.var(out))_0.$(self.get_num(*ty)) }
+                }
+            }
+            Instr::HandleNull(var) => quote! {
+                if ($(self.var(var)).toInt() == 0) {
+                    return null
+                }
+            },
+            Instr::LowerOption(arg, var, some, some_instr) => quote! {
+                if ($(self.var(arg)) != null) {
+                    val $(self.var(some)) = $(self.var(arg))
+                    $(self.var(var)) = 1
+                    $(for instr in some_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                } else {
+                    $(self.var(var)) = 0
+                }
+            },
+            Instr::LowerResult(arg, var, ok, ok_instr, err, err_instr) => quote! {
+                if ($(self.var(arg)).isSuccess) {
+                    val $(self.var(ok)) = $(self.var(arg)).getOrThrow()
+                    $(self.var(var)) = 1
+                    $(for instr in ok_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                } else {
+                    val $(self.var(err)) = $(self.var(arg)).exceptionOrNull()?.message ?: ""
+                    $(self.var(var)) = 0
+                    $(for instr in err_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                }
+            },
+            Instr::HandleError(var, ptr, len, cap) => quote! {
+                if ($(self.var(var)).toInt() == 0) {
+                    val $(self.var(var))_0 = ffiLiftString($(self.var(ptr)), $(self.var(len)))
+                    ffiFreeBytes($(self.var(ptr)), $(self.var(cap)), 1)
+                    throw FfiError($(self.var(var))_0)
+                }
+            },
+            Instr::LiftObject(obj, box_, drop, out) => quote! {
+                val $(self.var(out)) = $(self.type_ident(obj))(FfiBox($(self.var(box_)), $(self.drop_fn(drop))))
+            },
+            Instr::LiftRefObject(obj, box_, _leak, out) => quote! {
+                val $(self.var(out)) = $(self.type_ident(obj))(FfiBox($(self.var(box_)), null))
+            },
+            Instr::LiftIter(box_, next, drop, out) => quote! {
+                val $(self.var(out)) = FfiIter(FfiBox($(self.var(box_)), $(self.drop_fn(drop))), $(format!("::{}", wrapper_name(next))))
+            },
+            Instr::LiftRefIter(box_, next, _leak, out) => quote! {
+                val $(self.var(out)) = FfiIter(FfiBox($(self.var(box_)), null), $(format!("::{}", wrapper_name(next))))
+            },
+            Instr::LiftFuture(box_, poll, drop, out) => quote! {
+                val $(self.var(out)) = ffiAwait(FfiBox($(self.var(box_)), $(self.drop_fn(drop))), $(format!("::{}", wrapper_name(poll))))
+            },
+            Instr::LiftStream(box_, poll, drop, out) => quote! {
+                val $(self.var(out)) = ffiStream(FfiBox($(self.var(box_)), $(self.drop_fn(drop))), $(format!("::{}", wrapper_name(poll))))
+            },
+            Instr::LiftTuple(vars, out) => match vars.len() {
+                0 => quote!(val $(self.var(out)) = Unit),
+                1 => quote!(val $(self.var(out)) = $(self.var(&vars[0]))),
+                2 => {
+                    quote!(val $(self.var(out)) = Pair($(self.var(&vars[0])), $(self.var(&vars[1]))))
+                }
+                3 => quote! {
+                    val $(self.var(out)) = Triple($(self.var(&vars[0])), $(self.var(&vars[1])), $(self.var(&vars[2])))
+                },
+                _ => quote! {
+                    val $(self.var(out)) = $(tuple_name(vars.len()))($(for var in vars join (, ) => $(self.var(var))))
+                },
+            },
+            Instr::Call(symbol, ret, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.var(arg))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let call = format!("ffiLib.{}({})", symbol, args);
+                quote! {
+                    $(match ret {
+                        Some(ret) if !body.void_call => val $(self.var(ret)) = $call,
+                        _ => $call,
+                    })
+                    $(for value in &body.fences => $['\r']Reference.reachabilityFence($value))
+                }
+            }
+            Instr::BindRets(ret, vars) => match vars.len() {
+                0 => quote!(),
+                1 => quote!(val $(self.var(&vars[0])) = $(self.var(ret))),
+                _ => quote! {
+                    $(for (i, var) in vars.iter().enumerate() =>
+                        val $(self.var(var)) = $(self.var(ret)).$(format!("ret{}", i))$['\r'])
+                },
+            },
+            Instr::ReturnValue(ret) => quote!(return $(self.var(ret))),
+            Instr::ReturnVoid if body.unit => quote!(return Unit),
+            Instr::ReturnVoid => quote!(return),
+            Instr::LiftNumFromU32Tuple(..)
+            | Instr::LowerNumFromU32Tuple(..)
+            | Instr::CallRetArea(..) => unreachable!(),
+        }
+    }
+
+    /// Lambda calling the destructor `drop`.
+    fn drop_fn(&self, drop: &str) -> String {
+        format!("{{ boxed -> ffiLib.{}(0, boxed) }}", drop)
+    }
+
+    /// Converts an ffi value to the type of `ty`, unsigned numbers are
+    /// passed as signed ones of the same size.
+    fn lift_num(&self, ty: &AbiType) -> &'static str {
+        match ty {
+            AbiType::Num(NumType::U8) => ".toUByte()",
+            AbiType::Num(NumType::U16) => ".toUShort()",
+            AbiType::Num(NumType::U32) => ".toUInt()",
+            AbiType::Num(NumType::U64 | NumType::UPtr) | AbiType::Usize => ".toULong()",
+            _ => "",
+        }
+    }
+
+    /// Writes `value` to a `ByteBuffer`.
+    fn put_num(&self, ty: NumType, value: &str) -> String {
+        let ffi = self.ffi_num_type(ty);
+        let method = if ffi == "Byte" { "" } else { ffi };
+        format!("put{}({}.to{}())", method, value, ffi)
+    }
+
+    /// Reads the next value of type `ty` from a `ByteBuffer`.
+    fn get_num(&self, ty: NumType) -> String {
+        let ffi = self.ffi_num_type(ty);
+        let method = if ffi == "Byte" { "" } else { ffi };
+        format!("get{}(){}", method, self.lift_num(&AbiType::Num(ty)))
+    }
+
+    fn arg_type(&self, ty: &AbiType) -> String {
+        match ty {
+            AbiType::RefMutSlice(ty) => format!("MutableList<{}>", self.num_type(*ty)),
+            AbiType::Future(_)
+            | AbiType::RefFuture(_)
+            | AbiType::Stream(_)
+            | AbiType::RefStream(_) => unimplemented!("futures and streams can only be returned"),
+            ty => self.value_type(ty, true),
+        }
+    }
+
+    /// Type of a value, results passed as arguments are wrapped in a
+    /// `Result`, returned errors are thrown.
+    fn value_type(&self, ty: &AbiType, arg: bool) -> String {
+        match ty {
+            AbiType::Num(ty) => self.num_type(*ty).to_string(),
+            AbiType::Isize => "Long".to_string(),
+            AbiType::Usize => "ULong".to_string(),
+            AbiType::Bool => "Boolean".to_string(),
+            AbiType::RefStr | AbiType::String => "String".to_string(),
+            AbiType::RefSlice(ty) | AbiType::RefMutSlice(ty) | AbiType::Vec(ty) => {
+                format!("List<{}>", self.num_type(*ty))
+            }
+            AbiType::RefSliceStr => "List<String>".to_string(),
+            AbiType::RefSliceObject(ty) => format!("List<{}>", self.type_ident(ty)),
+            AbiType::RefObject(ty) | AbiType::Object(ty) | AbiType::RefEnum(ty) => {
+                self.type_ident(ty)
+            }
+            AbiType::Option(ty) => format!("{}?", self.value_type(ty, arg)),
+            AbiType::Result(ty) if arg => format!("Result<{}>", self.value_type(ty, arg)),
+            AbiType::Result(ty) => self.value_type(ty, arg),
+            AbiType::Tuple(tys) => {
+                let tys = tys
+                    .iter()
+                    .map(|ty| self.value_type(ty, arg))
+                    .collect::<Vec<_>>();
+                match tys.len() {
+                    0 => "Unit".to_string(),
+                    1 => tys[0].clone(),
+                    2 => format!("Pair<{}>", tys.join(", ")),
+                    3 => format!("Triple<{}>", tys.join(", ")),
+                    _ => format!("{}<{}>", tuple_name(tys.len()), tys.join(", ")),
+                }
+            }
+            AbiType::Iter(ty) | AbiType::RefIter(ty) => {
+                format!("FfiIter<{}>", self.value_type(ty, false))
+            }
+            AbiType::Future(ty) | AbiType::RefFuture(ty) => self.value_type(ty, false),
+            AbiType::Stream(ty) | AbiType::RefStream(ty) => {
+                format!("Flow<{}>", self.value_type(ty, false))
+            }
+            AbiType::Buffer(ty) | AbiType::RefBuffer(ty) => ffi_buffer_name_for(*ty).to_string(),
+            AbiType::List(ty) => format!("FfiList{}", ty),
+        }
+    }
+
+    fn ffi_type(&self, var: &Var) -> &'static str {
+        match &var.ty {
+            AbiType::Num(num) => self.ffi_num_type(*num),
+            ty => unreachable!("{:?} is not an ffi type", ty),
+        }
+    }
+
+    /// JNA has no unsigned types.
+    fn ffi_num_type(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::U8 | NumType::I8 => "Byte",
+            NumType::U16 | NumType::I16 => "Short",
+            NumType::U32 | NumType::I32 => "Int",
+            NumType::U64 | NumType::I64 | NumType::UPtr | NumType::IPtr => "Long",
+            NumType::F32 => "Float",
+            NumType::F64 => "Double",
+        }
+    }
+
+    fn num_type(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::U8 => "UByte",
+            NumType::U16 => "UShort",
+            NumType::U32 => "UInt",
+            NumType::U64 => "ULong",
+            NumType::I8 => "Byte",
+            NumType::I16 => "Short",
+            NumType::I32 => "Int",
+            NumType::I64 => "Long",
+            NumType::F32 => "Float",
+            NumType::F64 => "Double",
+            NumType::IPtr => "Long",
+            NumType::UPtr => "ULong",
+        }
+    }
+
+    fn zero(&self, var: &Var) -> &'static str {
+        match self.ffi_type(var) {
+            "Float" => "0.0f",
+            "Double" => "0.0",
+            _ => "0",
+        }
+    }
+
+    fn generate_doc(&self, doc: &[String]) -> Tokens {
+        if doc.is_empty() {
+            return quote!();
+        }
+        quote! {
+            $(static_literal("/**"))
+            $(for line in doc => $(format!(" * {}", line).trim_end().to_string())$['\r'])
+            $(static_literal(" */"))
+        }
+    }
+
+    fn var(&self, var: &Var) -> String {
+        format!("tmp{}", var.binding)
+    }
+
+    fn type_ident(&self, s: &str) -> String {
+        sanitize_identifier(s)
+    }
+
+    fn ident(&self, s: &str) -> String {
+        sanitize_identifier(&s.to_lower_camel_case())
+    }
+}
+
+/// Property names of the generated tuple classes, continuing the ones of
+/// `Pair` and `Triple`.
+static TUPLE_FIELDS: [&str; 10] = [
+    "first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth", "ninth", "tenth",
+];
+
+fn tuple_name(size: usize) -> String {
+    format!("Tuple{}", size)
+}
+
+fn tuple_field(index: usize) -> String {
+    match TUPLE_FIELDS.get(index) {
+        Some(field) => field.to_string(),
+        None => format!("value{}", index + 1),
+    }
+}
+
+/// Sizes of the tuples in the interface that don't fit into a `Triple`.
+fn tuple_sizes(iface: &Interface) -> BTreeSet<usize> {
+    fn visit(ty: &Type, sizes: &mut BTreeSet<usize>) {
+        match ty {
+            Type::Tuple(tys) => {
+                if tys.len() > 3 {
+                    sizes.insert(tys.len());
+                }
+                tys.iter().for_each(|ty| visit(ty, sizes));
+            }
+            Type::Buffer(ty)
+            | Type::Ref(ty)
+            | Type::RefMut(ty)
+            | Type::Slice(ty)
+            | Type::Vec(ty)
+            | Type::Option(ty)
+            | Type::Result(ty)
+            | Type::Iter(ty)
+            | Type::Future(ty)
+            | Type::Stream(ty) => visit(ty, sizes),
+            _ => {}
+        }
+    }
+    let mut sizes = BTreeSet::new();
+    let methods = iface.objects.iter().flat_map(|obj| obj.methods.iter());
+    for func in iface.functions.iter().chain(methods) {
+        for (_, ty) in &func.args {
+            visit(ty, &mut sizes);
+        }
+        if let Some(ty) = &func.ret {
+            visit(ty, &mut sizes);
+        }
+    }
+    sizes
+}
+
+/// Whether a value of type `ty` is borrowed by rust for the duration of a
+/// call.
+fn borrows(ty: &AbiType) -> bool {
+    match ty {
+        AbiType::RefObject(_)
+        | AbiType::RefSliceObject(_)
+        | AbiType::RefIter(_)
+        | AbiType::RefBuffer(_) => true,
+        AbiType::Option(ty) | AbiType::Result(ty) => borrows(ty),
+        _ => false,
+    }
+}
+
+/// Name of the wrapper around the `next` or `poll` function `symbol`.
+fn wrapper_name(symbol: &str) -> String {
+    format!("wrap_{}", symbol.trim_start_matches('_'))
+}
+
+/// Entry name and object of every enum entry wrapping an object.
+fn inner_objects(e: &Enum) -> Vec<(String, String)> {
+    e.entries
+        .iter()
+        .filter_map(|entry| match &entry.inner {
+            Some(Type::Ident(name)) => Some((entry.name.clone(), name.clone())),
+            Some(_) => unimplemented!("Enums can only wrap objects"),
+            None => None,
+        })
+        .collect()
+}
+
+/// Collects the variables defined inside of `LowerOption` and `LowerResult`.
+fn nested_defines(instr: &Instr, vars: &mut Vec<Var>) {
+    fn collect(instrs: &[Instr], vars: &mut Vec<Var>) {
+        for instr in instrs {
+            if let Instr::DefineArgs(defines) = instr {
+                vars.extend(defines.iter().cloned());
+            }
+            nested_defines(instr, vars);
+        }
+    }
+    match instr {
+        Instr::LowerOption(_, _, _, some) => collect(some, vars),
+        Instr::LowerResult(_, _, _, ok, _, err) => {
+            collect(ok, vars);
+            collect(err, vars);
+        }
+        _ => {}
+    }
+}
+
+fn sanitize_identifier(id: &str) -> String {
+    if RESERVED_IDENTIFIERS.contains(&id) {
+        format!("{}_", id)
+    } else {
+        id.to_string()
+    }
+}
+
+// https://kotlinlang.org/docs/keyword-reference.html#hard-keywords
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 28] = [
+    "as",
+    "break",
+    "class",
+    "continue",
+    "do",
+    "else",
+    "false",
+    "for",
+    "fun",
+    "if",
+    "in",
+    "interface",
+    "is",
+    "null",
+    "object",
+    "package",
+    "return",
+    "super",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typealias",
+    "typeof",
+    "val",
+    "var",
+    "when",
+    "while",
+];
+
+#[cfg(feature = "test_runner")]
+#[doc(hidden)]
+pub mod test_runner {
+    use super::*;
+    use crate::RustGenerator;
+    use anyhow::Result;
+    use std::io::Write;
+    use tempfile::{NamedTempFile, TempDir};
+    use trybuild::TestCases;
+
+    /// JNA and kotlinx-coroutines-core are taken from `CLASSPATH`.
+    pub fn compile_pass(iface: &str, rust: rust::Tokens, kotlin: Tokens) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let (mut rust_file, rust_file_path) = NamedTempFile::new()?.keep()?;
+        writeln!(rust_file, "#![feature(vec_into_raw_parts)]")?;
+        writeln!(rust_file, "#![feature(lazy_cell)]")?;
+        let rust_gen = RustGenerator::new(Abi::native());
+        let rust_tokens = rust_gen.generate(iface.clone());
+        let kotlin_gen =
+            KotlinGenerator::new("compile_pass".to_string(), "compile_pass".to_string());
+        let module = kotlin_gen.generate(iface).to_file_string()?;
+
+        let library_tokens: genco::Tokens<genco::lang::Rust> = quote! {
+            $rust_tokens
+            $rust
+        };
+
+        let main_tokens: Tokens = quote! {
+            import compile_pass.*
+            import kotlinx.coroutines.*
+            import kotlinx.coroutines.flow.*
+
+            fun main() {
+                runBlocking {
+                    $kotlin
+                }
+            }
+        };
+
+        let library_dir = TempDir::new()?.keep();
+        let library_file = library_dir.join("libcompile_pass.so");
+        let jar_file = library_dir.join("main.jar");
+        std::fs::write(library_dir.join("api.kt"), module)?;
+        std::fs::write(library_dir.join("main.kt"), main_tokens.to_file_string()?)?;
+
+        let library = library_tokens.to_file_string()?;
+        rust_file.write_all(library.as_bytes())?;
+
+        let classpath = std::env::var("CLASSPATH").unwrap_or_default();
+        let runtime_classpath = format!("{}:{}", jar_file.to_str().unwrap(), classpath);
+        let runner_tokens: rust::Tokens = quote! {
+            fn main() {
+                use std::process::Command;
+                let ret = Command::new("rustc")
+                    .arg("--edition")
+                    .arg("2021")
+                    .arg("--crate-name")
+                    .arg("compile_pass")
+                    .arg("--crate-type")
+                    .arg("cdylib")
+                    .arg("--cfg")
+                    .arg("feature=\"test_runner\"")
+                    .arg("-o")
+                    .arg($(quoted(library_file.as_path().to_str().unwrap())))
+                    .arg($(quoted(rust_file_path.as_path().to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+                let ret = Command::new("kotlinc")
+                    .current_dir($(quoted(library_dir.as_path().to_str().unwrap())))
+                    .arg("-cp")
+                    .arg($(quoted(&classpath)))
+                    .arg("-include-runtime")
+                    .arg("-d")
+                    .arg($(quoted(jar_file.as_path().to_str().unwrap())))
+                    .arg("api.kt")
+                    .arg("main.kt")
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+                let ret = Command::new("java")
+                    .arg($(quoted(format!("-Djna.library.path={}", library_dir.to_str().unwrap()))))
+                    .arg("-cp")
+                    .arg($(quoted(&runtime_classpath)))
+                    .arg("MainKt")
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+            }
+        };
+        let (mut runner_file, runner_file_path) = NamedTempFile::new()?.keep()?;
+        let runner = runner_tokens.to_file_string()?;
+        runner_file.write_all(runner.as_bytes())?;
+
+        let test = TestCases::new();
+        test.pass(runner_file_path.as_path().to_str().unwrap());
+        Ok(())
+    }
+}
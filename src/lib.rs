@@ -5,49 +5,145 @@
 #![feature(vec_into_raw_parts)]
 
 mod abi;
+mod c;
+mod cpp;
+mod csharp;
 mod dart;
+#[cfg(feature = "derive")]
+mod derive;
+mod go;
 mod js;
+mod kotlin;
+mod lint;
 mod parser;
+mod python;
+mod ruby;
 mod rust;
+mod swift;
+mod wasm;
+mod wit;
 
 use crate::abi::{
     export, import, AbiFunction, AbiFuture, AbiIter, AbiObject, AbiStream, AbiType, FunctionType,
-    NumType, Return, Var,
+    NumType, Return, Symbols, Var, NUM_TYPES,
 };
+use crate::c::CGenerator;
+use crate::cpp::CppGenerator;
+use crate::csharp::CSharpGenerator;
 use crate::dart::DartGenerator;
+use crate::go::GoGenerator;
 use crate::js::{JsGenerator, TsGenerator, WasmMultiValueShim};
-use crate::parser::Interface;
+use crate::kotlin::KotlinGenerator;
+use crate::python::PythonGenerator;
+use crate::ruby::RubyGenerator;
 use crate::rust::RustGenerator;
+use crate::swift::SwiftGenerator;
+use crate::wit::WitGenerator;
 use anyhow::{Context, Result};
 use std::path::Path;
 use std::process::Command;
+use std::str::FromStr;
 
 pub use crate::abi::Abi;
+pub use crate::lint::{Language, Lint, LintKind, Rename};
+pub use crate::parser::{
+    Enum, EnumEntry, Function, Interface, InterfaceBuilder, Object, Runtime, Type,
+};
+pub use crate::wit::Unsupported;
 
 /// Main entry point to `ffi-gen`.
 pub struct FfiGen {
     iface: Interface,
+    source: Option<String>,
+}
+
+impl FromStr for FfiGen {
+    type Err = anyhow::Error;
+
+    /// Parses an ffi-gen interface description.
+    fn from_str(s: &str) -> Result<Self> {
+        let iface = Interface::parse(s)?;
+        Ok(Self {
+            iface,
+            source: None,
+        })
+    }
 }
 
 impl FfiGen {
     /// Takes a path to an ffi-gen interface description file and constructs
     /// a new `FfiGen` instance.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let s = std::fs::read_to_string(path)?;
-        let iface = Interface::parse(&s)?;
-        Ok(Self { iface })
+        let s = std::fs::read_to_string(path.as_ref())?;
+        let mut ffigen: Self = s.parse()?;
+        ffigen.source = Some(path.as_ref().display().to_string());
+        Ok(ffigen)
+    }
+
+    /// Constructs a new `FfiGen` instance from an interface built with an
+    /// [`InterfaceBuilder`].
+    pub fn from_interface(iface: Interface) -> Self {
+        Self {
+            iface,
+            source: None,
+        }
+    }
+
+    /// Constructs a new `FfiGen` instance from the items annotated with
+    /// `#[ffi_gen_macro::export]` in a rust source file.
+    #[cfg(feature = "derive")]
+    pub fn from_rust_source(source: &str) -> Result<Self> {
+        let iface = InterfaceBuilder::new().rust_source(source)?.build()?;
+        Ok(Self::from_interface(iface))
+    }
+
+    /// Checks the interface for identifiers that can't be used verbatim in all
+    /// generated languages and for unused objects and enums.
+    pub fn lint(&self) -> Vec<Lint> {
+        self.iface.lint()
     }
 
     /// Generates the rust api.
     pub fn generate_rust(&self, abi: Abi) -> Result<String> {
-        let rust = RustGenerator::new(abi);
+        let rust = RustGenerator::new(abi).source(self.source.clone());
         let rust = rust.generate(self.iface.clone()).to_file_string()?;
         Ok(rust)
     }
 
-    /// Patches the ffi functions in a wasm blob to use multi-value returns.
+    /// Patches the ffi functions in a wasm32 blob to use multi-value returns.
+    ///
+    /// The patched module is written to `{path}.multivalue.wasm`.
     pub fn wasm_multi_value_shim<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        WasmMultiValueShim::new().run(path, self.iface.clone())
+        self.wasm_multi_value_shim_with_abi(Abi::Wasm32, path)
+    }
+
+    /// Like [`FfiGen::wasm_multi_value_shim`], for a blob compiled for one of
+    /// the wasm abis.
+    pub fn wasm_multi_value_shim_with_abi<P: AsRef<Path>>(&self, abi: Abi, path: P) -> Result<()> {
+        WasmMultiValueShim::new(abi).run(path, self.iface.clone())
+    }
+
+    /// Generates a c header declaring the functions exported by the rust api.
+    pub fn generate_c_header<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let header = CGenerator::new()
+            .generate(self.iface.clone())
+            .to_file_string()?;
+        std::fs::write(path.as_ref(), header)?;
+        Ok(())
+    }
+
+    /// Generates a header-only c++17 wrapper in namespace `namespace` around
+    /// the c header included as `c_header`.
+    pub fn generate_cpp<P: AsRef<Path>>(
+        &self,
+        path: P,
+        c_header: &str,
+        namespace: &str,
+    ) -> Result<()> {
+        let cpp = CppGenerator::new(c_header.to_string(), namespace.to_string());
+        let cpp = cpp.generate(self.iface.clone()).to_file_string()?;
+        std::fs::write(path.as_ref(), cpp)?;
+        Ok(())
     }
 
     /// Generates dart bindings for the rust api.
@@ -71,9 +167,148 @@ impl FfiGen {
         Ok(())
     }
 
-    /// Generates js bindings for the rust api.
+    /// Generates python bindings for the rust api using `ctypes`.
+    ///
+    /// The type stubs are written next to the module, with the extension
+    /// replaced by `.pyi`.
+    pub fn generate_python<P: AsRef<Path>>(&self, path: P, cdylib: &str) -> Result<()> {
+        let python = PythonGenerator::new(cdylib.to_string());
+        let module = python.generate(self.iface.clone()).to_file_string()?;
+        std::fs::write(path.as_ref(), module)?;
+        let stubs = python.generate_stubs(self.iface.clone()).to_file_string()?;
+        std::fs::write(path.as_ref().with_extension("pyi"), stubs)?;
+        Ok(())
+    }
+
+    /// Generates a swift module wrapping the c header, which needs to be
+    /// importable as the clang module `c_module`.
+    pub fn generate_swift<P: AsRef<Path>>(&self, path: P, c_module: &str) -> Result<()> {
+        let swift = SwiftGenerator::new(c_module.to_string());
+        let swift = swift.generate(self.iface.clone()).to_file_string()?;
+        std::fs::write(path.as_ref(), swift)?;
+        Ok(())
+    }
+
+    /// Generates kotlin bindings in `package` for the rust api using JNA.
+    ///
+    /// The generated file depends on JNA and kotlinx-coroutines-core.
+    pub fn generate_kotlin<P: AsRef<Path>>(
+        &self,
+        path: P,
+        package: &str,
+        cdylib: &str,
+    ) -> Result<()> {
+        let kotlin = KotlinGenerator::new(package.to_string(), cdylib.to_string());
+        let kotlin = kotlin.generate(self.iface.clone()).to_file_string()?;
+        std::fs::write(path.as_ref(), kotlin)?;
+        Ok(())
+    }
+
+    /// Generates c# bindings in `namespace` for the rust api.
+    ///
+    /// The generated file needs to be compiled with `AllowUnsafeBlocks`.
+    pub fn generate_csharp<P: AsRef<Path>>(
+        &self,
+        path: P,
+        namespace: &str,
+        cdylib: &str,
+    ) -> Result<()> {
+        let csharp = CSharpGenerator::new(namespace.to_string(), cdylib.to_string());
+        let csharp = csharp.generate(self.iface.clone()).to_file_string()?;
+        std::fs::write(path.as_ref(), csharp)?;
+        Ok(())
+    }
+
+    /// Generates a go package named `package` using cgo to call the rust api
+    /// through the c header `c_header` generated with [`FfiGen::generate_c_header`].
+    pub fn generate_go<P: AsRef<Path>>(
+        &self,
+        path: P,
+        package: &str,
+        c_header: &str,
+        cdylib: &str,
+    ) -> Result<()> {
+        let go = GoGenerator::new(
+            package.to_string(),
+            c_header.to_string(),
+            cdylib.to_string(),
+        );
+        let go = go.generate(self.iface.clone()).to_file_string()?;
+        std::fs::write(path.as_ref(), go)?;
+        Ok(())
+    }
+
+    /// Generates a ruby module named `module` for the rust api using the ffi
+    /// gem.
+    pub fn generate_ruby<P: AsRef<Path>>(&self, path: P, module: &str, cdylib: &str) -> Result<()> {
+        let ruby = RubyGenerator::new(module.to_string(), cdylib.to_string());
+        let ruby = ruby.generate(self.iface.clone()).to_file_string()?;
+        std::fs::write(path.as_ref(), ruby)?;
+        Ok(())
+    }
+
+    /// Generates a wit world exporting the interface `api` in `package`, e.g.
+    /// `effektio:api`, for use with the component model.
+    ///
+    /// Functions using types without a wit equivalent are left out and
+    /// returned, so the same interface can feed the other generators.
+    pub fn generate_wit<P: AsRef<Path>>(
+        &self,
+        path: P,
+        package: &str,
+        world: &str,
+    ) -> Result<Vec<Unsupported>> {
+        let wit = WitGenerator::new(package.to_string(), world.to_string());
+        let (wit, unsupported) = wit.generate(self.iface.clone());
+        std::fs::write(path.as_ref(), wit.to_file_string()?)?;
+        Ok(unsupported)
+    }
+
+    /// Generates js bindings for the rust api compiled for wasm32.
+    ///
+    /// The wasm module needs to be patched with
+    /// [`FfiGen::wasm_multi_value_shim`].
     pub fn generate_js<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let js = JsGenerator::default();
+        self.generate_js_with_abi(Abi::Wasm32, path)
+    }
+
+    /// Generates js bindings for the rust api compiled for one of the wasm
+    /// abis.
+    ///
+    /// With `Abi::Wasm32RetArea` or `Abi::Wasm64RetArea` the wasm module can be
+    /// loaded as is, otherwise it needs to be patched with
+    /// [`FfiGen::wasm_multi_value_shim_with_abi`].
+    pub fn generate_js_with_abi<P: AsRef<Path>>(&self, abi: Abi, path: P) -> Result<()> {
+        let js = JsGenerator::new(abi);
+        let js = js.generate(self.iface.clone()).to_file_string()?;
+        std::fs::write(path.as_ref(), js)?;
+        let status = Command::new("prettier")
+            .arg("--write")
+            .arg(path.as_ref())
+            .status()
+            .context("prettier not installed")?;
+        if !status.success() {
+            anyhow::bail!("prettier failed");
+        }
+        Ok(())
+    }
+
+    /// Generates the rust api with a Node-API module, so the native cdylib can
+    /// be loaded as a node addon by the bindings of [`FfiGen::generate_js_napi`].
+    pub fn generate_rust_napi(&self) -> Result<String> {
+        let rust = RustGenerator::new(Abi::native())
+            .source(self.source.clone())
+            .napi(true);
+        let rust = rust.generate(self.iface.clone()).to_file_string()?;
+        Ok(rust)
+    }
+
+    /// Generates js bindings loading the native cdylib as a node addon.
+    ///
+    /// The bindings have the same api as the wasm ones, so the same typescript
+    /// definitions apply.
+    pub fn generate_js_napi<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let js = JsGenerator::napi();
         let js = js.generate(self.iface.clone()).to_file_string()?;
         std::fs::write(path.as_ref(), js)?;
         let status = Command::new("prettier")
@@ -87,9 +322,15 @@ impl FfiGen {
         Ok(())
     }
 
-    /// Generates typescript type definitions for the js bindings.
+    /// Generates typescript type definitions for the wasm32 js bindings.
     pub fn generate_ts<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let ts = TsGenerator::default();
+        self.generate_ts_with_abi(Abi::Wasm32, path)
+    }
+
+    /// Generates typescript type definitions for the js bindings of one of the
+    /// wasm abis.
+    pub fn generate_ts_with_abi<P: AsRef<Path>>(&self, abi: Abi, path: P) -> Result<()> {
+        let ts = TsGenerator::new(abi);
         let ts = ts.generate(self.iface.clone()).to_file_string()?;
         std::fs::write(path.as_ref(), ts)?;
         let status = Command::new("prettier")
@@ -107,10 +348,28 @@ impl FfiGen {
 #[cfg(feature = "test_runner")]
 #[doc(hidden)]
 pub mod test_runner {
+    pub use crate::c::test_runner::compile_pass as compile_pass_c;
+    pub use crate::cpp::test_runner::compile_pass as compile_pass_cpp;
+    pub use crate::cpp::test_runner::compile_pass_header as compile_pass_cpp_header;
+    pub use crate::csharp::test_runner::compile_pass as compile_pass_csharp;
     pub use crate::dart::test_runner::compile_pass as compile_pass_dart;
+    pub use crate::go::test_runner::compile_pass as compile_pass_go;
+    pub use crate::js::test_runner::build_wasm64 as build_js_wasm64;
     pub use crate::js::test_runner::compile_pass as compile_pass_js;
+    pub use crate::js::test_runner::compile_pass_napi as compile_pass_js_napi;
+    pub use crate::js::test_runner::compile_pass_ret_area as compile_pass_js_ret_area;
     pub use crate::js::test_runner::compile_pass_ts;
+    pub use crate::js::test_runner::compile_pass_wasm64 as compile_pass_js_wasm64;
+    pub use crate::js::test_runner::node_supports_wasm64;
+    pub use crate::kotlin::test_runner::compile_pass as compile_pass_kotlin;
+    pub use crate::python::test_runner::compile_pass as compile_pass_python;
+    pub use crate::python::test_runner::compile_pass_import as compile_pass_python_import;
+    pub use crate::python::test_runner::compile_pass_shared as compile_pass_python_shared;
+    pub use crate::ruby::test_runner::compile_pass as compile_pass_ruby;
+    pub use crate::rust::test_runner::compile_fail as compile_fail_rust;
     pub use crate::rust::test_runner::compile_pass as compile_pass_rust;
+    pub use crate::rust::test_runner::compile_pass_abi as compile_pass_rust_abi;
+    pub use crate::swift::test_runner::compile_pass as compile_pass_swift;
 
     #[macro_export]
     macro_rules! compile_pass {
@@ -121,6 +380,21 @@ pub mod test_runner {
                     $crate::test_runner::compile_pass_rust($iface, genco::quote!($($api)*), genco::quote!($($rust)*)).unwrap();
                 }
 
+                #[test]
+                fn c() {
+                    $crate::test_runner::compile_pass_c($iface).unwrap();
+                }
+
+                #[test]
+                fn cpp() {
+                    $crate::test_runner::compile_pass_cpp_header($iface).unwrap();
+                }
+
+                #[test]
+                fn python() {
+                    $crate::test_runner::compile_pass_python_import($iface).unwrap();
+                }
+
                 #[test]
                 fn dart() {
                     $crate::test_runner::compile_pass_dart($iface, genco::quote!($($api)*), genco::quote!($($dart)*)).unwrap();
@@ -131,6 +405,11 @@ pub mod test_runner {
                     $crate::test_runner::compile_pass_js($iface, genco::quote!($($api)*), genco::quote!($($js)*)).unwrap();
                 }
 
+                #[test]
+                fn js_ret_area() {
+                    $crate::test_runner::compile_pass_js_ret_area($iface, genco::quote!($($api)*), genco::quote!($($js)*)).unwrap();
+                }
+
                 #[test]
                 fn ts() {
                     $crate::test_runner::compile_pass_ts($iface, genco::quote!($($ts)*)).unwrap();
@@ -139,6 +418,123 @@ pub mod test_runner {
         }
     }
 
+    #[macro_export]
+    macro_rules! compile_pass_wasm64 {
+        ($ident:ident, $iface:expr, ($($api:tt)*), [$($expected:expr),* $(,)?], ($($js:tt)*)) => {
+            mod $ident {
+                #[test]
+                fn build() {
+                    $crate::test_runner::build_js_wasm64($iface, genco::quote!($($api)*), &[$($expected),*]).unwrap();
+                }
+
+                #[test]
+                fn js() {
+                    if !$crate::test_runner::node_supports_wasm64() {
+                        eprintln!("skipped, node can't load modules with 64bit memories and tables");
+                        return;
+                    }
+                    $crate::test_runner::compile_pass_js_wasm64($iface, genco::quote!($($api)*), genco::quote!($($js)*)).unwrap();
+                }
+            }
+        }
+    }
+
+    #[macro_export]
+    macro_rules! compile_pass_napi {
+        ($ident:ident, $iface:expr, ($($api:tt)*), ($($js:tt)*)) => {
+            mod $ident {
+                #[test]
+                fn js() {
+                    $crate::test_runner::compile_pass_js_napi($iface, genco::quote!($($api)*), genco::quote!($($js)*)).unwrap();
+                }
+            }
+        }
+    }
+
+    #[macro_export]
+    macro_rules! compile_pass_cpp {
+        ($ident:ident, $iface:expr, ($($api:tt)*), ($($cpp:tt)*)) => {
+            mod $ident {
+                #[test]
+                fn cpp() {
+                    $crate::test_runner::compile_pass_cpp($iface, genco::quote!($($api)*), genco::quote!($($cpp)*)).unwrap();
+                }
+            }
+        }
+    }
+
+    #[macro_export]
+    macro_rules! compile_pass_python {
+        ($ident:ident, $iface:expr, ($($api:tt)*), ($($python:tt)*)) => {
+            mod $ident {
+                #[test]
+                fn python() {
+                    $crate::test_runner::compile_pass_python($iface, genco::quote!($($api)*), genco::quote!($($python)*)).unwrap();
+                }
+            }
+        }
+    }
+
+    #[macro_export]
+    macro_rules! compile_pass_swift {
+        ($ident:ident, $iface:expr, ($($api:tt)*), ($($swift:tt)*)) => {
+            mod $ident {
+                #[test]
+                fn swift() {
+                    $crate::test_runner::compile_pass_swift($iface, genco::quote!($($api)*), genco::quote!($($swift)*)).unwrap();
+                }
+            }
+        }
+    }
+
+    #[macro_export]
+    macro_rules! compile_pass_kotlin {
+        ($ident:ident, $iface:expr, ($($api:tt)*), ($($kotlin:tt)*)) => {
+            mod $ident {
+                #[test]
+                fn kotlin() {
+                    $crate::test_runner::compile_pass_kotlin($iface, genco::quote!($($api)*), genco::quote!($($kotlin)*)).unwrap();
+                }
+            }
+        }
+    }
+
+    #[macro_export]
+    macro_rules! compile_pass_csharp {
+        ($ident:ident, $iface:expr, ($($api:tt)*), ($($csharp:tt)*)) => {
+            mod $ident {
+                #[test]
+                fn csharp() {
+                    $crate::test_runner::compile_pass_csharp($iface, genco::quote!($($api)*), genco::quote!($($csharp)*)).unwrap();
+                }
+            }
+        }
+    }
+
+    #[macro_export]
+    macro_rules! compile_pass_go {
+        ($ident:ident, $iface:expr, ($($api:tt)*), ($($go:tt)*)) => {
+            mod $ident {
+                #[test]
+                fn go() {
+                    $crate::test_runner::compile_pass_go($iface, genco::quote!($($api)*), genco::quote!($($go)*)).unwrap();
+                }
+            }
+        }
+    }
+
+    #[macro_export]
+    macro_rules! compile_pass_ruby {
+        ($ident:ident, $iface:expr, ($($api:tt)*), ($($ruby:tt)*)) => {
+            mod $ident {
+                #[test]
+                fn ruby() {
+                    $crate::test_runner::compile_pass_ruby($iface, genco::quote!($($api)*), genco::quote!($($ruby)*)).unwrap();
+                }
+            }
+        }
+    }
+
     #[macro_export]
     macro_rules! compile_pass_no_js {
         ($ident:ident, $iface:expr, ($($api:tt)*), ($($rust:tt)*), ($($dart:tt)*)) => {
@@ -148,6 +544,21 @@ pub mod test_runner {
                     $crate::test_runner::compile_pass_rust($iface, genco::quote!($($api)*), genco::quote!($($rust)*)).unwrap();
                 }
 
+                #[test]
+                fn c() {
+                    $crate::test_runner::compile_pass_c($iface).unwrap();
+                }
+
+                #[test]
+                fn cpp() {
+                    $crate::test_runner::compile_pass_cpp_header($iface).unwrap();
+                }
+
+                #[test]
+                fn python() {
+                    $crate::test_runner::compile_pass_python_import($iface).unwrap();
+                }
+
                 #[test]
                 fn dart() {
                     $crate::test_runner::compile_pass_dart($iface, genco::quote!($($api)*), genco::quote!($($dart)*)).unwrap();
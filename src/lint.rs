@@ -0,0 +1,566 @@
+use crate::parser::{Function, Interface, Type};
+use heck::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Language an identifier is emitted in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Language {
+    /// Rust api
+    Rust,
+    /// Dart bindings
+    Dart,
+    /// Js bindings
+    Js,
+    /// Typescript definitions
+    Ts,
+    /// C++ wrapper
+    Cpp,
+    /// Python bindings
+    Python,
+    /// Swift bindings
+    Swift,
+    /// Kotlin bindings
+    Kotlin,
+    /// C# bindings
+    CSharp,
+    /// Go bindings
+    Go,
+    /// Ruby bindings
+    Ruby,
+    /// Wit world
+    Wit,
+}
+
+impl Language {
+    /// All languages identifiers are checked in.
+    pub const ALL: [Language; 12] = [
+        Self::Rust,
+        Self::Dart,
+        Self::Js,
+        Self::Ts,
+        Self::Cpp,
+        Self::Python,
+        Self::Swift,
+        Self::Kotlin,
+        Self::CSharp,
+        Self::Go,
+        Self::Ruby,
+        Self::Wit,
+    ];
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Rust => "rust",
+            Self::Dart => "dart",
+            Self::Js => "js",
+            Self::Ts => "ts",
+            Self::Cpp => "cpp",
+            Self::Python => "python",
+            Self::Swift => "swift",
+            Self::Kotlin => "kotlin",
+            Self::CSharp => "csharp",
+            Self::Go => "go",
+            Self::Ruby => "ruby",
+            Self::Wit => "wit",
+        })
+    }
+}
+
+/// Kind of problem found by the linter.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LintKind {
+    /// The identifier is reserved in some of the generated languages.
+    Reserved(Vec<Language>),
+    /// The identifier maps to the same name as another item after case conversion.
+    Collision {
+        /// The item it collides with.
+        other: String,
+        /// The name both items map to.
+        name: String,
+    },
+    /// The object isn't reachable from any function.
+    UnusedObject,
+    /// The enum isn't reachable from any function.
+    UnusedEnum,
+}
+
+/// Warning returned by [`FfiGen::lint`](crate::FfiGen::lint).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Lint {
+    /// Path of the item, e.g. `Greeter::greet` or `Greeter::greet(name)`.
+    pub item: String,
+    /// What is wrong with the item.
+    pub kind: LintKind,
+    /// Suggested name for the item.
+    ///
+    /// Always `None` for [`LintKind::UnusedObject`] and [`LintKind::UnusedEnum`],
+    /// renaming doesn't help there, the item needs to be used or removed.
+    pub rename: Option<Rename>,
+}
+
+/// Name suggested by a [`Lint`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Rename {
+    /// Name for a `#[rename = "..."]` attribute on the function, object or
+    /// enum, which keeps its rust name.
+    Attribute(String),
+    /// New identifier for the argument or enum entry, which have no rename
+    /// attribute. Enum entries need to be renamed in rust too.
+    Ident(String),
+}
+
+impl fmt::Display for Rename {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Attribute(name) => write!(f, "adding `#[rename = \"{}\"]`", name),
+            Self::Ident(name) => write!(f, "renaming it to `{}`", name),
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            LintKind::Reserved(langs) => {
+                let langs = langs.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+                write!(
+                    f,
+                    "`{}` is a reserved identifier in {}",
+                    self.item,
+                    langs.join(", ")
+                )?;
+            }
+            LintKind::Collision { other, name } => {
+                write!(f, "`{}` collides with `{}` as `{}`", self.item, other, name)?;
+            }
+            LintKind::UnusedObject => write!(f, "object `{}` is never used", self.item)?,
+            LintKind::UnusedEnum => write!(f, "enum `{}` is never used", self.item)?,
+        }
+        if let Some(rename) = &self.rename {
+            write!(f, ", consider {}", rename)?;
+        }
+        Ok(())
+    }
+}
+
+/// How an identifier is converted by the generators.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Class {
+    /// Objects and enums.
+    Type,
+    /// Functions and methods.
+    Function,
+    /// Arguments, only emitted in the bindings.
+    Arg,
+    /// Enum entries, emitted verbatim.
+    Entry,
+}
+
+impl Class {
+    fn convert(self, lang: Language, ident: &str) -> Option<String> {
+        match (self, lang) {
+            (Self::Arg, Language::Rust) => None,
+            (Self::Entry, Language::Js | Language::Ts) => None,
+            // exported and screaming case names can't clash with keywords, ruby emits enum entries
+            // as symbols.
+            (Self::Function, Language::CSharp | Language::Go)
+            | (Self::Entry, Language::Kotlin | Language::CSharp | Language::Go | Language::Ruby)
+            | (Self::Type, Language::Ruby) => None,
+            (_, Language::Wit) => Some(ident.to_kebab_case()),
+            (Self::Function | Self::Arg, Language::Python | Language::Ruby) => {
+                Some(ident.to_snake_case())
+            }
+            (Self::Entry, Language::Swift) => Some(ident.to_lower_camel_case()),
+            (Self::Entry, Language::Python) => Some(ident.to_upper_camel_case()),
+            (_, Language::Rust | Language::Cpp)
+            | (Self::Entry, _)
+            | (Self::Type, Language::Swift | Language::Kotlin | Language::CSharp | Language::Go) => {
+                Some(ident.to_string())
+            }
+            (Self::Type, _) => Some(ident.to_upper_camel_case()),
+            (_, Language::Ts) => None,
+            (_, _) => Some(ident.to_lower_camel_case()),
+        }
+    }
+
+    fn is_reserved(self, lang: Language, ident: &str) -> bool {
+        let reserved: &[&str] = match lang {
+            Language::Rust => &crate::rust::RESERVED_IDENTIFIERS,
+            Language::Dart => &crate::dart::RESERVED_IDENTIFIERS,
+            Language::Js => &crate::js::RESERVED_IDENTIFIERS,
+            Language::Ts => &crate::js::TS_RESERVED_TYPE_IDENTIFIERS,
+            Language::Cpp => &crate::cpp::RESERVED_IDENTIFIERS,
+            Language::Python => &crate::python::RESERVED_IDENTIFIERS,
+            Language::Swift => &crate::swift::RESERVED_IDENTIFIERS,
+            Language::Kotlin => &crate::kotlin::RESERVED_IDENTIFIERS,
+            Language::CSharp => &crate::csharp::RESERVED_IDENTIFIERS,
+            Language::Go => &crate::go::RESERVED_IDENTIFIERS,
+            Language::Ruby => &crate::ruby::RESERVED_IDENTIFIERS,
+            Language::Wit => &crate::wit::RESERVED_IDENTIFIERS,
+        };
+        self.convert(lang, ident)
+            .map(|ident| reserved.contains(&ident.as_str()))
+            .unwrap_or_default()
+    }
+
+    fn reserved_in(self, ident: &str) -> Vec<Language> {
+        Language::ALL
+            .into_iter()
+            .filter(|lang| self.is_reserved(*lang, ident))
+            .collect()
+    }
+
+    /// Name used to detect collisions between items of the same scope.
+    fn key(self, ident: &str) -> String {
+        match self {
+            Self::Type => ident.to_upper_camel_case(),
+            Self::Function | Self::Arg => ident.to_lower_camel_case(),
+            Self::Entry => ident.to_string(),
+        }
+    }
+
+    fn rename(self, name: String) -> Rename {
+        match self {
+            Self::Type | Self::Function => Rename::Attribute(name),
+            Self::Arg | Self::Entry => Rename::Ident(name),
+        }
+    }
+
+    fn suffix(self) -> &'static str {
+        match self {
+            Self::Type => "Type",
+            Self::Function => "_fn",
+            Self::Arg => "_arg",
+            Self::Entry => "Entry",
+        }
+    }
+}
+
+/// Identifiers of items that share a namespace in the generated code.
+#[derive(Default)]
+struct Scope {
+    names: HashMap<String, String>,
+}
+
+impl Scope {
+    fn is_free(&self, class: Class, ident: &str) -> bool {
+        !self.names.contains_key(&class.key(ident))
+    }
+}
+
+#[derive(Default)]
+struct Linter {
+    lints: Vec<Lint>,
+}
+
+impl Linter {
+    fn check(&mut self, scope: &mut Scope, class: Class, item: String, ident: &str) {
+        let key = class.key(ident);
+        let reserved = class.reserved_in(ident);
+        let collision = scope.names.get(&key).cloned();
+        let is_ok =
+            |ident: &str| class.reserved_in(ident).is_empty() && scope.is_free(class, ident);
+        let rename = || {
+            let candidates = [
+                format!("{}_", ident),
+                format!("{}{}", ident, class.suffix()),
+            ];
+            candidates
+                .into_iter()
+                .chain((2..).map(|i| format!("{}{}", ident, i)))
+                .find(|ident| is_ok(ident))
+                .map(|name| class.rename(name))
+        };
+        if !reserved.is_empty() {
+            self.lints.push(Lint {
+                item: item.clone(),
+                kind: LintKind::Reserved(reserved),
+                rename: rename(),
+            });
+        }
+        if let Some(other) = collision {
+            self.lints.push(Lint {
+                item,
+                kind: LintKind::Collision { other, name: key },
+                rename: rename(),
+            });
+        } else {
+            scope.names.insert(key, item);
+        }
+    }
+
+    fn check_function(&mut self, scope: &mut Scope, prefix: &str, func: &Function) {
+        let item = format!("{}{}", prefix, func.ident);
+        let name = func.rename.as_ref().unwrap_or(&func.ident);
+        self.check(scope, Class::Function, item.clone(), name);
+        let mut args = Scope::default();
+        for (name, _) in &func.args {
+            let arg = format!("{}({})", item, name);
+            self.check(&mut args, Class::Arg, arg, name);
+        }
+    }
+}
+
+fn find_idents<'a>(ty: &'a Type, idents: &mut HashSet<&'a str>) {
+    match ty {
+        Type::Ident(ident) => {
+            idents.insert(ident);
+        }
+        Type::Buffer(ty)
+        | Type::Ref(ty)
+        | Type::RefMut(ty)
+        | Type::Slice(ty)
+        | Type::Vec(ty)
+        | Type::Option(ty)
+        | Type::Result(ty)
+        | Type::Iter(ty)
+        | Type::Future(ty)
+        | Type::Stream(ty) => find_idents(ty, idents),
+        Type::Tuple(tys) => tys.iter().for_each(|ty| find_idents(ty, idents)),
+        _ => {}
+    }
+}
+
+fn find_function_idents<'a>(func: &'a Function, idents: &mut HashSet<&'a str>) {
+    for (_, ty) in &func.args {
+        find_idents(ty, idents);
+    }
+    if let Some(ty) = &func.ret {
+        find_idents(ty, idents);
+    }
+}
+
+impl Interface {
+    /// Checks the names in the bindings for reserved words and case conversion
+    /// collisions in all generated languages and looks for unused objects and
+    /// enums. Items are reported by their rust names.
+    pub fn lint(&self) -> Vec<Lint> {
+        let mut linter = Linter::default();
+
+        let mut types = Scope::default();
+        for obj in &self.objects {
+            let name = obj.rename.as_ref().unwrap_or(&obj.ident);
+            linter.check(&mut types, Class::Type, obj.ident.clone(), name);
+        }
+        for e in &self.enums {
+            let name = e.rename.as_ref().unwrap_or(&e.ident);
+            linter.check(&mut types, Class::Type, e.ident.clone(), name);
+        }
+
+        let mut functions = Scope::default();
+        for func in &self.functions {
+            linter.check_function(&mut functions, "", func);
+        }
+        for obj in &self.objects {
+            let mut methods = Scope::default();
+            let prefix = format!("{}::", obj.ident);
+            for method in &obj.methods {
+                linter.check_function(&mut methods, &prefix, method);
+            }
+        }
+        for e in &self.enums {
+            let mut entries = Scope::default();
+            for entry in &e.entries {
+                let item = format!("{}::{}", e.ident, entry.name);
+                linter.check(&mut entries, Class::Entry, item, &entry.name);
+            }
+        }
+
+        // objects are only usable if some function hands them out, calling an instance
+        // method requires an instance in the first place.
+        let mut used = HashSet::new();
+        for func in &self.functions {
+            find_function_idents(func, &mut used);
+        }
+        for obj in &self.objects {
+            for method in &obj.methods {
+                let mut idents = HashSet::new();
+                find_function_idents(method, &mut idents);
+                if !method.is_static {
+                    idents.remove(obj.ident.as_str());
+                }
+                used.extend(idents);
+            }
+        }
+        for e in &self.enums {
+            for entry in &e.entries {
+                if let Some(ty) = &entry.inner {
+                    find_idents(ty, &mut used);
+                }
+            }
+        }
+        for obj in &self.objects {
+            if !used.contains(obj.ident.as_str()) {
+                linter.lints.push(Lint {
+                    item: obj.ident.clone(),
+                    kind: LintKind::UnusedObject,
+                    rename: None,
+                });
+            }
+        }
+        for e in &self.enums {
+            if !used.contains(e.ident.as_str()) {
+                linter.lints.push(Lint {
+                    item: e.ident.clone(),
+                    kind: LintKind::UnusedEnum,
+                    rename: None,
+                });
+            }
+        }
+
+        linter.lints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_reserved() -> Result<()> {
+        let iface = Interface::parse(
+            r#"
+            fn create() -> Greeter;
+            object Greeter {
+                fn type() -> u8;
+                fn new(match: u8, lambda: u8);
+            }
+            "#,
+        )?;
+        let lints = iface.lint();
+        assert_eq!(
+            lints,
+            vec![
+                Lint {
+                    item: "Greeter::type".to_string(),
+                    kind: LintKind::Reserved(vec![Language::Rust, Language::Wit]),
+                    rename: Some(Rename::Attribute("type_fn".to_string())),
+                },
+                Lint {
+                    item: "Greeter::new".to_string(),
+                    kind: LintKind::Reserved(vec![Language::Dart, Language::Js, Language::Cpp]),
+                    rename: Some(Rename::Attribute("new_fn".to_string())),
+                },
+                Lint {
+                    item: "Greeter::new(lambda)".to_string(),
+                    kind: LintKind::Reserved(vec![Language::Python]),
+                    rename: Some(Rename::Ident("lambda_arg".to_string())),
+                },
+            ]
+        );
+        assert_eq!(
+            lints[1].to_string(),
+            "`Greeter::new` is a reserved identifier in dart, js, cpp, consider adding `#[rename = \"new_fn\"]`"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_collision() -> Result<()> {
+        let iface = Interface::parse(
+            r#"
+            fn get_value(a_b: u8, aB: u8) -> u8;
+            fn getValue() -> u8;
+            "#,
+        )?;
+        assert_eq!(
+            iface.lint(),
+            vec![
+                Lint {
+                    item: "get_value(aB)".to_string(),
+                    kind: LintKind::Collision {
+                        other: "get_value(a_b)".to_string(),
+                        name: "aB".to_string(),
+                    },
+                    rename: Some(Rename::Ident("aB_arg".to_string())),
+                },
+                Lint {
+                    item: "getValue".to_string(),
+                    kind: LintKind::Collision {
+                        other: "get_value".to_string(),
+                        name: "getValue".to_string(),
+                    },
+                    rename: Some(Rename::Attribute("getValue_fn".to_string())),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename() -> Result<()> {
+        let iface = Interface::parse(
+            r#"
+            fn create() -> Greeter;
+            object Greeter {
+                #[rename = "kind"]
+                fn type() -> u8;
+                #[rename = "default"]
+                fn value() -> u8;
+            }
+            "#,
+        )?;
+        let lints = iface.lint();
+        assert_eq!(
+            lints,
+            vec![Lint {
+                item: "Greeter::value".to_string(),
+                kind: LintKind::Reserved(vec![
+                    Language::Dart,
+                    Language::Js,
+                    Language::Cpp,
+                    Language::Swift
+                ]),
+                rename: Some(Rename::Attribute("default_fn".to_string())),
+            }]
+        );
+        assert_eq!(
+            lints[0].to_string(),
+            "`Greeter::value` is a reserved identifier in dart, js, cpp, swift, consider adding `#[rename = \"default_fn\"]`"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unused() -> Result<()> {
+        let iface = Interface::parse(
+            r#"
+            object Used {
+                static fn new() -> Used;
+            }
+            object Unused {
+                fn clone() -> Unused;
+            }
+            object Wrapped {}
+            enum Shape {
+                Wrapped(Wrapped),
+                None
+            }
+            "#,
+        )?;
+        assert_eq!(
+            iface
+                .lint()
+                .into_iter()
+                .filter(|lint| lint.rename.is_none())
+                .collect::<Vec<_>>(),
+            vec![
+                Lint {
+                    item: "Unused".to_string(),
+                    kind: LintKind::UnusedObject,
+                    rename: None,
+                },
+                Lint {
+                    item: "Shape".to_string(),
+                    kind: LintKind::UnusedEnum,
+                    rename: None,
+                },
+            ]
+        );
+        Ok(())
+    }
+}
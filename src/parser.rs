@@ -2,93 +2,536 @@ use anyhow::Result;
 use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 struct GrammarParser;
 
+/// An ffi-gen interface description.
+///
+/// Either parsed from an `.rsh` file with [`Interface::parse`] or composed
+/// with an [`InterfaceBuilder`].
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Interface {
+    /// Module level documentation.
     pub doc: Vec<String>,
+    /// Prefix of all exported symbols.
+    pub prefix: Option<String>,
+    /// Library exporting the runtime helpers.
+    pub runtime: Runtime,
+    /// Free standing functions.
     pub functions: Vec<Function>,
+    /// Objects exposed to the foreign language.
     pub objects: Vec<Object>,
+    /// Enums exposed to the foreign language.
     pub enums: Vec<Enum>,
     idents: HashSet<String>,
 }
 
 impl Interface {
+    /// Parses an interface description.
     pub fn parse(input: &str) -> Result<Self> {
         let pairs = GrammarParser::parse(Rule::root, input)?;
-        let mut doc = vec![];
-        let mut functions = vec![];
-        let mut enums = vec![];
-        let mut objects = vec![];
-        let mut idents = HashSet::new();
+        let mut builder = InterfaceBuilder::new();
         for pair in pairs {
             for pair in pair.into_inner() {
                 match pair.as_rule() {
                     Rule::module_docs => {
-                        doc.push(pair.as_str()[3..].trim().to_string());
+                        let doc = pair.as_str()[3..].trim();
+                        if let Some(prefix) = doc.strip_prefix("@prefix ") {
+                            builder = builder.prefix(prefix.trim());
+                        } else if let Some(runtime) = doc.strip_prefix("@runtime ") {
+                            builder = builder.runtime(Runtime::parse(runtime)?);
+                        } else {
+                            builder = builder.doc(doc);
+                        }
                     }
                     Rule::object => {
-                        let obj = Object::parse(pair)?;
-                        if idents.contains(&obj.ident) {
-                            anyhow::bail!("duplicate object identifier");
-                        }
-                        idents.insert(obj.ident.clone());
-                        objects.push(obj);
+                        builder = builder.object(Object::parse(pair)?);
                     }
                     Rule::function => {
-                        let fun = Function::parse(pair)?;
-                        functions.push(fun);
+                        builder = builder.function(Function::parse(pair)?);
                     }
                     Rule::enum_ => {
-                        let e = Enum::parse(pair)?;
-                        enums.push(e);
+                        builder = builder.enum_(Enum::parse(pair)?);
                     }
                     _ => {}
                 }
             }
         }
-        Ok(Self {
-            doc,
-            functions,
-            objects,
-            idents,
-            enums,
-        })
+        builder.build()
     }
 
+    /// Returns `true` if `name` is an object of this interface.
     pub fn is_object(&self, name: &str) -> bool {
         self.idents.contains(name)
     }
 
+    #[cfg(test)]
+    pub(crate) fn without_lines(mut self) -> Self {
+        let methods = self
+            .objects
+            .iter_mut()
+            .flat_map(|obj| obj.methods.iter_mut());
+        for func in self.functions.iter_mut().chain(methods) {
+            func.line = None;
+        }
+        self
+    }
+
+    /// Returns `true` if `name` is an enum of this interface.
     pub fn is_enum(&self, name: &str) -> bool {
         self.enums
             .iter()
             .map(|e| e.ident.as_str())
             .any(|n| n == name)
     }
+
+    /// Returns the interface with the renamed items under their names in the
+    /// bindings. The generators emit everything, including the exported
+    /// symbols, from it.
+    pub(crate) fn renamed(&self) -> Self {
+        let mut iface = self.clone();
+        let mut types = HashMap::new();
+        for obj in &mut iface.objects {
+            if let Some(rename) = obj.rename.take() {
+                iface.idents.remove(&obj.ident);
+                iface.idents.insert(rename.clone());
+                types.insert(std::mem::replace(&mut obj.ident, rename.clone()), rename);
+            }
+        }
+        for e in &mut iface.enums {
+            if let Some(rename) = e.rename.take() {
+                types.insert(std::mem::replace(&mut e.ident, rename.clone()), rename);
+            }
+        }
+        let rename_ty = |ty: &mut Type| {
+            ty.visit_mut(&mut |ty| {
+                if let Type::Ident(ident) = ty {
+                    if let Some(rename) = types.get(ident) {
+                        *ident = rename.clone();
+                    }
+                }
+            })
+        };
+        let methods = iface
+            .objects
+            .iter_mut()
+            .flat_map(|obj| obj.methods.iter_mut());
+        for func in iface.functions.iter_mut().chain(methods) {
+            if let Some(rename) = func.rename.take() {
+                func.ident = rename;
+            }
+            for (_, ty) in &mut func.args {
+                rename_ty(ty);
+            }
+            if let Some(ty) = &mut func.ret {
+                rename_ty(ty);
+            }
+        }
+        for entry in iface.enums.iter_mut().flat_map(|e| e.entries.iter_mut()) {
+            if let Some(ty) = &mut entry.inner {
+                rename_ty(ty);
+            }
+        }
+        iface
+    }
+}
+
+/// Library exporting the runtime helpers, the allocator, buffer and string
+/// functions every interface uses.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum Runtime {
+    /// The library exports the helpers itself.
+    #[default]
+    Export,
+    /// The helpers are exported by another ffi-gen library linked into the
+    /// same binary, with the given symbol prefix. Set with
+    /// `//! @runtime extern <prefix>`.
+    Extern(Option<String>),
+}
+
+impl Runtime {
+    fn parse(runtime: &str) -> Result<Self> {
+        let mut words = runtime.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("export"), None, _) => Ok(Self::Export),
+            (Some("extern"), prefix, None) => Ok(Self::Extern(prefix.map(str::to_string))),
+            _ => anyhow::bail!("invalid runtime `{}`", runtime.trim()),
+        }
+    }
+}
+
+/// Builds an [`Interface`] programmatically.
+///
+/// ```
+/// use ffi_gen::{Function, InterfaceBuilder, Object, Type};
+///
+/// let iface = InterfaceBuilder::new()
+///     .object(
+///         Object::new("Greeter")
+///             .method(Function::new("new").make_static().returns(Type::Ident("Greeter".into())))
+///             .method(Function::new("greet").returns(Type::String)),
+///     )
+///     .function(Function::new("hello_world").arg("name", Type::String))
+///     .build()
+///     .unwrap();
+/// assert!(iface.is_object("Greeter"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct InterfaceBuilder {
+    doc: Vec<String>,
+    prefix: Option<String>,
+    runtime: Runtime,
+    functions: Vec<Function>,
+    objects: Vec<Object>,
+    enums: Vec<Enum>,
 }
 
+impl InterfaceBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a line of module documentation.
+    pub fn doc(mut self, line: impl Into<String>) -> Self {
+        self.doc.push(line.into());
+        self
+    }
+
+    /// Namespaces all exported symbols with `prefix`, so that several ffi-gen
+    /// libraries can be linked into one binary.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Sets the library exporting the runtime helpers. Only one of several
+    /// ffi-gen libraries linked into one binary exports them.
+    pub fn runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Adds a free standing function.
+    pub fn function(mut self, function: Function) -> Self {
+        self.functions.push(function);
+        self
+    }
+
+    /// Adds an object.
+    pub fn object(mut self, object: Object) -> Self {
+        self.objects.push(object);
+        self
+    }
+
+    /// Adds an enum.
+    pub fn enum_(mut self, e: Enum) -> Self {
+        self.enums.push(e);
+        self
+    }
+
+    /// Returns the object `ident`, adding it if it doesn't exist yet.
+    #[cfg(feature = "derive")]
+    pub(crate) fn object_mut(&mut self, ident: String) -> &mut Object {
+        match self.objects.iter().position(|obj| obj.ident == ident) {
+            Some(i) => &mut self.objects[i],
+            None => {
+                self.objects.push(Object::new(ident));
+                self.objects.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Validates the interface and returns it.
+    ///
+    /// Fails on invalid symbol prefixes, identifiers rust can't escape
+    /// (`crate`, `self`, `Self` and `super`), duplicate type identifiers or
+    /// names in the bindings, static
+    /// free standing functions, borrowed objects or iterators returned without
+    /// `self`, borrowed futures and streams, `&mut` other than `&mut [T]`
+    /// arguments of numbers and identifiers that don't refer to an object or
+    /// enum.
+    pub fn build(self) -> Result<Interface> {
+        let runtime = match &self.runtime {
+            Runtime::Extern(prefix) => prefix.as_ref(),
+            Runtime::Export => None,
+        };
+        for prefix in self.prefix.iter().chain(runtime) {
+            let mut chars = prefix.chars();
+            let valid = chars
+                .next()
+                .map(|c| c.is_ascii_alphabetic() || c == '_')
+                .unwrap_or(false)
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                anyhow::bail!("invalid symbol prefix `{}`", prefix);
+            }
+        }
+        let keyword = |ident: &str, line: Option<usize>| -> Result<()> {
+            if matches!(ident, "crate" | "self" | "Self" | "super") {
+                return Err(at_line(
+                    format!("`{}` can't be used as an identifier", ident),
+                    line,
+                ));
+            }
+            Ok(())
+        };
+        for obj in &self.objects {
+            keyword(&obj.ident, None)?;
+            if let Some(rename) = &obj.rename {
+                keyword(rename, None)?;
+            }
+        }
+        for e in &self.enums {
+            keyword(&e.ident, None)?;
+            if let Some(rename) = &e.rename {
+                keyword(rename, None)?;
+            }
+            for entry in &e.entries {
+                keyword(&entry.name, None)?;
+            }
+        }
+        let functions = self
+            .objects
+            .iter()
+            .flat_map(|obj| obj.methods.iter())
+            .chain(self.functions.iter());
+        for func in functions {
+            keyword(&func.ident, func.line)?;
+            if let Some(rename) = &func.rename {
+                keyword(rename, func.line)?;
+            }
+            for (arg, _) in &func.args {
+                keyword(arg, func.line)?;
+            }
+        }
+        // the exported symbols are named after the items in the bindings
+        let scopes = self
+            .objects
+            .iter()
+            .map(|obj| &obj.methods)
+            .chain(std::iter::once(&self.functions));
+        for functions in scopes {
+            let mut names = HashSet::new();
+            for func in functions {
+                if !names.insert(func.rename.as_ref().unwrap_or(&func.ident)) {
+                    return Err(at_line(
+                        format!(
+                            "duplicate function name `{}` in the bindings",
+                            func.rename.as_ref().unwrap_or(&func.ident)
+                        ),
+                        func.line,
+                    ));
+                }
+            }
+        }
+        let mut idents = HashSet::new();
+        for obj in &self.objects {
+            if !idents.insert(obj.ident.clone()) {
+                anyhow::bail!("duplicate object identifier");
+            }
+        }
+        let mut enums = HashSet::new();
+        for e in &self.enums {
+            if idents.contains(&e.ident) || !enums.insert(e.ident.as_str()) {
+                anyhow::bail!("duplicate enum identifier");
+            }
+        }
+        // types can't be renamed to the rust name of another type either, the
+        // glue refers to both
+        let mut names: HashSet<&str> = idents.iter().map(|ident| ident.as_str()).collect();
+        names.extend(enums.iter());
+        let renames = self
+            .objects
+            .iter()
+            .map(|obj| obj.rename.as_ref().filter(|name| **name != obj.ident))
+            .chain(
+                self.enums
+                    .iter()
+                    .map(|e| e.rename.as_ref().filter(|name| **name != e.ident)),
+            );
+        for name in renames.flatten() {
+            if !names.insert(name) {
+                anyhow::bail!("duplicate type name `{}` in the bindings", name);
+            }
+        }
+        for func in &self.functions {
+            if func.is_static {
+                anyhow::bail!("free standing function `{}` can't be static", func.ident);
+            }
+        }
+        let statics = self
+            .objects
+            .iter()
+            .flat_map(|obj| obj.methods.iter())
+            .filter(|func| func.is_static)
+            .chain(self.functions.iter());
+        for func in statics {
+            if func.ret.as_ref().map(|ty| ty.is_borrowed_handle(&idents)) == Some(true) {
+                return Err(at_line(
+                    format!(
+                        "`{}` can't return a borrowed value without `self`",
+                        func.ident
+                    ),
+                    func.line,
+                ));
+            }
+        }
+        let functions = self
+            .objects
+            .iter()
+            .flat_map(|obj| obj.methods.iter())
+            .chain(self.functions.iter());
+        for func in functions {
+            for (_, ty) in &func.args {
+                if ty.is_invalid_mut_borrow(true) {
+                    return Err(at_line(
+                        format!("`{}` can only take `&mut [T]` of numbers", func.ident),
+                        func.line,
+                    ));
+                }
+            }
+            if let Some(ty) = &func.ret {
+                if ty.is_invalid_mut_borrow(false) {
+                    return Err(at_line(
+                        format!("`{}` can't return `&mut`", func.ident),
+                        func.line,
+                    ));
+                }
+            }
+            let tys = func.args.iter().map(|(_, ty)| ty).chain(func.ret.as_ref());
+            for ty in tys {
+                if ty.is_borrowed_task() {
+                    return Err(at_line(
+                        format!("`{}` can't borrow a future or stream", func.ident),
+                        func.line,
+                    ));
+                }
+            }
+        }
+        let check = |ty: &Type| -> Result<()> {
+            let mut res = Ok(());
+            ty.visit(&mut |ty| {
+                if let Type::Ident(ident) = ty {
+                    if res.is_ok() && !idents.contains(ident) && !enums.contains(ident.as_str()) {
+                        res = Err(anyhow::anyhow!("unknown identifier `{}`", ident));
+                    }
+                }
+            });
+            res
+        };
+        let functions = self
+            .objects
+            .iter()
+            .flat_map(|obj| obj.methods.iter())
+            .chain(self.functions.iter());
+        for func in functions {
+            for (_, ty) in &func.args {
+                check(ty)?;
+            }
+            if let Some(ty) = &func.ret {
+                check(ty)?;
+            }
+        }
+        for e in &self.enums {
+            for entry in &e.entries {
+                match &entry.inner {
+                    Some(Type::Ident(ident)) if idents.contains(ident) => {}
+                    Some(_) => anyhow::bail!("enum entry `{}` can only wrap objects", entry.name),
+                    None => {}
+                }
+            }
+        }
+        Ok(Interface {
+            doc: self.doc,
+            prefix: self.prefix,
+            runtime: self.runtime,
+            functions: self.functions,
+            objects: self.objects,
+            enums: self.enums,
+            idents,
+        })
+    }
+}
+
+fn parse_rename(pair: Pair<Rule>) -> String {
+    let ident = pair
+        .into_inner()
+        .next()
+        .unwrap()
+        .into_inner()
+        .next()
+        .unwrap();
+    ident.as_str().to_string()
+}
+
+fn at_line(msg: String, line: Option<usize>) -> anyhow::Error {
+    match line {
+        Some(line) => anyhow::anyhow!("{} (line {})", msg, line),
+        None => anyhow::anyhow!(msg),
+    }
+}
+
+/// An object exposed to the foreign language.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Object {
+    /// Documentation of the object.
     pub doc: Vec<String>,
+    /// Name of the object.
     pub ident: String,
+    /// Name of the object in the bindings, if it differs from the rust name.
+    pub rename: Option<String>,
+    /// Methods of the object, including static constructors.
     pub methods: Vec<Function>,
 }
 
 impl Object {
-    pub fn parse(pair: Pair<Rule>) -> Result<Self> {
+    /// Creates an object without methods.
+    pub fn new(ident: impl Into<String>) -> Self {
+        Self {
+            doc: vec![],
+            ident: ident.into(),
+            rename: None,
+            methods: vec![],
+        }
+    }
+
+    /// Adds a line of documentation.
+    pub fn doc(mut self, line: impl Into<String>) -> Self {
+        self.doc.push(line.into());
+        self
+    }
+
+    /// Names the object `name` in the bindings, like a `#[rename = "name"]`
+    /// attribute. The identifier still names the rust object.
+    pub fn rename(mut self, name: impl Into<String>) -> Self {
+        self.rename = Some(name.into());
+        self
+    }
+
+    /// Adds a method.
+    pub fn method(mut self, method: Function) -> Self {
+        self.methods.push(method);
+        self
+    }
+
+    pub(crate) fn parse(pair: Pair<Rule>) -> Result<Self> {
         let mut doc = vec![];
         let mut ident = None;
+        let mut rename = None;
         let mut methods = vec![];
         for pair in pair.into_inner() {
             match pair.as_rule() {
                 Rule::item_docs => {
                     doc.push(pair.as_str()[3..].trim().to_string());
                 }
+                Rule::rename => {
+                    rename = Some(parse_rename(pair));
+                }
                 Rule::ident => {
                     ident = Some(pair.as_str().to_string());
                 }
@@ -102,36 +545,97 @@ impl Object {
         Ok(Self {
             doc,
             ident: ident.unwrap(),
+            rename,
             methods,
         })
     }
 }
 
+/// A free standing function or a method of an object.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Function {
+    /// Documentation of the function.
     pub doc: Vec<String>,
+    /// Whether the method is called without an object instance.
     pub is_static: bool,
+    /// Name of the function.
     pub ident: String,
+    /// Name of the function in the bindings, if it differs from the rust name.
+    pub rename: Option<String>,
+    /// Named arguments.
     pub args: Vec<(String, Type)>,
+    /// Return type, `None` if the function returns nothing.
     pub ret: Option<Type>,
+    /// Line of the declaration in the interface description.
+    pub line: Option<usize>,
 }
 
 impl Function {
-    pub fn parse(pair: Pair<Rule>) -> Result<Self> {
+    /// Creates a non static function without arguments or return value.
+    pub fn new(ident: impl Into<String>) -> Self {
+        Self {
+            doc: vec![],
+            is_static: false,
+            ident: ident.into(),
+            rename: None,
+            args: vec![],
+            ret: None,
+            line: None,
+        }
+    }
+
+    /// Adds a line of documentation.
+    pub fn doc(mut self, line: impl Into<String>) -> Self {
+        self.doc.push(line.into());
+        self
+    }
+
+    /// Names the function `name` in the bindings, like a `#[rename = "name"]`
+    /// attribute. The identifier still names the rust function.
+    pub fn rename(mut self, name: impl Into<String>) -> Self {
+        self.rename = Some(name.into());
+        self
+    }
+
+    /// Marks the method as static.
+    pub fn make_static(mut self) -> Self {
+        self.is_static = true;
+        self
+    }
+
+    /// Adds an argument.
+    pub fn arg(mut self, name: impl Into<String>, ty: Type) -> Self {
+        self.args.push((name.into(), ty));
+        self
+    }
+
+    /// Sets the return type.
+    pub fn returns(mut self, ty: Type) -> Self {
+        self.ret = Some(ty);
+        self
+    }
+
+    pub(crate) fn parse(pair: Pair<Rule>) -> Result<Self> {
         let mut doc = vec![];
         let mut is_static = false;
         let mut ident = None;
+        let mut rename = None;
         let mut args = vec![];
         let mut ret = None;
+        let mut line = None;
         for pair in pair.into_inner() {
             match pair.as_rule() {
                 Rule::item_docs => {
                     doc.push(pair.as_str()[3..].trim().to_string());
                 }
+                Rule::rename => {
+                    rename = Some(parse_rename(pair));
+                }
                 Rule::static_ => {
                     is_static = true;
                 }
                 Rule::ident => {
+                    line = Some(pair.line_col().0);
                     ident = Some(pair.as_str().to_string());
                 }
                 Rule::args => {
@@ -164,20 +668,25 @@ impl Function {
             doc,
             is_static,
             ident: ident.unwrap(),
+            rename,
             args,
             ret,
+            line,
         })
     }
 }
 
+/// A variant of an [`Enum`].
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct EnumEntry {
+    /// Name of the variant.
     pub name: String,
+    /// Object wrapped by the variant.
     pub inner: Option<Type>,
 }
 
 impl EnumEntry {
-    pub fn parse(pair: Pair<Rule>) -> Result<Self> {
+    pub(crate) fn parse(pair: Pair<Rule>) -> Result<Self> {
         let mut name = None;
         let mut inner = None;
         for pair in pair.into_inner() {
@@ -211,23 +720,74 @@ impl EnumEntry {
     }
 }
 
+/// An enum exposed to the foreign language.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Enum {
+    /// Documentation of the enum.
     pub doc: Vec<String>,
+    /// Name of the enum.
     pub ident: String,
+    /// Name of the enum in the bindings, if it differs from the rust name.
+    pub rename: Option<String>,
+    /// Variants of the enum.
     pub entries: Vec<EnumEntry>,
 }
 
 impl Enum {
-    pub fn parse(pair: Pair<Rule>) -> Result<Self> {
+    /// Creates an enum without variants.
+    pub fn new(ident: impl Into<String>) -> Self {
+        Self {
+            doc: vec![],
+            ident: ident.into(),
+            rename: None,
+            entries: vec![],
+        }
+    }
+
+    /// Adds a line of documentation.
+    pub fn doc(mut self, line: impl Into<String>) -> Self {
+        self.doc.push(line.into());
+        self
+    }
+
+    /// Names the enum `name` in the bindings, like a `#[rename = "name"]`
+    /// attribute. The identifier still names the rust enum.
+    pub fn rename(mut self, name: impl Into<String>) -> Self {
+        self.rename = Some(name.into());
+        self
+    }
+
+    /// Adds a variant without data.
+    pub fn entry(mut self, name: impl Into<String>) -> Self {
+        self.entries.push(EnumEntry {
+            name: name.into(),
+            inner: None,
+        });
+        self
+    }
+
+    /// Adds a variant wrapping the object `object`.
+    pub fn entry_with(mut self, name: impl Into<String>, object: impl Into<String>) -> Self {
+        self.entries.push(EnumEntry {
+            name: name.into(),
+            inner: Some(Type::Ident(object.into())),
+        });
+        self
+    }
+
+    pub(crate) fn parse(pair: Pair<Rule>) -> Result<Self> {
         let mut doc = vec![];
         let mut ident = None;
+        let mut rename = None;
         let mut entries = vec![];
         for pair in pair.into_inner() {
             match pair.as_rule() {
                 Rule::item_docs => {
                     doc.push(pair.as_str()[3..].trim().to_string());
                 }
+                Rule::rename => {
+                    rename = Some(parse_rename(pair));
+                }
                 Rule::ident => {
                     ident = Some(pair.as_str().to_string());
                 }
@@ -241,42 +801,165 @@ impl Enum {
         Ok(Self {
             doc,
             ident: ident.unwrap(),
+            rename,
             entries,
         })
     }
 }
 
+/// A type in an interface description.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Type {
+    /// `u8`
     U8,
+    /// `u16`
     U16,
+    /// `u32`
     U32,
+    /// `u64`
     U64,
+    /// `usize`
     Usize,
+    /// `i8`
     I8,
+    /// `i16`
     I16,
+    /// `i32`
     I32,
+    /// `i64`
     I64,
+    /// `isize`
     Isize,
+    /// `bool`
     Bool,
+    /// `f32`
     F32,
+    /// `f64`
     F64,
+    /// `string`
     String,
+    /// `buffer<T>`
     Buffer(Box<Type>),
+    /// `&T`
     Ref(Box<Type>),
+    /// `&mut T`
+    RefMut(Box<Type>),
+    /// An object or enum.
     Ident(String),
+    /// `[T]`
     Slice(Box<Type>),
+    /// `Vec<T>`
     Vec(Box<Type>),
+    /// `Option<T>`
     Option(Box<Type>),
+    /// `Result<T>`
     Result(Box<Type>),
+    /// `Iterator<T>`
     Iter(Box<Type>),
+    /// `Future<T>`
     Future(Box<Type>),
+    /// `Stream<T>`
     Stream(Box<Type>),
+    /// `(A, B, ..)`
     Tuple(Vec<Type>),
 }
 
 impl Type {
-    pub fn parse(pair: Pair<Rule>) -> Result<Self> {
+    /// Whether the type contains a borrowed object or iterator, which can only
+    /// be returned from a method.
+    fn is_borrowed_handle(&self, objects: &HashSet<String>) -> bool {
+        let mut res = false;
+        self.visit(&mut |ty| {
+            if let Type::Ref(inner) = ty {
+                match &**inner {
+                    Type::Ident(ident) if objects.contains(ident) => res = true,
+                    Type::Iter(_) => res = true,
+                    _ => {}
+                }
+            }
+        });
+        res
+    }
+
+    /// Futures and streams can only be passed by value.
+    fn is_borrowed_task(&self) -> bool {
+        let mut res = false;
+        self.visit(&mut |ty| {
+            if let Type::Ref(inner) = ty {
+                res |= matches!(&**inner, Type::Future(_) | Type::Stream(_));
+            }
+        });
+        res
+    }
+
+    /// Only numbers can be borrowed mutably, as a slice passed directly as an
+    /// argument, so the changes can be written back.
+    fn is_invalid_mut_borrow(&self, is_arg: bool) -> bool {
+        if let (Type::RefMut(inner), true) = (self, is_arg) {
+            if let Type::Slice(inner) = &**inner {
+                return !inner.is_number();
+            }
+        }
+        let mut res = false;
+        self.visit(&mut |ty| res |= matches!(ty, Type::RefMut(_)));
+        res
+    }
+
+    fn is_number(&self) -> bool {
+        matches!(
+            self,
+            Type::U8
+                | Type::U16
+                | Type::U32
+                | Type::U64
+                | Type::Usize
+                | Type::I8
+                | Type::I16
+                | Type::I32
+                | Type::I64
+                | Type::Isize
+                | Type::F32
+                | Type::F64
+        )
+    }
+
+    fn visit<F: FnMut(&Type)>(&self, f: &mut F) {
+        f(self);
+        match self {
+            Type::Buffer(inner)
+            | Type::Ref(inner)
+            | Type::RefMut(inner)
+            | Type::Slice(inner)
+            | Type::Vec(inner)
+            | Type::Option(inner)
+            | Type::Result(inner)
+            | Type::Iter(inner)
+            | Type::Future(inner)
+            | Type::Stream(inner) => inner.visit(f),
+            Type::Tuple(tys) => tys.iter().for_each(|ty| ty.visit(f)),
+            _ => {}
+        }
+    }
+
+    fn visit_mut<F: FnMut(&mut Type)>(&mut self, f: &mut F) {
+        f(self);
+        match self {
+            Type::Buffer(inner)
+            | Type::Ref(inner)
+            | Type::RefMut(inner)
+            | Type::Slice(inner)
+            | Type::Vec(inner)
+            | Type::Option(inner)
+            | Type::Result(inner)
+            | Type::Iter(inner)
+            | Type::Future(inner)
+            | Type::Stream(inner) => inner.visit_mut(f),
+            Type::Tuple(tys) => tys.iter_mut().for_each(|ty| ty.visit_mut(f)),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn parse(pair: Pair<Rule>) -> Result<Self> {
         let pair = pair.into_inner().next().unwrap();
         Ok(match pair.as_rule() {
             Rule::primitive => match pair.as_str() {
@@ -312,6 +995,8 @@ impl Type {
                     "i32" => Type::I32,
                     "u64" => Type::U64,
                     "i64" => Type::I64,
+                    "usize" => Type::Usize,
+                    "isize" => Type::Isize,
                     "f32" => Type::F32,
                     "f64" => Type::F64,
                     _ => panic!(),
@@ -324,10 +1009,12 @@ impl Type {
             | Rule::opt
             | Rule::res
             | Rule::ref_
+            | Rule::ref_mut
             | Rule::iter
             | Rule::fut
             | Rule::stream => {
                 let first = pair.as_str().chars().next().unwrap();
+                let pair_rule = pair.as_rule();
                 let mut inner = None;
                 for pair in pair.into_inner() {
                     if pair.as_rule() == Rule::type_ {
@@ -336,6 +1023,7 @@ impl Type {
                 }
                 let inner = inner.unwrap();
                 match first {
+                    '&' if pair_rule == Rule::ref_mut => Type::RefMut(inner),
                     '[' => Type::Slice(inner),
                     'V' => Type::Vec(inner),
                     'O' => Type::Option(inner),
@@ -359,6 +1047,71 @@ impl Type {
     }
 }
 
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_static {
+            write!(f, "static ")?;
+        }
+        write!(f, "fn {}(", self.ident)?;
+        for (i, (name, ty)) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", name, ty)?;
+        }
+        write!(f, ")")?;
+        if let Some(ret) = &self.ret {
+            write!(f, " -> {}", ret)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::U8 => write!(f, "u8"),
+            Type::U16 => write!(f, "u16"),
+            Type::U32 => write!(f, "u32"),
+            Type::U64 => write!(f, "u64"),
+            Type::Usize => write!(f, "usize"),
+            Type::I8 => write!(f, "i8"),
+            Type::I16 => write!(f, "i16"),
+            Type::I32 => write!(f, "i32"),
+            Type::I64 => write!(f, "i64"),
+            Type::Isize => write!(f, "isize"),
+            Type::Bool => write!(f, "bool"),
+            Type::F32 => write!(f, "f32"),
+            Type::F64 => write!(f, "f64"),
+            Type::String => write!(f, "string"),
+            Type::Buffer(inner) => write!(f, "buffer<{}>", inner),
+            Type::Ref(inner) => write!(f, "&{}", inner),
+            Type::RefMut(inner) => write!(f, "&mut {}", inner),
+            Type::Ident(ident) => write!(f, "{}", ident),
+            Type::Slice(inner) => write!(f, "[{}]", inner),
+            Type::Vec(inner) => write!(f, "Vec<{}>", inner),
+            Type::Option(inner) => write!(f, "Option<{}>", inner),
+            Type::Result(inner) => write!(f, "Result<{}>", inner),
+            Type::Iter(inner) => write!(f, "Iterator<{}>", inner),
+            Type::Future(inner) => write!(f, "Future<{}>", inner),
+            Type::Stream(inner) => write!(f, "Stream<{}>", inner),
+            Type::Tuple(tys) => {
+                write!(f, "(")?;
+                for (i, ty) in tys.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", ty)?;
+                }
+                if tys.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -370,6 +1123,8 @@ mod tests {
             res,
             Interface {
                 doc: Default::default(),
+                prefix: None,
+                runtime: Runtime::Export,
                 enums: vec![],
                 objects: vec![],
                 functions: vec![],
@@ -381,14 +1136,18 @@ mod tests {
             res,
             Interface {
                 doc: Default::default(),
+                prefix: None,
+                runtime: Runtime::Export,
                 enums: vec![],
                 objects: vec![],
                 functions: vec![Function {
                     doc: Default::default(),
                     is_static: false,
                     ident: "hello".to_string(),
+                    rename: None,
                     args: vec![],
                     ret: None,
+                    line: Some(1),
                 }],
                 idents: Default::default(),
             }
@@ -398,14 +1157,18 @@ mod tests {
             res,
             Interface {
                 doc: Default::default(),
+                prefix: None,
+                runtime: Runtime::Export,
                 enums: vec![],
                 objects: vec![],
                 functions: vec![Function {
                     doc: Default::default(),
                     is_static: false,
                     ident: "hello".to_string(),
+                    rename: None,
                     args: vec![("a".to_string(), Type::U8)],
                     ret: None,
+                    line: Some(1),
                 }],
                 idents: Default::default(),
             }
@@ -415,14 +1178,18 @@ mod tests {
             res,
             Interface {
                 doc: Default::default(),
+                prefix: None,
+                runtime: Runtime::Export,
                 enums: vec![],
                 objects: vec![],
                 functions: vec![Function {
                     doc: Default::default(),
                     is_static: false,
                     ident: "hello".to_string(),
+                    rename: None,
                     args: vec![],
                     ret: Some(Type::U8),
+                    line: Some(1),
                 }],
                 idents: Default::default(),
             }
@@ -432,14 +1199,18 @@ mod tests {
             res,
             Interface {
                 doc: Default::default(),
+                prefix: None,
+                runtime: Runtime::Export,
                 enums: vec![],
                 objects: vec![],
                 functions: vec![Function {
                     doc: Default::default(),
                     is_static: false,
                     ident: "hello".to_string(),
+                    rename: None,
                     args: vec![("a".to_string(), Type::Ref(Box::new(Type::String)))],
                     ret: None,
+                    line: Some(1),
                 }],
                 idents: Default::default(),
             }
@@ -449,34 +1220,50 @@ mod tests {
             res,
             Interface {
                 doc: Default::default(),
+                prefix: None,
+                runtime: Runtime::Export,
                 enums: vec![],
                 objects: vec![],
                 functions: vec![Function {
                     doc: Default::default(),
                     is_static: false,
                     ident: "hello".to_string(),
+                    rename: None,
                     args: vec![(
                         "a".to_string(),
                         Type::Ref(Box::new(Type::Slice(Box::new(Type::U8))))
                     )],
                     ret: Some(Type::Vec(Box::new(Type::I64))),
+                    line: Some(1),
                 }],
                 idents: Default::default(),
             }
         );
+        let res = Interface::parse("fn hello(a: &mut [f32]);")?;
+        assert_eq!(
+            res.functions[0].args,
+            vec![(
+                "a".to_string(),
+                Type::RefMut(Box::new(Type::Slice(Box::new(Type::F32))))
+            )]
+        );
         let res = Interface::parse("fn hello() -> Future<u8>;")?;
         assert_eq!(
             res,
             Interface {
                 doc: Default::default(),
+                prefix: None,
+                runtime: Runtime::Export,
                 enums: vec![],
                 objects: vec![],
                 functions: vec![Function {
                     doc: Default::default(),
                     is_static: false,
                     ident: "hello".to_string(),
+                    rename: None,
                     args: vec![],
                     ret: Some(Type::Future(Box::new(Type::U8))),
+                    line: Some(1),
                 }],
                 idents: Default::default(),
             }
@@ -499,6 +1286,8 @@ mod tests {
         assert_eq!(
             res,
             Interface {
+                prefix: None,
+                runtime: Runtime::Export,
                 doc: vec![
                     "A greeter".to_string(),
                     "".to_string(),
@@ -510,20 +1299,25 @@ mod tests {
                 objects: vec![Object {
                     doc: vec!["The main entry point of this example.".to_string()],
                     ident: "Greeter".to_string(),
+                    rename: None,
                     methods: vec![
                         Function {
                             doc: vec!["Creates a new greeter.".to_string()],
                             is_static: true,
                             ident: "new".to_string(),
+                            rename: None,
                             args: vec![],
                             ret: Some(Type::Ident("Greeter".to_string())),
+                            line: Some(10),
                         },
                         Function {
                             doc: vec!["Returns a friendly greeting.".to_string()],
                             is_static: false,
                             ident: "greet".to_string(),
+                            rename: None,
                             args: vec![],
                             ret: Some(Type::String),
+                            line: Some(12),
                         },
                     ],
                 }],
@@ -542,35 +1336,45 @@ mod tests {
             res,
             Interface {
                 doc: Default::default(),
+                prefix: None,
+                runtime: Runtime::Export,
                 enums: vec![],
                 functions: vec![
                     Function {
                         doc: Default::default(),
                         is_static: false,
                         ident: "tuple0".to_string(),
+                        rename: None,
                         args: vec![],
                         ret: Some(Type::Tuple(vec![])),
+                        line: Some(2),
                     },
                     Function {
                         doc: Default::default(),
                         is_static: false,
                         ident: "tuple1".to_string(),
+                        rename: None,
                         args: vec![],
                         ret: Some(Type::Tuple(vec![Type::U8])),
+                        line: Some(3),
                     },
                     Function {
                         doc: Default::default(),
                         is_static: false,
                         ident: "tuple2".to_string(),
+                        rename: None,
                         args: vec![],
                         ret: Some(Type::Tuple(vec![Type::U8, Type::U8])),
+                        line: Some(4),
                     },
                     Function {
                         doc: Default::default(),
                         is_static: false,
                         ident: "tuple3".to_string(),
+                        rename: None,
                         args: vec![],
                         ret: Some(Type::Tuple(vec![Type::U8, Type::U8, Type::U8])),
+                        line: Some(5),
                     },
                 ],
                 objects: Default::default(),
@@ -579,4 +1383,211 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_builder() -> Result<()> {
+        let res = InterfaceBuilder::new()
+            .doc("A greeter")
+            .object(
+                Object::new("Greeter")
+                    .doc("The main entry point of this example.")
+                    .method(
+                        Function::new("new")
+                            .make_static()
+                            .returns(Type::Ident("Greeter".to_string())),
+                    )
+                    .method(Function::new("greet").returns(Type::String)),
+            )
+            .enum_(
+                Enum::new("Greeting")
+                    .entry("None")
+                    .entry_with("Some", "Greeter"),
+            )
+            .function(Function::new("hello").arg("a", Type::Ref(Box::new(Type::String))))
+            .build()?;
+        let parsed = Interface::parse(
+            r#"
+            //! A greeter
+
+            /// The main entry point of this example.
+            object Greeter {
+                static fn new() -> Greeter;
+                fn greet() -> string;
+            }
+
+            enum Greeting {
+                None,
+                Some(Greeter)
+            }
+
+            fn hello(a: &string);
+            "#,
+        )?;
+        assert_eq!(res, parsed.without_lines());
+
+        let prefixed = Interface::parse("//! @prefix greeter\n//! A greeter\n")?;
+        assert_eq!(prefixed.prefix.as_deref(), Some("greeter"));
+        assert_eq!(prefixed.doc, vec!["A greeter".to_string()]);
+        let prefix = InterfaceBuilder::new().prefix("1greeter").build();
+        assert!(prefix.is_err());
+        let runtime = Interface::parse("//! @prefix greeter\n//! @runtime extern core\n")?;
+        assert_eq!(runtime.runtime, Runtime::Extern(Some("core".to_string())));
+        let runtime = Interface::parse("//! @runtime extern\n")?;
+        assert_eq!(runtime.runtime, Runtime::Extern(None));
+        assert!(Interface::parse("//! @runtime shared\n").is_err());
+        let runtime = InterfaceBuilder::new()
+            .runtime(Runtime::Extern(Some("1core".to_string())))
+            .build();
+        assert!(runtime.is_err());
+
+        let dup = InterfaceBuilder::new()
+            .object(Object::new("Greeter"))
+            .object(Object::new("Greeter"))
+            .build();
+        assert!(dup.is_err());
+        let unknown = InterfaceBuilder::new()
+            .function(Function::new("hello").returns(Type::Ident("Greeter".to_string())))
+            .build();
+        assert!(unknown.is_err());
+        let stat = InterfaceBuilder::new()
+            .function(Function::new("hello").make_static())
+            .build();
+        assert!(stat.is_err());
+        let borrowed = Interface::parse(
+            r#"
+            object Greeter {}
+            fn greeter() -> &Greeter;
+            "#,
+        );
+        assert!(borrowed.is_err());
+        let borrowed = Interface::parse(
+            r#"
+            object Greeter {
+                static fn words() -> &Iterator<string>;
+            }
+            "#,
+        );
+        assert!(borrowed.is_err());
+        let borrowed = Interface::parse("object B {\n    fn a() -> &Future<u8>;\n}\nfn b() -> B;")
+            .unwrap_err();
+        assert_eq!(
+            borrowed.to_string(),
+            "`a` can't borrow a future or stream (line 2)"
+        );
+        let borrowed =
+            Interface::parse("object B {}\nfn b() -> B;\nfn c(s: &Stream<u8>);").unwrap_err();
+        assert_eq!(
+            borrowed.to_string(),
+            "`c` can't borrow a future or stream (line 3)"
+        );
+        for iface in [
+            "fn f(a: &mut u32);",
+            "fn f(a: &mut string);",
+            "fn f(a: &mut [string]);",
+            "fn f(a: Option<&mut [u8]>);",
+        ] {
+            let err = Interface::parse(iface).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "`f` can only take `&mut [T]` of numbers (line 1)"
+            );
+        }
+        let err = Interface::parse("fn f() -> &mut [u8];").unwrap_err();
+        assert_eq!(err.to_string(), "`f` can't return `&mut` (line 1)");
+        assert!(Interface::parse("fn f(a: &mut [usize]);").is_ok());
+        let keyword = Interface::parse("fn hello();\nfn crate();").unwrap_err();
+        assert_eq!(
+            keyword.to_string(),
+            "`crate` can't be used as an identifier (line 2)"
+        );
+        let keyword = Interface::parse("fn hello(self: u8);").unwrap_err();
+        assert_eq!(
+            keyword.to_string(),
+            "`self` can't be used as an identifier (line 1)"
+        );
+        let keyword = InterfaceBuilder::new().object(Object::new("Self")).build();
+        assert!(keyword.is_err());
+
+        let renamed = Interface::parse(
+            r#"
+            /// A greeter.
+            #[rename = "Greeting"]
+            object Greeter {
+                #[rename = "create"]
+                static fn new() -> Greeter;
+            }
+            #[rename = "Kind"]
+            enum Type {
+                Greeter(Greeter)
+            }
+            #[rename = "kind"]
+            fn type(greeter: &Greeter) -> Type;
+            "#,
+        )?;
+        assert_eq!(renamed.objects[0].rename, Some("Greeting".to_string()));
+        assert_eq!(renamed.objects[0].doc, vec!["A greeter.".to_string()]);
+        assert_eq!(
+            renamed.objects[0].methods[0].rename,
+            Some("create".to_string())
+        );
+        let built = InterfaceBuilder::new()
+            .object(
+                Object::new("Greeter")
+                    .doc("A greeter.")
+                    .rename("Greeting")
+                    .method(
+                        Function::new("new")
+                            .rename("create")
+                            .make_static()
+                            .returns(Type::Ident("Greeter".to_string())),
+                    ),
+            )
+            .enum_(
+                Enum::new("Type")
+                    .rename("Kind")
+                    .entry_with("Greeter", "Greeter"),
+            )
+            .function(
+                Function::new("type")
+                    .rename("kind")
+                    .arg(
+                        "greeter",
+                        Type::Ref(Box::new(Type::Ident("Greeter".to_string()))),
+                    )
+                    .returns(Type::Ident("Type".to_string())),
+            )
+            .build()?;
+        assert_eq!(renamed.clone().without_lines(), built);
+        let bindings = renamed.renamed();
+        assert!(bindings.is_object("Greeting"));
+        assert!(!bindings.is_object("Greeter"));
+        assert_eq!(bindings.objects[0].methods[0].ident, "create");
+        assert_eq!(
+            bindings.objects[0].methods[0].ret,
+            Some(Type::Ident("Greeting".to_string()))
+        );
+        assert_eq!(
+            bindings.enums[0].entries[0].inner,
+            Some(Type::Ident("Greeting".to_string()))
+        );
+        assert_eq!(bindings.functions[0].ident, "kind");
+        assert_eq!(
+            bindings.functions[0].ret,
+            Some(Type::Ident("Kind".to_string()))
+        );
+        let dup = Interface::parse("object A {}\n#[rename = \"A\"]\nobject B {}").unwrap_err();
+        assert_eq!(dup.to_string(), "duplicate type name `A` in the bindings");
+        let dup = Interface::parse("fn a();\n#[rename = \"a\"]\nfn b();").unwrap_err();
+        assert_eq!(
+            dup.to_string(),
+            "duplicate function name `a` in the bindings (line 3)"
+        );
+        let keyword = Interface::parse("#[rename = \"self\"]\nfn a();").unwrap_err();
+        assert_eq!(
+            keyword.to_string(),
+            "`self` can't be used as an identifier (line 2)"
+        );
+        assert!(Interface::parse("#[rename = \"1a\"]\nfn a();").is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,1170 @@
+use crate::dart::ffi_buffer_name_for;
+use crate::import::Instr;
+use crate::parser::{Enum, Type};
+use crate::{
+    Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Symbols, Var,
+    NUM_TYPES,
+};
+use genco::prelude::*;
+use genco::tokens::static_literal;
+use heck::*;
+
+pub struct PythonGenerator {
+    abi: Abi,
+    cdylib_name: String,
+}
+
+/// State shared by the instructions of a single function body.
+struct Body<'a> {
+    symbols: &'a Symbols,
+    api: &'a str,
+    /// `Some(())` is returned as `()` to tell it apart from `None`.
+    unit: bool,
+}
+
+impl PythonGenerator {
+    pub fn new(cdylib_name: String) -> Self {
+        Self {
+            abi: Abi::native(),
+            cdylib_name,
+        }
+    }
+
+    pub fn generate(&self, iface: Interface) -> python::Tokens {
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
+        let objects: Vec<String> = iface.objects().into_iter().map(|obj| obj.name).collect();
+        quote! {
+            $(static_literal("#")) AUTO GENERATED FILE, DO NOT EDIT.
+            $(static_literal("#"))
+            $(static_literal("#")) Generated by "ffi-gen".
+
+            $(self.generate_doc(&iface.doc))
+
+            import asyncio
+            import ctypes
+            import enum
+            import itertools
+            import sys
+            import threading
+
+            $(self.generate_runtime(&symbols))
+
+            $(for ty in NUM_TYPES join ($['\n']) => $(self.generate_ffi_buffer(&symbols, ty)))
+
+            $(for func in iface.imports(&self.abi) join ($['\n']) => $(self.generate_return_struct(&func.ffi_ret)))
+
+            class Api:
+                $(docstring("Main entry point to library."))
+
+                def __init__(self, path=None):
+                    $(docstring("Loads the library from path, or based on platform conventions."))
+                    if path is None:
+                        if sys.platform == "darwin":
+                            path = $(quoted(format!("lib{}.dylib", self.cdylib_name)))
+                        elif sys.platform == "win32":
+                            path = $(quoted(format!("{}.dll", self.cdylib_name)))
+                        else:
+                            path = $(quoted(format!("lib{}.so", self.cdylib_name)))
+                    self._lib = ctypes.CDLL(path)
+                    self._drops = {}
+                    $(self.declare(&symbols.allocate(), &["c_size_t", "c_size_t"], "ctypes.c_ssize_t"))
+                    $(self.declare(&symbols.deallocate(), &["c_ssize_t", "c_size_t", "c_size_t"], "None"))
+                    $(self.declare(&symbols.ffi_buffer("new"), &["c_size_t", "c_size_t"], "ctypes.c_ssize_t"))
+                    $(self.declare(&symbols.ffi_buffer("address"), &["c_ssize_t"], "ctypes.c_size_t"))
+                    $(self.declare(&symbols.ffi_buffer("size"), &["c_ssize_t"], "ctypes.c_size_t"))
+                    $(self.declare(&symbols.string_into_parts(), &["c_ssize_t"], "_FfiStringParts"))
+                    $(for e in iface.enums.iter() =>
+                        $(self.declare(&symbols.destructure_enum(&e.ident), &["c_ssize_t"], "_EnumWrapper"))$['\r'])
+                    $(for ty in iface.listed_types() =>
+                        $(self.declare_list(&symbols, &objects, &ty))$['\r'])
+                    $(for func in iface.imports(&self.abi) => $(self.declare_import(&func.symbol, &func.ffi_args, &func.ffi_ret))$['\r'])
+
+                def _function(self, symbol, argtypes, restype):
+                    function = self._lib[symbol]
+                    function.argtypes = argtypes
+                    function.restype = restype
+                    return function
+
+                def _drop_fn(self, symbol):
+                    drop = self._drops.get(symbol)
+                    if drop is None:
+                        drop = self._function(symbol, [ctypes.c_ssize_t, ctypes.c_ssize_t], None)
+                        self._drops[symbol] = drop
+                    return drop
+
+                $(for func in iface.functions() join ($['\n']) => $(self.generate_function(&func)))
+
+                $(for iter in iface.iterators() join ($['\n']) => $(self.generate_function(&iter.next())))
+
+                $(for fut in iface.futures() join ($['\n']) => $(self.generate_function(&fut.poll())))
+
+                $(for stream in iface.streams() join ($['\n']) => $(self.generate_function(&stream.poll())))
+
+            $(for obj in iface.objects() join ($['\n']) => $(self.generate_object(&obj)))
+
+            $(for e in iface.enums.iter() join ($['\n']) => $(self.generate_enum(&symbols, e)))
+
+            $(for ty in iface.listed_types() join ($['\n']) => $(self.generate_list_type(&symbols, &objects, &ty)))
+        }
+    }
+
+    /// Generates the `.pyi` stubs describing the module returned by
+    /// [`PythonGenerator::generate`].
+    pub fn generate_stubs(&self, iface: Interface) -> python::Tokens {
+        let iface = iface.renamed();
+        let objects: Vec<String> = iface.objects().into_iter().map(|obj| obj.name).collect();
+        quote! {
+            $(static_literal("#")) AUTO GENERATED FILE, DO NOT EDIT.
+            $(static_literal("#"))
+            $(static_literal("#")) Generated by "ffi-gen".
+
+            import ctypes
+            import enum
+            from typing import (
+                Any,
+                Generator,
+                Generic,
+                Iterator,
+                List,
+                MutableSequence,
+                Optional,
+                Sequence,
+                Tuple,
+                TypeVar,
+            )
+
+            T = TypeVar("T")
+            _S = TypeVar("_S", bound="_Object")
+
+            class FfiError(Exception): ...
+
+            class FfiResult(Generic[T]):
+                is_ok: bool
+                @property
+                def value(self) -> T: ...
+                @property
+                def error(self) -> str: ...
+                @staticmethod
+                def ok(value: T) -> FfiResult[T]: ...
+                @staticmethod
+                def err(error: str) -> FfiResult[Any]: ...
+
+            class _Object:
+                def __enter__(self: _S) -> _S: ...
+                def __exit__(self, *exc: object) -> None: ...
+                def drop(self) -> None: ...
+
+            class Iter(_Object, Generic[T]):
+                def __iter__(self) -> Iter[T]: ...
+                def __next__(self) -> T: ...
+
+            class Future(_Object, Generic[T]):
+                def __await__(self) -> Generator[Any, None, T]: ...
+
+            class Stream(_Object, Generic[T]):
+                def __aiter__(self) -> Stream[T]: ...
+                async def __anext__(self) -> T: ...
+
+            class FfiString(_Object):
+                def __str__(self) -> str: ...
+
+            $(for ty in NUM_TYPES join ($['\n']) => $(self.generate_ffi_buffer_stub(ty)))
+
+            class Api:
+                def __init__(self, path: Optional[str] = None) -> None: ...
+                $(for func in iface.functions() => $(self.generate_function_stub(&func))$['\r'])
+
+            $(for obj in iface.objects() => $(self.generate_object_stub(&obj))$['\n'])
+
+            $(for e in iface.enums.iter() => $(self.generate_enum_stub(e))$['\n'])
+
+            $(for ty in iface.listed_types() => $(self.generate_list_type_stub(&objects, &ty))$['\n'])
+        }
+    }
+
+    fn generate_runtime(&self, symbols: &Symbols) -> python::Tokens {
+        quote! {
+            class FfiError(Exception):
+                $(docstring("Error returned by rust."))
+
+            class FfiResult:
+                $(docstring("Result passed as an argument to rust."))
+
+                def __init__(self, is_ok, value, error):
+                    self.is_ok = is_ok
+                    self._value = value
+                    self._error = error
+
+                @staticmethod
+                def ok(value):
+                    return FfiResult(True, value, None)
+
+                @staticmethod
+                def err(error):
+                    return FfiResult(False, None, error)
+
+                @property
+                def value(self):
+                    if not self.is_ok:
+                        raise ValueError("result is an error")
+                    return self._value
+
+                @property
+                def error(self):
+                    if self.is_ok:
+                        raise ValueError("result is not an error")
+                    return self._error
+
+            class _Box:
+                def __init__(self, api, ptr, drop, parent=None):
+                    self._api = api
+                    self._ptr = ptr
+                    self._drop = drop
+                    self._parent = parent
+                    self._dropped = False
+                    self._moved = False
+
+                def borrow(self):
+                    if self._dropped:
+                        raise RuntimeError("use after free")
+                    if self._moved:
+                        raise RuntimeError("use after move")
+                    parent = self._parent
+                    if parent is not None and (parent._dropped or parent._moved):
+                        raise RuntimeError("use of borrowed value after its owner was dropped")
+                    return self._ptr
+
+                def move(self):
+                    if self._dropped:
+                        raise RuntimeError("use after free")
+                    if self._moved:
+                        raise RuntimeError("can't move value twice")
+                    if self._parent is not None:
+                        raise RuntimeError("can't move borrowed value")
+                    self._moved = True
+                    return self._ptr
+
+                def drop(self):
+                    if self._dropped or self._moved:
+                        return
+                    self._dropped = True
+                    self._api._drop_fn(self._drop)(0, self._ptr)
+
+                def __del__(self):
+                    self.drop()
+
+            class _Object:
+                def __init__(self, api, box):
+                    self._api = api
+                    self._box = box
+
+                def __enter__(self):
+                    return self
+
+                def __exit__(self, *exc):
+                    self.drop()
+
+                def drop(self):
+                    $(docstring("Drops the rust value, it can't be used afterwards."))
+                    self._box.drop()
+
+            class _FfiStringParts(ctypes.Structure):
+                _fields_ = [
+                    ("addr", ctypes.c_ssize_t),
+                    ("len", ctypes.c_size_t),
+                    ("capacity", ctypes.c_size_t),
+                ]
+
+            class _EnumWrapper(ctypes.Structure):
+                _fields_ = [
+                    ("tag", ctypes.c_uint32),
+                    ("inner", ctypes.c_ssize_t),
+                ]
+
+            _ports = {}
+            _ports_lock = threading.Lock()
+            _port_ids = itertools.count(1)
+
+            def _register_port(loop, callback):
+                with _ports_lock:
+                    port = next(_port_ids)
+                    _ports[port] = (loop, callback)
+                return port
+
+            def _unregister_port(port):
+                with _ports_lock:
+                    _ports.pop(port, None)
+
+            @ctypes.CFUNCTYPE(None, ctypes.c_int64, ctypes.c_void_p)
+            def _post_cobject(port, message):
+                with _ports_lock:
+                    entry = _ports.get(port)
+                if entry is not None:
+                    loop, callback = entry
+                    try:
+                        loop.call_soon_threadsafe(callback)
+                    except RuntimeError:
+                        pass
+
+            _POST_COBJECT = ctypes.cast(_post_cobject, ctypes.c_void_p).value
+
+            class Iter(_Object):
+                $(docstring("Implements the iterator protocol for a rust iterator."))
+
+                def __init__(self, box, next_):
+                    super().__init__(box._api, box)
+                    self._next = next_
+
+                def __iter__(self):
+                    return self
+
+                def __next__(self):
+                    ret = self._next(self._box.borrow())
+                    if ret is None:
+                        raise StopIteration
+                    return ret
+
+            class Future(_Object):
+                $(docstring("Awaitable rust future, polled on the running asyncio event loop."))
+
+                def __init__(self, box, poll):
+                    super().__init__(box._api, box)
+                    self._poll = poll
+
+                def __await__(self):
+                    return self._run().__await__()
+
+                async def _run(self):
+                    event = asyncio.Event()
+                    port = _register_port(asyncio.get_running_loop(), event.set)
+                    try:
+                        while True:
+                            event.clear()
+                            ret = self._poll(self._box.borrow(), _POST_COBJECT, port)
+                            if ret is not None:
+                                return ret
+                            await event.wait()
+                    finally:
+                        _unregister_port(port)
+                        self.drop()
+
+            class Stream(_Object):
+                $(docstring("Asynchronous iterator over a rust stream."))
+
+                def __init__(self, box, poll):
+                    super().__init__(box._api, box)
+                    self._poll = poll
+                    self._done = False
+
+                def __aiter__(self):
+                    return self
+
+                async def __anext__(self):
+                    if self._done:
+                        raise StopAsyncIteration
+                    loop = asyncio.get_running_loop()
+                    event = asyncio.Event()
+
+                    def finish():
+                        self._done = True
+                        event.set()
+
+                    port = _register_port(loop, event.set)
+                    done = _register_port(loop, finish)
+                    try:
+                        while True:
+                            event.clear()
+                            ret = self._poll(self._box.borrow(), _POST_COBJECT, port, done)
+                            if ret is not None:
+                                return ret
+                            await event.wait()
+                            if self._done:
+                                break
+                    finally:
+                        _unregister_port(port)
+                        _unregister_port(done)
+                    self.drop()
+                    raise StopAsyncIteration
+
+            class FfiString(_Object):
+                def __str__(self):
+                    parts = self._api.$(raw(&symbols.string_into_parts()))(self._box.borrow())
+                    return ctypes.string_at(parts.addr, parts.len).decode("utf-8", "replace")
+        }
+    }
+
+    /// Looks up `symbol` and sets the types of its arguments and return value.
+    fn declare(&self, symbol: &str, args: &[&str], ret: &str) -> python::Tokens {
+        let args = args
+            .iter()
+            .map(|arg| format!("ctypes.{}", arg))
+            .collect::<Vec<_>>()
+            .join(", ");
+        quote!(self.$(raw(symbol)) = self._function($(quoted(symbol)), [$args], $ret))
+    }
+
+    fn declare_import(&self, symbol: &str, args: &[Var], ret: &Return) -> python::Tokens {
+        let args = args
+            .iter()
+            .map(|var| self.ctype(var.ty.num()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let ret = match ret {
+            Return::Void => "None".to_string(),
+            Return::Num(var) => self.ctype(var.ty.num()).to_string(),
+            Return::Struct(_, s) => format!("_{}", self.type_ident(s)),
+        };
+        quote!(self.$(raw(symbol)) = self._function($(quoted(symbol)), [$args], $ret))
+    }
+
+    fn declare_list(&self, symbols: &Symbols, objects: &[String], ty: &str) -> python::Tokens {
+        let ptr = "ctypes.c_ssize_t";
+        quote! {
+            $(self.declare(&symbols.list(ty, "Create"), &[], ptr))
+            $(self.declare(&symbols.list(ty, "Len"), &["c_ssize_t"], "ctypes.c_uint32"))
+            $(self.declare(&symbols.list(ty, "ElementAt"), &["c_ssize_t", "c_uint32"], ptr))
+            $(if objects.iter().any(|obj| obj == ty) {
+                $(self.declare(&symbols.list(ty, "Remove"), &["c_ssize_t", "c_uint32"], ptr))
+                $(self.declare(&symbols.list(ty, "Add"), &["c_ssize_t", "c_ssize_t"], "None"))
+                $(self.declare(&symbols.list(ty, "Insert"), &["c_ssize_t", "c_uint32", "c_ssize_t"], "None"))
+            })
+        }
+    }
+
+    fn generate_return_struct(&self, ret: &Return) -> python::Tokens {
+        if let Return::Struct(vars, name) = ret {
+            quote! {
+                class $(format!("_{}", self.type_ident(name)))(ctypes.Structure):
+                    _fields_ = [
+                        $(for (i, var) in vars.iter().enumerate() =>
+                            ($(quoted(format!("arg{}", i))), $(self.ctype(var.ty.num()))),$['\r'])
+                    ]
+            }
+        } else {
+            quote!()
+        }
+    }
+
+    fn generate_ffi_buffer(&self, symbols: &Symbols, ty: NumType) -> python::Tokens {
+        let fixed = self.abi.fixed_size(ty);
+        let (bytes, _) = self.abi.layout(fixed);
+        let class_name = ffi_buffer_name_for(ty);
+        quote! {
+            class $(class_name)(_Object):
+                @staticmethod
+                def allocate(api, length):
+                    $(docstring("Allocates a zeroed buffer that can be filled through as_array and passed to rust without copying."))
+                    ptr = api.$(raw(&symbols.ffi_buffer("new")))(length * $(bytes), $(bytes))
+                    return $(class_name)(api, _Box(api, ptr, $(quoted(symbols.destructor("FfiBuffer")))))
+
+                def as_array(self):
+                    $(docstring("Returns a ctypes array viewing the buffer, the view keeps the buffer alive."))
+                    buffer = self._box.borrow()
+                    address = self._api.$(raw(&symbols.ffi_buffer("address")))(buffer)
+                    size = self._api.$(raw(&symbols.ffi_buffer("size")))(buffer) $(static_literal("//")) $(bytes)
+                    array = ($(self.ctype(fixed)) * size).from_address(address)
+                    array._owner = self
+                    return array
+        }
+    }
+
+    fn generate_object(&self, obj: &AbiObject) -> python::Tokens {
+        quote! {
+            class $(&obj.name)(_Object):
+                $(self.generate_doc(&obj.doc))
+                $(if obj.doc.is_empty() && obj.methods.is_empty() {
+                    pass
+                })
+                $(for func in &obj.methods join ($['\n']) => $(self.generate_function(func)))
+        }
+    }
+
+    fn generate_enum(&self, symbols: &Symbols, e: &Enum) -> python::Tokens {
+        let tag = format!("{}Tag", e.ident);
+        quote! {
+            class $(&tag)(enum.IntEnum):
+                $(for (i, entry) in e.entries.iter().enumerate() => $(self.type_ident(&entry.name)) = $i$['\r'])
+                $(if e.entries.is_empty() {
+                    pass
+                })
+
+            class $(&e.ident)(_Object):
+                $(self.generate_doc(&e.doc))
+
+                def __init__(self, api, box):
+                    super().__init__(api, box)
+                    parts = api.$(raw(&symbols.destructure_enum(&e.ident)))(box.borrow())
+                    self._tag = $(&tag)(parts.tag)
+                    self._inner = None
+                    $(for (entry, inner) in inner_objects(e) =>
+                        if self._tag == $(&tag).$(self.type_ident(&entry)):
+                            self._inner = $(&inner)(api, _Box(api, parts.inner, $(quoted(symbols.destructor(&inner)))))$['\r'])
+
+                @property
+                def tag(self):
+                    $(docstring("The tag of this enum object."))
+                    return self._tag
+
+                @property
+                def inner(self):
+                    $(docstring("The object wrapped by this enum object, if any."))
+                    return self._inner
+        }
+    }
+
+    fn generate_list_type(
+        &self,
+        symbols: &Symbols,
+        objects: &[String],
+        ty: &str,
+    ) -> python::Tokens {
+        let list_name = format!("FfiList{}", ty);
+        quote! {
+            class $(&list_name)(_Object):
+                @staticmethod
+                def create(api):
+                    ptr = api.$(raw(&symbols.list(ty, "Create")))()
+                    return $(&list_name)(api, _Box(api, ptr, $(quoted(symbols.destructor(&list_name)))))
+
+                def __len__(self):
+                    return self._api.$(raw(&symbols.list(ty, "Len")))(self._box.borrow())
+
+                def __getitem__(self, index):
+                    $(docstring("Returns a reference to the element, it keeps the list alive."))
+                    if index < 0:
+                        index += len(self)
+                    if index < 0 or index >= len(self):
+                        raise IndexError("list index out of range")
+                    ptr = self._api.$(raw(&symbols.list(ty, "ElementAt")))(self._box.borrow(), index)
+                    return $ty(self._api, _Box(self._api, ptr, $(quoted(symbols.destructor("Leak"))), parent=self._box))
+
+                def __iter__(self):
+                    for index in range(len(self)):
+                        yield self[index]
+                $(if objects.iter().any(|obj| obj == ty) {
+                    $['\n']
+                    def remove(self, index):
+                        $(docstring("Moves the element out of this list and returns it."))
+                        ptr = self._api.$(raw(&symbols.list(ty, "Remove")))(self._box.borrow(), index)
+                        return $ty(self._api, _Box(self._api, ptr, $(quoted(symbols.destructor(ty)))))
+
+                    def append(self, element):
+                        $(docstring("Moves the element into this list, it can't be used afterwards."))
+                        self._api.$(raw(&symbols.list(ty, "Add")))(self._box.borrow(), element._box.move())
+
+                    def insert(self, index, element):
+                        $(docstring("Moves the element into this list, it can't be used afterwards."))
+                        self._api.$(raw(&symbols.list(ty, "Insert")))(self._box.borrow(), index, element._box.move())
+                })
+        }
+    }
+
+    fn generate_function(&self, func: &AbiFunction) -> python::Tokens {
+        let ffi = self.abi.import(func);
+        let api = match &func.ty {
+            FunctionType::Constructor(_) => "api",
+            FunctionType::Method(_) => "self._api",
+            FunctionType::Function
+            | FunctionType::NextIter(_, _)
+            | FunctionType::PollFuture(_, _)
+            | FunctionType::PollStream(_, _) => "self",
+        };
+        let name = self.function_name(func, &ffi.symbol);
+        let body = Body {
+            symbols: &func.symbols,
+            api,
+            unit: ffi.abi_ret.is_none() && matches!(func.ret, Some(AbiType::Option(_))),
+        };
+        let mut args = vec![match &func.ty {
+            FunctionType::Constructor(_) => "api".to_string(),
+            _ => "self".to_string(),
+        }];
+        args.extend(ffi.abi_args.iter().map(|(name, _)| self.ident(name)));
+        let args = args.join(", ");
+        // variables defined inside of an option or result need to outlive
+        // the branch for the cleanup instructions.
+        let mut nested = vec![];
+        for instr in &ffi.instr {
+            nested_defines(instr, &mut nested);
+        }
+        let instrs: python::Tokens = quote! {
+            $(for var in &nested => $(self.var(var)) = $(self.literal(var.ty.num()))$['\r'])
+            $(for instr in &ffi.instr => $(self.generate_instr(&body, instr))$['\r'])
+        };
+        quote! {
+            $(if let FunctionType::Constructor(_) = &func.ty {
+                @staticmethod$['\r']
+            })
+            def $name($args):
+                $(self.generate_doc(&func.doc))
+                $instrs
+        }
+    }
+
+    fn generate_instr(&self, body: &Body, instr: &Instr) -> python::Tokens {
+        let api = body.api;
+        match instr {
+            Instr::BorrowSelf(out) => quote!($(self.var(out)) = self._box.borrow()),
+            Instr::BorrowObject(in_, out)
+            | Instr::BorrowIter(in_, out)
+            | Instr::BorrowFuture(in_, out)
+            | Instr::BorrowStream(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_))._box.borrow())
+            }
+            Instr::MoveObject(in_, out)
+            | Instr::MoveIter(in_, out)
+            | Instr::MoveFuture(in_, out)
+            | Instr::MoveStream(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_))._box.move())
+            }
+            Instr::LiftObject(obj, box_, drop, out) => quote! {
+                $(self.var(out)) = $obj($api, _Box($api, $(self.var(box_)), $(quoted(drop))))
+            },
+            Instr::LiftRefObject(obj, box_, leak, out) => quote! {
+                $(self.var(out)) = $obj($api, _Box($api, $(self.var(box_)), $(quoted(leak)), parent=self._box))
+            },
+            Instr::BindArg(arg, out) => quote!($(self.var(out)) = $(self.ident(arg))),
+            Instr::BindRets(ret, vars) => match vars.len() {
+                0 => quote!(),
+                1 => quote!($(self.var(&vars[0])) = $(self.var(ret))),
+                _ => quote! {
+                    $(for (idx, var) in vars.iter().enumerate() =>
+                        $(self.var(var)) = $(self.var(ret)).$(format!("arg{}", idx))$['\r'])
+                },
+            },
+            Instr::LowerNum(in_, out) | Instr::LiftNum(in_, out, _) => {
+                quote!($(self.var(out)) = $(self.var(in_)))
+            }
+            Instr::LowerBool(in_, out) => {
+                quote!($(self.var(out)) = 1 if $(self.var(in_)) else 0)
+            }
+            Instr::LiftBool(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)) > 0)
+            }
+            Instr::Deallocate(ptr, len, size, align) => quote! {
+                if $(self.var(len)) > 0:
+                    $api.$(raw(&body.symbols.deallocate()))($(self.var(ptr)), $(self.var(len)) * $(*size), $(*align))
+            },
+            Instr::LowerString(in_, ptr, len, cap, size, align) => quote! {
+                $(self.var(in_))_0 = $(self.var(in_)).encode("utf-8")
+                $(self.var(len)) = len($(self.var(in_))_0)
+                $(self.var(ptr)) = $api.$(raw(&body.symbols.allocate()))($(self.var(len)) * $(*size), $(*align))
+                ctypes.memmove($(self.var(ptr)), $(self.var(in_))_0, $(self.var(len)))
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::LiftString(ptr, len, out) => quote! {
+                $(self.var(out)) = ctypes.string_at($(self.var(ptr)), $(self.var(len))).decode("utf-8", "replace")
+            },
+            Instr::LowerVec(in_, ptr, len, cap, ty, size, align)
+            | Instr::LowerMutSlice(in_, ptr, len, cap, ty, size, align) => quote! {
+                $(self.var(len)) = len($(self.var(in_)))
+                $(self.var(ptr)) = $api.$(raw(&body.symbols.allocate()))($(self.var(len)) * $(*size), $(*align))
+                ($(self.ctype(self.abi.fixed_size(*ty))) * $(self.var(len))).from_address($(self.var(ptr)))[:] = $(self.var(in_))
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::LowerStrSlice(in_, ptr, len, cap) => quote! {
+                $(self.var(in_))_0 = [s.encode("utf-8") for s in $(self.var(in_))]
+                $(self.var(len)) = len($(self.var(in_))_0)
+                $(self.var(cap)) = $(self.var(len)) * 4 + sum(len(s) for s in $(self.var(in_))_0)
+                $(self.var(ptr)) = $api.$(raw(&body.symbols.allocate()))($(self.var(cap)), 4)
+                (ctypes.c_uint32 * $(self.var(len))).from_address($(self.var(ptr)))[:] = [len(s) for s in $(self.var(in_))_0]
+                ctypes.memmove($(self.var(ptr)) + $(self.var(len)) * 4, bytes().join($(self.var(in_))_0), $(self.var(cap)) - $(self.var(len)) * 4)
+            },
+            Instr::LowerObjectSlice(in_, ptr, len, cap, size, align) => quote! {
+                $(self.var(len)) = len($(self.var(in_)))
+                $(self.var(ptr)) = $api.$(raw(&body.symbols.allocate()))($(self.var(len)) * $(*size), $(*align))
+                (ctypes.c_ssize_t * $(self.var(len))).from_address($(self.var(ptr)))[:] = [obj._box.borrow() for obj in $(self.var(in_))]
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::WriteBack(in_, ptr, len, cap, ty) => quote! {
+                if $(self.var(cap)) > 0:
+                    $(self.var(in_))[:] = ($(self.ctype(self.abi.fixed_size(*ty))) * $(self.var(len))).from_address($(self.var(ptr)))[:]
+            },
+            Instr::LiftVec(ptr, len, out, ty) => quote! {
+                $(self.var(out)) = list(($(self.ctype(self.abi.fixed_size(*ty))) * $(self.var(len))).from_address($(self.var(ptr))))
+            },
+            Instr::Call(symbol, ret, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.var(arg))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let invoke = quote!($api.$(raw(symbol))($args));
+                if let Some(ret) = ret {
+                    quote!($(self.var(ret)) = $invoke)
+                } else {
+                    invoke
+                }
+            }
+            Instr::DefineArgs(vars) => quote! {
+                $(for var in vars => $(self.var(var)) = $(self.literal(var.ty.num()))$['\r'])
+            },
+            Instr::ReturnValue(ret) => quote!(return $(self.var(ret))),
+            Instr::ReturnVoid if body.unit => quote!(return ()),
+            Instr::ReturnVoid => quote!(return),
+            Instr::HandleNull(var) => quote! {
+                if $(self.var(var)) == 0:
+                    return None
+            },
+            Instr::LowerOption(arg, var, some, some_instr) => quote! {
+                if $(self.var(arg)) is None:
+                    $(self.var(var)) = 0
+                else:
+                    $(self.var(var)) = 1
+                    $(self.var(some)) = $(self.var(arg))
+                    $(for inst in some_instr => $(self.generate_instr(body, inst))$['\r'])
+            },
+            Instr::LowerResult(arg, var, ok, ok_instr, err, err_instr) => quote! {
+                if $(self.var(arg)).is_ok:
+                    $(self.var(var)) = 1
+                    $(self.var(ok)) = $(self.var(arg)).value
+                    $(for inst in ok_instr => $(self.generate_instr(body, inst))$['\r'])
+                else:
+                    $(self.var(var)) = 0
+                    $(self.var(err)) = $(self.var(arg)).error
+                    $(for inst in err_instr => $(self.generate_instr(body, inst))$['\r'])
+            },
+            Instr::HandleError(var, ptr, len, cap) => quote! {
+                if $(self.var(var)) == 0:
+                    $(self.var(var))_0 = ctypes.string_at($(self.var(ptr)), $(self.var(len))).decode("utf-8", "replace")
+                    if $(self.var(cap)) > 0:
+                        $api.$(raw(&body.symbols.deallocate()))($(self.var(ptr)), $(self.var(cap)), 1)
+                    raise FfiError($(self.var(var))_0)
+            },
+            Instr::LiftIter(box_, next, drop, out) => quote! {
+                $(self.var(out)) = Iter(_Box($api, $(self.var(box_)), $(quoted(drop))), $api.$(wrapper(next)))
+            },
+            Instr::LiftRefIter(box_, next, leak, out) => quote! {
+                $(self.var(out)) = Iter(_Box($api, $(self.var(box_)), $(quoted(leak)), parent=self._box), $api.$(wrapper(next)))
+            },
+            Instr::LiftFuture(box_, poll, drop, out) => quote! {
+                $(self.var(out)) = Future(_Box($api, $(self.var(box_)), $(quoted(drop))), $api.$(wrapper(poll)))
+            },
+            Instr::LiftStream(box_, poll, drop, out) => quote! {
+                $(self.var(out)) = Stream(_Box($api, $(self.var(box_)), $(quoted(drop))), $api.$(wrapper(poll)))
+            },
+            Instr::LiftTuple(vars, out) => match vars.len() {
+                0 => quote!($(self.var(out)) = ()),
+                1 => quote!($(self.var(out)) = $(self.var(&vars[0]))),
+                _ => {
+                    let vars = vars
+                        .iter()
+                        .map(|var| self.var(var))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    quote!($(self.var(out)) = ($vars))
+                }
+            },
+            Instr::LiftNumFromU32Tuple(..)
+            | Instr::LowerNumFromU32Tuple(..)
+            | Instr::CallRetArea(..) => unreachable!(),
+        }
+    }
+
+    fn generate_ffi_buffer_stub(&self, ty: NumType) -> python::Tokens {
+        let class_name = ffi_buffer_name_for(ty);
+        quote! {
+            class $(class_name)(_Object):
+                @staticmethod
+                def allocate(api: Api, length: int) -> $(class_name): ...
+                def as_array(self) -> ctypes.Array[$(self.ctype(self.abi.fixed_size(ty)))]: ...
+        }
+    }
+
+    fn generate_function_stub(&self, func: &AbiFunction) -> python::Tokens {
+        let ffi = self.abi.import(func);
+        let name = self.function_name(func, &ffi.symbol);
+        let mut args = vec![match &func.ty {
+            FunctionType::Constructor(_) => "api: Api".to_string(),
+            _ => "self".to_string(),
+        }];
+        args.extend(
+            ffi.abi_args
+                .iter()
+                .map(|(name, ty)| format!("{}: {}", self.ident(name), self.py_type(ty, true))),
+        );
+        let args = args.join(", ");
+        let ret = match (&ffi.abi_ret, &func.ret) {
+            (Some(ty), _) => self.py_type(ty, false),
+            (None, Some(ty @ AbiType::Option(_))) => self.py_type(ty, false),
+            (None, _) => "None".to_string(),
+        };
+        quote! {
+            $(if let FunctionType::Constructor(_) = &func.ty {
+                @staticmethod$['\r']
+            })
+            def $name($args) -> $ret: ...
+        }
+    }
+
+    fn generate_object_stub(&self, obj: &AbiObject) -> python::Tokens {
+        quote! {
+            class $(&obj.name)(_Object):
+                $(if obj.methods.is_empty() {
+                    ...
+                })
+                $(for func in &obj.methods => $(self.generate_function_stub(func))$['\r'])
+        }
+    }
+
+    fn generate_enum_stub(&self, e: &Enum) -> python::Tokens {
+        let tag = format!("{}Tag", e.ident);
+        quote! {
+            class $(&tag)(enum.IntEnum):
+                $(for (i, entry) in e.entries.iter().enumerate() => $(self.type_ident(&entry.name)) = $i$['\r'])
+                $(if e.entries.is_empty() {
+                    ...
+                })
+
+            class $(&e.ident)(_Object):
+                @property
+                def tag(self) -> $(&tag): ...
+                @property
+                def inner(self) -> Optional[_Object]: ...
+        }
+    }
+
+    fn generate_list_type_stub(&self, objects: &[String], ty: &str) -> python::Tokens {
+        let list_name = format!("FfiList{}", ty);
+        quote! {
+            class $(&list_name)(_Object):
+                @staticmethod
+                def create(api: Api) -> $(&list_name): ...
+                def __len__(self) -> int: ...
+                def __getitem__(self, index: int) -> $ty: ...
+                def __iter__(self) -> Iterator[$ty]: ...
+                $(if objects.iter().any(|obj| obj == ty) {
+                    def remove(self, index: int) -> $ty: ...$['\r']
+                    def append(self, element: $ty) -> None: ...$['\r']
+                    def insert(self, index: int, element: $ty) -> None: ...
+                })
+        }
+    }
+
+    fn function_name(&self, func: &AbiFunction, symbol: &str) -> String {
+        match &func.ty {
+            FunctionType::NextIter(_, _)
+            | FunctionType::PollFuture(_, _)
+            | FunctionType::PollStream(_, _) => wrapper(symbol),
+            _ => self.ident(&func.name),
+        }
+    }
+
+    /// Like the type of a return value, but sequences are accepted as slices
+    /// and results are passed wrapped in a `FfiResult`.
+    fn py_type(&self, ty: &AbiType, arg: bool) -> String {
+        match ty {
+            AbiType::Num(NumType::F32 | NumType::F64) => "float".to_string(),
+            AbiType::Num(_) | AbiType::Isize | AbiType::Usize => "int".to_string(),
+            AbiType::Bool => "bool".to_string(),
+            AbiType::RefStr | AbiType::String => "str".to_string(),
+            AbiType::RefSlice(ty) | AbiType::Vec(ty) => {
+                let num = self.py_type(&AbiType::Num(*ty), arg);
+                if arg {
+                    format!("Sequence[{}]", num)
+                } else {
+                    format!("List[{}]", num)
+                }
+            }
+            AbiType::RefMutSlice(ty) => {
+                format!("MutableSequence[{}]", self.py_type(&AbiType::Num(*ty), arg))
+            }
+            AbiType::RefSliceStr => "Sequence[str]".to_string(),
+            AbiType::RefSliceObject(ty) => format!("Sequence[{}]", ty),
+            AbiType::Option(ty) => format!("Optional[{}]", self.py_type(ty, arg)),
+            AbiType::Result(ty) if arg => format!("FfiResult[{}]", self.py_type(ty, arg)),
+            AbiType::Result(ty) => self.py_type(ty, arg),
+            AbiType::Tuple(tys) => match tys.len() {
+                0 => "Tuple[()]".to_string(),
+                1 => self.py_type(&tys[0], arg),
+                _ => format!(
+                    "Tuple[{}]",
+                    tys.iter()
+                        .map(|ty| self.py_type(ty, arg))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            },
+            AbiType::RefObject(ty) | AbiType::Object(ty) | AbiType::RefEnum(ty) => ty.clone(),
+            AbiType::RefIter(ty) | AbiType::Iter(ty) => {
+                format!("Iter[{}]", self.py_type(ty, false))
+            }
+            AbiType::RefFuture(ty) | AbiType::Future(ty) => {
+                format!("Future[{}]", self.py_type(ty, false))
+            }
+            AbiType::RefStream(ty) | AbiType::Stream(ty) => {
+                format!("Stream[{}]", self.py_type(ty, false))
+            }
+            AbiType::Buffer(ty) | AbiType::RefBuffer(ty) => ffi_buffer_name_for(*ty).to_string(),
+            AbiType::List(ty) => format!("FfiList{}", ty),
+        }
+    }
+
+    fn ctype(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::I8 => "ctypes.c_int8",
+            NumType::I16 => "ctypes.c_int16",
+            NumType::I32 => "ctypes.c_int32",
+            NumType::I64 => "ctypes.c_int64",
+            NumType::U8 => "ctypes.c_uint8",
+            NumType::U16 => "ctypes.c_uint16",
+            NumType::U32 => "ctypes.c_uint32",
+            NumType::U64 => "ctypes.c_uint64",
+            NumType::F32 => "ctypes.c_float",
+            NumType::F64 => "ctypes.c_double",
+            NumType::IPtr => "ctypes.c_ssize_t",
+            NumType::UPtr => "ctypes.c_size_t",
+        }
+    }
+
+    fn literal(&self, ty: NumType) -> python::Tokens {
+        match ty {
+            NumType::F32 | NumType::F64 => quote!(0.0),
+            _ => quote!(0),
+        }
+    }
+
+    fn generate_doc(&self, doc: &[String]) -> python::Tokens {
+        if doc.is_empty() {
+            return quote!();
+        }
+        let mut lines: Vec<String> = doc
+            .iter()
+            .map(|line| line.replace('\\', "\\\\").replace('"', "\\\""))
+            .collect();
+        lines[0] = format!("\"\"\"{}", lines[0]);
+        lines.last_mut().unwrap().push_str("\"\"\"");
+        quote!($(for line in lines => $line$['\r']))
+    }
+
+    fn var(&self, var: &Var) -> String {
+        format!("tmp{}", var.binding)
+    }
+
+    fn type_ident(&self, s: &str) -> String {
+        sanitize_identifier(&s.to_upper_camel_case())
+    }
+
+    fn ident(&self, s: &str) -> String {
+        sanitize_identifier(&s.to_snake_case())
+    }
+}
+
+/// Single line docstring.
+fn docstring(doc: &str) -> String {
+    format!("\"\"\"{}\"\"\"", doc)
+}
+
+/// Entry name and object of every enum entry wrapping an object.
+fn inner_objects(e: &Enum) -> Vec<(String, String)> {
+    e.entries
+        .iter()
+        .filter_map(|entry| match &entry.inner {
+            Some(Type::Ident(name)) => Some((entry.name.clone(), name.clone())),
+            Some(_) => unimplemented!("Enums can only wrap objects"),
+            None => None,
+        })
+        .collect()
+}
+
+/// Name of the ctypes function looked up for `symbol`.
+fn raw(symbol: &str) -> String {
+    format!("_c_{}", symbol.trim_start_matches('_'))
+}
+
+/// Name of the wrapper around the `next` or `poll` function `symbol`.
+fn wrapper(symbol: &str) -> String {
+    format!("_{}", symbol.trim_start_matches('_'))
+}
+
+/// Collects the variables defined inside of `LowerOption` and `LowerResult`.
+fn nested_defines(instr: &Instr, vars: &mut Vec<Var>) {
+    fn collect(instrs: &[Instr], vars: &mut Vec<Var>) {
+        for instr in instrs {
+            if let Instr::DefineArgs(defines) = instr {
+                vars.extend(defines.iter().cloned());
+            }
+            nested_defines(instr, vars);
+        }
+    }
+    match instr {
+        Instr::LowerOption(_, _, _, some) => collect(some, vars),
+        Instr::LowerResult(_, _, _, ok, _, err) => {
+            collect(ok, vars);
+            collect(err, vars);
+        }
+        _ => {}
+    }
+}
+
+fn sanitize_identifier(id: &str) -> String {
+    if RESERVED_IDENTIFIERS.contains(&id) {
+        format!("{}_", id)
+    } else {
+        id.to_string()
+    }
+}
+
+// https://docs.python.org/3/reference/lexical_analysis.html#keywords
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 36] = [
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "self", "try",
+    "while", "with", "yield",
+];
+
+#[cfg(feature = "test_runner")]
+#[doc(hidden)]
+pub mod test_runner {
+    use super::*;
+    use crate::RustGenerator;
+    use anyhow::Result;
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::Command;
+    use tempfile::{NamedTempFile, TempDir};
+    use trybuild::TestCases;
+
+    pub fn compile_pass(iface: &str, rust: rust::Tokens, python: python::Tokens) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let (mut rust_file, rust_file_path) = NamedTempFile::new()?.keep()?;
+        writeln!(rust_file, "#![feature(vec_into_raw_parts)]")?;
+        writeln!(rust_file, "#![feature(lazy_cell)]")?;
+        let rust_gen = RustGenerator::new(Abi::native());
+        let rust_tokens = rust_gen.generate(iface.clone());
+        let python_gen = PythonGenerator::new("compile_pass".to_string());
+        let module = python_gen.generate(iface.clone()).to_file_string()?;
+        let stubs = python_gen.generate_stubs(iface).to_file_string()?;
+
+        let library_tokens: genco::Tokens<genco::lang::Rust> = quote! {
+            $rust_tokens
+            $rust
+        };
+
+        let bin_tokens: python::Tokens = quote! {
+            import asyncio
+            from compile_pass import *
+
+            async def main():
+                api = Api()
+                $python
+
+            asyncio.run(main())
+        };
+
+        let library_dir = TempDir::new()?.keep();
+        let python_file = library_dir.join("main.py");
+        std::fs::write(library_dir.join("compile_pass.py"), module)?;
+        std::fs::write(library_dir.join("compile_pass.pyi"), stubs)?;
+        std::fs::write(&python_file, bin_tokens.to_file_string()?)?;
+
+        let library = library_tokens.to_file_string()?;
+        rust_file.write_all(library.as_bytes())?;
+        run(&library_dir, &rust_file_path, &python_file)
+    }
+
+    /// Builds one library from several interfaces, each in its own rust module
+    /// and python package named after it, and runs the python code against it.
+    pub fn compile_pass_shared(
+        ifaces: &[(&str, &str, rust::Tokens)],
+        python: python::Tokens,
+    ) -> Result<()> {
+        let (mut rust_file, rust_file_path) = NamedTempFile::new()?.keep()?;
+        writeln!(rust_file, "#![feature(vec_into_raw_parts)]")?;
+        writeln!(rust_file, "#![feature(lazy_cell)]")?;
+        let library_dir = TempDir::new()?.keep();
+        let python_gen = PythonGenerator::new("compile_pass".to_string());
+        let mut library_tokens = rust::Tokens::new();
+        for (name, iface, rust) in ifaces {
+            let iface = Interface::parse(iface)?;
+            let rust_tokens = RustGenerator::new(Abi::native()).generate(iface.clone());
+            let module = python_gen.generate(iface).to_file_string()?;
+            std::fs::write(library_dir.join(format!("{}.py", name)), module)?;
+            quote_in! { library_tokens =>
+                mod $(*name) {
+                    $rust_tokens
+                    $rust
+                }
+            }
+        }
+
+        let bin_tokens: python::Tokens = quote! {
+            import asyncio
+            $(for (name, _, _) in ifaces => import $(*name)$['\r'])
+
+            async def main():
+                $python
+
+            asyncio.run(main())
+        };
+        let python_file = library_dir.join("main.py");
+        std::fs::write(&python_file, bin_tokens.to_file_string()?)?;
+
+        let library = library_tokens.to_file_string()?;
+        rust_file.write_all(library.as_bytes())?;
+        run(&library_dir, &rust_file_path, &python_file)
+    }
+
+    fn run(library_dir: &Path, rust_file_path: &Path, python_file: &Path) -> Result<()> {
+        let library_file = library_dir.join("libcompile_pass.so");
+        let runner_tokens: rust::Tokens = quote! {
+            fn main() {
+                use std::process::Command;
+                let ret = Command::new("rustc")
+                    .arg("--edition")
+                    .arg("2021")
+                    .arg("--crate-name")
+                    .arg("compile_pass")
+                    .arg("--crate-type")
+                    .arg("cdylib")
+                    .arg("--cfg")
+                    .arg("feature=\"test_runner\"")
+                    .arg("-o")
+                    .arg($(quoted(library_file.as_path().to_str().unwrap())))
+                    .arg($(quoted(rust_file_path.to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+                let ret = Command::new("python3")
+                    .env("LD_LIBRARY_PATH", $(quoted(library_dir.to_str().unwrap())))
+                    .arg("-X")
+                    .arg("dev")
+                    .arg($(quoted(python_file.to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+            }
+        };
+        let (mut runner_file, runner_file_path) = NamedTempFile::new()?.keep()?;
+        let runner = runner_tokens.to_file_string()?;
+        runner_file.write_all(runner.as_bytes())?;
+
+        let test = TestCases::new();
+        test.pass(runner_file_path.as_path().to_str().unwrap());
+        Ok(())
+    }
+
+    /// Checks that the generated module can be imported without loading the
+    /// library and that the stubs parse.
+    pub fn compile_pass_import(iface: &str) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let python_gen = PythonGenerator::new("compile_pass".to_string());
+        let module = python_gen.generate(iface.clone()).to_file_string()?;
+        let stubs = python_gen.generate_stubs(iface).to_file_string()?;
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("compile_pass.py"), module)?;
+        std::fs::write(dir.path().join("compile_pass.pyi"), stubs)?;
+        let status = Command::new("python3")
+            .arg("-c")
+            .arg("import ast, compile_pass; ast.parse(open(\"compile_pass.pyi\").read())")
+            .current_dir(dir.path())
+            .status()?;
+        if !status.success() {
+            anyhow::bail!("importing the python module failed");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1013 @@
+use crate::dart::ffi_buffer_name_for;
+use crate::import::{Import, Instr};
+use crate::parser::{Enum, Type};
+use crate::{
+    Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Symbols, Var,
+    NUM_TYPES,
+};
+use genco::prelude::*;
+use genco::tokens::static_literal;
+use heck::*;
+
+/// Ruby isn't supported by genco, the python formatter is close enough as
+/// long as the strings don't contain `#{`.
+pub type Tokens = python::Tokens;
+
+pub struct RubyGenerator {
+    abi: Abi,
+    module: String,
+    cdylib_name: String,
+}
+
+/// State shared by the instructions of a single function body.
+struct Body<'a> {
+    symbols: &'a Symbols,
+    /// Box of the object borrowed values returned by a method are borrowed
+    /// from.
+    parent: &'a str,
+    /// `Some(())` is returned as `true` to tell it apart from `nil`.
+    unit: bool,
+    /// `next` and `poll` wrappers return whether the value is ready and the
+    /// value.
+    wrapper: bool,
+}
+
+impl RubyGenerator {
+    /// Generates a ruby file defining `module` which loads the library
+    /// `cdylib_name` using the ffi gem.
+    pub fn new(module: String, cdylib_name: String) -> Self {
+        Self {
+            abi: Abi::native(),
+            module,
+            cdylib_name,
+        }
+    }
+
+    pub fn generate(&self, iface: Interface) -> Tokens {
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
+        let objects: Vec<String> = iface.objects().into_iter().map(|obj| obj.name).collect();
+        let imports = iface.imports(&self.abi);
+        let destructors = iface.destructors();
+        let mut wrappers = vec![];
+        for iter in iface.iterators() {
+            wrappers.push(iter.next());
+        }
+        for fut in iface.futures() {
+            wrappers.push(fut.poll());
+        }
+        for stream in iface.streams() {
+            wrappers.push(stream.poll());
+        }
+        quote! {
+            $(static_literal("#")) AUTO GENERATED FILE, DO NOT EDIT.
+            $(static_literal("#"))
+            $(static_literal("#")) Generated by "ffi-gen".
+
+            require "ffi"
+
+            $(self.generate_doc(&iface.doc))
+            module $(&self.module)
+                $(self.generate_native(&iface, &symbols, &imports, &destructors))
+
+                $(self.generate_runtime(&symbols))
+
+                $(for drop in &destructors join ($['\n']) => $(self.generate_pointer(drop)))
+
+                $(for obj in iface.objects() join ($['\n']) => $(self.generate_object(&obj)))
+
+                $(for e in &iface.enums join ($['\n']) => $(self.generate_enum(&symbols, e)))
+
+                $(for ty in NUM_TYPES join ($['\n']) => $(self.generate_ffi_buffer(&symbols, ty)))
+
+                $(for ty in iface.listed_types() join ($['\n']) => $(self.generate_list_type(&symbols, &objects, &ty)))
+
+                module Wrappers
+                    $(for func in &wrappers join ($['\n']) => $(self.generate_function(func)))
+                end
+
+                $(for func in iface.functions() join ($['\n']) => $(self.generate_function(&func)))
+            end
+        }
+    }
+
+    /// Module attaching every exported symbol, handles are passed as
+    /// `intptr_t`.
+    fn generate_native(
+        &self,
+        iface: &Interface,
+        symbols: &Symbols,
+        imports: &[Import],
+        destructors: &[String],
+    ) -> Tokens {
+        quote! {
+            module Native
+                extend FFI::Library
+                ffi_lib $(quoted(&self.cdylib_name))
+
+                class FfiStringParts < FFI::Struct
+                    layout :ptr, :intptr_t, :len, :uintptr_t, :capacity, :uintptr_t
+                end
+
+                class EnumWrapper < FFI::Struct
+                    layout :tag, :uint32, :inner, :intptr_t
+                end
+                $(for func in imports => $(self.generate_return_struct(&func.ffi_ret)))
+
+                $(self.attach(&symbols.allocate(), &["uintptr_t", "uintptr_t"], ":intptr_t"))
+                $(self.attach(&symbols.deallocate(), &["intptr_t", "uintptr_t", "uintptr_t"], ":void"))
+                $(self.attach(&symbols.ffi_buffer("new"), &["uintptr_t", "uintptr_t"], ":intptr_t"))
+                $(self.attach(&symbols.ffi_buffer("address"), &["intptr_t"], ":uintptr_t"))
+                $(self.attach(&symbols.ffi_buffer("size"), &["intptr_t"], ":uintptr_t"))
+                $(self.attach(&symbols.string_into_parts(), &["intptr_t"], "FfiStringParts.by_value"))
+                $(for e in &iface.enums =>
+                    $(self.attach(&symbols.destructure_enum(&e.ident), &["intptr_t"], "EnumWrapper.by_value"))$['\r'])
+                $(for drop in destructors => $(self.attach(drop, &["intptr_t", "intptr_t"], ":void"))$['\r'])
+                $(for ty in iface.listed_types() => $(self.attach_list(symbols, iface, &ty))$['\r'])
+                $(for func in imports => $(self.attach_import(func))$['\r'])
+            end
+        }
+    }
+
+    fn attach(&self, symbol: &str, args: &[&str], ret: &str) -> Tokens {
+        let args = args
+            .iter()
+            .map(|arg| format!(":{}", arg))
+            .collect::<Vec<_>>()
+            .join(", ");
+        quote!(attach_function $(format!(":{}", symbol)), [$args], $ret)
+    }
+
+    fn attach_import(&self, func: &Import) -> Tokens {
+        let args = func
+            .ffi_args
+            .iter()
+            .map(|var| self.ffi_type(var.ty.num()))
+            .collect::<Vec<_>>();
+        let ret = match &func.ffi_ret {
+            Return::Void => ":void".to_string(),
+            Return::Num(var) => format!(":{}", self.ffi_type(var.ty.num())),
+            Return::Struct(_, name) => format!("{}.by_value", self.struct_ident(name)),
+        };
+        self.attach(&func.symbol, &args, &ret)
+    }
+
+    fn attach_list(&self, symbols: &Symbols, iface: &Interface, ty: &str) -> Tokens {
+        let owned = iface.objects().iter().any(|obj| obj.name == ty);
+        quote! {
+            $(self.attach(&symbols.list(ty, "Create"), &[], ":intptr_t"))
+            $(self.attach(&symbols.list(ty, "Len"), &["intptr_t"], ":uint32"))
+            $(self.attach(&symbols.list(ty, "ElementAt"), &["intptr_t", "uint32"], ":intptr_t"))
+            $(if owned {
+                $(self.attach(&symbols.list(ty, "Remove"), &["intptr_t", "uint32"], ":intptr_t"))
+                $(self.attach(&symbols.list(ty, "Add"), &["intptr_t", "intptr_t"], ":void"))
+                $(self.attach(&symbols.list(ty, "Insert"), &["intptr_t", "uint32", "intptr_t"], ":void"))
+            })
+        }
+    }
+
+    fn generate_return_struct(&self, ret: &Return) -> Tokens {
+        if let Return::Struct(vars, name) = ret {
+            let fields = vars
+                .iter()
+                .enumerate()
+                .map(|(i, var)| format!(":ret{}, :{}", i, self.ffi_type(var.ty.num())))
+                .collect::<Vec<_>>()
+                .join(", ");
+            quote! {
+                $['\n']
+                class $(self.struct_ident(name)) < FFI::Struct
+                    layout $fields
+                end
+            }
+        } else {
+            quote!()
+        }
+    }
+
+    fn generate_runtime(&self, symbols: &Symbols) -> Tokens {
+        quote! {
+            $(static_literal("#")) Error returned by rust.
+            class FfiError < StandardError
+            end
+
+            $(static_literal("#")) Result passed as an argument to rust.
+            class FfiResult
+                attr_reader :value, :error
+
+                def self.ok(value)
+                    new(true, value, nil)
+                end
+
+                def self.err(error)
+                    new(false, nil, error)
+                end
+
+                def initialize(ok, value, error)
+                    @ok = ok
+                    @value = value
+                    @error = error
+                end
+
+                def ok?
+                    @ok
+                end
+            end
+
+            $(static_literal("#")) Handle to a rust value, owned handles are dropped by an FFI::AutoPointer
+            $(static_literal("#")) once they are garbage collected. Borrowed handles keep their parent alive.
+            class FfiBox
+                def initialize(pointer, parent = nil)
+                    @pointer = pointer
+                    @parent = parent
+                end
+
+                def borrow
+                    raise "use after free" if @pointer.nil?
+                    @pointer.address
+                end
+
+                def move
+                    raise "use after free" if @pointer.nil?
+                    raise "can not move borrowed value" unless @pointer.is_a?(FFI::AutoPointer)
+                    @pointer.autorelease = false
+                    address = @pointer.address
+                    @pointer = nil
+                    address
+                end
+
+                def drop
+                    @pointer.free if @pointer.is_a?(FFI::AutoPointer)
+                    @pointer = nil
+                end
+            end
+
+            class FfiObject
+                $(static_literal("#")) Wraps a handle without calling the constructor of the class.
+                def self.from_box(box)
+                    object = allocate
+                    object.instance_variable_set(:@ffi_box, box)
+                    object
+                end
+
+                attr_reader :ffi_box
+
+                $(static_literal("#")) Drops the rust value, it can not be used afterwards.
+                def drop
+                    @ffi_box.drop
+                end
+            end
+
+            $(static_literal("#")) Callbacks woken by rust through the post_cobject function, which may be
+            $(static_literal("#")) called from any thread.
+            module FfiPorts
+                @lock = Mutex.new
+                @callbacks = {}
+                @next_port = 0
+
+                def self.register(&callback)
+                    @lock.synchronize do
+                        @next_port += 1
+                        @callbacks[@next_port] = callback
+                        @next_port
+                    end
+                end
+
+                def self.unregister(port)
+                    @lock.synchronize { @callbacks.delete(port) }
+                end
+
+                POST_COBJECT = FFI::Function.new(:void, [:int64, :pointer]) do |port, _message|
+                    callback = @lock.synchronize { @callbacks[port] }
+                    callback&.call
+                end
+
+                def self.address
+                    POST_COBJECT.address
+                end
+            end
+
+            $(static_literal("#")) Enumerable rust iterator, it is dropped once it is exhausted.
+            class FfiIter < FfiObject
+                include Enumerable
+
+                def initialize(box, next_fn)
+                    @ffi_box = box
+                    @next = next_fn
+                end
+
+                def each
+                    return enum_for(:each) unless block_given?
+                    loop do
+                        ready, item = @next.call(@ffi_box.borrow)
+                        break unless ready
+                        yield item
+                    end
+                    drop
+                    self
+                end
+            end
+
+            $(static_literal("#")) Rust future, polled on the calling thread.
+            class FfiFuture < FfiObject
+                def initialize(box, poll)
+                    @ffi_box = box
+                    @poll = poll
+                end
+
+                $(static_literal("#")) Blocks until the future is ready and returns its value.
+                def value
+                    queue = Queue.new
+                    port = FfiPorts.register { queue << true }
+                    begin
+                        loop do
+                            ready, result = @poll.call(@ffi_box.borrow, FfiPorts.address, port)
+                            return result if ready
+                            queue.pop
+                        end
+                    ensure
+                        FfiPorts.unregister(port)
+                        drop
+                    end
+                end
+            end
+
+            $(static_literal("#")) Enumerable rust stream, polled on the calling thread. It blocks on a queue
+            $(static_literal("#")) the wakers push to while rust is pending.
+            class FfiStream < FfiObject
+                include Enumerable
+
+                def initialize(box, poll)
+                    @ffi_box = box
+                    @poll = poll
+                end
+
+                def each
+                    return enum_for(:each) unless block_given?
+                    queue = Queue.new
+                    done = false
+                    port = FfiPorts.register { queue << true }
+                    done_port = FfiPorts.register do
+                        done = true
+                        queue << true
+                    end
+                    begin
+                        until done
+                            ready, item = @poll.call(@ffi_box.borrow, FfiPorts.address, port, done_port)
+                            if ready
+                                yield item
+                            elsif !done
+                                queue.pop
+                            end
+                        end
+                    ensure
+                        FfiPorts.unregister(port)
+                        FfiPorts.unregister(done_port)
+                        drop
+                    end
+                    self
+                end
+            end
+
+            $(static_literal("#")) Rust String owned by a list.
+            class FfiString < FfiObject
+                def to_s
+                    parts = Native.$(symbols.string_into_parts())(@ffi_box.borrow)
+                    return "" if parts[:len].zero?
+                    FFI::Pointer.new(parts[:ptr]).read_bytes(parts[:len]).force_encoding("UTF-8")
+                end
+            end
+        }
+    }
+
+    /// AutoPointer releasing a handle through the destructor `drop`.
+    fn generate_pointer(&self, drop: &str) -> Tokens {
+        quote! {
+            class $(pointer_class(drop)) < FFI::AutoPointer
+                def self.release(pointer)
+                    Native.$drop(0, pointer.address)
+                end
+            end
+        }
+    }
+
+    fn generate_object(&self, obj: &AbiObject) -> Tokens {
+        quote! {
+            $(self.generate_doc(&obj.doc))
+            class $(&obj.name) < FfiObject
+                $(for func in &obj.methods join ($['\n']) => $(self.generate_function(func)))
+            end
+        }
+    }
+
+    fn generate_enum(&self, symbols: &Symbols, e: &Enum) -> Tokens {
+        let tags = e
+            .entries
+            .iter()
+            .map(|entry| format!(":{}", entry.name.to_snake_case()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        quote! {
+            $(self.generate_doc(&e.doc))
+            class $(&e.ident) < FfiObject
+                TAGS = [$tags].freeze
+
+                $(static_literal("#")) The tag of this enum object and the object wrapped by it, if any.
+                attr_reader :tag, :inner
+
+                def self.from_box(box)
+                    object = super
+                    object.send(:destructure)
+                    object
+                end
+
+                private
+
+                def destructure
+                    parts = Native.$(symbols.destructure_enum(&e.ident))(@ffi_box.borrow)
+                    @tag = TAGS[parts[:tag]]
+                    @inner = nil
+                    $(for (entry, inner) in inner_objects(e) =>
+                        if @tag == $(format!(":{}", entry.to_snake_case()))
+                            @inner = $(&inner).from_box(FfiBox.new($(pointer_class(&symbols.destructor(&inner))).new(FFI::Pointer.new(parts[:inner]))))
+                        end$['\r'])
+                end
+            end
+        }
+    }
+
+    fn generate_ffi_buffer(&self, symbols: &Symbols, ty: NumType) -> Tokens {
+        let name = ffi_buffer_name_for(ty);
+        let fixed = self.abi.fixed_size(ty);
+        let (size, _) = self.abi.layout(fixed);
+        let num = self.memory_type(fixed);
+        let ptr =
+            quote!(FFI::Pointer.new(Native.$(symbols.ffi_buffer("address"))(@ffi_box.borrow)));
+        quote! {
+            $(static_literal("#")) Buffer that can be filled in place and passed to rust without copying.
+            class $name < FfiObject
+                include Enumerable
+
+                $(static_literal("#")) Allocates a zeroed buffer of length elements.
+                def self.new(length)
+                    ptr = Native.$(symbols.ffi_buffer("new"))(length * $size, $size)
+                    from_box(FfiBox.new($(pointer_class(&symbols.destructor("FfiBuffer"))).new(FFI::Pointer.new(ptr))))
+                end
+
+                def length
+                    Native.$(symbols.ffi_buffer("size"))(@ffi_box.borrow) / $size
+                end
+
+                def [](index)
+                    check_index(index)
+                    $(&ptr).$(format!("get_{}", num))(index * $size)
+                end
+
+                def []=(index, value)
+                    check_index(index)
+                    $(&ptr).$(format!("put_{}", num))(index * $size, value)
+                end
+
+                def each
+                    return enum_for(:each) unless block_given?
+                    length.times { |index| yield self[index] }
+                    self
+                end
+
+                private
+
+                def check_index(index)
+                    raise IndexError, "index out of range" if index < 0 || index >= length
+                end
+            end
+        }
+    }
+
+    fn generate_list_type(&self, symbols: &Symbols, objects: &[String], ty: &str) -> Tokens {
+        let name = format!("FfiList{}", ty);
+        let owned = objects.iter().any(|obj| obj == ty);
+        quote! {
+            $(static_literal("#")) Rust Vec owning its elements.
+            class $(&name) < FfiObject
+                include Enumerable
+
+                def self.new
+                    ptr = Native.$(symbols.list(ty, "Create"))
+                    from_box(FfiBox.new($(pointer_class(&symbols.destructor(&name))).new(FFI::Pointer.new(ptr))))
+                end
+
+                def length
+                    Native.$(symbols.list(ty, "Len"))(@ffi_box.borrow)
+                end
+
+                $(static_literal("#")) Returns a reference to the element, it keeps the list alive.
+                def [](index)
+                    index = check_index(index, length)
+                    ptr = Native.$(symbols.list(ty, "ElementAt"))(@ffi_box.borrow, index)
+                    $ty.from_box(FfiBox.new(FFI::Pointer.new(ptr), @ffi_box))
+                end
+
+                def each
+                    return enum_for(:each) unless block_given?
+                    length.times { |index| yield self[index] }
+                    self
+                end
+                $(if owned {
+                    $['\n']
+                    $(static_literal("#")) Moves the element out of this list and returns it.
+                    def delete_at(index)
+                        index = check_index(index, length)
+                        ptr = Native.$(symbols.list(ty, "Remove"))(@ffi_box.borrow, index)
+                        $ty.from_box(FfiBox.new($(pointer_class(&symbols.destructor(ty))).new(FFI::Pointer.new(ptr))))
+                    end
+
+                    $(static_literal("#")) Moves the element into this list, it can not be used afterwards.
+                    def push(element)
+                        Native.$(symbols.list(ty, "Add"))(@ffi_box.borrow, element.ffi_box.move)
+                        self
+                    end
+
+                    $(static_literal("#")) Moves the element into this list, it can not be used afterwards.
+                    def insert(index, element)
+                        index = check_index(index, length + 1)
+                        Native.$(symbols.list(ty, "Insert"))(@ffi_box.borrow, index, element.ffi_box.move)
+                        self
+                    end
+                })
+
+                private
+
+                def check_index(index, len)
+                    index += len if index < 0
+                    raise IndexError, "index out of range" if index < 0 || index >= len
+                    index
+                end
+            end
+        }
+    }
+
+    fn generate_function(&self, func: &AbiFunction) -> Tokens {
+        let ffi = self.abi.import(func);
+        let wrapper = matches!(
+            func.ty,
+            FunctionType::NextIter(_, _)
+                | FunctionType::PollFuture(_, _)
+                | FunctionType::PollStream(_, _)
+        );
+        let (name, parent) = match &func.ty {
+            FunctionType::Method(_) => (func.name.to_snake_case(), "@ffi_box"),
+            FunctionType::Constructor(_) | FunctionType::Function => {
+                (format!("self.{}", func.name.to_snake_case()), "nil")
+            }
+            _ => (format!("self.{}", wrapper_name(&ffi.symbol)), "nil"),
+        };
+        let body = Body {
+            symbols: &func.symbols,
+            parent,
+            unit: ffi.abi_ret.is_none() && matches!(func.ret, Some(AbiType::Option(_))),
+            wrapper,
+        };
+        let args = ffi
+            .abi_args
+            .iter()
+            .map(|(name, _)| self.ident(name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        // variables defined inside of an option or result need to outlive
+        // the branch for the cleanup instructions.
+        let mut nested = vec![];
+        for instr in &ffi.instr {
+            nested_defines(instr, &mut nested);
+        }
+        quote! {
+            $(self.generate_doc(&func.doc))
+            def $name($args)
+                $(for var in &nested => $(self.var(var)) = $(self.literal(var.ty.num()))$['\r'])
+                $(for instr in &ffi.instr => $(self.generate_instr(&body, instr))$['\r'])
+            end
+        }
+    }
+
+    fn generate_instr(&self, body: &Body, instr: &Instr) -> Tokens {
+        match instr {
+            Instr::BorrowSelf(out) => quote!($(self.var(out)) = @ffi_box.borrow),
+            Instr::BorrowObject(in_, out)
+            | Instr::BorrowIter(in_, out)
+            | Instr::BorrowFuture(in_, out)
+            | Instr::BorrowStream(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).ffi_box.borrow)
+            }
+            Instr::MoveObject(in_, out)
+            | Instr::MoveIter(in_, out)
+            | Instr::MoveFuture(in_, out)
+            | Instr::MoveStream(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).ffi_box.move)
+            }
+            Instr::LiftObject(obj, box_, drop, out) => quote! {
+                $(self.var(out)) = $obj.from_box($(self.owned_box(drop, box_)))
+            },
+            Instr::LiftRefObject(obj, box_, _leak, out) => quote! {
+                $(self.var(out)) = $obj.from_box($(self.borrowed_box(body, box_)))
+            },
+            Instr::BindArg(arg, out) => quote!($(self.var(out)) = $(self.ident(arg))),
+            Instr::BindRets(ret, vars) => match vars.len() {
+                0 => quote!(),
+                1 => quote!($(self.var(&vars[0])) = $(self.var(ret))),
+                _ => quote! {
+                    $(for (i, var) in vars.iter().enumerate() =>
+                        $(self.var(var)) = $(self.var(ret))[$(format!(":ret{}", i))]$['\r'])
+                },
+            },
+            Instr::LowerNum(in_, out) | Instr::LiftNum(in_, out, _) => {
+                quote!($(self.var(out)) = $(self.var(in_)))
+            }
+            Instr::LowerBool(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)) ? 1 : 0)
+            }
+            Instr::LiftBool(in_, out) => quote!($(self.var(out)) = $(self.var(in_)) != 0),
+            Instr::Deallocate(ptr, len, size, align) => quote! {
+                Native.$(body.symbols.deallocate())($(self.var(ptr)), $(self.var(len)) * $(*size), $(*align)) if $(self.var(len)) > 0
+            },
+            Instr::LowerString(in_, ptr, len, cap, size, align) => quote! {
+                $(self.var(in_))_0 = $(self.var(in_)).encode("UTF-8").b
+                $(self.var(len)) = $(self.var(in_))_0.bytesize
+                $(self.var(ptr)) = Native.$(body.symbols.allocate())($(self.var(len)) * $(*size), $(*align))
+                FFI::Pointer.new($(self.var(ptr))).put_bytes(0, $(self.var(in_))_0) if $(self.var(len)) > 0
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::LiftString(ptr, len, out) => quote! {
+                $(self.var(out)) = $(self.var(len)) > 0 ? FFI::Pointer.new($(self.var(ptr))).read_bytes($(self.var(len))).force_encoding("UTF-8") : ""
+            },
+            Instr::LowerVec(in_, ptr, len, cap, ty, size, align)
+            | Instr::LowerMutSlice(in_, ptr, len, cap, ty, size, align) => quote! {
+                $(self.var(len)) = $(self.var(in_)).length
+                $(self.var(ptr)) = Native.$(body.symbols.allocate())($(self.var(len)) * $(*size), $(*align))
+                FFI::Pointer.new($(self.var(ptr))).$(self.put_array(*ty))(0, $(self.var(in_))) if $(self.var(len)) > 0
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::LowerStrSlice(in_, ptr, len, cap) => quote! {
+                $(self.var(in_))_0 = $(self.var(in_)).map { |str| str.encode("UTF-8").b }
+                $(self.var(len)) = $(self.var(in_))_0.length
+                $(self.var(cap)) = $(self.var(len)) * 4 + $(self.var(in_))_0.sum(&:bytesize)
+                $(self.var(ptr)) = Native.$(body.symbols.allocate())($(self.var(cap)), 4)
+                if $(self.var(len)) > 0
+                    FFI::Pointer.new($(self.var(ptr))).put_array_of_uint32(0, $(self.var(in_))_0.map(&:bytesize))
+                    FFI::Pointer.new($(self.var(ptr))).put_bytes($(self.var(len)) * 4, $(self.var(in_))_0.join)
+                end
+            },
+            Instr::LowerObjectSlice(in_, ptr, len, cap, size, align) => quote! {
+                $(self.var(len)) = $(self.var(in_)).length
+                $(self.var(ptr)) = Native.$(body.symbols.allocate())($(self.var(len)) * $(*size), $(*align))
+                FFI::Pointer.new($(self.var(ptr))).$(self.put_array(NumType::IPtr))(0, $(self.var(in_)).map { |obj| obj.ffi_box.borrow }) if $(self.var(len)) > 0
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::WriteBack(in_, ptr, len, cap, ty) => quote! {
+                $(self.var(in_)).replace(FFI::Pointer.new($(self.var(ptr))).$(self.get_array(*ty))(0, $(self.var(len)))) if $(self.var(cap)) > 0
+            },
+            Instr::LiftVec(ptr, len, out, ty) => quote! {
+                $(self.var(out)) = $(self.var(len)) > 0 ? FFI::Pointer.new($(self.var(ptr))).$(self.get_array(*ty))(0, $(self.var(len))) : []
+            },
+            Instr::Call(symbol, ret, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.var(arg))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let invoke = quote!(Native.$symbol($args));
+                if let Some(ret) = ret {
+                    quote!($(self.var(ret)) = $invoke)
+                } else {
+                    invoke
+                }
+            }
+            Instr::DefineArgs(vars) => quote! {
+                $(for var in vars => $(self.var(var)) = $(self.literal(var.ty.num()))$['\r'])
+            },
+            Instr::ReturnValue(ret) if body.wrapper => quote!(return [true, $(self.var(ret))]),
+            Instr::ReturnValue(ret) => quote!(return $(self.var(ret))),
+            Instr::ReturnVoid if body.wrapper => quote!(return [true, nil]),
+            Instr::ReturnVoid if body.unit => quote!(return true),
+            Instr::ReturnVoid => quote!(return nil),
+            Instr::HandleNull(var) => {
+                let none = if body.wrapper { "[false, nil]" } else { "nil" };
+                quote!(return $none if $(self.var(var)) == 0)
+            }
+            Instr::LowerOption(arg, var, some, some_instr) => quote! {
+                if $(self.var(arg)).nil?
+                    $(self.var(var)) = 0
+                else
+                    $(self.var(var)) = 1
+                    $(self.var(some)) = $(self.var(arg))
+                    $(for inst in some_instr => $(self.generate_instr(body, inst))$['\r'])
+                end
+            },
+            Instr::LowerResult(arg, var, ok, ok_instr, err, err_instr) => quote! {
+                if $(self.var(arg)).ok?
+                    $(self.var(var)) = 1
+                    $(self.var(ok)) = $(self.var(arg)).value
+                    $(for inst in ok_instr => $(self.generate_instr(body, inst))$['\r'])
+                else
+                    $(self.var(var)) = 0
+                    $(self.var(err)) = $(self.var(arg)).error.to_s
+                    $(for inst in err_instr => $(self.generate_instr(body, inst))$['\r'])
+                end
+            },
+            Instr::HandleError(var, ptr, len, cap) => quote! {
+                if $(self.var(var)) == 0
+                    $(self.var(var))_0 = FFI::Pointer.new($(self.var(ptr))).read_bytes($(self.var(len))).force_encoding("UTF-8")
+                    Native.$(body.symbols.deallocate())($(self.var(ptr)), $(self.var(cap)), 1) if $(self.var(cap)) > 0
+                    raise FfiError, $(self.var(var))_0
+                end
+            },
+            Instr::LiftIter(box_, next, drop, out) => quote! {
+                $(self.var(out)) = FfiIter.new($(self.owned_box(drop, box_)), Wrappers.method(:$(wrapper_name(next))))
+            },
+            Instr::LiftRefIter(box_, next, _leak, out) => quote! {
+                $(self.var(out)) = FfiIter.new($(self.borrowed_box(body, box_)), Wrappers.method(:$(wrapper_name(next))))
+            },
+            Instr::LiftFuture(box_, poll, drop, out) => quote! {
+                $(self.var(out)) = FfiFuture.new($(self.owned_box(drop, box_)), Wrappers.method(:$(wrapper_name(poll))))
+            },
+            Instr::LiftStream(box_, poll, drop, out) => quote! {
+                $(self.var(out)) = FfiStream.new($(self.owned_box(drop, box_)), Wrappers.method(:$(wrapper_name(poll))))
+            },
+            Instr::LiftTuple(vars, out) => match vars.len() {
+                1 => quote!($(self.var(out)) = $(self.var(&vars[0]))),
+                _ => {
+                    let vars = vars
+                        .iter()
+                        .map(|var| self.var(var))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    quote!($(self.var(out)) = [$vars])
+                }
+            },
+            Instr::LiftNumFromU32Tuple(..)
+            | Instr::LowerNumFromU32Tuple(..)
+            | Instr::CallRetArea(..) => unreachable!(),
+        }
+    }
+
+    /// Box dropping the handle `box_` through the destructor `drop` once it
+    /// is garbage collected.
+    fn owned_box(&self, drop: &str, box_: &Var) -> String {
+        format!(
+            "FfiBox.new({}.new(FFI::Pointer.new({})))",
+            pointer_class(drop),
+            self.var(box_)
+        )
+    }
+
+    fn borrowed_box(&self, body: &Body, box_: &Var) -> String {
+        format!(
+            "FfiBox.new(FFI::Pointer.new({}), {})",
+            self.var(box_),
+            body.parent
+        )
+    }
+
+    fn put_array(&self, ty: NumType) -> String {
+        format!("put_array_of_{}", self.memory_type(self.abi.fixed_size(ty)))
+    }
+
+    fn get_array(&self, ty: NumType) -> String {
+        format!("get_array_of_{}", self.memory_type(self.abi.fixed_size(ty)))
+    }
+
+    /// Suffix of the `FFI::Pointer` accessors of a fixed size `ty`.
+    fn memory_type(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::U8 => "uint8",
+            NumType::U16 => "uint16",
+            NumType::U32 => "uint32",
+            NumType::U64 => "uint64",
+            NumType::I8 => "int8",
+            NumType::I16 => "int16",
+            NumType::I32 => "int32",
+            NumType::I64 => "int64",
+            NumType::F32 => "float32",
+            NumType::F64 => "float64",
+            NumType::IPtr | NumType::UPtr => unreachable!("{:?} is not fixed size", ty),
+        }
+    }
+
+    fn ffi_type(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::U8 => "uint8",
+            NumType::U16 => "uint16",
+            NumType::U32 => "uint32",
+            NumType::U64 => "uint64",
+            NumType::I8 => "int8",
+            NumType::I16 => "int16",
+            NumType::I32 => "int32",
+            NumType::I64 => "int64",
+            NumType::F32 => "float",
+            NumType::F64 => "double",
+            NumType::IPtr => "intptr_t",
+            NumType::UPtr => "uintptr_t",
+        }
+    }
+
+    fn literal(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::F32 | NumType::F64 => "0.0",
+            _ => "0",
+        }
+    }
+
+    fn generate_doc(&self, doc: &[String]) -> Tokens {
+        quote! {
+            $(for line in doc => $(format!("# {}", line).trim_end().to_string())$['\r'])
+        }
+    }
+
+    fn var(&self, var: &Var) -> String {
+        format!("tmp{}", var.binding)
+    }
+
+    fn struct_ident(&self, s: &str) -> String {
+        s.to_upper_camel_case()
+    }
+
+    fn ident(&self, s: &str) -> String {
+        sanitize_identifier(&s.to_snake_case())
+    }
+}
+
+/// Name of the `FFI::AutoPointer` releasing handles through `drop`.
+fn pointer_class(drop: &str) -> String {
+    format!("{}Pointer", drop.to_upper_camel_case())
+}
+
+/// Name of the wrapper around the `next` or `poll` function `symbol`.
+fn wrapper_name(symbol: &str) -> String {
+    symbol.trim_start_matches('_').to_string()
+}
+
+/// Entry name and object of every enum entry wrapping an object.
+fn inner_objects(e: &Enum) -> Vec<(String, String)> {
+    e.entries
+        .iter()
+        .filter_map(|entry| match &entry.inner {
+            Some(Type::Ident(name)) => Some((entry.name.clone(), name.clone())),
+            Some(_) => unimplemented!("Enums can only wrap objects"),
+            None => None,
+        })
+        .collect()
+}
+
+/// Collects the variables defined inside of `LowerOption` and `LowerResult`.
+fn nested_defines(instr: &Instr, vars: &mut Vec<Var>) {
+    fn collect(instrs: &[Instr], vars: &mut Vec<Var>) {
+        for instr in instrs {
+            if let Instr::DefineArgs(defines) = instr {
+                vars.extend(defines.iter().cloned());
+            }
+            nested_defines(instr, vars);
+        }
+    }
+    match instr {
+        Instr::LowerOption(_, _, _, some) => collect(some, vars),
+        Instr::LowerResult(_, _, _, ok, _, err) => {
+            collect(ok, vars);
+            collect(err, vars);
+        }
+        _ => {}
+    }
+}
+
+fn sanitize_identifier(id: &str) -> String {
+    if RESERVED_IDENTIFIERS.contains(&id) {
+        format!("{}_", id)
+    } else {
+        id.to_string()
+    }
+}
+
+// https://docs.ruby-lang.org/en/master/syntax/keywords_rdoc.html
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 41] = [
+    "__ENCODING__",
+    "__LINE__",
+    "__FILE__",
+    "BEGIN",
+    "END",
+    "alias",
+    "and",
+    "begin",
+    "break",
+    "case",
+    "class",
+    "def",
+    "defined",
+    "do",
+    "else",
+    "elsif",
+    "end",
+    "ensure",
+    "false",
+    "for",
+    "if",
+    "in",
+    "module",
+    "next",
+    "nil",
+    "not",
+    "or",
+    "redo",
+    "rescue",
+    "retry",
+    "return",
+    "self",
+    "super",
+    "then",
+    "true",
+    "undef",
+    "unless",
+    "until",
+    "when",
+    "while",
+    "yield",
+];
+
+#[cfg(feature = "test_runner")]
+#[doc(hidden)]
+pub mod test_runner {
+    use super::*;
+    use crate::RustGenerator;
+    use anyhow::Result;
+    use std::io::Write;
+    use tempfile::{NamedTempFile, TempDir};
+    use trybuild::TestCases;
+
+    pub fn compile_pass(iface: &str, rust: rust::Tokens, ruby: Tokens) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let (mut rust_file, rust_file_path) = NamedTempFile::new()?.keep()?;
+        writeln!(rust_file, "#![feature(vec_into_raw_parts)]")?;
+        writeln!(rust_file, "#![feature(lazy_cell)]")?;
+        let rust_gen = RustGenerator::new(Abi::native());
+        let rust_tokens = rust_gen.generate(iface.clone());
+        let ruby_gen = RubyGenerator::new("Api".to_string(), "compile_pass".to_string());
+        let module = ruby_gen.generate(iface).to_file_string()?;
+
+        let library_tokens: genco::Tokens<genco::lang::Rust> = quote! {
+            $rust_tokens
+            $rust
+        };
+
+        let bin_tokens: Tokens = quote! {
+            require_relative "compile_pass"
+
+            def check(condition)
+                raise "check failed" unless condition
+            end
+
+            $ruby
+        };
+
+        let library_dir = TempDir::new()?.keep();
+        let library_file = library_dir.join("libcompile_pass.so");
+        let ruby_file = library_dir.join("main.rb");
+        std::fs::write(library_dir.join("compile_pass.rb"), module)?;
+        std::fs::write(&ruby_file, bin_tokens.to_file_string()?)?;
+
+        let library = library_tokens.to_file_string()?;
+        rust_file.write_all(library.as_bytes())?;
+
+        let runner_tokens: rust::Tokens = quote! {
+            fn main() {
+                use std::process::Command;
+                let ret = Command::new("rustc")
+                    .arg("--edition")
+                    .arg("2021")
+                    .arg("--crate-name")
+                    .arg("compile_pass")
+                    .arg("--crate-type")
+                    .arg("cdylib")
+                    .arg("--cfg")
+                    .arg("feature=\"test_runner\"")
+                    .arg("-o")
+                    .arg($(quoted(library_file.as_path().to_str().unwrap())))
+                    .arg($(quoted(rust_file_path.as_path().to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+                let ret = Command::new("ruby")
+                    .env("LD_LIBRARY_PATH", $(quoted(library_dir.as_path().to_str().unwrap())))
+                    .arg("-w")
+                    .arg($(quoted(ruby_file.as_path().to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+            }
+        };
+        let (mut runner_file, runner_file_path) = NamedTempFile::new()?.keep()?;
+        let runner = runner_tokens.to_file_string()?;
+        runner_file.write_all(runner.as_bytes())?;
+
+        let test = TestCases::new();
+        test.pass(runner_file_path.as_path().to_str().unwrap());
+        Ok(())
+    }
+}
@@ -1,21 +1,102 @@
 use crate::export::Instr;
-use crate::parser::Enum;
+use crate::parser::{Enum, Function};
 use crate::{
     Abi, AbiFunction, AbiFuture, AbiIter, AbiObject, AbiStream, AbiType, FunctionType, Interface,
-    NumType, Return, Var,
+    NumType, Return, Symbols, Var,
 };
 use genco::prelude::*;
+use std::collections::HashMap;
 
+#[derive(Clone)]
 pub struct RustGenerator {
     abi: Abi,
+    source: Option<String>,
+    napi: bool,
+    names: RustNames,
+}
+
+/// Rust names of the items renamed in the bindings, keyed by the names in the
+/// bindings. Methods are keyed by the name of their object in the bindings.
+#[derive(Clone, Default)]
+struct RustNames {
+    types: HashMap<String, String>,
+    functions: HashMap<(Option<String>, String), String>,
+}
+
+impl RustNames {
+    fn new(iface: &Interface) -> Self {
+        let mut names = Self::default();
+        for func in &iface.functions {
+            if let Some(rename) = &func.rename {
+                names
+                    .functions
+                    .insert((None, rename.clone()), func.ident.clone());
+            }
+        }
+        for obj in &iface.objects {
+            let object = obj.rename.clone().unwrap_or_else(|| obj.ident.clone());
+            if obj.rename.is_some() {
+                names.types.insert(object.clone(), obj.ident.clone());
+            }
+            for method in &obj.methods {
+                if let Some(rename) = &method.rename {
+                    let key = (Some(object.clone()), rename.clone());
+                    names.functions.insert(key, method.ident.clone());
+                }
+            }
+        }
+        for e in &iface.enums {
+            if let Some(rename) = &e.rename {
+                names.types.insert(rename.clone(), e.ident.clone());
+            }
+        }
+        names
+    }
 }
 
 impl RustGenerator {
     pub fn new(abi: Abi) -> Self {
-        Self { abi }
+        Self {
+            abi,
+            source: None,
+            napi: false,
+            names: Default::default(),
+        }
+    }
+
+    /// Sets the name of the interface file used in conformance errors.
+    pub fn source(mut self, source: Option<String>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Registers the exported functions as a Node-API module, so the cdylib
+    /// can be loaded as a node addon.
+    pub fn napi(mut self, napi: bool) -> Self {
+        self.napi = napi;
+        self
     }
 
     pub fn generate(&self, iface: Interface) -> rust::Tokens {
+        // the glue is generated from the names in the bindings, which the
+        // exported symbols are named after, and calls the renamed items by their
+        // rust names. The conformance checks name the rust items directly.
+        let generator = Self {
+            names: RustNames::new(&iface),
+            ..self.clone()
+        };
+        generator.generate_glue(&iface, iface.renamed())
+    }
+
+    fn generate_glue(&self, rust: &Interface, iface: Interface) -> rust::Tokens {
+        let symbols = iface.symbols();
+        // the helpers are still needed by the glue, but only the library
+        // providing the runtime exports them
+        let runtime_export: rust::Tokens = if symbols.exports_runtime() {
+            quote!(#[no_mangle])
+        } else {
+            quote!()
+        };
         let wasm_bindgen: rust::Tokens = if cfg!(feature = "wasm-bindgen") {
             quote! {
                 // Workaround for combined use with `wasm-bindgen`, so we don't have to
@@ -67,8 +148,8 @@ impl RustGenerator {
 
             pub type Result<T, E = String> = core::result::Result<T, E>;
 
-            #[no_mangle]
-            pub unsafe extern "C" fn allocate(size: usize, align: usize) -> *mut u8 {
+            $(&runtime_export)
+            pub unsafe extern "C" fn $(symbols.allocate())(size: usize, align: usize) -> *mut u8 {
                 let layout = std::alloc::Layout::from_size_align_unchecked(size, align);
                 let ptr = std::alloc::alloc(layout);
                 if ptr.is_null() {
@@ -77,17 +158,21 @@ impl RustGenerator {
                 ptr
             }
 
-            #[no_mangle]
-            pub unsafe extern "C" fn deallocate(ptr: *mut u8, size: usize, align: usize) {
+            $(&runtime_export)
+            pub unsafe extern "C" fn $(symbols.deallocate())(ptr: *mut u8, size: usize, align: usize) {
                 let layout = std::alloc::Layout::from_size_align_unchecked(size, align);
                 std::alloc::dealloc(ptr, layout);
             }
 
+            /// Buffers are passed between the ffi-gen libraries linked into one binary, so
+            /// they have the same layout in all of them.
             #[derive(Debug)]
+            #[repr(C)]
             pub struct FfiBuffer<T> {
                 pub addr: usize,
                 pub size: usize,
                 pub alloc: usize,
+                pub align: usize,
                 pub phantom: std::marker::PhantomData<T>
             }
 
@@ -99,28 +184,72 @@ impl RustGenerator {
                             addr: std::mem::transmute(addr),
                             size: size * std::mem::size_of::<T>(),
                             alloc: alloc * std::mem::size_of::<T>(),
+                            align: std::mem::align_of::<T>(),
                             phantom: Default::default(),
                         }
                     }
                 }
             }
 
+            impl<T> core::ops::Deref for FfiBuffer<T> {
+                type Target = [T];
+
+                fn deref(&self) -> &[T] {
+                    unsafe {
+                        std::slice::from_raw_parts(self.addr as *const T, self.size / std::mem::size_of::<T>())
+                    }
+                }
+            }
+
+            impl<T> core::ops::DerefMut for FfiBuffer<T> {
+                fn deref_mut(&mut self) -> &mut [T] {
+                    unsafe {
+                        std::slice::from_raw_parts_mut(self.addr as *mut T, self.size / std::mem::size_of::<T>())
+                    }
+                }
+            }
+
             impl<T> Drop for FfiBuffer<T> {
                 fn drop(&mut self) {
-                    unsafe {
-                        Vec::from_raw_parts(self.addr as *mut u8, self.size, self.alloc);
+                    // the buffer is dropped as `FfiBuffer<u8>` by the host, so it is
+                    // freed with the layout it was allocated with
+                    if self.alloc != 0 {
+                        unsafe {
+                            $(symbols.deallocate())(self.addr as *mut u8, self.alloc, self.align);
+                        }
                     }
                 }
             }
 
-            #[no_mangle]
-            pub unsafe extern "C" fn __ffi_buffer_address(ptr: *mut c_void) -> UPtr {
+            /// Allocates a zeroed buffer through `allocate` and boxes it, so the host
+            /// gets a handle it can fill before passing it to rust.
+            $(&runtime_export)
+            pub unsafe extern "C" fn $(symbols.ffi_buffer("new"))(size: UPtr, align: UPtr) -> IPtr {
+                let addr = if size == 0 {
+                    align as usize
+                } else {
+                    let ptr = $(symbols.allocate())(size as _, align as _);
+                    ptr.write_bytes(0, size as _);
+                    ptr as usize
+                };
+                let buffer = FfiBuffer::<u8> {
+                    addr,
+                    size: size as _,
+                    alloc: size as _,
+                    align: align as _,
+                    phantom: Default::default(),
+                };
+                Box::into_raw(Box::new(buffer)) as _
+            }
+
+            $(&runtime_export)
+            pub unsafe extern "C" fn $(symbols.ffi_buffer("address"))(ptr: *mut c_void) -> UPtr {
                 let buffer = &*(ptr as *mut FfiBuffer<u8>);
                 buffer.addr as _
             }
 
-            #[no_mangle]
-            pub unsafe extern "C" fn __ffi_buffer_size(ptr: *mut c_void) -> UPtr {
+            $(&runtime_export)
+            pub unsafe extern "C" fn $(symbols.ffi_buffer("size"))(ptr: *mut c_void) -> UPtr {
                 let buffer = &*(ptr as *mut FfiBuffer<u8>);
                 buffer.size as _
             }
@@ -132,8 +261,8 @@ impl RustGenerator {
                 capacity: UPtr,
             }
 
-            #[no_mangle]
-            pub unsafe extern "C" fn __ffi_string_into_parts(ptr: *mut c_void) -> _FfiStringParts {
+            $(&runtime_export)
+            pub unsafe extern "C" fn $(symbols.string_into_parts())(ptr: *mut c_void) -> _FfiStringParts {
                 let s = &*(ptr as *mut FfiString);
                 let obj = ManuallyDrop::new(s);
                 _FfiStringParts {
@@ -143,15 +272,15 @@ impl RustGenerator {
                 }
             }
 
-            #[no_mangle]
-            pub extern "C" fn drop_box_FfiBuffer(_: IPtr, boxed: IPtr) {
+            $(&runtime_export)
+            pub extern "C" fn $(symbols.destructor("FfiBuffer"))(_: IPtr, boxed: IPtr) {
                 panic_abort(move || {
                     unsafe { Box::<FfiBuffer<u8>>::from_raw(boxed as *mut _) };
                 });
             }
 
-            #[no_mangle]
-            pub extern "C" fn drop_box_Leak(_: IPtr, boxed: IPtr) {
+            $(&runtime_export)
+            pub extern "C" fn $(symbols.destructor("Leak"))(_: IPtr, boxed: IPtr) {
             }
 
             #[repr(C)]
@@ -288,6 +417,8 @@ impl RustGenerator {
                     let mut ctx = Context::from_waker(&waker);
                     match Pin::new(&mut self.0).poll_next(&mut ctx) {
                         Poll::Ready(Some(res)) => {
+                            // a ready stream isn't required to keep the waker, the
+                            // host is woken so it polls for the next item.
                             ffi_waker(post_cobject, port).wake();
                             Some(res)
                         }
@@ -300,27 +431,233 @@ impl RustGenerator {
                 }
             }
 
+            $(self.generate_conform_traits())
+            $(for func in rust.functions.iter() => $(self.generate_conformance(rust, None, func)))
+            $(for obj in rust.objects.iter() =>
+                $(for method in obj.methods.iter() => $(self.generate_conformance(rust, Some(&obj.ident), method))$['\r']))
             $(for func in iface.functions() => $(self.generate_function(&func)))
             $(for obj in iface.objects() => $(self.generate_object(&obj)))
             $(for iter in iface.iterators() => $(self.generate_iterator(&iter)))
             $(for fut in iface.futures() => $(self.generate_future(&fut)))
             $(for stream in iface.streams() => $(self.generate_stream(&stream)))
-            $(for ty in iface.listed_types() => $(self.generate_list_type_methods(ty.as_str())))
-            $(for e in iface.enums.iter() => $(self.generate_enum_helpers(e)))
+            $(for ty in iface.listed_types() => $(self.generate_list_type_methods(&symbols, ty.as_str())))
+            $(for e in iface.enums.iter() => $(self.generate_enum_helpers(&symbols, e)))
+            $(if self.napi => $(self.generate_napi(&iface)))
+        }
+        }
+    }
+
+    /// Checks the implementation of an item against the signature from the
+    /// interface, so mismatches are reported with the declaration instead of
+    /// inside the generated glue code.
+    ///
+    /// The check is a trait implemented for everything the glue can call.
+    /// Numbers can be taken and returned as any number type, as the glue
+    /// casts them, methods can take `&self` or `&mut self`, `&buffer<T>`
+    /// arguments can be taken as `&FfiBuffer<T>` or as a slice and the error
+    /// types of results are only bounded. `Iterator`, `Future` and `Stream`
+    /// returns are bounded by the trait the glue wraps them with.
+    fn generate_conformance(
+        &self,
+        iface: &Interface,
+        object: Option<&str>,
+        func: &Function,
+    ) -> rust::Tokens {
+        let mut params = vec![];
+        let mut args = vec![];
+        if let (Some(object), false) = (object, func.is_static) {
+            args.push(self.conform_param(
+                &mut params,
+                format!("conform::Receiver<{}>", self.ident(object)),
+            ));
         }
+        for (_, ty) in &func.args {
+            args.push(self.conform_arg(&iface.to_type(ty), &mut params));
+        }
+        let ret = func
+            .ret
+            .as_ref()
+            .map(|ty| self.conform_ret(&iface.to_type(ty), &mut params));
+        let mut sig = format!("Fn({})", args.join(", "));
+        if let Some(ret) = &ret {
+            sig.push_str(" -> ");
+            sig.push_str(ret);
+        }
+        // a borrowed return can only borrow from the arguments, all references
+        // share the lifetime the function is called with
+        let borrows = ret.map(|ret| ret.contains('&')).unwrap_or_default();
+        if borrows {
+            sig = sig.replace('&', "&'a ");
+        }
+        let generics = borrows
+            .then(|| "'a".to_string())
+            .into_iter()
+            .chain(std::iter::once("F".to_string()))
+            .chain(
+                params
+                    .iter()
+                    .map(|(param, bound)| format!("{}: {}", param, bound)),
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+        let marker = tuple(
+            &params
+                .iter()
+                .map(|(param, _)| param.clone())
+                .collect::<Vec<_>>(),
+        );
+        let path = match object {
+            Some(object) => format!("{}::{}", self.ident(object), self.ident(&func.ident)),
+            None => self.ident(&func.ident),
+        };
+        let item = match object {
+            Some(object) => format!("{}::{}", object, func.ident),
+            None => func.ident.clone(),
+        };
+        let location = match (&self.source, func.line) {
+            (Some(source), Some(line)) => format!("{}:{}: ", source, line),
+            (None, Some(line)) => format!("line {}: ", line),
+            _ => String::new(),
+        };
+        let message = format!("{}expected signature `{}` for `{}`", location, func, item)
+            .replace('{', "{{")
+            .replace('}', "}}");
+        // the impl that never applies keeps rustc from selecting the other
+        // one early, which would report its bounds instead of the message
+        quote! {
+            const _: () = {
+                #[diagnostic::on_unimplemented(message = $(quoted(message)))]
+                trait Conform<M> {}
+                impl<$generics> Conform<$marker> for F where F: $sig {}
+                impl<F: conform::Unmatched> Conform<conform::Never> for F {}
+                const fn check<M, F: Conform<M>>(_: &F) {}
+                check(&$path);
+            };
+        }
+    }
+
+    /// Adds a generic parameter with `bound` to a conformance check.
+    fn conform_param(&self, params: &mut Vec<(String, String)>, bound: String) -> String {
+        let param = format!("P{}", params.len());
+        params.push((param.clone(), bound));
+        param
+    }
+
+    /// Type the implementation receives for an argument.
+    fn conform_arg(&self, ty: &AbiType, params: &mut Vec<(String, String)>) -> String {
+        match ty {
+            AbiType::Option(inner) => format!("Option<{}>", self.conform_arg(inner, params)),
+            AbiType::Tuple(tys) => {
+                let tys: Vec<_> = tys.iter().map(|ty| self.conform_arg(ty, params)).collect();
+                tuple(&tys)
+            }
+            AbiType::Object(object) => format!("Box<{}>", self.ident(object)),
+            AbiType::List(object) => format!("&[{}]", self.ident(object)),
+            AbiType::Result(inner) => {
+                let ok = self.conform_arg(inner, params);
+                let err = self.conform_param(params, "From<String>".to_string());
+                format!("core::result::Result<{}, {}>", ok, err)
+            }
+            AbiType::RefBuffer(num) => {
+                let bound = format!("conform::RefBuffer<{}>", render(self.num_type(*num)));
+                self.conform_param(params, bound)
+            }
+            _ => self.conform_exact(ty, params),
+        }
+    }
+
+    /// Type the implementation has to return.
+    fn conform_ret(&self, ty: &AbiType, params: &mut Vec<(String, String)>) -> String {
+        match ty {
+            AbiType::Option(inner) => format!("Option<{}>", self.conform_ret(inner, params)),
+            AbiType::Tuple(tys) => {
+                let tys: Vec<_> = tys.iter().map(|ty| self.conform_ret(ty, params)).collect();
+                tuple(&tys)
+            }
+            AbiType::Object(object) => self.ident(object),
+            AbiType::List(object) => format!("Vec<{}>", self.ident(object)),
+            AbiType::Result(inner) => {
+                let ok = self.conform_ret(inner, params);
+                let err = self.conform_param(params, "ToString".to_string());
+                format!("core::result::Result<{}, {}>", ok, err)
+            }
+            AbiType::Iter(inner) => {
+                let bound = format!("IntoIterator<Item = {}>", render(self.ty(inner)));
+                self.conform_param(params, bound)
+            }
+            AbiType::Future(inner) => {
+                let output = match &**inner {
+                    AbiType::Result(ok) => {
+                        let err = self.conform_param(params, "ToString".to_string());
+                        format!("core::result::Result<{}, {}>", render(self.ty(ok)), err)
+                    }
+                    _ => render(self.ty(inner)),
+                };
+                let bound = format!("Future<Output = {}> + Send + 'static", output);
+                self.conform_param(params, bound)
+            }
+            AbiType::Stream(inner) => {
+                let bound = format!("Stream<Item = {}> + Send + 'static", render(self.ty(inner)));
+                self.conform_param(params, bound)
+            }
+            AbiType::RefIter(inner) => format!("&mut FfiIter<{}>", render(self.ty(inner))),
+            AbiType::RefFuture(inner) => format!("&mut FfiFuture<{}>", render(self.ty(inner))),
+            AbiType::RefStream(inner) => format!("&mut FfiStream<{}>", render(self.ty(inner))),
+            _ => self.conform_exact(ty, params),
         }
     }
 
-    fn generate_enum_helpers(&self, e: &Enum) -> rust::Tokens {
-        let destructure_function_name = format!("destructure_enum_{}", e.ident);
-        let drop_function_name = format!("drop_box_{}", e.ident);
+    /// Numbers are cast by the glue, everything else is passed as is.
+    fn conform_exact(&self, ty: &AbiType, params: &mut Vec<(String, String)>) -> String {
+        match ty {
+            AbiType::Num(num) if self.abi.splits_u64(*num) => render(self.num_type(*num)),
+            AbiType::Num(_) | AbiType::Isize | AbiType::Usize => {
+                self.conform_param(params, "conform::Num".to_string())
+            }
+            AbiType::RefEnum(ident) => self.ident(ident),
+            _ => render(self.ty(ty)),
+        }
+    }
+
+    /// Traits the conformance checks bound the implementation with.
+    fn generate_conform_traits(&self) -> rust::Tokens {
+        let nums = [
+            "u8", "u16", "u32", "u64", "usize", "i8", "i16", "i32", "i64", "isize", "f32", "f64",
+        ];
+        quote! {
+            mod conform {
+                use super::FfiBuffer;
+
+                pub trait Num {}
+                $(for num in nums join ($['\r']) => impl Num for $num {})
+
+                pub trait Receiver<T> {}
+                impl<T> Receiver<T> for &T {}
+                impl<T> Receiver<T> for &mut T {}
+
+                pub trait RefBuffer<T> {}
+                impl<T> RefBuffer<T> for &FfiBuffer<T> {}
+                impl<T> RefBuffer<T> for &mut FfiBuffer<T> {}
+                impl<T> RefBuffer<T> for &[T] {}
+                impl<T> RefBuffer<T> for &mut [T] {}
+
+                pub trait Unmatched {}
+                pub enum Never {}
+            }
+        }
+    }
+
+    fn generate_enum_helpers(&self, symbols: &Symbols, e: &Enum) -> rust::Tokens {
+        let destructure_function_name = symbols.destructure_enum(&e.ident);
+        let drop_function_name = symbols.destructor(&e.ident);
+        let ident = self.type_ident(&e.ident);
         let mut entry_index = -1;
         quote!(
             #[no_mangle]
-            pub unsafe fn $(&destructure_function_name)(ptr: *mut c_void) -> EnumWrapper {
-                let e = &*(ptr as *mut $(&e.ident)).clone();
+            pub unsafe extern "C" fn $(&destructure_function_name)(ptr: *mut c_void) -> EnumWrapper {
+                let e = &*(ptr as *mut $(&ident)).clone();
                 let (tag, inner) = match *e {
-                    $(for sub in &e.entries => $(&e.ident)::$(&sub.name)
+                    $(for sub in &e.entries => $(&ident)::$(self.ident(&sub.name))
                         $(if sub.inner.is_some() { (inner) }) =>
                             (
                                 $({ entry_index += 1; entry_index }),
@@ -337,37 +674,38 @@ impl RustGenerator {
             #[no_mangle]
             pub extern "C" fn $(&drop_function_name)(_: IPtr, boxed: IPtr) {
                 panic_abort(move || {
-                    unsafe { Box::<$(&e.ident)>::from_raw(boxed as *mut _) };
+                    unsafe { Box::<$(&ident)>::from_raw(boxed as *mut _) };
                 });
             }
 
         )
     }
 
-    fn generate_list_type_methods(&self, ty: &str) -> rust::Tokens {
+    fn generate_list_type_methods(&self, symbols: &Symbols, ty: &str) -> rust::Tokens {
         let name_s = format!("FfiList{}", ty);
         let name = name_s.as_str();
+        let rust_ty = self.type_ident(ty);
         quote!(
             #[no_mangle]
-            pub extern "C" fn $(format!("__{}Create", name))() -> usize {
+            pub extern "C" fn $(symbols.list(ty, "Create"))() -> usize {
                 panic_abort(move || unsafe {
-                    let list = Box::new(Vec::<$ty>::new());
+                    let list = Box::new(Vec::<$(&rust_ty)>::new());
                     Box::into_raw(list) as _
                 })
             }
 
             #[no_mangle]
-            pub extern "C" fn $(format!("drop_box_{}", name))(_: IPtr, boxed: IPtr) {
+            pub extern "C" fn $(symbols.destructor(name))(_: IPtr, boxed: IPtr) {
                 panic_abort(move || unsafe {
-                    // Box::<Vec<$ty>>::from_raw(boxed as _);
+                    // Box::<Vec<$(&rust_ty)>>::from_raw(boxed as _);
                     // FIXME: we will just leak these for now
                 })
             }
 
             #[no_mangle]
-            pub extern "C" fn $(format!("__{}Len", name))(boxed: usize) -> u32 {
+            pub extern "C" fn $(symbols.list(ty, "Len"))(boxed: usize) -> u32 {
                 panic_abort(move || unsafe {
-                    let list = Box::<Vec<$ty>>::from_raw(boxed as _);
+                    let list = Box::<Vec<$(&rust_ty)>>::from_raw(boxed as _);
                     let result = list.len() as u32;
                     Box::into_raw(list);
                     result as _
@@ -375,9 +713,9 @@ impl RustGenerator {
             }
 
             #[no_mangle]
-            pub extern "C" fn $(format!("__{}ElementAt", name))(boxed: usize, index: u32) -> usize {
+            pub extern "C" fn $(symbols.list(ty, "ElementAt"))(boxed: usize, index: u32) -> usize {
                 panic_abort(move || unsafe {
-                    let list = Box::<Vec<$ty>>::from_raw(boxed as _);
+                    let list = Box::<Vec<$(&rust_ty)>>::from_raw(boxed as _);
                     let result = list.get(index as usize).unwrap() as *const _;
                     Box::into_raw(list);
                     result as _
@@ -385,28 +723,28 @@ impl RustGenerator {
             }
 
             #[no_mangle]
-            pub extern "C" fn $(format!("__{}Remove", name))(boxed: usize, index: u32) -> usize {
+            pub extern "C" fn $(symbols.list(ty, "Remove"))(boxed: usize, index: u32) -> usize {
                 panic_abort(move || unsafe {
-                    let mut list = &mut *(boxed as *mut Vec<$ty>);
+                    let mut list = &mut *(boxed as *mut Vec<$(&rust_ty)>);
                     let el = Box::new(list.remove(index as _));
                     Box::into_raw(el) as _
                 })
             }
 
             #[no_mangle]
-            pub extern "C" fn $(format!("__{}Add", name))(boxed: usize, element: usize) {
+            pub extern "C" fn $(symbols.list(ty, "Add"))(boxed: usize, element: usize) {
                 panic_abort(move || unsafe {
-                    let mut list = &mut *(boxed as *mut Vec<$ty>);
-                    let el = Box::<$ty>::from_raw(element as _);
+                    let mut list = &mut *(boxed as *mut Vec<$(&rust_ty)>);
+                    let el = Box::<$(&rust_ty)>::from_raw(element as _);
                     list.push(*el);
                 })
             }
 
             #[no_mangle]
-            pub extern "C" fn $(format!("__{}Insert", name))(boxed: usize, index: u32, element: usize) {
+            pub extern "C" fn $(symbols.list(ty, "Insert"))(boxed: usize, index: u32, element: usize) {
                 panic_abort(move || unsafe {
-                    let mut list = &mut *(boxed as *mut Vec<$ty>);
-                    let el = Box::<$ty>::from_raw(element as _);
+                    let mut list = &mut *(boxed as *mut Vec<$(&rust_ty)>);
+                    let el = Box::<$(&rust_ty)>::from_raw(element as _);
                     list.insert(index as _, *el);
                 })
             }
@@ -415,21 +753,33 @@ impl RustGenerator {
 
     fn generate_function(&self, func: &AbiFunction) -> rust::Tokens {
         let ffi = self.abi.export(func);
+        let ret_area: genco::Tokens<genco::lang::Rust> = match &ffi.ffi_ret {
+            Return::Struct(_, name) if self.abi.has_ret_area() => quote!(__ret: *mut $name,),
+            _ => quote!(),
+        };
         let args: genco::Tokens<genco::lang::Rust> =
-            quote!($(for var in &ffi.ffi_args => $(self.var(var)): $(self.ty(&var.ty)),));
+            quote!($ret_area $(for var in &ffi.ffi_args => $(self.var(var)): $(self.ty(&var.ty)),));
         let ret: genco::Tokens<genco::lang::Rust> = match &ffi.ffi_ret {
             Return::Void => quote!(),
             Return::Num(var) => quote!(-> $(self.ty(&var.ty))),
+            Return::Struct(_, _) if self.abi.has_ret_area() => quote!(),
             Return::Struct(_, name) => quote!(-> $name),
         };
         let return_ = match &ffi.ffi_ret {
             Return::Void => quote!(),
             Return::Num(var) => self.var(var),
-            Return::Struct(vars, name) => quote! {
-                $name {
-                    $(for (i, var) in vars.iter().enumerate() => $(format!("ret{}", i)): $(self.var(var)),)
+            Return::Struct(vars, name) => {
+                let value = quote! {
+                    $name {
+                        $(for (i, var) in vars.iter().enumerate() => $(format!("ret{}", i)): $(self.var(var)),)
+                    }
+                };
+                if self.abi.has_ret_area() {
+                    quote!(unsafe { __ret.write($value) };)
+                } else {
+                    value
                 }
-            },
+            }
         };
         let return_struct = if let Return::Struct(_, _) = &ffi.ffi_ret {
             self.generate_return_struct(func)
@@ -449,11 +799,11 @@ impl RustGenerator {
     }
 
     fn generate_object(&self, obj: &AbiObject) -> rust::Tokens {
-        let destructor_name = format!("drop_box_{}", &obj.name);
-        let destructor_type: genco::Tokens<genco::lang::Rust> = quote!($(&obj.name));
+        let destructor_type: genco::Tokens<genco::lang::Rust> =
+            quote!($(self.type_ident(&obj.name)));
         quote! {
             $(for method in &obj.methods => $(self.generate_function(method)))
-            $(self.generate_destructor(&destructor_name, destructor_type))
+            $(self.generate_destructor(&obj.destructor, destructor_type))
         }
     }
 
@@ -471,6 +821,9 @@ impl RustGenerator {
     }
 
     fn generate_iterator(&self, iter: &AbiIter) -> rust::Tokens {
+        if iter.borrowed {
+            return self.generate_function(&iter.next());
+        }
         let destructor_name = format!("{}_iter_drop", &iter.symbol);
         let destructor_type: genco::Tokens<genco::lang::Rust> =
             quote!(FfiIter<$(self.ty(&iter.ty))>);
@@ -514,6 +867,343 @@ impl RustGenerator {
         }
     }
 
+    /// Generates a Node-API module wrapping every exported function, so the
+    /// cdylib can be loaded with `process.dlopen`. Numbers are passed as js
+    /// numbers, 64bit integers and pointers on 64bit platforms as `BigInt`s.
+    fn generate_napi(&self, iface: &Interface) -> rust::Tokens {
+        let symbols = iface.symbols();
+        let mut functions = vec![
+            (
+                symbols.allocate(),
+                vec![NumType::UPtr, NumType::UPtr],
+                vec![NumType::IPtr],
+            ),
+            (
+                symbols.deallocate(),
+                vec![NumType::IPtr, NumType::UPtr, NumType::UPtr],
+                vec![],
+            ),
+            (
+                symbols.ffi_buffer("new"),
+                vec![NumType::UPtr, NumType::UPtr],
+                vec![NumType::IPtr],
+            ),
+            (
+                symbols.ffi_buffer("address"),
+                vec![NumType::IPtr],
+                vec![NumType::UPtr],
+            ),
+            (
+                symbols.ffi_buffer("size"),
+                vec![NumType::IPtr],
+                vec![NumType::UPtr],
+            ),
+            (
+                symbols.destructor("Leak"),
+                vec![NumType::IPtr, NumType::IPtr],
+                vec![],
+            ),
+        ];
+        for drop in iface.destructors() {
+            functions.push((drop, vec![NumType::IPtr, NumType::IPtr], vec![]));
+        }
+        for import in iface.imports(&self.abi) {
+            let args = import.ffi_args.iter().map(|var| var.ty.num()).collect();
+            let rets = match &import.ffi_ret {
+                Return::Void => vec![],
+                Return::Num(var) => vec![var.ty.num()],
+                Return::Struct(vars, _) => vars.iter().map(|var| var.ty.num()).collect(),
+            };
+            functions.push((import.symbol, args, rets));
+        }
+        quote! {
+            #[cfg(not(target_family = "wasm"))]
+            mod napi {
+                use super::*;
+                use core::ptr::null_mut;
+                use std::sync::atomic::{AtomicPtr, Ordering};
+
+                type NapiEnv = *mut c_void;
+                type NapiValue = *mut c_void;
+                type NapiCallbackInfo = *mut c_void;
+                type NapiThreadsafeFunction = *mut c_void;
+                type NapiCallback = unsafe extern "C" fn(NapiEnv, NapiCallbackInfo) -> NapiValue;
+
+                // resolved from the node process loading the addon
+                extern "C" {
+                    fn napi_get_cb_info(
+                        env: NapiEnv,
+                        info: NapiCallbackInfo,
+                        argc: *mut usize,
+                        argv: *mut NapiValue,
+                        this: *mut NapiValue,
+                        data: *mut *mut c_void,
+                    ) -> i32;
+                    fn napi_create_function(
+                        env: NapiEnv,
+                        name: *const u8,
+                        len: usize,
+                        cb: NapiCallback,
+                        data: *mut c_void,
+                        result: *mut NapiValue,
+                    ) -> i32;
+                    fn napi_create_string_utf8(env: NapiEnv, s: *const u8, len: usize, result: *mut NapiValue) -> i32;
+                    fn napi_set_property(env: NapiEnv, object: NapiValue, key: NapiValue, value: NapiValue) -> i32;
+                    fn napi_set_element(env: NapiEnv, object: NapiValue, index: u32, value: NapiValue) -> i32;
+                    fn napi_get_undefined(env: NapiEnv, result: *mut NapiValue) -> i32;
+                    fn napi_get_value_int64(env: NapiEnv, value: NapiValue, result: *mut i64) -> i32;
+                    fn napi_get_value_double(env: NapiEnv, value: NapiValue, result: *mut f64) -> i32;
+                    fn napi_get_value_bigint_int64(env: NapiEnv, value: NapiValue, result: *mut i64, lossless: *mut bool) -> i32;
+                    fn napi_get_value_bigint_uint64(env: NapiEnv, value: NapiValue, result: *mut u64, lossless: *mut bool) -> i32;
+                    fn napi_create_double(env: NapiEnv, value: f64, result: *mut NapiValue) -> i32;
+                    fn napi_create_bigint_int64(env: NapiEnv, value: i64, result: *mut NapiValue) -> i32;
+                    fn napi_create_bigint_uint64(env: NapiEnv, value: u64, result: *mut NapiValue) -> i32;
+                    fn napi_create_array_with_length(env: NapiEnv, len: usize, result: *mut NapiValue) -> i32;
+                    fn napi_create_arraybuffer(env: NapiEnv, len: usize, data: *mut *mut c_void, result: *mut NapiValue) -> i32;
+                    fn napi_create_external_arraybuffer(
+                        env: NapiEnv,
+                        data: *mut c_void,
+                        len: usize,
+                        finalize: Option<unsafe extern "C" fn(NapiEnv, *mut c_void, *mut c_void)>,
+                        hint: *mut c_void,
+                        result: *mut NapiValue,
+                    ) -> i32;
+                    fn napi_call_function(
+                        env: NapiEnv,
+                        recv: NapiValue,
+                        func: NapiValue,
+                        argc: usize,
+                        argv: *const NapiValue,
+                        result: *mut NapiValue,
+                    ) -> i32;
+                    fn napi_create_threadsafe_function(
+                        env: NapiEnv,
+                        func: NapiValue,
+                        resource: NapiValue,
+                        resource_name: NapiValue,
+                        max_queue_size: usize,
+                        initial_thread_count: usize,
+                        finalize_data: *mut c_void,
+                        finalize: *mut c_void,
+                        context: *mut c_void,
+                        call_js: unsafe extern "C" fn(NapiEnv, NapiValue, *mut c_void, *mut c_void),
+                        result: *mut NapiThreadsafeFunction,
+                    ) -> i32;
+                    fn napi_call_threadsafe_function(func: NapiThreadsafeFunction, data: *mut c_void, blocking: i32) -> i32;
+                    fn napi_release_threadsafe_function(func: NapiThreadsafeFunction, mode: i32) -> i32;
+                    fn napi_ref_threadsafe_function(env: NapiEnv, func: NapiThreadsafeFunction) -> i32;
+                    fn napi_unref_threadsafe_function(env: NapiEnv, func: NapiThreadsafeFunction) -> i32;
+                }
+
+                unsafe fn args<const N: usize>(env: NapiEnv, info: NapiCallbackInfo) -> [NapiValue; N] {
+                    let mut argv = [null_mut(); N];
+                    let mut argc = N;
+                    napi_get_cb_info(env, info, &mut argc, argv.as_mut_ptr(), null_mut(), null_mut());
+                    argv
+                }
+
+                unsafe fn arg_int(env: NapiEnv, value: NapiValue) -> i64 {
+                    let mut result = 0;
+                    napi_get_value_int64(env, value, &mut result);
+                    result
+                }
+
+                unsafe fn arg_float(env: NapiEnv, value: NapiValue) -> f64 {
+                    let mut result = 0.0;
+                    napi_get_value_double(env, value, &mut result);
+                    result
+                }
+
+                unsafe fn arg_bigint_i64(env: NapiEnv, value: NapiValue) -> i64 {
+                    let mut result = 0;
+                    let mut lossless = false;
+                    napi_get_value_bigint_int64(env, value, &mut result, &mut lossless);
+                    result
+                }
+
+                unsafe fn arg_bigint_u64(env: NapiEnv, value: NapiValue) -> u64 {
+                    let mut result = 0;
+                    let mut lossless = false;
+                    napi_get_value_bigint_uint64(env, value, &mut result, &mut lossless);
+                    result
+                }
+
+                unsafe fn ret_undefined(env: NapiEnv) -> NapiValue {
+                    let mut result = null_mut();
+                    napi_get_undefined(env, &mut result);
+                    result
+                }
+
+                unsafe fn ret_number(env: NapiEnv, value: f64) -> NapiValue {
+                    let mut result = null_mut();
+                    napi_create_double(env, value, &mut result);
+                    result
+                }
+
+                unsafe fn ret_bigint_i64(env: NapiEnv, value: i64) -> NapiValue {
+                    let mut result = null_mut();
+                    napi_create_bigint_int64(env, value, &mut result);
+                    result
+                }
+
+                unsafe fn ret_bigint_u64(env: NapiEnv, value: u64) -> NapiValue {
+                    let mut result = null_mut();
+                    napi_create_bigint_uint64(env, value, &mut result);
+                    result
+                }
+
+                unsafe fn ret_array(env: NapiEnv, values: &[NapiValue]) -> NapiValue {
+                    let mut result = null_mut();
+                    napi_create_array_with_length(env, values.len(), &mut result);
+                    for (i, value) in values.iter().enumerate() {
+                        napi_set_element(env, result, i as u32, *value);
+                    }
+                    result
+                }
+
+                unsafe fn set(env: NapiEnv, exports: NapiValue, name: &str, value: NapiValue) {
+                    let mut key = null_mut();
+                    napi_create_string_utf8(env, name.as_ptr(), name.len(), &mut key);
+                    napi_set_property(env, exports, key, value);
+                }
+
+                unsafe fn register(env: NapiEnv, exports: NapiValue, name: &str, cb: NapiCallback) {
+                    let mut func = null_mut();
+                    napi_create_function(env, name.as_ptr(), name.len(), cb, null_mut(), &mut func);
+                    set(env, exports, name, func);
+                }
+
+                /// Threadsafe function calling the js notifier with the port of
+                /// a woken future or stream.
+                static NOTIFIER: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
+
+                extern "C" fn post_cobject(port: i64, _obj: *const c_void) {
+                    let notifier = NOTIFIER.load(Ordering::SeqCst);
+                    if !notifier.is_null() {
+                        unsafe { napi_call_threadsafe_function(notifier, port as isize as *mut c_void, 0) };
+                    }
+                }
+
+                unsafe extern "C" fn notify(env: NapiEnv, callback: NapiValue, _context: *mut c_void, data: *mut c_void) {
+                    if env.is_null() {
+                        return;
+                    }
+                    let port = ret_number(env, data as isize as f64);
+                    napi_call_function(env, ret_undefined(env), callback, 1, &port, null_mut());
+                }
+
+                unsafe extern "C" fn set_notifier(env: NapiEnv, info: NapiCallbackInfo) -> NapiValue {
+                    let [callback] = args::<1>(env, info);
+                    let name = "ffi-gen notifier";
+                    let mut resource_name = null_mut();
+                    napi_create_string_utf8(env, name.as_ptr(), name.len(), &mut resource_name);
+                    let mut notifier = null_mut();
+                    napi_create_threadsafe_function(
+                        env,
+                        callback,
+                        null_mut(),
+                        resource_name,
+                        0,
+                        1,
+                        null_mut(),
+                        null_mut(),
+                        null_mut(),
+                        notify,
+                        &mut notifier,
+                    );
+                    // only keeps the process alive while js waits on a notification
+                    napi_unref_threadsafe_function(env, notifier);
+                    let previous = NOTIFIER.swap(notifier, Ordering::SeqCst);
+                    if !previous.is_null() {
+                        napi_release_threadsafe_function(previous, 0);
+                    }
+                    ret_undefined(env)
+                }
+
+                unsafe extern "C" fn ref_notifier(env: NapiEnv, _info: NapiCallbackInfo) -> NapiValue {
+                    napi_ref_threadsafe_function(env, NOTIFIER.load(Ordering::SeqCst));
+                    ret_undefined(env)
+                }
+
+                unsafe extern "C" fn unref_notifier(env: NapiEnv, _info: NapiCallbackInfo) -> NapiValue {
+                    napi_unref_threadsafe_function(env, NOTIFIER.load(Ordering::SeqCst));
+                    ret_undefined(env)
+                }
+
+                /// Returns an `ArrayBuffer` viewing `len` bytes of memory at `ptr`.
+                unsafe extern "C" fn memory(env: NapiEnv, info: NapiCallbackInfo) -> NapiValue {
+                    let [ptr, len] = args::<2>(env, info);
+                    let ptr = arg_int(env, ptr) as isize as *mut c_void;
+                    let len = arg_int(env, len) as usize;
+                    let mut result = null_mut();
+                    if len == 0 {
+                        napi_create_arraybuffer(env, 0, null_mut(), &mut result);
+                    } else {
+                        napi_create_external_arraybuffer(env, ptr, len, None, null_mut(), &mut result);
+                    }
+                    result
+                }
+
+                $(for (symbol, args, rets) in &functions => $(self.generate_napi_function(symbol, args, rets)))
+
+                #[no_mangle]
+                pub unsafe extern "C" fn napi_register_module_v1(env: NapiEnv, exports: NapiValue) -> NapiValue {
+                    register(env, exports, "__napi_memory", memory);
+                    register(env, exports, "__napi_set_notifier", set_notifier);
+                    register(env, exports, "__napi_ref", ref_notifier);
+                    register(env, exports, "__napi_unref", unref_notifier);
+                    set(env, exports, "__napi_post_cobject", ret_number(env, post_cobject as usize as f64));
+                    $(for (symbol, _, _) in &functions =>
+                        register(env, exports, $(quoted(symbol)), $(format!("{}_napi", symbol)));)
+                    exports
+                }
+            }
+        }
+    }
+
+    fn generate_napi_function(
+        &self,
+        symbol: &str,
+        args: &[NumType],
+        rets: &[NumType],
+    ) -> rust::Tokens {
+        let ret = match rets.len() {
+            0 => quote!(ret_undefined(env)),
+            1 => self.napi_ret(rets[0], quote!(ret)),
+            _ => quote! {
+                ret_array(env, &[
+                    $(for (i, ty) in rets.iter().enumerate() =>
+                        $(self.napi_ret(*ty, quote!(ret.$(format!("ret{}", i))))),)
+                ])
+            },
+        };
+        quote! {
+            unsafe extern "C" fn $(format!("{}_napi", symbol))(env: NapiEnv, info: NapiCallbackInfo) -> NapiValue {
+                let argv = args::<$(args.len())>(env, info);
+                let ret = $symbol($(for (i, ty) in args.iter().enumerate() => $(self.napi_arg(*ty, quote!(argv[$i]))) as _,));
+                $ret
+            }
+        }
+    }
+
+    fn napi_arg(&self, ty: NumType, value: rust::Tokens) -> rust::Tokens {
+        match self.abi.fixed_size(ty) {
+            NumType::I64 => quote!(arg_bigint_i64(env, $value)),
+            NumType::U64 => quote!(arg_bigint_u64(env, $value)),
+            NumType::F32 | NumType::F64 => quote!(arg_float(env, $value)),
+            _ => quote!(arg_int(env, $value)),
+        }
+    }
+
+    fn napi_ret(&self, ty: NumType, value: rust::Tokens) -> rust::Tokens {
+        match self.abi.fixed_size(ty) {
+            NumType::I64 => quote!(ret_bigint_i64(env, $value as i64)),
+            NumType::U64 => quote!(ret_bigint_u64(env, $value as u64)),
+            NumType::F32 | NumType::F64 => quote!(ret_number(env, $value as f64)),
+            _ => quote!(ret_number(env, $value as i64 as f64)),
+        }
+    }
+
     fn instr(&self, instr: &Instr) -> rust::Tokens {
         match instr {
             Instr::LiftNum(in_, out) | Instr::LiftIsize(in_, out) | Instr::LiftUsize(in_, out) => {
@@ -527,8 +1217,8 @@ impl RustGenerator {
             }
             Instr::LowerNumAsU32Tuple(r#in, low, high, _num_type) => {
                 quote! {
-                    $(self.var(low)) = $(self.var(r#in)) as u32;
-                    $(self.var(high)) = ($(self.var(r#in)) >> 32) as u32;
+                    $(self.var(low)) = $(self.var(r#in)) as u32 as _;
+                    $(self.var(high)) = ($(self.var(r#in)) >> 32) as u32 as _;
                 }
             }
             Instr::LowerNum(in_, out)
@@ -568,6 +1258,33 @@ impl RustGenerator {
                 let $(self.var(out)): &[$(self.num_type(*ty))] =
                     unsafe { core::slice::from_raw_parts($(self.var(ptr)) as _, $(self.var(len)) as _) };
             },
+            Instr::LiftStrSlice(ptr, len, out) => quote! {
+                let $(self.var(out))_0: &[u32] =
+                    unsafe { core::slice::from_raw_parts($(self.var(ptr)) as _, $(self.var(len)) as _) };
+                let mut $(self.var(out))_1 = ($(self.var(ptr)) as usize + $(self.var(out))_0.len() * 4) as *const u8;
+                let $(self.var(out))_2: Vec<&str> = $(self.var(out))_0
+                    .iter()
+                    .map(|len| unsafe {
+                        let bytes = core::slice::from_raw_parts($(self.var(out))_1, *len as _);
+                        $(self.var(out))_1 = $(self.var(out))_1.add(bytes.len());
+                        std::str::from_utf8_unchecked(bytes)
+                    })
+                    .collect();
+                let $(self.var(out)): &[&str] = &$(self.var(out))_2;
+            },
+            Instr::LiftObjectSlice(ptr, len, out, object) => quote! {
+                let $(self.var(out))_0: &[IPtr] =
+                    unsafe { core::slice::from_raw_parts($(self.var(ptr)) as _, $(self.var(len)) as _) };
+                let $(self.var(out))_1: Vec<&$(self.type_ident(object))> = $(self.var(out))_0
+                    .iter()
+                    .map(|ptr| unsafe { &*(*ptr as *const $(self.type_ident(object))) })
+                    .collect();
+                let $(self.var(out)): &[&$(self.type_ident(object))] = &$(self.var(out))_1;
+            },
+            Instr::LiftMutSlice(ptr, len, out, ty) => quote! {
+                let $(self.var(out)): &mut [$(self.num_type(*ty))] =
+                    unsafe { core::slice::from_raw_parts_mut($(self.var(ptr)) as _, $(self.var(len)) as _) };
+            },
             Instr::LowerSlice(in_, ptr, len, _ty) => quote! {
                 $(self.var(ptr)) = $(self.var(in_)).as_ptr() as _;
                 $(self.var(len)) = $(self.var(in_)).len() as _;
@@ -582,13 +1299,19 @@ impl RustGenerator {
                 };
             },
             Instr::LiftRefObject(in_, out, object) => quote! {
-                let $(self.var(out)) = unsafe { &mut *($(self.var(in_)) as *mut $object) };
+                let $(self.var(out)) = unsafe { &mut *($(self.var(in_)) as *mut $(self.type_ident(object))) };
             },
             Instr::LowerRefObject(in_, out) => quote! {
                 $(self.var(out)) = $(self.var(in_)) as *const _ as _;
             },
+            Instr::LiftBuffer(in_, out, ty) => quote! {
+                let $(self.var(out)) = *unsafe { Box::from_raw($(self.var(in_)) as *mut FfiBuffer<$(self.num_type(*ty))>) };
+            },
+            Instr::LiftRefBuffer(in_, out, ty) => quote! {
+                let $(self.var(out)) = unsafe { &mut *($(self.var(in_)) as *mut FfiBuffer<$(self.num_type(*ty))>) };
+            },
             Instr::LiftObject(in_, out, object) => quote! {
-                let $(self.var(out)) = unsafe { Box::from_raw($(self.var(in_)) as *mut $object) };
+                let $(self.var(out)) = unsafe { Box::from_raw($(self.var(in_)) as *mut $(self.type_ident(object))) };
             },
             Instr::LowerObject(in_, out) => quote! {
                 let $(self.var(in_))_0 = assert_send_static($(self.var(in_)));
@@ -672,6 +1395,15 @@ impl RustGenerator {
                     $(self.var(var)) = 0;
                 }
             },
+            Instr::LiftResult(var, out, ok, ok_instr, err, err_instr) => quote! {
+                let $(self.var(out)) = if $(self.var(var)) > 0 {
+                    $(for instr in ok_instr => $(self.instr(instr)))
+                    Ok($(self.var(ok)))
+                } else {
+                    $(for instr in err_instr => $(self.instr(instr)))
+                    Err($(self.var(err)).into())
+                };
+            },
             Instr::LowerResult(in_, var, ok, ok_instr, err, err_instr) => quote! {
                 match $(self.var(in_)) {
                     Ok($(self.var(ok))) => {
@@ -694,16 +1426,18 @@ impl RustGenerator {
             Instr::CallAbi(ty, self_, name, ret, args) => {
                 let invoke: genco::Tokens<genco::lang::Rust> = match ty {
                     FunctionType::Constructor(object) => {
-                        quote!($object::$name)
+                        quote!($(self.type_ident(object))::$(self.fn_ident(Some(object), name)))
+                    }
+                    FunctionType::Method(object) => {
+                        quote!($(self.var(self_.as_ref().unwrap())).$(self.fn_ident(Some(object), name)))
                     }
-                    FunctionType::Method(_)
-                    | FunctionType::NextIter(_, _)
+                    FunctionType::NextIter(_, _)
                     | FunctionType::PollFuture(_, _)
                     | FunctionType::PollStream(_, _) => {
-                        quote!($(self.var(self_.as_ref().unwrap())).$name)
+                        quote!($(self.var(self_.as_ref().unwrap())).$(self.ident(name)))
                     }
                     FunctionType::Function => {
-                        quote!($name)
+                        quote!($(self.fn_ident(None, name)))
                     }
                 };
                 let args: genco::Tokens<genco::lang::Rust> =
@@ -718,7 +1452,7 @@ impl RustGenerator {
                 $(for var in vars => #[allow(unused_assignments)] let mut $(self.var(var)) = Default::default();)
             },
             Instr::AssertType(var, ty) => {
-                quote!(let $(self.var(var))_type_test: &$ty = &$(self.var(var));)
+                quote!(let $(self.var(var))_type_test: &$(self.ty(ty)) = &$(self.var(var));)
             }
         }
     }
@@ -727,6 +1461,27 @@ impl RustGenerator {
         quote!($(format!("tmp{}", var.binding)))
     }
 
+    fn ident(&self, s: &str) -> String {
+        sanitize_identifier(s)
+    }
+
+    /// Rust name of the object or enum named `s` in the bindings.
+    fn type_ident(&self, s: &str) -> String {
+        sanitize_identifier(self.names.types.get(s).map(String::as_str).unwrap_or(s))
+    }
+
+    /// Rust name of the function or method named `s` in the bindings.
+    fn fn_ident(&self, object: Option<&str>, s: &str) -> String {
+        let key = (object.map(str::to_string), s.to_string());
+        sanitize_identifier(
+            self.names
+                .functions
+                .get(&key)
+                .map(String::as_str)
+                .unwrap_or(s),
+        )
+    }
+
     fn ty(&self, ty: &AbiType) -> rust::Tokens {
         match ty {
             AbiType::Num(num) => self.ffi_num_type(*num),
@@ -736,11 +1491,14 @@ impl RustGenerator {
             AbiType::RefStr => quote!(&str),
             AbiType::String => quote!(String),
             AbiType::RefSlice(ty) => quote!(&[$(self.num_type(*ty))]),
+            AbiType::RefSliceStr => quote!(&[&str]),
+            AbiType::RefSliceObject(ident) => quote!(&[&$(self.type_ident(ident))]),
+            AbiType::RefMutSlice(ty) => quote!(&mut [$(self.num_type(*ty))]),
             AbiType::Vec(ty) => quote!(Vec<$(self.num_type(*ty))>),
             AbiType::Option(ty) => quote!(Option<$(self.ty(ty))>),
             AbiType::Result(ty) => quote!(Result<$(self.ty(ty))>),
-            AbiType::Object(ident) => quote!($ident),
-            AbiType::RefObject(ident) => quote!(&$ident),
+            AbiType::Object(ident) => quote!($(self.type_ident(ident))),
+            AbiType::RefObject(ident) => quote!(&$(self.type_ident(ident))),
             AbiType::Tuple(ty) => quote!(($(for ty in ty => $(self.ty(ty)),))),
             AbiType::RefIter(ty) => quote!(&Vec<$(self.ty(ty))>),
             AbiType::Iter(ty) => quote!(Vec<$(self.ty(ty))>),
@@ -749,7 +1507,8 @@ impl RustGenerator {
             AbiType::RefStream(ty) => quote!(&impl Stream<Item = $(self.ty(ty))>),
             AbiType::Stream(ty) => quote!(impl Stream<Item = $(self.ty(ty))>),
             AbiType::Buffer(ty) => quote!(FfiBuffer<$(self.num_type(*ty))>),
-            AbiType::List(ty) => quote!($(format!("Vec<{}>", ty))),
+            AbiType::RefBuffer(ty) => quote!(&FfiBuffer<$(self.num_type(*ty))>),
+            AbiType::List(ty) => quote!($(format!("Vec<{}>", self.type_ident(ty)))),
             AbiType::RefEnum(ty) => quote!($(format!("{}_Wrapper", ty))),
         }
     }
@@ -772,7 +1531,7 @@ impl RustGenerator {
     }
 
     fn ffi_num_type(&self, ty: NumType) -> rust::Tokens {
-        let is_wasm = matches!(self.abi, Abi::Wasm32 | Abi::Wasm64);
+        let is_wasm = self.abi.is_wasm();
         match ty {
             NumType::U8 if !is_wasm => quote!(u8),
             NumType::U16 if !is_wasm => quote!(u16),
@@ -790,6 +1549,33 @@ impl RustGenerator {
     }
 }
 
+fn render(tokens: rust::Tokens) -> String {
+    tokens.to_string().unwrap()
+}
+
+fn tuple(tys: &[String]) -> String {
+    let comma = if tys.len() == 1 { "," } else { "" };
+    format!("({}{})", tys.join(", "), comma)
+}
+
+/// Escapes keywords as raw identifiers. The exported symbols use the unescaped names.
+fn sanitize_identifier(id: &str) -> String {
+    if RESERVED_IDENTIFIERS.contains(&id) {
+        format!("r#{}", id)
+    } else {
+        id.to_string()
+    }
+}
+
+// https://doc.rust-lang.org/reference/keywords.html
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 52] = [
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
 #[cfg(feature = "test_runner")]
 #[doc(hidden)]
 pub mod test_runner {
@@ -821,4 +1607,70 @@ pub mod test_runner {
         test.pass(tmp.as_ref());
         Ok(())
     }
+
+    /// Compiles the api generated for `abi` to a cdylib for the rust `target`,
+    /// e.g. the 32bit native abi for `wasm32-unknown-unknown`.
+    pub fn compile_pass_abi(iface: &str, abi: Abi, target: &str, api: rust::Tokens) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let gen_tokens = RustGenerator::new(abi).generate(iface);
+        let tokens: genco::Tokens<genco::lang::Rust> = quote! {
+            $gen_tokens
+            $api
+        };
+        let res = tokens.to_file_string()?;
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("lib.rs");
+        let mut file = std::fs::File::create(&path)?;
+        writeln!(file, "#![feature(vec_into_raw_parts)]")?;
+        writeln!(file, "#![allow(warnings)]")?;
+        file.write_all(res.as_bytes())?;
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let output = std::process::Command::new(rustc)
+            .arg("--edition=2021")
+            .arg("--crate-name=compile_pass")
+            .arg("--crate-type=cdylib")
+            .arg("--cfg=feature=\"test_runner\"")
+            .arg("--target")
+            .arg(target)
+            .arg("--out-dir")
+            .arg(tmp.path())
+            .arg(&path)
+            .output()?;
+        if !output.status.success() {
+            anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr));
+        }
+        Ok(())
+    }
+
+    /// Compiles the api generated for `api.rsh` and returns the errors.
+    pub fn compile_fail(iface: &str, api: rust::Tokens) -> Result<String> {
+        let iface = Interface::parse(iface)?;
+        let gen = RustGenerator::new(Abi::native()).source(Some("api.rsh".to_string()));
+        let gen_tokens = gen.generate(iface);
+        let tokens: genco::Tokens<genco::lang::Rust> = quote! {
+            $gen_tokens
+            $api
+            fn main() {}
+        };
+        let res = tokens.to_file_string()?;
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("main.rs");
+        let mut file = std::fs::File::create(&path)?;
+        writeln!(file, "#![feature(vec_into_raw_parts)]")?;
+        writeln!(file, "#![allow(warnings)]")?;
+        file.write_all(res.as_bytes())?;
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let output = std::process::Command::new(rustc)
+            .arg("--edition=2021")
+            .arg("--emit=metadata")
+            .arg("--cfg=feature=\"test_runner\"")
+            .arg("--out-dir")
+            .arg(tmp.path())
+            .arg(&path)
+            .output()?;
+        if output.status.success() {
+            anyhow::bail!("the api compiled without errors");
+        }
+        Ok(String::from_utf8(output.stderr)?)
+    }
 }
@@ -0,0 +1,1124 @@
+use crate::c::CGenerator;
+use crate::dart::ffi_buffer_name_for;
+use crate::import::{Import, Instr};
+use crate::parser::{Enum, Type};
+use crate::{
+    Abi, AbiFunction, AbiObject, AbiType, FunctionType, Interface, NumType, Return, Symbols, Var,
+    NUM_TYPES,
+};
+use genco::prelude::*;
+use genco::tokens::static_literal;
+use heck::*;
+use std::collections::HashMap;
+
+pub struct SwiftGenerator {
+    abi: Abi,
+    c: CGenerator,
+    module: String,
+}
+
+/// State shared by the instructions of a single function body.
+struct Body<'a> {
+    objects: &'a [String],
+    handle_args: Vec<Option<String>>,
+    handle_ret: bool,
+    void_call: bool,
+    /// `Some(())` is returned as `()` to tell it apart from `nil`.
+    unit: bool,
+    /// Mutable slices are written back to the `inout` argument they were
+    /// bound from.
+    inout_args: HashMap<u32, String>,
+}
+
+impl SwiftGenerator {
+    /// Generates a swift file that imports the c header as the clang module
+    /// `module`.
+    pub fn new(module: String) -> Self {
+        Self {
+            abi: Abi::native(),
+            c: CGenerator::new(),
+            module,
+        }
+    }
+
+    pub fn generate(&self, iface: Interface) -> swift::Tokens {
+        let iface = iface.renamed();
+        let symbols = iface.symbols();
+        let objects: Vec<String> = iface.objects().into_iter().map(|obj| obj.name).collect();
+        let mut wrappers = vec![];
+        for iter in iface.iterators() {
+            wrappers.push(iter.next());
+        }
+        for fut in iface.futures() {
+            wrappers.push(fut.poll());
+        }
+        for stream in iface.streams() {
+            wrappers.push(stream.poll());
+        }
+        quote! {
+            $(static_literal("//")) AUTO GENERATED FILE, DO NOT EDIT.
+            $(static_literal("//"))
+            $(static_literal("//")) Generated by "ffi-gen".
+
+            import Foundation
+            import $(&self.module)
+
+            $(self.generate_doc(&iface.doc))
+
+            $(self.generate_runtime(&symbols))
+
+            $(for obj in iface.objects() join ($['\n']) => $(self.generate_object(&objects, &obj)))
+
+            $(for e in &iface.enums join ($['\n']) => $(self.generate_enum(&symbols, e)))
+
+            $(for ty in NUM_TYPES join ($['\n']) => $(self.generate_ffi_buffer(&symbols, ty)))
+
+            $(for ty in iface.listed_types() join ($['\n']) => $(self.generate_list_type(&symbols, &objects, &ty)))
+
+            $(for func in &wrappers join ($['\n']) => $(self.generate_function(&objects, func)))
+
+            $(for func in iface.functions() join ($['\n']) => $(self.generate_function(&objects, &func)))
+        }
+    }
+
+    fn generate_runtime(&self, symbols: &Symbols) -> swift::Tokens {
+        quote! {
+            $(static_literal("///")) Error returned by rust.
+            public struct FfiError: Error, CustomStringConvertible {
+                public let message: String
+
+                public init(_ message: String) {
+                    self.message = message
+                }
+
+                public var description: String {
+                    message
+                }
+            }
+
+            $(static_literal("///")) Handle to a rust object, boxes without a drop function are borrowed
+            $(static_literal("/// from another object which is kept alive as `parent`."))
+            final class FfiBox {
+                private var ptr: Int
+                private var dropFn: ((Int) -> Void)?
+                private let parent: AnyObject?
+
+                init(_ ptr: Int, _ drop: ((Int) -> Void)?, parent: AnyObject? = nil) {
+                    self.ptr = ptr
+                    self.dropFn = drop
+                    self.parent = parent
+                }
+
+                func borrow() -> Int {
+                    precondition(ptr != 0, "use after move")
+                    return ptr
+                }
+
+                func release() -> Int {
+                    precondition(dropFn != nil, "borrowed values can not be moved")
+                    let ptr = borrow()
+                    self.ptr = 0
+                    dropFn = nil
+                    return ptr
+                }
+
+                func drop() {
+                    if ptr != 0, let dropFn = dropFn {
+                        dropFn(ptr)
+                    }
+                    ptr = 0
+                    dropFn = nil
+                }
+            }
+
+            $(static_literal("///")) Copies the items into memory owned by rust.
+            func ffiLowerBytes<T>(_ items: [T], _ align: UInt) -> Int {
+                items.withUnsafeBytes { (bytes: UnsafeRawBufferPointer) -> Int in
+                    if bytes.count == 0 {
+                        return Int(align)
+                    }
+                    let ptr = UnsafeMutableRawPointer($(symbols.allocate())(UInt(bytes.count), align)!)
+                    ptr.copyMemory(from: bytes.baseAddress!, byteCount: bytes.count)
+                    return Int(bitPattern: ptr)
+                }
+            }
+
+            func ffiFreeBytes(_ ptr: Int, _ size: UInt, _ align: UInt) {
+                if size > 0 {
+                    $(symbols.deallocate())(UnsafeMutablePointer<UInt8>(bitPattern: ptr), size, align)
+                }
+            }
+
+            $(static_literal("///")) Packs the u32 byte length of every string followed by the utf8 bytes.
+            func ffiPackStrs(_ strs: [String]) -> [UInt8] {
+                let lens: [UInt32] = strs.map { str in UInt32(str.utf8.count) }
+                var bytes: [UInt8] = []
+                lens.withUnsafeBytes { lens in bytes.append(contentsOf: lens) }
+                for str in strs {
+                    bytes.append(contentsOf: str.utf8)
+                }
+                return bytes
+            }
+
+            $(static_literal("///")) Copies the memory rust wrote to back into the items.
+            func ffiWriteBack<T>(_ items: inout [T], _ ptr: Int) {
+                items.withUnsafeMutableBytes { (bytes: UnsafeMutableRawBufferPointer) in
+                    bytes.copyMemory(from: UnsafeRawBufferPointer(start: UnsafeRawPointer(bitPattern: ptr), count: bytes.count))
+                }
+            }
+
+            func ffiLiftString(_ ptr: Int, _ len: UInt) -> String {
+                if len == 0 {
+                    return ""
+                }
+                let bytes = UnsafeRawBufferPointer(start: UnsafeRawPointer(bitPattern: ptr), count: Int(len))
+                return String(decoding: bytes, as: UTF8.self)
+            }
+
+            func ffiLiftVec<T>(_ ptr: Int, _ len: UInt, _ type: T.Type) -> [T] {
+                if len == 0 {
+                    return []
+                }
+                return Array(UnsafeBufferPointer(start: UnsafePointer<T>(bitPattern: ptr), count: Int(len)))
+            }
+
+            final class FfiWakers {
+                private let lock = NSLock()
+                private var nextPort: Int64 = 0
+                private var wakers: [Int64: () -> Void] = [:]
+
+                func register(_ waker: @escaping () -> Void) -> Int64 {
+                    lock.lock()
+                    defer { lock.unlock() }
+                    nextPort += 1
+                    wakers[nextPort] = waker
+                    return nextPort
+                }
+
+                func unregister(_ port: Int64) {
+                    lock.lock()
+                    defer { lock.unlock() }
+                    wakers[port] = nil
+                }
+
+                func wake(_ port: Int64) {
+                    lock.lock()
+                    let waker = wakers[port]
+                    lock.unlock()
+                    waker?()
+                }
+            }
+
+            let ffiWakers = FfiWakers()
+
+            $(static_literal("///")) A closure without captures, so it can be handed to rust as a c function pointer.
+            let ffiPostCObject: @convention(c) (Int64, UnsafeRawPointer?) -> Void = { port, _ in
+                ffiWakers.wake(port)
+            }
+
+            let ffiPostCObjectAddress = unsafeBitCast(ffiPostCObject, to: Int.self)
+
+            $(static_literal("///")) Polls a rust future or stream whenever it is woken.
+            class FfiTask {
+                private let lock = NSLock()
+                private var polling = false
+                private var woken = false
+                private var finished = false
+
+                func wake() {
+                    lock.lock()
+                    if finished {
+                        lock.unlock()
+                        return
+                    }
+                    if polling {
+                        woken = true
+                        lock.unlock()
+                        return
+                    }
+                    polling = true
+                    lock.unlock()
+                    while true {
+                        let finished = poll()
+                        lock.lock()
+                        if finished || !woken {
+                            self.finished = finished
+                            polling = false
+                            lock.unlock()
+                            return
+                        }
+                        woken = false
+                        lock.unlock()
+                    }
+                }
+
+                $(static_literal("///")) Returns true once the task is finished.
+                func poll() -> Bool {
+                    fatalError("poll needs to be overridden")
+                }
+            }
+
+            final class FfiFutureTask<T>: FfiTask {
+                private let box: FfiBox
+                private let pollFn: (Int, Int, Int64) throws -> T?
+                private let continuation: CheckedContinuation<T, Error>
+                private var port: Int64 = 0
+
+                init(_ box: FfiBox, _ poll: @escaping (Int, Int, Int64) throws -> T?, _ continuation: CheckedContinuation<T, Error>) {
+                    self.box = box
+                    self.pollFn = poll
+                    self.continuation = continuation
+                    super.init()
+                }
+
+                func start() {
+                    port = ffiWakers.register { [self] in self.wake() }
+                    wake()
+                }
+
+                override func poll() -> Bool {
+                    do {
+                        guard let value = try pollFn(box.borrow(), ffiPostCObjectAddress, port) else {
+                            return false
+                        }
+                        continuation.resume(returning: value)
+                    } catch {
+                        continuation.resume(throwing: error)
+                    }
+                    ffiWakers.unregister(port)
+                    box.drop()
+                    return true
+                }
+            }
+
+            $(static_literal("///")) Suspends until the rust future is ready.
+            func ffiAwait<T>(_ box: FfiBox, _ poll: @escaping (Int, Int, Int64) throws -> T?) async throws -> T {
+                try await withCheckedThrowingContinuation { continuation in
+                    FfiFutureTask(box, poll, continuation).start()
+                }
+            }
+
+            final class FfiStreamTask<T>: FfiTask {
+                private let box: FfiBox
+                private let pollFn: (Int, Int, Int64, Int64) throws -> T?
+                private let continuation: AsyncThrowingStream<T, Error>.Continuation
+                private let endLock = NSLock()
+                private var ended = false
+                private var port: Int64 = 0
+                private var done: Int64 = 0
+
+                init(_ box: FfiBox, _ poll: @escaping (Int, Int, Int64, Int64) throws -> T?, _ continuation: AsyncThrowingStream<T, Error>.Continuation) {
+                    self.box = box
+                    self.pollFn = poll
+                    self.continuation = continuation
+                    super.init()
+                }
+
+                func start() {
+                    port = ffiWakers.register { [self] in self.wake() }
+                    done = ffiWakers.register { [self] in self.end() }
+                    wake()
+                }
+
+                $(static_literal("///")) Stops polling, called once rust is done or the stream is no longer consumed.
+                func end() {
+                    endLock.lock()
+                    ended = true
+                    endLock.unlock()
+                    wake()
+                }
+
+                private func isEnded() -> Bool {
+                    endLock.lock()
+                    defer { endLock.unlock() }
+                    return ended
+                }
+
+                $(static_literal("///")) Yields items to the continuation until rust is pending, errors finish the
+                $(static_literal("///")) stream right away.
+                override func poll() -> Bool {
+                    do {
+                        while !isEnded() {
+                            guard let item = try pollFn(box.borrow(), ffiPostCObjectAddress, port, done) else {
+                                return false
+                            }
+                            continuation.yield(item)
+                        }
+                    } catch {
+                        continuation.finish(throwing: error)
+                    }
+                    ffiWakers.unregister(port)
+                    ffiWakers.unregister(done)
+                    box.drop()
+                    continuation.finish()
+                    return true
+                }
+            }
+
+            func ffiStream<T>(_ box: FfiBox, _ poll: @escaping (Int, Int, Int64, Int64) throws -> T?) -> AsyncThrowingStream<T, Error> {
+                AsyncThrowingStream { continuation in
+                    let task = FfiStreamTask(box, poll, continuation)
+                    continuation.onTermination = { _ in task.end() }
+                    task.start()
+                }
+            }
+
+            $(static_literal("///")) A rust iterator, errors returned by rust are fatal.
+            public final class FfiIter<T>: Sequence, IteratorProtocol {
+                let box: FfiBox
+                private let nextFn: (Int) throws -> T?
+
+                init(_ box: FfiBox, _ next: @escaping (Int) throws -> T?) {
+                    self.box = box
+                    self.nextFn = next
+                }
+
+                deinit {
+                    box.drop()
+                }
+
+                public func next() -> T? {
+                    try! nextFn(box.borrow())
+                }
+            }
+
+            $(static_literal("///")) A rust String owned by a list.
+            public final class FfiString: CustomStringConvertible {
+                let box: FfiBox
+
+                init(_ box: FfiBox) {
+                    self.box = box
+                }
+
+                deinit {
+                    box.drop()
+                }
+
+                public var description: String {
+                    let parts = $(symbols.string_into_parts())(UnsafeMutableRawPointer(bitPattern: box.borrow()))
+                    return ffiLiftString(parts.ptr, parts.len)
+                }
+            }
+        }
+    }
+
+    fn generate_object(&self, objects: &[String], obj: &AbiObject) -> swift::Tokens {
+        quote! {
+            $(self.generate_doc(&obj.doc))
+            public final class $(self.type_ident(&obj.name)) {
+                let box: FfiBox
+
+                init(_ box: FfiBox) {
+                    self.box = box
+                }
+
+                deinit {
+                    box.drop()
+                }
+                $(for method in &obj.methods =>
+                    $['\n']
+                    $(self.generate_function(objects, method))
+                )
+            }
+        }
+    }
+
+    fn generate_enum(&self, symbols: &Symbols, e: &Enum) -> swift::Tokens {
+        let name = self.type_ident(&e.ident);
+        let tag = format!("{}Tag", name);
+        quote! {
+            public enum $(&tag): UInt32 {
+                $(for (i, entry) in e.entries.iter().enumerate() => case $(self.ident(&entry.name)) = $i$['\r'])
+            }
+
+            $(self.generate_doc(&e.doc))
+            public final class $(&name) {
+                let box: FfiBox
+                $(static_literal("///")) The tag of this enum object.
+                public let tag: $(&tag)
+                $(for (entry, inner) in inner_objects(e) =>
+                    public let $(self.ident(&entry)): $(self.type_ident(&inner))?$['\r'])
+
+                init(_ box: FfiBox) {
+                    let parts = $(symbols.destructure_enum(&e.ident))(UnsafeMutableRawPointer(bitPattern: box.borrow()))
+                    guard let tag = $(&tag)(rawValue: parts.tag) else {
+                        fatalError("destructuring enum gave back an invalid tag")
+                    }
+                    self.box = box
+                    self.tag = tag
+                    $(for (entry, inner) in inner_objects(e) =>
+                        self.$(self.ident(&entry)) = tag == .$(self.ident(&entry))
+                            ? $(self.type_ident(&inner))(FfiBox(Int(bitPattern: parts.inner), $(self.drop_fn(&symbols.destructor(&inner), true))))
+                            : nil$['\r'])
+                }
+
+                deinit {
+                    box.drop()
+                }
+            }
+        }
+    }
+
+    fn generate_ffi_buffer(&self, symbols: &Symbols, ty: NumType) -> swift::Tokens {
+        let name = ffi_buffer_name_for(ty);
+        let num = self.num_type(ty);
+        let (size, _) = self.abi.layout(ty);
+        let buffer = quote!(UnsafeMutableRawPointer(bitPattern: box.borrow()));
+        quote! {
+            $(static_literal("/// A buffer that can be filled through `elements` and passed to rust without copying."))
+            public final class $name {
+                let box: FfiBox
+
+                init(_ box: FfiBox) {
+                    self.box = box
+                }
+
+                deinit {
+                    box.drop()
+                }
+
+                $(static_literal("/// Allocates a zeroed buffer of `count` elements."))
+                public static func allocate(_ count: Int) -> $name {
+                    let ptr = $(symbols.ffi_buffer("new"))(UInt(count * $size), $size)
+                    return $name(FfiBox(ptr, $(self.drop_fn(&symbols.destructor("FfiBuffer"), false))))
+                }
+
+                $(static_literal("///")) The elements of the buffer, only valid while the buffer is alive.
+                public var elements: UnsafeMutableBufferPointer<$num> {
+                    let address = $(symbols.ffi_buffer("address"))($(&buffer))
+                    let count = Int($(symbols.ffi_buffer("size"))($(&buffer))) / $size
+                    return UnsafeMutableBufferPointer(start: UnsafeMutablePointer<$num>(bitPattern: address), count: count)
+                }
+            }
+        }
+    }
+
+    /// Strings can't be moved in or out of a list, rust doesn't export a
+    /// destructor for them.
+    fn generate_list_type(&self, symbols: &Symbols, objects: &[String], ty: &str) -> swift::Tokens {
+        let name = format!("FfiList{}", ty);
+        let elem = self.type_ident(ty);
+        let owned = objects.iter().any(|obj| obj == ty);
+        let boxed = quote!(UInt(bitPattern: box.borrow()));
+        quote! {
+            $(static_literal("///")) A rust Vec owning its elements.
+            public final class $(&name): RandomAccessCollection {
+                let box: FfiBox
+
+                init(_ box: FfiBox) {
+                    self.box = box
+                }
+
+                deinit {
+                    box.drop()
+                }
+
+                public static func create() -> $(&name) {
+                    let ptr = Int(bitPattern: $(symbols.list(ty, "Create"))())
+                    return $(&name)(FfiBox(ptr, $(self.drop_fn(&symbols.destructor(&name), false))))
+                }
+
+                public var startIndex: Int {
+                    0
+                }
+
+                public var endIndex: Int {
+                    Int($(symbols.list(ty, "Len"))($(&boxed)))
+                }
+
+                $(static_literal("///")) The returned element is borrowed from the list and keeps it alive.
+                public subscript(position: Int) -> $(&elem) {
+                    let ptr = $(symbols.list(ty, "ElementAt"))($(&boxed), UInt32(position))
+                    return $(&elem)(FfiBox(Int(bitPattern: ptr), nil, parent: self))
+                }
+                $(if owned {
+                    $['\n']
+                    $(static_literal("///")) Moves the element out of the list.
+                    @discardableResult
+                    public func remove(at index: Int) -> $(&elem) {
+                        let ptr = $(symbols.list(ty, "Remove"))($(&boxed), UInt32(index))
+                        return $(&elem)(FfiBox(Int(bitPattern: ptr), $(self.drop_fn(&symbols.destructor(ty), true))))
+                    }
+
+                    $(static_literal("///")) Moves the element into the list.
+                    public func append(_ element: $(&elem)) {
+                        $(symbols.list(ty, "Add"))($(&boxed), UInt(bitPattern: element.box.release()))
+                    }
+
+                    $(static_literal("///")) Moves the element into the list.
+                    public func insert(_ element: $(&elem), at index: Int) {
+                        $(symbols.list(ty, "Insert"))($(&boxed), UInt32(index), UInt(bitPattern: element.box.release()))
+                    }
+                })
+            }
+        }
+    }
+
+    fn generate_function(&self, objects: &[String], func: &AbiFunction) -> swift::Tokens {
+        let ffi = self.abi.import(func);
+        let unit = ffi.abi_ret.is_none() && matches!(func.ret, Some(AbiType::Option(_)));
+        let inout_args = ffi
+            .instr
+            .iter()
+            .filter_map(|instr| match instr {
+                Instr::BindArg(arg, var) if matches!(var.ty, AbiType::RefMutSlice(_)) => {
+                    Some((var.binding, self.ident(arg)))
+                }
+                _ => None,
+            })
+            .collect();
+        let body = Body {
+            objects,
+            handle_args: self.c.handle_args(func),
+            handle_ret: self.c.handle_ret(func).is_some(),
+            void_call: matches!(ffi.ffi_ret, Return::Void),
+            unit,
+            inout_args,
+        };
+        // variables defined inside of an option or result need to outlive
+        // the branch for the cleanup instructions.
+        let mut nested = vec![];
+        for instr in &ffi.instr {
+            nested_defines(instr, &mut nested);
+        }
+        let signature = self.signature(func, &ffi, unit);
+        quote! {
+            $(self.generate_doc(&func.doc))
+            $(match &func.ty {
+                FunctionType::Function | FunctionType::Method(_) => { public func $signature }
+                FunctionType::Constructor(_) => { public static func $signature }
+                FunctionType::NextIter(_, _)
+                | FunctionType::PollFuture(_, _)
+                | FunctionType::PollStream(_, _) => { fileprivate func $signature }
+            }) {
+                $(for var in &nested => var $(self.var(var)): $(self.ffi_type(var)) = 0$['\r'])
+                $(for instr in &ffi.instr => $(self.generate_instr(&body, instr, false))$['\r'])
+            }
+        }
+    }
+
+    /// Name, arguments, effects and return type of `func`. Wrappers around
+    /// `next` and `poll` functions take unlabeled arguments.
+    fn signature(&self, func: &AbiFunction, ffi: &Import, unit: bool) -> String {
+        let wrapper = matches!(
+            func.ty,
+            FunctionType::NextIter(_, _)
+                | FunctionType::PollFuture(_, _)
+                | FunctionType::PollStream(_, _)
+        );
+        let name = if wrapper {
+            wrapper_name(&ffi.symbol)
+        } else {
+            self.ident(&func.name)
+        };
+        let args = ffi
+            .abi_args
+            .iter()
+            .map(|(name, ty)| {
+                let label = if wrapper { "_ " } else { "" };
+                format!("{}{}: {}", label, self.ident(name), self.arg_type(ty))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let asynchronous = matches!(func.ret, Some(AbiType::Future(_)));
+        let effects = match (asynchronous, func.ret.as_ref().map(throws)) {
+            (true, _) => " async throws",
+            (false, Some(true)) => " throws",
+            (false, _) => "",
+        };
+        let ret = match &ffi.abi_ret {
+            Some(ty) => format!(" -> {}", self.value_type(ty, false)),
+            None if unit => " -> ()?".to_string(),
+            None => "".to_string(),
+        };
+        format!("{}({}){}{}", name, args, effects, ret)
+    }
+
+    fn generate_instr(&self, body: &Body, instr: &Instr, nested: bool) -> swift::Tokens {
+        match instr {
+            Instr::BindArg(arg, out) => quote!(let $(self.var(out)) = $(self.ident(arg))),
+            Instr::DefineArgs(_) if nested => quote!(),
+            Instr::DefineArgs(vars) => quote! {
+                $(for var in vars => var $(self.var(var)): $(self.ffi_type(var)) = 0$['\r'])
+            },
+            Instr::BorrowSelf(out) => quote!($(self.var(out)) = box.borrow()),
+            Instr::BorrowObject(in_, out) | Instr::BorrowIter(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).box.borrow())
+            }
+            Instr::MoveObject(in_, out) | Instr::MoveIter(in_, out) => {
+                quote!($(self.var(out)) = $(self.var(in_)).box.release())
+            }
+            Instr::BorrowFuture(_, _)
+            | Instr::MoveFuture(_, _)
+            | Instr::BorrowStream(_, _)
+            | Instr::MoveStream(_, _) => unimplemented!("futures and streams can only be returned"),
+            Instr::LowerNum(in_, out) => {
+                quote!($(self.var(out)) = $(self.ffi_type(out))($(self.var(in_))))
+            }
+            Instr::LiftNum(in_, out, _) => quote!(let $(self.var(out)) = $(self.var(in_))),
+            Instr::LowerBool(in_, out) => quote!($(self.var(out)) = $(self.var(in_)) ? 1 : 0),
+            Instr::LiftBool(in_, out) => quote!(let $(self.var(out)) = $(self.var(in_)) != 0),
+            Instr::LowerString(in_, ptr, len, cap, _, _) => quote! {
+                let $(self.var(ptr))_0 = Array($(self.var(in_)).utf8)
+                $(self.var(len)) = UInt($(self.var(ptr))_0.count)
+                $(self.var(ptr)) = ffiLowerBytes($(self.var(ptr))_0, 1)
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::LowerVec(in_, ptr, len, cap, _, _, align)
+            | Instr::LowerMutSlice(in_, ptr, len, cap, _, _, align) => quote! {
+                $(self.var(len)) = UInt($(self.var(in_)).count)
+                $(self.var(ptr)) = ffiLowerBytes($(self.var(in_)), $(*align))
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::LowerStrSlice(in_, ptr, len, cap) => quote! {
+                let $(self.var(ptr))_0 = ffiPackStrs($(self.var(in_)))
+                $(self.var(len)) = UInt($(self.var(in_)).count)
+                $(self.var(cap)) = UInt($(self.var(ptr))_0.count)
+                $(self.var(ptr)) = ffiLowerBytes($(self.var(ptr))_0, 4)
+            },
+            Instr::LowerObjectSlice(in_, ptr, len, cap, _, align) => quote! {
+                let $(self.var(ptr))_0 = $(self.var(in_)).map { obj in obj.box.borrow() }
+                $(self.var(len)) = UInt($(self.var(in_)).count)
+                $(self.var(ptr)) = ffiLowerBytes($(self.var(ptr))_0, $(*align))
+                $(self.var(cap)) = $(self.var(len))
+            },
+            Instr::WriteBack(in_, ptr, _, cap, _) => {
+                let arg = match body.inout_args.get(&in_.binding) {
+                    Some(arg) => arg,
+                    None => unimplemented!("\"&mut [T]\" can only be used as top level argument"),
+                };
+                quote! {
+                    if $(self.var(cap)) > 0 {
+                        ffiWriteBack(&$arg, $(self.var(ptr)))
+                    }
+                }
+            }
+            Instr::Deallocate(ptr, len, size, align) => quote! {
+                ffiFreeBytes($(self.var(ptr)), $(self.var(len)) * $(*size), $(*align))
+            },
+            Instr::LiftString(ptr, len, out) => quote! {
+                let $(self.var(out)) = ffiLiftString($(self.var(ptr)), $(self.var(len)))
+            },
+            Instr::LiftVec(ptr, len, out, ty) => quote! {
+                let $(self.var(out)) = ffiLiftVec($(self.var(ptr)), $(self.var(len)), $(self.num_type(*ty)).self)
+            },
+            Instr::HandleNull(var) => quote! {
+                if $(self.var(var)) == 0 {
+                    return nil
+                }
+            },
+            Instr::LowerOption(arg, var, some, some_instr) => quote! {
+                if let $(self.var(some)) = $(self.var(arg)) {
+                    $(self.var(var)) = 1
+                    $(for instr in some_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                } else {
+                    $(self.var(var)) = 0
+                }
+            },
+            Instr::LowerResult(arg, var, ok, ok_instr, err, err_instr) => quote! {
+                switch $(self.var(arg)) {
+                case .success(let $(self.var(ok))):
+                    $(self.var(var)) = 1
+                    $(for instr in ok_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                case .failure(let $(self.var(err))_0):
+                    $(self.var(var)) = 0
+                    let $(self.var(err)) = $(self.var(err))_0.message
+                    $(for instr in err_instr => $(self.generate_instr(body, instr, true))$['\r'])
+                }
+            },
+            Instr::HandleError(var, ptr, len, cap) => quote! {
+                if $(self.var(var)) == 0 {
+                    let $(self.var(var))_0 = ffiLiftString($(self.var(ptr)), $(self.var(len)))
+                    ffiFreeBytes($(self.var(ptr)), $(self.var(cap)), 1)
+                    throw FfiError($(self.var(var))_0)
+                }
+            },
+            Instr::LiftObject(obj, box_, drop, out) => {
+                let typed = body.objects.iter().any(|o| o == obj);
+                quote! {
+                    let $(self.var(out)) = $(self.type_ident(obj))(FfiBox($(self.var(box_)), $(self.drop_fn(drop, typed))))
+                }
+            }
+            Instr::LiftRefObject(obj, box_, _leak, out) => quote! {
+                let $(self.var(out)) = $(self.type_ident(obj))(FfiBox($(self.var(box_)), nil))
+            },
+            Instr::LiftIter(box_, next, drop, out) => quote! {
+                let $(self.var(out)) = FfiIter(FfiBox($(self.var(box_)), $(self.drop_fn(drop, false))), $(wrapper_name(next)))
+            },
+            Instr::LiftRefIter(box_, next, _leak, out) => quote! {
+                let $(self.var(out)) = FfiIter(FfiBox($(self.var(box_)), nil), $(wrapper_name(next)))
+            },
+            Instr::LiftFuture(box_, poll, drop, out) => quote! {
+                let $(self.var(out)) = try await ffiAwait(FfiBox($(self.var(box_)), $(self.drop_fn(drop, false))), $(wrapper_name(poll)))
+            },
+            Instr::LiftStream(box_, poll, drop, out) => quote! {
+                let $(self.var(out)) = ffiStream(FfiBox($(self.var(box_)), $(self.drop_fn(drop, false))), $(wrapper_name(poll)))
+            },
+            Instr::LiftTuple(vars, out) => match vars.len() {
+                0 => quote!(let $(self.var(out)) = ()),
+                1 => quote!(let $(self.var(out)) = $(self.var(&vars[0]))),
+                _ => quote! {
+                    let $(self.var(out)) = ($(for var in vars join (, ) => $(self.var(var))))
+                },
+            },
+            Instr::Call(symbol, ret, args) => {
+                let args = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| match body.handle_args.get(i) {
+                        Some(Some(_)) => format!("OpaquePointer(bitPattern: {})", self.var(arg)),
+                        _ => self.var(arg),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let call = format!("{}({})", symbol, args);
+                match ret {
+                    Some(ret) if !body.void_call => {
+                        if body.handle_ret {
+                            quote!(let $(self.var(ret)) = Int(bitPattern: $call))
+                        } else {
+                            quote!(let $(self.var(ret)) = $call)
+                        }
+                    }
+                    _ => quote!($call),
+                }
+            }
+            Instr::BindRets(ret, vars) => match vars.len() {
+                0 => quote!(),
+                1 => quote!(let $(self.var(&vars[0])) = $(self.var(ret))),
+                _ => quote! {
+                    $(for (i, var) in vars.iter().enumerate() =>
+                        let $(self.var(var)) = $(self.var(ret)).$(format!("ret{}", i))$['\r'])
+                },
+            },
+            Instr::ReturnValue(ret) => quote!(return $(self.var(ret))),
+            Instr::ReturnVoid if body.unit => quote!(return ()),
+            Instr::ReturnVoid => quote!(return),
+            Instr::LiftNumFromU32Tuple(..)
+            | Instr::LowerNumFromU32Tuple(..)
+            | Instr::CallRetArea(..) => unreachable!(),
+        }
+    }
+
+    /// Closure calling the destructor `drop`, object destructors take a
+    /// typed handle.
+    fn drop_fn(&self, drop: &str, typed: bool) -> String {
+        if typed {
+            format!(
+                "{{ boxed in {}(0, OpaquePointer(bitPattern: boxed)) }}",
+                drop
+            )
+        } else {
+            format!("{{ boxed in {}(0, boxed) }}", drop)
+        }
+    }
+
+    fn arg_type(&self, ty: &AbiType) -> String {
+        match ty {
+            AbiType::RefMutSlice(ty) => format!("inout [{}]", self.num_type(*ty)),
+            AbiType::Future(_)
+            | AbiType::RefFuture(_)
+            | AbiType::Stream(_)
+            | AbiType::RefStream(_) => unimplemented!("futures and streams can only be returned"),
+            ty => self.value_type(ty, true),
+        }
+    }
+
+    /// Type of a value, results passed as arguments are wrapped in a
+    /// `Result`, returned errors are thrown.
+    fn value_type(&self, ty: &AbiType, arg: bool) -> String {
+        match ty {
+            AbiType::Num(ty) => self.num_type(*ty).to_string(),
+            AbiType::Isize => "Int".to_string(),
+            AbiType::Usize => "UInt".to_string(),
+            AbiType::Bool => "Bool".to_string(),
+            AbiType::RefStr | AbiType::String => "String".to_string(),
+            AbiType::RefSlice(ty) | AbiType::RefMutSlice(ty) | AbiType::Vec(ty) => {
+                format!("[{}]", self.num_type(*ty))
+            }
+            AbiType::RefSliceStr => "[String]".to_string(),
+            AbiType::RefSliceObject(ty) => format!("[{}]", self.type_ident(ty)),
+            AbiType::RefObject(ty) | AbiType::Object(ty) | AbiType::RefEnum(ty) => {
+                self.type_ident(ty)
+            }
+            AbiType::Option(ty) => format!("{}?", self.value_type(ty, arg)),
+            AbiType::Result(ty) if arg => {
+                format!("Result<{}, FfiError>", self.value_type(ty, arg))
+            }
+            AbiType::Result(ty) => self.value_type(ty, arg),
+            AbiType::Tuple(tys) => match tys.len() {
+                1 => self.value_type(&tys[0], arg),
+                _ => format!(
+                    "({})",
+                    tys.iter()
+                        .map(|ty| self.value_type(ty, arg))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            },
+            AbiType::Iter(ty) | AbiType::RefIter(ty) => {
+                format!("FfiIter<{}>", self.value_type(ty, false))
+            }
+            AbiType::Future(ty) | AbiType::RefFuture(ty) => self.value_type(ty, false),
+            AbiType::Stream(ty) | AbiType::RefStream(ty) => {
+                format!("AsyncThrowingStream<{}, Error>", self.value_type(ty, false))
+            }
+            AbiType::Buffer(ty) | AbiType::RefBuffer(ty) => ffi_buffer_name_for(*ty).to_string(),
+            AbiType::List(ty) => format!("FfiList{}", ty),
+        }
+    }
+
+    fn ffi_type(&self, var: &Var) -> &'static str {
+        match &var.ty {
+            AbiType::Num(num) => self.num_type(*num),
+            ty => unreachable!("{:?} is not an ffi type", ty),
+        }
+    }
+
+    fn num_type(&self, ty: NumType) -> &'static str {
+        match ty {
+            NumType::U8 => "UInt8",
+            NumType::U16 => "UInt16",
+            NumType::U32 => "UInt32",
+            NumType::U64 => "UInt64",
+            NumType::I8 => "Int8",
+            NumType::I16 => "Int16",
+            NumType::I32 => "Int32",
+            NumType::I64 => "Int64",
+            NumType::F32 => "Float",
+            NumType::F64 => "Double",
+            NumType::IPtr => "Int",
+            NumType::UPtr => "UInt",
+        }
+    }
+
+    fn generate_doc(&self, doc: &[String]) -> Vec<swift::Tokens> {
+        doc.iter()
+            .enumerate()
+            .map(|(index, line)| {
+                if index < doc.len() - 1 {
+                    quote!($(format!("/// {}\n", line)))
+                } else {
+                    quote!($(format!("/// {}", line)))
+                }
+            })
+            .collect()
+    }
+
+    fn var(&self, var: &Var) -> String {
+        format!("tmp{}", var.binding)
+    }
+
+    fn type_ident(&self, s: &str) -> String {
+        sanitize_identifier(s)
+    }
+
+    fn ident(&self, s: &str) -> String {
+        sanitize_identifier(&s.to_lower_camel_case())
+    }
+}
+
+/// Whether returning `ty` can throw.
+fn throws(ty: &AbiType) -> bool {
+    match ty {
+        AbiType::Result(_) | AbiType::Future(_) => true,
+        AbiType::Option(ty) => throws(ty),
+        AbiType::Tuple(tys) => tys.iter().any(throws),
+        _ => false,
+    }
+}
+
+/// Name of the wrapper around the `next` or `poll` function `symbol`.
+fn wrapper_name(symbol: &str) -> String {
+    format!("wrap_{}", symbol.trim_start_matches('_'))
+}
+
+/// Entry name and object of every enum entry wrapping an object.
+fn inner_objects(e: &Enum) -> Vec<(String, String)> {
+    e.entries
+        .iter()
+        .filter_map(|entry| match &entry.inner {
+            Some(Type::Ident(name)) => Some((entry.name.clone(), name.clone())),
+            Some(_) => unimplemented!("Enums can only wrap objects"),
+            None => None,
+        })
+        .collect()
+}
+
+/// Collects the variables defined inside of `LowerOption` and `LowerResult`.
+fn nested_defines(instr: &Instr, vars: &mut Vec<Var>) {
+    fn collect(instrs: &[Instr], vars: &mut Vec<Var>) {
+        for instr in instrs {
+            if let Instr::DefineArgs(defines) = instr {
+                vars.extend(defines.iter().cloned());
+            }
+            nested_defines(instr, vars);
+        }
+    }
+    match instr {
+        Instr::LowerOption(_, _, _, some) => collect(some, vars),
+        Instr::LowerResult(_, _, _, ok, _, err) => {
+            collect(ok, vars);
+            collect(err, vars);
+        }
+        _ => {}
+    }
+}
+
+fn sanitize_identifier(id: &str) -> String {
+    if RESERVED_IDENTIFIERS.contains(&id) {
+        format!("{}_", id)
+    } else {
+        id.to_string()
+    }
+}
+
+// https://docs.swift.org/swift-book/documentation/the-swift-programming-language/lexicalstructure/#Keywords-and-Punctuation
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 55] = [
+    "Any",
+    "Self",
+    "as",
+    "associatedtype",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "continue",
+    "default",
+    "defer",
+    "deinit",
+    "do",
+    "else",
+    "enum",
+    "extension",
+    "fallthrough",
+    "false",
+    "fileprivate",
+    "for",
+    "func",
+    "guard",
+    "if",
+    "import",
+    "in",
+    "init",
+    "inout",
+    "internal",
+    "is",
+    "let",
+    "nil",
+    "open",
+    "operator",
+    "precedencegroup",
+    "private",
+    "protocol",
+    "public",
+    "repeat",
+    "rethrows",
+    "return",
+    "self",
+    "static",
+    "struct",
+    "subscript",
+    "super",
+    "switch",
+    "throw",
+    "throws",
+    "true",
+    "try",
+    "typealias",
+    "var",
+    "where",
+    "while",
+];
+
+#[cfg(feature = "test_runner")]
+#[doc(hidden)]
+pub mod test_runner {
+    use super::*;
+    use crate::RustGenerator;
+    use anyhow::Result;
+    use std::io::Write;
+    use tempfile::{NamedTempFile, TempDir};
+    use trybuild::TestCases;
+
+    pub fn compile_pass(iface: &str, rust: rust::Tokens, swift: swift::Tokens) -> Result<()> {
+        let iface = Interface::parse(iface)?;
+        let (mut rust_file, rust_file_path) = NamedTempFile::new()?.keep()?;
+        writeln!(rust_file, "#![feature(vec_into_raw_parts)]")?;
+        writeln!(rust_file, "#![feature(lazy_cell)]")?;
+        let rust_gen = RustGenerator::new(Abi::native());
+        let rust_tokens = rust_gen.generate(iface.clone());
+        let header = CGenerator::new().generate(iface.clone()).to_file_string()?;
+        let swift_gen = SwiftGenerator::new("CompilePassC".to_string());
+        let module = swift_gen.generate(iface).to_file_string()?;
+
+        let library_tokens: genco::Tokens<genco::lang::Rust> = quote! {
+            $rust_tokens
+            $rust
+        };
+
+        let library_dir = TempDir::new()?.keep();
+        let library_file = library_dir.join("libcompile_pass.so");
+        let bin_file = library_dir.join("main");
+        std::fs::write(library_dir.join("api.h"), header)?;
+        std::fs::write(
+            library_dir.join("module.modulemap"),
+            "module CompilePassC {\n    header \"api.h\"\n    link \"compile_pass\"\n    export *\n}\n",
+        )?;
+        std::fs::write(library_dir.join("compile_pass.swift"), module)?;
+        std::fs::write(library_dir.join("main.swift"), swift.to_file_string()?)?;
+
+        let library = library_tokens.to_file_string()?;
+        rust_file.write_all(library.as_bytes())?;
+
+        let runner_tokens: rust::Tokens = quote! {
+            fn main() {
+                use std::process::Command;
+                let ret = Command::new("rustc")
+                    .arg("--edition")
+                    .arg("2021")
+                    .arg("--crate-name")
+                    .arg("compile_pass")
+                    .arg("--crate-type")
+                    .arg("cdylib")
+                    .arg("--cfg")
+                    .arg("feature=\"test_runner\"")
+                    .arg("-o")
+                    .arg($(quoted(library_file.as_path().to_str().unwrap())))
+                    .arg($(quoted(rust_file_path.as_path().to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+                let ret = Command::new("swiftc")
+                    .current_dir($(quoted(library_dir.as_path().to_str().unwrap())))
+                    .arg("-I")
+                    .arg(".")
+                    .arg("-L")
+                    .arg(".")
+                    .arg("-o")
+                    .arg($(quoted(bin_file.as_path().to_str().unwrap())))
+                    .arg("compile_pass.swift")
+                    .arg("main.swift")
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+                let ret = Command::new($(quoted(bin_file.as_path().to_str().unwrap())))
+                    .env("LD_LIBRARY_PATH", $(quoted(library_dir.as_path().to_str().unwrap())))
+                    .status()
+                    .unwrap()
+                    .success();
+                assert!(ret);
+            }
+        };
+        let (mut runner_file, runner_file_path) = NamedTempFile::new()?.keep()?;
+        let runner = runner_tokens.to_file_string()?;
+        runner_file.write_all(runner.as_bytes())?;
+
+        let test = TestCases::new();
+        test.pass(runner_file_path.as_path().to_str().unwrap());
+        Ok(())
+    }
+}
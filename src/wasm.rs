@@ -0,0 +1,582 @@
+//! Minimal wasm binary rewriting for the multi-value shim.
+//!
+//! Only the type, import, function, export and code sections are decoded,
+//! all other sections are passed through unchanged.
+use anyhow::Result;
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_CODE: u8 = 10;
+
+const KIND_FUNC: u8 = 0;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    fn from_byte(byte: u8) -> Option<Self> {
+        Some(match byte {
+            0x7f => Self::I32,
+            0x7e => Self::I64,
+            0x7d => Self::F32,
+            0x7c => Self::F64,
+            _ => return None,
+        })
+    }
+
+    fn byte(self) -> u8 {
+        match self {
+            Self::I32 => 0x7f,
+            Self::I64 => 0x7e,
+            Self::F32 => 0x7d,
+            Self::F64 => 0x7c,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I32 | Self::F32 => 4,
+            Self::I64 | Self::F64 => 8,
+        }
+    }
+
+    fn load(self) -> u8 {
+        match self {
+            Self::I32 => 0x28,
+            Self::I64 => 0x29,
+            Self::F32 => 0x2a,
+            Self::F64 => 0x2b,
+        }
+    }
+}
+
+impl std::fmt::Display for ValType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::I32 => "i32",
+            Self::I64 => "i64",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An export returning its results through a return pointer passed as first
+/// argument, that should return them as multiple values instead.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MultiValueExport {
+    pub symbol: String,
+    pub results: Vec<ValType>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct FuncType {
+    params: Vec<u8>,
+    results: Vec<u8>,
+}
+
+impl std::fmt::Display for FuncType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let fmt = |tys: &[u8]| {
+            tys.iter()
+                .map(|ty| match ValType::from_byte(*ty) {
+                    Some(ty) => ty.to_string(),
+                    None => format!("{:#x}", ty),
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(f, "({}) -> ({})", fmt(&self.params), fmt(&self.results))
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| anyhow::anyhow!("unexpected end of wasm module"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift >= 64 {
+                anyhow::bail!("invalid leb128 integer");
+            }
+            result |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::try_from(self.u64()?)?)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| anyhow::anyhow!("unexpected end of wasm module"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos..];
+        self.pos = self.data.len();
+        rest
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        Ok(std::str::from_utf8(self.bytes(len)?)?.to_string())
+    }
+
+    fn val_types(&mut self) -> Result<Vec<u8>> {
+        let len = self.u32()?;
+        let mut tys = vec![];
+        for _ in 0..len {
+            let ty = self.u8()?;
+            // numeric, vector and the abbreviated reference types
+            if !matches!(ty, 0x7b..=0x7f | 0x70 | 0x6f) {
+                anyhow::bail!("unsupported value type {:#x}", ty);
+            }
+            tys.push(ty);
+        }
+        Ok(tys)
+    }
+
+    fn limits(&mut self) -> Result<()> {
+        let flags = self.u8()?;
+        self.u64()?;
+        if flags & 0x01 != 0 {
+            self.u64()?;
+        }
+        if flags & 0x08 != 0 {
+            self.u32()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_u64(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_i64(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_vec(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u64(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Rewrites `exports` to return their results as multiple values.
+///
+/// Every export is replaced by a wrapper that allocates a return area with
+/// the `allocate` export, passes it to the original function, loads the
+/// results and frees the return area with the `deallocate` export.
+pub fn multi_value_xform(
+    wasm: &[u8],
+    exports: &[MultiValueExport],
+    memory64: bool,
+    allocate: &str,
+    deallocate: &str,
+) -> Result<Vec<u8>> {
+    let mut reader = Reader::new(wasm);
+    if reader.bytes(8).ok() != Some(b"\0asm\x01\0\0\0".as_slice()) {
+        anyhow::bail!("not a wasm module");
+    }
+    let mut sections = vec![];
+    while !reader.is_empty() {
+        let id = reader.u8()?;
+        let len = reader.u32()? as usize;
+        sections.push((id, reader.bytes(len)?.to_vec()));
+    }
+    let section = |id: u8| sections.iter().position(|(i, _)| *i == id);
+
+    let type_index =
+        section(SECTION_TYPE).ok_or_else(|| anyhow::anyhow!("missing type section"))?;
+    let mut types = vec![];
+    {
+        let mut reader = Reader::new(&sections[type_index].1);
+        for _ in 0..reader.u32()? {
+            if reader.u8()? != 0x60 {
+                anyhow::bail!("unsupported type section entry");
+            }
+            let params = reader.val_types()?;
+            let results = reader.val_types()?;
+            types.push(FuncType { params, results });
+        }
+    }
+    let mut funcs = vec![];
+    if let Some(i) = section(SECTION_IMPORT) {
+        let mut reader = Reader::new(&sections[i].1);
+        for _ in 0..reader.u32()? {
+            reader.name()?;
+            reader.name()?;
+            match reader.u8()? {
+                0x00 => funcs.push(reader.u32()?),
+                0x01 => {
+                    reader.u8()?;
+                    reader.limits()?;
+                }
+                0x02 => reader.limits()?,
+                0x03 => {
+                    reader.u8()?;
+                    reader.u8()?;
+                }
+                0x04 => {
+                    reader.u8()?;
+                    reader.u32()?;
+                }
+                kind => anyhow::bail!("unsupported import kind {:#x}", kind),
+            }
+        }
+    }
+    if let Some(i) = section(SECTION_FUNCTION) {
+        let mut reader = Reader::new(&sections[i].1);
+        for _ in 0..reader.u32()? {
+            funcs.push(reader.u32()?);
+        }
+    }
+    let mut module_exports = vec![];
+    let export_section =
+        section(SECTION_EXPORT).ok_or_else(|| anyhow::anyhow!("missing export section"))?;
+    {
+        let mut reader = Reader::new(&sections[export_section].1);
+        for _ in 0..reader.u32()? {
+            let name = reader.name()?;
+            let kind = reader.u8()?;
+            let index = reader.u32()?;
+            module_exports.push((name, kind, index));
+        }
+    }
+    let find = |name: &str| {
+        module_exports
+            .iter()
+            .find(|(n, kind, _)| n == name && *kind == KIND_FUNC)
+            .map(|(_, _, index)| *index)
+    };
+
+    let ptr = if memory64 { ValType::I64 } else { ValType::I32 };
+    let mut errors = vec![];
+    let allocate = find(allocate);
+    let deallocate = find(deallocate);
+    if allocate.is_none() || deallocate.is_none() {
+        errors.push("missing `allocate` or `deallocate` export".to_string());
+    }
+    let mut wrappers = vec![];
+    for export in exports {
+        let index = match find(&export.symbol) {
+            Some(index) => index,
+            None => {
+                errors.push(format!("{}: missing export", export.symbol));
+                continue;
+            }
+        };
+        let ty = match funcs
+            .get(index as usize)
+            .and_then(|ty| types.get(*ty as usize))
+        {
+            Some(ty) => ty,
+            None => {
+                errors.push(format!(
+                    "{}: invalid function or type index {}",
+                    export.symbol, index
+                ));
+                continue;
+            }
+        };
+        if ty.params.first() != Some(&ptr.byte()) || !ty.results.is_empty() {
+            errors.push(format!(
+                "{}: expected ({}, ..) -> (), found {}",
+                export.symbol, ptr, ty
+            ));
+            continue;
+        }
+        wrappers.push((export, index, ty.params[1..].to_vec()));
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("multi-value transform failed for:\n{}", errors.join("\n"));
+    }
+    let (allocate, deallocate) = (allocate.unwrap(), deallocate.unwrap());
+
+    let ptr_const = |out: &mut Vec<u8>, value: usize| {
+        out.push(if memory64 { 0x42 } else { 0x41 });
+        write_i64(out, value as i64);
+    };
+    let mut renamed = vec![];
+    let mut new_funcs = vec![];
+    let mut bodies = vec![];
+    for (i, (export, index, params)) in wrappers.into_iter().enumerate() {
+        let results = export.results.iter().map(|ty| ty.byte()).collect();
+        new_funcs.push(types.len() as u32);
+        types.push(FuncType {
+            params: params.clone(),
+            results,
+        });
+        renamed.push((export.symbol.as_str(), (funcs.len() + i) as u32));
+
+        let mut offsets = vec![];
+        let mut offset = 0usize;
+        let mut align = 1;
+        for ty in &export.results {
+            offset = offset.next_multiple_of(ty.size());
+            offsets.push(offset);
+            offset += ty.size();
+            align = core::cmp::max(align, ty.size());
+        }
+        let size = offset.next_multiple_of(align);
+        let ret = params.len() as u64;
+
+        let mut body = vec![1, 1, ptr.byte()];
+        ptr_const(&mut body, size);
+        ptr_const(&mut body, align);
+        body.push(0x10);
+        write_u64(&mut body, allocate.into());
+        body.push(0x21);
+        write_u64(&mut body, ret);
+        body.push(0x20);
+        write_u64(&mut body, ret);
+        for param in 0..ret {
+            body.push(0x20);
+            write_u64(&mut body, param);
+        }
+        body.push(0x10);
+        write_u64(&mut body, index.into());
+        for (ty, offset) in export.results.iter().zip(offsets) {
+            body.push(0x20);
+            write_u64(&mut body, ret);
+            body.push(ty.load());
+            write_u64(&mut body, ty.size().trailing_zeros().into());
+            write_u64(&mut body, offset as u64);
+        }
+        body.push(0x20);
+        write_u64(&mut body, ret);
+        ptr_const(&mut body, size);
+        ptr_const(&mut body, align);
+        body.push(0x10);
+        write_u64(&mut body, deallocate.into());
+        body.push(0x0b);
+        bodies.push(body);
+    }
+
+    let mut type_section = vec![];
+    write_u64(&mut type_section, types.len() as u64);
+    for ty in &types {
+        type_section.push(0x60);
+        write_vec(&mut type_section, &ty.params);
+        write_vec(&mut type_section, &ty.results);
+    }
+    let mut export_payload = vec![];
+    write_u64(&mut export_payload, module_exports.len() as u64);
+    for (name, kind, index) in &module_exports {
+        let index = match renamed.iter().find(|(symbol, _)| symbol == name) {
+            Some((_, index)) if *kind == KIND_FUNC => *index,
+            _ => *index,
+        };
+        write_vec(&mut export_payload, name.as_bytes());
+        export_payload.push(*kind);
+        write_u64(&mut export_payload, index.into());
+    }
+    let function_section =
+        section(SECTION_FUNCTION).ok_or_else(|| anyhow::anyhow!("missing function section"))?;
+    let mut function_payload = vec![];
+    {
+        let mut reader = Reader::new(&sections[function_section].1);
+        let count = reader.u32()?;
+        write_u64(
+            &mut function_payload,
+            (count as usize + new_funcs.len()) as u64,
+        );
+        function_payload.extend_from_slice(reader.rest());
+        for ty in &new_funcs {
+            write_u64(&mut function_payload, (*ty).into());
+        }
+    }
+    let code_section =
+        section(SECTION_CODE).ok_or_else(|| anyhow::anyhow!("missing code section"))?;
+    let mut code_payload = vec![];
+    {
+        let mut reader = Reader::new(&sections[code_section].1);
+        let count = reader.u32()?;
+        write_u64(&mut code_payload, (count as usize + bodies.len()) as u64);
+        code_payload.extend_from_slice(reader.rest());
+        for body in &bodies {
+            write_vec(&mut code_payload, body);
+        }
+    }
+    sections[type_index].1 = type_section;
+    sections[export_section].1 = export_payload;
+    sections[function_section].1 = function_payload;
+    sections[code_section].1 = code_payload;
+
+    let mut out = wasm[..8].to_vec();
+    for (id, payload) in &sections {
+        out.push(*id);
+        write_vec(&mut out, payload);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(out: &mut Vec<u8>, id: u8, payload: &[u8]) {
+        out.push(id);
+        write_vec(out, payload);
+    }
+
+    // (i32) -> (), (i32, i32) -> i32, (i32, i32, i32) -> () and (i64) -> ()
+    fn module() -> Vec<u8> {
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+        section(
+            &mut out,
+            SECTION_TYPE,
+            &[
+                4, 0x60, 1, 0x7f, 0, 0x60, 2, 0x7f, 0x7f, 1, 0x7f, 0x60, 3, 0x7f, 0x7f, 0x7f, 0,
+                0x60, 1, 0x7e, 0,
+            ],
+        );
+        section(&mut out, SECTION_FUNCTION, &[4, 0, 1, 2, 3]);
+        let mut exports = vec![4];
+        for (i, name) in ["f", "allocate", "deallocate", "g"].iter().enumerate() {
+            write_vec(&mut exports, name.as_bytes());
+            exports.extend_from_slice(&[KIND_FUNC, i as u8]);
+        }
+        section(&mut out, SECTION_EXPORT, &exports);
+        section(
+            &mut out,
+            SECTION_CODE,
+            &[4, 2, 0, 0x0b, 4, 0, 0x41, 0, 0x0b, 2, 0, 0x0b, 2, 0, 0x0b],
+        );
+        out
+    }
+
+    #[test]
+    fn test_multi_value_xform() {
+        let export = MultiValueExport {
+            symbol: "f".into(),
+            results: vec![ValType::I32, ValType::I64],
+        };
+        let wasm =
+            multi_value_xform(&module(), &[export], false, "allocate", "deallocate").unwrap();
+        let mut reader = Reader::new(&wasm[8..]);
+        let mut exports = vec![];
+        while !reader.is_empty() {
+            let id = reader.u8().unwrap();
+            let len = reader.u32().unwrap() as usize;
+            let mut payload = Reader::new(reader.bytes(len).unwrap());
+            match id {
+                SECTION_FUNCTION => assert_eq!(payload.rest(), &[5, 0, 1, 2, 3, 4]),
+                SECTION_EXPORT => {
+                    for _ in 0..payload.u32().unwrap() {
+                        let name = payload.name().unwrap();
+                        payload.u8().unwrap();
+                        exports.push((name, payload.u32().unwrap()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(exports[0], ("f".to_string(), 4));
+        assert_eq!(exports[3], ("g".to_string(), 3));
+    }
+
+    #[test]
+    fn test_multi_value_xform_mismatch() {
+        let exports = ["g", "h"].map(|symbol| MultiValueExport {
+            symbol: symbol.into(),
+            results: vec![ValType::I32],
+        });
+        let err = multi_value_xform(&module(), &exports, false, "allocate", "deallocate")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("g: expected (i32, ..) -> (), found (i64) -> ()"));
+        assert!(err.contains("h: missing export"));
+    }
+
+    #[test]
+    fn test_multi_value_xform_invalid_index() {
+        let mut wasm = module();
+        // point the export of `f` at a function that doesn't exist
+        let f = wasm
+            .windows(3)
+            .position(|w| w == [1, b'f', KIND_FUNC])
+            .unwrap();
+        wasm[f + 3] = 9;
+        let export = MultiValueExport {
+            symbol: "f".into(),
+            results: vec![ValType::I32],
+        };
+        let err = multi_value_xform(&wasm, &[export], false, "allocate", "deallocate")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("f: invalid function or type index 9"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_multi_value_xform_missing_type_section() {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        section(&mut wasm, SECTION_FUNCTION, &[0]);
+        section(&mut wasm, SECTION_EXPORT, &[0]);
+        section(&mut wasm, SECTION_CODE, &[0]);
+        let err = multi_value_xform(&wasm, &[], false, "allocate", "deallocate")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "missing type section");
+    }
+}
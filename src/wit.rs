@@ -0,0 +1,426 @@
+use crate::parser::{Enum, EnumEntry, Function, Interface, Object, Type};
+use genco::prelude::*;
+use heck::*;
+use std::fmt;
+
+/// Wit has no genco language, its syntax is close enough to c.
+pub type Tokens = c::Tokens;
+
+// https://github.com/WebAssembly/component-model/blob/main/design/mvp/WIT.md#keywords
+pub(crate) static RESERVED_IDENTIFIERS: [&str; 42] = [
+    "as",
+    "async",
+    "bool",
+    "borrow",
+    "char",
+    "constructor",
+    "enum",
+    "export",
+    "f32",
+    "f64",
+    "flags",
+    "from",
+    "func",
+    "future",
+    "import",
+    "include",
+    "interface",
+    "list",
+    "option",
+    "own",
+    "package",
+    "record",
+    "resource",
+    "result",
+    "s16",
+    "s32",
+    "s64",
+    "s8",
+    "static",
+    "stream",
+    "string",
+    "tuple",
+    "type",
+    "u16",
+    "u32",
+    "u64",
+    "u8",
+    "use",
+    "variant",
+    "with",
+    "world",
+    "error-context",
+];
+
+/// An item left out of the wit world, because its signature uses a type
+/// without a wit equivalent.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Unsupported {
+    /// Path of the item, e.g. `Greeter::greet`.
+    pub item: String,
+    /// The offending type.
+    pub ty: Type,
+    /// Line of the declaration in the interface description.
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`{}` uses `{}`, which has no wit equivalent",
+            self.item, self.ty
+        )?;
+        if let Some(line) = self.line {
+            write!(f, " (line {})", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Translates an interface into a wit world for the component model.
+///
+/// Objects become resources, enums variants and errors strings. `usize` and
+/// `isize` are widened to 64bit and `Option<()>` becomes a `bool`. Functions
+/// using iterators, futures, streams, buffers or mutable slices are skipped
+/// and returned by [`WitGenerator::generate`].
+pub struct WitGenerator {
+    package: String,
+    world: String,
+}
+
+impl WitGenerator {
+    /// Generates the interface `api` in `package`, exported by `world`.
+    pub fn new(package: String, world: String) -> Self {
+        Self { package, world }
+    }
+
+    pub fn generate(&self, iface: Interface) -> (Tokens, Vec<Unsupported>) {
+        let iface = iface.renamed();
+        let mut unsupported = vec![];
+        let objects: Vec<_> = iface
+            .objects
+            .iter()
+            .map(|obj| self.generate_object(&iface, obj, &mut unsupported))
+            .collect();
+        let functions: Vec<_> = iface
+            .functions
+            .iter()
+            .map(|func| self.generate_function(&iface, None, func, &mut unsupported))
+            .collect();
+        let tokens = quote! {
+            $("// AUTO GENERATED FILE, DO NOT EDIT.")
+            $("//")
+            $("// Generated by \"ffi-gen\".")
+
+            package $(&self.package);
+
+            $(self.generate_doc(&iface.doc))
+            interface api {
+                $(for obj in objects join ($['\n']) => $obj)
+                $(if !iface.objects.is_empty() && !iface.enums.is_empty() { $['\n'] })
+                $(for e in &iface.enums join ($['\n']) => $(self.generate_enum(&iface, e)))
+                $(if !iface.objects.is_empty() || !iface.enums.is_empty() { $['\n'] })
+                $(for func in functions join ($['\r']) => $func)
+            }
+
+            world $(self.ident(&self.world)) {
+                export api;
+            }
+        };
+        (tokens, unsupported)
+    }
+
+    fn generate_doc(&self, doc: &[String]) -> Tokens {
+        quote!($(for line in doc => $(format!("/// {}", line))$['\r']))
+    }
+
+    fn generate_object(
+        &self,
+        iface: &Interface,
+        obj: &Object,
+        unsupported: &mut Vec<Unsupported>,
+    ) -> Tokens {
+        let methods: Vec<_> = obj
+            .methods
+            .iter()
+            .map(|method| self.generate_function(iface, Some(obj), method, unsupported))
+            .collect();
+        quote! {
+            $(self.generate_doc(&obj.doc))
+            resource $(self.ident(&obj.ident)) {
+                $(for method in methods join ($['\r']) => $method)
+            }
+        }
+    }
+
+    fn generate_enum(&self, iface: &Interface, e: &Enum) -> Tokens {
+        quote! {
+            $(self.generate_doc(&e.doc))
+            variant $(self.ident(&e.ident)) {
+                $(for entry in &e.entries => $(self.generate_entry(iface, entry))$['\r'])
+            }
+        }
+    }
+
+    fn generate_entry(&self, iface: &Interface, entry: &EnumEntry) -> String {
+        match &entry.inner {
+            Some(inner) => format!(
+                "{}({}),",
+                self.ident(&entry.name),
+                self.ty(iface, inner, false).unwrap()
+            ),
+            None => format!("{},", self.ident(&entry.name)),
+        }
+    }
+
+    fn generate_function(
+        &self,
+        iface: &Interface,
+        obj: Option<&Object>,
+        func: &Function,
+        unsupported: &mut Vec<Unsupported>,
+    ) -> Tokens {
+        let item = match obj {
+            Some(obj) => format!("{}::{}", obj.ident, func.ident),
+            None => func.ident.clone(),
+        };
+        match self.signature(iface, obj, func) {
+            Ok(signature) => quote! {
+                $(self.generate_doc(&func.doc))
+                $signature
+            },
+            Err(ty) => {
+                let skipped = Unsupported {
+                    item,
+                    ty,
+                    line: func.line,
+                };
+                let comment = format!("// skipped: {}", skipped);
+                unsupported.push(skipped);
+                quote!($comment)
+            }
+        }
+    }
+
+    fn signature(
+        &self,
+        iface: &Interface,
+        obj: Option<&Object>,
+        func: &Function,
+    ) -> Result<Tokens, Type> {
+        let mut args = vec![];
+        for (name, ty) in &func.args {
+            args.push(format!(
+                "{}: {}",
+                self.ident(name),
+                self.ty(iface, ty, true)?
+            ));
+        }
+        let args = args.join(", ");
+        let ret = match &func.ret {
+            None => None,
+            Some(Type::Tuple(tys)) if tys.is_empty() => None,
+            Some(ty) => Some(self.ty(iface, ty, false)?),
+        };
+        let constructor = match (obj, &func.ret) {
+            (Some(obj), Some(Type::Ident(ret))) => {
+                func.is_static && func.ident == "new" && ret == &obj.ident
+            }
+            _ => false,
+        };
+        let name = self.ident(&func.ident);
+        let kind = if obj.is_some() && func.is_static {
+            "static func"
+        } else {
+            "func"
+        };
+        Ok(if constructor {
+            quote!(constructor($args);)
+        } else if let Some(ret) = ret {
+            quote!($name: $kind($args) -> $ret;)
+        } else {
+            quote!($name: $kind($args);)
+        })
+    }
+
+    /// Translates a type, arguments borrow referenced objects while they can't
+    /// be returned. Returns the type without a wit equivalent on failure.
+    fn ty(&self, iface: &Interface, ty: &Type, arg: bool) -> Result<String, Type> {
+        Ok(match ty {
+            Type::U8 => "u8".into(),
+            Type::U16 => "u16".into(),
+            Type::U32 => "u32".into(),
+            Type::U64 | Type::Usize => "u64".into(),
+            Type::I8 => "s8".into(),
+            Type::I16 => "s16".into(),
+            Type::I32 => "s32".into(),
+            Type::I64 | Type::Isize => "s64".into(),
+            Type::Bool => "bool".into(),
+            Type::F32 => "f32".into(),
+            Type::F64 => "f64".into(),
+            Type::String => "string".into(),
+            Type::Ident(ident) => self.ident(ident),
+            Type::Ref(inner) => match &**inner {
+                Type::Ident(ident) if iface.is_object(ident) => {
+                    if !arg {
+                        return Err(ty.clone());
+                    }
+                    format!("borrow<{}>", self.ident(ident))
+                }
+                Type::Slice(elem) => {
+                    let elem = Type::Ref(elem.clone());
+                    format!("list<{}>", self.ty(iface, &elem, arg)?)
+                }
+                Type::Buffer(_) => return Err((**inner).clone()),
+                _ => self.ty(iface, inner, arg)?,
+            },
+            Type::Slice(inner) | Type::Vec(inner) => {
+                format!("list<{}>", self.ty(iface, inner, arg)?)
+            }
+            Type::Option(inner) => match &**inner {
+                Type::Tuple(tys) if tys.is_empty() => "bool".into(),
+                _ => format!("option<{}>", self.ty(iface, inner, arg)?),
+            },
+            Type::Result(inner) => match &**inner {
+                Type::Tuple(tys) if tys.is_empty() => "result<_, string>".into(),
+                _ => format!("result<{}, string>", self.ty(iface, inner, arg)?),
+            },
+            Type::Tuple(tys) if !tys.is_empty() => {
+                let mut elems = vec![];
+                for ty in tys {
+                    elems.push(self.ty(iface, ty, arg)?);
+                }
+                format!("tuple<{}>", elems.join(", "))
+            }
+            Type::Tuple(_)
+            | Type::RefMut(_)
+            | Type::Buffer(_)
+            | Type::Iter(_)
+            | Type::Future(_)
+            | Type::Stream(_) => return Err(ty.clone()),
+        })
+    }
+
+    fn ident(&self, s: &str) -> String {
+        let ident = s.to_kebab_case();
+        if RESERVED_IDENTIFIERS.contains(&ident.as_str()) {
+            format!("%{}", ident)
+        } else {
+            ident
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn generate(iface: &str) -> Result<(String, Vec<Unsupported>)> {
+        let iface = Interface::parse(iface)?;
+        let gen = WitGenerator::new("effektio:api".into(), "bindings".into());
+        let (tokens, unsupported) = gen.generate(iface);
+        Ok((tokens.to_file_string()?, unsupported))
+    }
+
+    #[test]
+    fn test_world() -> Result<()> {
+        let (wit, unsupported) = generate(
+            r#"
+            //! Counting things.
+            /// Counts.
+            object Counter {
+                static fn new(start: u32) -> Counter;
+                static fn zero() -> Counter;
+                fn inc(by: usize);
+                fn get() -> u32;
+                fn merge(other: &Counter) -> Result<()>;
+            }
+            enum Shape {
+                Square,
+                Circle(Counter)
+            }
+            fn hello(name: &string) -> string;
+            fn evens(n: u32) -> Vec<u32>;
+            fn sum(values: &[Counter]) -> Option<i64>;
+            fn split(n: u32) -> (u32, f64);
+            fn present(flag: bool) -> Option<()>;
+            fn type(shape: Shape) -> Result<u8>;
+            "#,
+        )?;
+        assert_eq!(
+            wit,
+            r#"// AUTO GENERATED FILE, DO NOT EDIT.
+//
+// Generated by "ffi-gen".
+
+package effektio:api;
+
+/// Counting things.
+interface api {
+    /// Counts.
+    resource counter {
+        constructor(start: u32);
+        zero: static func() -> counter;
+        inc: func(by: u64);
+        get: func() -> u32;
+        merge: func(other: borrow<counter>) -> result<_, string>;
+    }
+
+    variant shape {
+        square,
+        circle(counter),
+    }
+
+    hello: func(name: string) -> string;
+    evens: func(n: u32) -> list<u32>;
+    sum: func(values: list<borrow<counter>>) -> option<s64>;
+    split: func(n: u32) -> tuple<u32, f64>;
+    present: func(flag: bool) -> bool;
+    %type: func(shape: shape) -> result<u8, string>;
+}
+
+world bindings {
+    export api;
+}
+"#
+        );
+        assert!(unsupported.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported() -> Result<()> {
+        let (wit, unsupported) = generate(
+            r#"
+            object Items {
+                fn first() -> &Items;
+                fn values() -> Iterator<string>;
+            }
+            fn items() -> Items;
+            fn fill(buf: &mut [u8]);
+            fn sum(data: &buffer<u32>) -> u32;
+            fn numbers() -> Stream<u32>;
+            fn create() -> Future<u32>;
+            "#,
+        )?;
+        let unsupported: Vec<_> = unsupported.iter().map(|u| u.to_string()).collect();
+        assert_eq!(
+            unsupported,
+            vec![
+                "`Items::first` uses `&Items`, which has no wit equivalent (line 3)",
+                "`Items::values` uses `Iterator<string>`, which has no wit equivalent (line 4)",
+                "`fill` uses `&mut [u8]`, which has no wit equivalent (line 7)",
+                "`sum` uses `buffer<u32>`, which has no wit equivalent (line 8)",
+                "`numbers` uses `Stream<u32>`, which has no wit equivalent (line 9)",
+                "`create` uses `Future<u32>`, which has no wit equivalent (line 10)",
+            ]
+        );
+        assert!(wit.contains("    items: func() -> items;\n"));
+        assert!(wit.contains(
+            "    // skipped: `numbers` uses `Stream<u32>`, which has no wit equivalent (line 9)\n"
+        ));
+        Ok(())
+    }
+}
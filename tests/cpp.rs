@@ -0,0 +1,314 @@
+use ffi_gen::compile_pass_cpp;
+
+compile_pass_cpp! {
+    strings_and_slices,
+    r#"fn hello(name: &string) -> string;
+    fn total_len(strs: &[string]) -> usize;
+    fn fill(buf: &mut [u8]);
+    "#,
+    (
+        pub fn hello(name: &str) -> String {
+            format!("hello {}", name)
+        }
+
+        pub fn total_len(strs: &[&str]) -> usize {
+            strs.iter().map(|s| s.len()).sum()
+        }
+
+        pub fn fill(buf: &mut [u8]) {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = i as u8 * 2;
+            }
+        }
+    ),
+    (
+        std::string name = "world";
+        assert(api::hello(std::string_view(name).substr(0, 3)) == "hello wor");
+        assert(api::hello("") == "hello ");
+        assert(api::total_len({"ab", "cde", ""}) == 5);
+        std::vector<uint8_t> buf(4);
+        api::fill(buf);
+        assert((buf == std::vector<uint8_t>{0, 2, 4, 6}));
+    )
+}
+
+compile_pass_cpp! {
+    exceptions_and_optionals,
+    r#"fn positive(num: i64) -> Result<i64>;
+    fn unwrap_or_len(res: Result<u32>) -> u32;
+    fn non_zero(num: i64) -> Option<i64>;
+    fn present(flag: bool) -> Option<()>;
+    fn split(n: u32) -> (u32, f64);
+    "#,
+    (
+        pub fn positive(num: i64) -> Result<i64, &'static str> {
+            if num > 0 {
+                Ok(num)
+            } else {
+                Err("not positive")
+            }
+        }
+
+        pub fn unwrap_or_len(res: Result<u32, String>) -> u32 {
+            match res {
+                Ok(n) => n,
+                Err(err) => err.len() as u32,
+            }
+        }
+
+        pub fn non_zero(num: i64) -> Option<i64> {
+            if num == 0 {
+                None
+            } else {
+                Some(num)
+            }
+        }
+
+        pub fn present(flag: bool) -> Option<()> {
+            if flag {
+                Some(())
+            } else {
+                None
+            }
+        }
+
+        pub fn split(n: u32) -> (u32, f64) {
+            (n / 2, n as f64 / 2.0)
+        }
+    ),
+    (
+        assert(api::positive(7) == 7);
+        try {
+            api::positive(0);
+            assert(false);
+        } catch (const std::runtime_error &err) {
+            assert(std::string(err.what()) == "not positive");
+        }
+        assert(api::unwrap_or_len(api::Result<uint32_t>::ok(42)) == 42);
+        assert(api::unwrap_or_len(api::Result<uint32_t>::err("four")) == 4);
+        assert(!api::non_zero(0).has_value());
+        assert(api::non_zero(-3) == -3);
+        assert((api::present(true) == std::tuple<>()));
+        assert(!api::present(false).has_value());
+        auto [half, exact] = api::split(5);
+        assert(half == 2 && exact == 2.5);
+    )
+}
+
+compile_pass_cpp! {
+    raii,
+    r#"object Counter {
+        static fn new(start: u32) -> Counter;
+        fn inc(by: u32);
+        fn get() -> u32;
+    }
+    fn total(counters: &[Counter]) -> u32;
+    fn dropped() -> u32;
+    "#,
+    (
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+        pub struct Counter(std::cell::Cell<u32>);
+
+        impl Counter {
+            pub fn new(start: u32) -> Self {
+                Self(std::cell::Cell::new(start))
+            }
+
+            pub fn inc(&self, by: u32) {
+                self.0.set(self.0.get() + by);
+            }
+
+            pub fn get(&self) -> u32 {
+                self.0.get()
+            }
+        }
+
+        impl Drop for Counter {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        pub fn total(counters: &[&Counter]) -> u32 {
+            counters.iter().map(|c| c.0.get()).sum()
+        }
+
+        pub fn dropped() -> u32 {
+            DROPPED.load(Ordering::SeqCst)
+        }
+    ),
+    (
+        auto counter = api::Counter::new_(40);
+        counter.inc(2);
+        assert(counter.get() == 42);
+        {
+            auto other = api::Counter::new_(1);
+            assert(api::total({&counter, &other}) == 43);
+        }
+        assert(api::dropped() == 1);
+        auto moved = std::move(counter);
+        try {
+            counter.get();
+            assert(false);
+        } catch (const std::logic_error &) {
+        }
+        {
+            auto temporary = std::move(moved);
+        }
+        assert(api::dropped() == 2);
+    )
+}
+
+compile_pass_cpp! {
+    futures_and_streams,
+    r#"fn delayed(value: u32) -> Future<Result<u32>>;
+    fn values(values: &[u32]) -> Stream<u32>;
+    "#,
+    (
+        use crate::api::Stream;
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Poll};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        struct Delayed(Arc<Mutex<bool>>, Option<u32>);
+
+        impl Future for Delayed {
+            type Output = u32;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+                if *self.0.lock().unwrap() {
+                    return Poll::Ready(self.1.take().unwrap());
+                }
+                let ready = self.0.clone();
+                let waker = cx.waker().clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(10));
+                    *ready.lock().unwrap() = true;
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+        }
+
+        pub async fn delayed(value: u32) -> Result<u32, &'static str> {
+            match Delayed(Arc::new(Mutex::new(false)), Some(value)).await {
+                0 => Err("is zero"),
+                value => Ok(value),
+            }
+        }
+
+        struct Values(Vec<u32>);
+
+        impl Stream for Values {
+            type Item = u32;
+
+            fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+                Poll::Ready(self.0.pop())
+            }
+        }
+
+        pub fn values(values: &[u32]) -> impl Stream<Item = u32> {
+            Values(values.iter().rev().copied().collect())
+        }
+    ),
+    (
+        auto first = api::delayed(1);
+        auto second = api::delayed(2);
+        assert(first.get() == 1 && second.get() == 2);
+        try {
+            api::delayed(0).get();
+            assert(false);
+        } catch (const std::runtime_error &err) {
+            assert(std::string(err.what()) == "is zero");
+        }
+
+        std::vector<uint32_t> items;
+        std::promise<void> done;
+        api::values({42, 99}).listen(
+            [&](uint32_t item) { items.push_back(item); },
+            nullptr,
+            [&] { done.set_value(); });
+        done.get_future().wait();
+        assert((items == std::vector<uint32_t>{42, 99}));
+    )
+}
+
+compile_pass_cpp! {
+    iterators_buffers_and_lists,
+    r#"object Item {
+        fn id() -> u32;
+    }
+    enum Shape {
+        Square,
+        Circle
+    }
+    fn names() -> Iterator<string>;
+    fn sum(data: &buffer<u32>) -> u32;
+    fn counting(n: usize) -> buffer<u16>;
+    fn items(n: u32) -> Vec<Item>;
+    fn shape(round: bool) -> Shape;
+    "#,
+    (
+        pub struct Item(u32);
+
+        impl Item {
+            pub fn id(&self) -> u32 {
+                self.0
+            }
+        }
+
+        #[derive(Clone)]
+        pub enum Shape {
+            Square,
+            Circle,
+        }
+
+        pub fn names() -> impl Iterator<Item = String> + Send + 'static {
+            vec!["hello".to_string(), "world".to_string()].into_iter()
+        }
+
+        pub fn sum(data: &[u32]) -> u32 {
+            data.iter().sum()
+        }
+
+        pub fn counting(n: usize) -> api::FfiBuffer<u16> {
+            api::FfiBuffer::new((0..n).map(|n| n as u16).collect())
+        }
+
+        pub fn items(n: u32) -> Vec<Item> {
+            (0..n).map(Item).collect()
+        }
+
+        pub fn shape(round: bool) -> Shape {
+            if round {
+                Shape::Circle
+            } else {
+                Shape::Square
+            }
+        }
+    ),
+    (
+        assert((api::names().collect() == std::vector<std::string>{"hello", "world"}));
+        auto buf = api::FfiBufferUint32::allocate(3);
+        buf.data()[0] = 1;
+        buf.data()[1] = 2;
+        buf.data()[2] = 3;
+        assert(api::sum(buf) == 6);
+        auto counted = api::counting(4);
+        std::vector<uint16_t> elements(counted.data(), counted.data() + counted.size());
+        assert((elements == std::vector<uint16_t>{0, 1, 2, 3}));
+        auto items = api::items(3);
+        assert(items.size() == 3);
+        auto first = items.remove(0);
+        assert(first.id() == 0 && items.size() == 2);
+        items.insert(0, std::move(first));
+        assert(items.at(0).id() == 0 && items.at(2).id() == 2);
+        assert(api::shape(true).tag() == api::ShapeTag::Circle);
+        assert(api::shape(false).tag() == api::ShapeTag::Square);
+    )
+}
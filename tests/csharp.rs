@@ -0,0 +1,250 @@
+use ffi_gen::compile_pass_csharp;
+
+compile_pass_csharp! {
+    exceptions,
+    r#"fn positive(num: i64) -> Result<i64>;
+    fn unwrap_or_len(res: Result<u32>) -> u32;
+    fn non_zero(num: i64) -> Option<i64>;
+    fn split(n: u32) -> (u32, f64);
+    "#,
+    (
+        pub fn positive(num: i64) -> Result<i64, &'static str> {
+            if num > 0 {
+                Ok(num)
+            } else {
+                Err("not positive")
+            }
+        }
+
+        pub fn unwrap_or_len(res: Result<u32, String>) -> u32 {
+            match res {
+                Ok(n) => n,
+                Err(err) => err.len() as u32,
+            }
+        }
+
+        pub fn non_zero(num: i64) -> Option<i64> {
+            if num == 0 {
+                None
+            } else {
+                Some(num)
+            }
+        }
+
+        pub fn split(n: u32) -> (u32, f64) {
+            (n / 2, n as f64 / 2.0)
+        }
+    ),
+    (
+        Check(Api.Positive(7) == 7);
+        try {
+            Api.Positive(0);
+            throw new Exception("positive should throw");
+        } catch (FfiException err) {
+            Check(err.Message == "not positive");
+        }
+        Check(Api.UnwrapOrLen(FfiResult<uint>.Ok(42)) == 42);
+        Check(Api.UnwrapOrLen(FfiResult<uint>.Err("four")) == 4);
+        long? missing = Api.NonZero(0);
+        Check(!missing.HasValue && (Api.NonZero(-3) ?? 0) == -3);
+        var (half, exact) = Api.Split(5);
+        Check(half == 2 && exact == 2.5);
+    )
+}
+
+compile_pass_csharp! {
+    safe_handles,
+    r#"object Counter {
+        static fn new(start: u32) -> Counter;
+        fn inc(by: u32);
+        fn get() -> u32;
+    }
+    fn dropped() -> u32;
+    "#,
+    (
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+        pub struct Counter(std::cell::Cell<u32>);
+
+        impl Counter {
+            pub fn new(start: u32) -> Self {
+                Self(std::cell::Cell::new(start))
+            }
+
+            pub fn inc(&self, by: u32) {
+                self.0.set(self.0.get() + by);
+            }
+
+            pub fn get(&self) -> u32 {
+                self.0.get()
+            }
+        }
+
+        impl Drop for Counter {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        pub fn dropped() -> u32 {
+            DROPPED.load(Ordering::SeqCst)
+        }
+    ),
+    (
+        using (var counter = Counter.New(40)) {
+            counter.Inc(2);
+            Check(counter.Get() == 42);
+        }
+        Check(Api.Dropped() == 1);
+        var disposed = Counter.New(0);
+        disposed.Dispose();
+        disposed.Dispose();
+        Check(Api.Dropped() == 2);
+        try {
+            disposed.Get();
+            throw new Exception("get should throw");
+        } catch (ObjectDisposedException) {
+        }
+        static void Leak() {
+            for (uint i = 0; i < 100; i++) {
+                Counter.New(i);
+            }
+        }
+        Leak();
+        GC.Collect();
+        GC.WaitForPendingFinalizers();
+        Check(Api.Dropped() == 102);
+    )
+}
+
+compile_pass_csharp! {
+    tasks,
+    r#"fn delayed(value: u32) -> Future<Result<u32>>;
+    fn values(values: &[u32]) -> Stream<u32>;
+    "#,
+    (
+        use crate::api::Stream;
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Poll};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        struct Delayed(Arc<Mutex<bool>>, Option<u32>);
+
+        impl Future for Delayed {
+            type Output = u32;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+                if *self.0.lock().unwrap() {
+                    return Poll::Ready(self.1.take().unwrap());
+                }
+                let ready = self.0.clone();
+                let waker = cx.waker().clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(10));
+                    *ready.lock().unwrap() = true;
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+        }
+
+        pub async fn delayed(value: u32) -> Result<u32, &'static str> {
+            match Delayed(Arc::new(Mutex::new(false)), Some(value)).await {
+                0 => Err("is zero"),
+                value => Ok(value),
+            }
+        }
+
+        struct Values(Vec<u32>);
+
+        impl Stream for Values {
+            type Item = u32;
+
+            fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+                Poll::Ready(self.0.pop())
+            }
+        }
+
+        pub fn values(values: &[u32]) -> impl Stream<Item = u32> {
+            Values(values.iter().rev().copied().collect())
+        }
+    ),
+    (
+        var both = await Task.WhenAll(Api.Delayed(1), Api.Delayed(2));
+        Check(both.SequenceEqual(new uint[] { 1, 2 }));
+        try {
+            await Api.Delayed(0);
+            throw new Exception("delayed should throw");
+        } catch (FfiException err) {
+            Check(err.Message == "is zero");
+        }
+        Task<uint> pending = Api.Delayed(3);
+        Check(await pending.ContinueWith(task => task.Result * 2) == 6);
+        IAsyncEnumerable<uint> stream = Api.Values(new uint[] { 42, 99 });
+        var items = new List<uint>();
+        await foreach (var item in stream) {
+            items.Add(item);
+        }
+        Check(items.SequenceEqual(new uint[] { 42, 99 }));
+        await foreach (var item in Api.Values(new uint[] { 1, 2, 3 })) {
+            Check(item == 1);
+            break;
+        }
+    )
+}
+
+compile_pass_csharp! {
+    collections,
+    r#"object Item {
+        fn id() -> u32;
+    }
+    fn names() -> Iterator<string>;
+    fn items(n: u32) -> Vec<Item>;
+    fn fill(buf: &mut [u8]);
+    fn counting(n: usize) -> buffer<u16>;
+    "#,
+    (
+        pub struct Item(u32);
+
+        impl Item {
+            pub fn id(&self) -> u32 {
+                self.0
+            }
+        }
+
+        pub fn names() -> impl Iterator<Item = String> + Send + 'static {
+            vec!["hello".to_string(), "world".to_string()].into_iter()
+        }
+
+        pub fn items(n: u32) -> Vec<Item> {
+            (0..n).map(Item).collect()
+        }
+
+        pub fn fill(buf: &mut [u8]) {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = i as u8 * 2;
+            }
+        }
+
+        pub fn counting(n: usize) -> api::FfiBuffer<u16> {
+            api::FfiBuffer::new((0..n).map(|n| n as u16).collect())
+        }
+    ),
+    (
+        Check(Api.Names().Select(name => name.ToUpper()).SequenceEqual(new[] { "HELLO", "WORLD" }));
+        using var list = Api.Items(3);
+        Check(list.Select(item => item.Id()).SequenceEqual(new uint[] { 0, 1, 2 }));
+        var first = list.RemoveAt(0);
+        list.Add(first);
+        Check(list.Last().Id() == 0);
+        var buf = new byte[4];
+        Api.Fill(buf);
+        Check(buf.SequenceEqual(new byte[] { 0, 2, 4, 6 }));
+        using var counted = Api.Counting(4);
+        Check(counted.Elements.ToArray().SequenceEqual(new ushort[] { 0, 1, 2, 3 }));
+    )
+}
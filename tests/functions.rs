@@ -291,6 +291,112 @@ compile_pass! {
     })
 }
 
+compile_pass! {
+    args_slice_usize_ret_vec_isize,
+    r#"fn negate(b: &[usize]) -> Vec<isize>;
+    fn split(n: usize) -> (usize, isize);"#,
+    (
+        pub fn negate(b: &[usize]) -> Vec<isize> {
+            b.iter().map(|n| -(*n as isize)).collect()
+        }
+
+        pub fn split(n: usize) -> (usize, isize) {
+            (n / 2, -((n % 2) as isize))
+        }
+    ),
+    (
+        let ret = __split(5);
+        assert_eq!((ret.ret0, ret.ret1), (2, -1));
+    ),
+    (
+        assert(api.negate([]).equals([]));
+        assert(api.negate([0, 1, 2]).equals([0, -1, -2]));
+        assert(api.split(5).equals([2, -1]));
+    ),
+    (
+        assert.deepEqual(api.negate([]), []);
+        assert.deepEqual(api.negate([0, 1, 2]), [0, -1, -2]);
+        assert.deepEqual(api.split(5), [2, -1]);
+    ),
+    (
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        negate(b: Array<number>): Array<number>;
+
+        split(n: number): [number, number];
+    })
+}
+
+compile_pass! {
+    args_buffer_usize,
+    r#"fn sum(b: &buffer<usize>) -> usize;
+    fn fill(b: &mut [isize]);"#,
+    (
+        pub fn sum(b: &[usize]) -> usize {
+            b.iter().sum()
+        }
+
+        pub fn fill(b: &mut [isize]) {
+            for (i, n) in b.iter_mut().enumerate() {
+                *n = -(i as isize);
+            }
+        }
+    ),
+    (
+        unsafe {
+            let buf = __ffi_buffer_new((3 * std::mem::size_of::<usize>()) as _, 8);
+            let addr = __ffi_buffer_address(buf as _) as *mut usize;
+            std::slice::from_raw_parts_mut(addr, 3).copy_from_slice(&[1, 2, 39]);
+            assert_eq!(__sum(buf as _), 42);
+            drop_box_FfiBuffer(0, buf);
+        }
+        let mut nums = vec![1isize; 3];
+        __fill(nums.as_mut_ptr() as _, nums.len() as _);
+        assert_eq!(nums, [0, -1, -2]);
+    ),
+    (
+        final buf = FfiBufferUintPtr.allocate(api, 3);
+        buf.asTypedList().setAll(0, [1, 2, 39]);
+        assert(api.sum(buf) == 42);
+        buf.drop();
+
+        final nums = Int64List(3);
+        api.fill(nums);
+        assert(nums[2] == -2);
+    ),
+    (
+        const buf = FfiBufferUintPtr.allocate(api, 3);
+        buf.asTypedArray().set([1, 2, 39]);
+        assert.equal(api.sum(buf), 42);
+        buf.drop();
+
+        const nums = new Int32Array(3);
+        api.fill(nums);
+        assert.deepEqual([...nums], [0, -1, -2]);
+    ),
+    (
+    export class FfiBufferUintPtr {
+        static allocate(api: Api, length: number): FfiBufferUintPtr;
+
+        asTypedArray(): Uint32Array;
+
+        drop(): void;
+    }
+
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        sum(b: FfiBufferUintPtr): number;
+
+        fill(b: Int32Array): void;
+    })
+}
+
 compile_pass! {
     args_i64_ret_opt_i64,
     "fn non_zero(num: i64) -> Option<i64>;",
@@ -641,6 +747,307 @@ compile_pass! {
     })
 }
 
+compile_pass! {
+    arg_res_u32_ret_u32,
+    "fn unwrap_or_len(res: Result<u32>) -> u32;",
+    (
+        pub fn unwrap_or_len(res: Result<u32, String>) -> u32 {
+            match res {
+                Ok(n) => n,
+                Err(err) => err.len() as _,
+            }
+        }
+    ),
+    (
+        use core::mem::ManuallyDrop;
+        assert_eq!(__unwrap_or_len(1, 0, 0, 0, 42), 42);
+        let s = ManuallyDrop::new("error".to_string());
+        assert_eq!(__unwrap_or_len(0, s.as_ptr() as _, s.len() as _, s.capacity() as _, 0), 5);
+    ),
+    (
+        assert(api.unwrapOrLen(FfiResult.ok(42)) == 42);
+        assert(api.unwrapOrLen(FfiResult.err("error")) == 5);
+    ),
+    (
+        assert.equal(api.unwrapOrLen(ok(42)), 42);
+        assert.equal(api.unwrapOrLen(err("error")), 5);
+        assert.equal(api.unwrapOrLen(err(new Error("failed"))), 6);
+    ),
+    (
+    export type FfiResult<T> = { ok: true, value: T } | { ok: false, error: string };
+
+    export function ok<T>(value: T): FfiResult<T>;
+
+    export function err<T>(error: Error | string): FfiResult<T>;
+
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        unwrapOrLen(res: FfiResult<number>): number;
+    })
+}
+
+compile_pass! {
+    arg_res_typed_error,
+    "fn code(res: Result<()>) -> u32;",
+    (
+        pub struct ErrorCode(u32);
+
+        impl From<String> for ErrorCode {
+            fn from(msg: String) -> Self {
+                Self(msg.parse().unwrap_or(1))
+            }
+        }
+
+        pub fn code(res: core::result::Result<(), ErrorCode>) -> u32 {
+            match res {
+                Ok(()) => 0,
+                Err(ErrorCode(code)) => code,
+            }
+        }
+    ),
+    (
+        use core::mem::ManuallyDrop;
+        assert_eq!(__code(1, 0, 0, 0), 0);
+        let s = ManuallyDrop::new("42".to_string());
+        assert_eq!(__code(0, s.as_ptr() as _, s.len() as _, s.capacity() as _), 42);
+    ),
+    (
+        assert(api.code(FfiResult.ok(null)) == 0);
+        assert(api.code(FfiResult.err("42")) == 42);
+        assert(api.code(FfiResult.err("failed")) == 1);
+    ),
+    (
+        assert.equal(api.code(ok()), 0);
+        assert.equal(api.code(err("42")), 42);
+        assert.equal(api.code(err(new Error("failed"))), 1);
+    ),
+    (
+    export type FfiResult<T> = { ok: true, value: T } | { ok: false, error: string };
+
+    export function ok<T>(value: T): FfiResult<T>;
+
+    export function err<T>(error: Error | string): FfiResult<T>;
+
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        code(res: FfiResult<void>): number;
+    })
+}
+
+compile_pass! {
+    args_buffer,
+    "fn sum(data: &buffer<u8>) -> u32;
+    fn invert(data: &buffer<u8>);
+    fn total(data: buffer<u32>) -> u32;",
+    (
+        pub fn sum(data: &[u8]) -> u32 {
+            data.iter().map(|b| *b as u32).sum()
+        }
+
+        pub fn invert(data: &mut [u8]) {
+            for b in data {
+                *b = !*b;
+            }
+        }
+
+        pub fn total(data: api::FfiBuffer<u32>) -> u32 {
+            data.iter().sum()
+        }
+    ),
+    (
+        unsafe {
+            let buf = __ffi_buffer_new(3, 1);
+            let addr = __ffi_buffer_address(buf as _) as *mut u8;
+            std::slice::from_raw_parts_mut(addr, 3).copy_from_slice(&[1, 2, 3]);
+            assert_eq!(__sum(buf as _), 6);
+            __invert(buf as _);
+            assert_eq!(std::slice::from_raw_parts(addr, 3), &[254, 253, 252]);
+            drop_box_FfiBuffer(0, buf);
+
+            let buf = __ffi_buffer_new(8, 4);
+            let addr = __ffi_buffer_address(buf as _) as *mut u32;
+            std::slice::from_raw_parts_mut(addr, 2).copy_from_slice(&[20, 22]);
+            assert_eq!(__total(buf as _), 42);
+        }
+    ),
+    (
+        final buf = FfiBufferUint8.allocate(api, 3);
+        buf.asTypedList().setAll(0, [1, 2, 3]);
+        assert(api.sum(buf) == 6);
+        api.invert(buf);
+        assert(buf.asTypedList()[0] == 254);
+        buf.drop();
+
+        final nums = FfiBufferUint32.allocate(api, 2);
+        nums.asTypedList().setAll(0, [20, 22]);
+        assert(api.total(nums) == 42);
+    ),
+    (
+        const buf = FfiBufferUint8.allocate(api, 3);
+        buf.asTypedArray().set([1, 2, 3]);
+        assert.equal(api.sum(buf), 6);
+        api.invert(buf);
+        assert.deepEqual([...buf.asTypedArray()], [254, 253, 252]);
+        buf.drop();
+
+        const nums = FfiBufferUint32.allocate(api, 2);
+        nums.asTypedArray().set([20, 22]);
+        assert.equal(api.total(nums), 42);
+        assert.throws(() => nums.asTypedArray(), /use after move/);
+    ),
+    (
+    export class FfiBufferUint8 {
+        static allocate(api: Api, length: number): FfiBufferUint8;
+
+        asTypedArray(): Uint8Array;
+
+        drop(): void;
+    }
+
+    export class FfiBufferUint32 {
+        static allocate(api: Api, length: number): FfiBufferUint32;
+
+        asTypedArray(): Uint32Array;
+
+        drop(): void;
+    }
+
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        sum(data: FfiBufferUint8): number;
+
+        invert(data: FfiBufferUint8): void;
+
+        total(data: FfiBufferUint32): number;
+    })
+}
+
+compile_pass! {
+    args_mut_slice,
+    "fn fill(buf: &mut [u8]) -> usize;
+    fn gain(samples: &mut [f32], factor: f32);",
+    (
+        pub fn fill(buf: &mut [u8]) -> usize {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = i as u8 + 1;
+            }
+            buf.len()
+        }
+
+        pub fn gain(samples: &mut [f32], factor: f32) {
+            for s in samples {
+                *s *= factor;
+            }
+        }
+    ),
+    (
+        let mut buf = vec![0u8; 4];
+        assert_eq!(__fill(buf.as_mut_ptr() as _, buf.len() as _), 4);
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        let mut samples = vec![1.0f32, -0.5];
+        __gain(samples.as_mut_ptr() as _, samples.len() as _, 2.0);
+        assert_eq!(samples, [2.0, -1.0]);
+    ),
+    (
+        final buf = Uint8List(4);
+        assert(api.fill(buf) == 4);
+        assert(buf[3] == 4);
+
+        final samples = Float32List.fromList([1.0, -0.5]);
+        api.gain(samples, 2.0);
+        assert(samples[1] == -1.0);
+    ),
+    (
+        const buf = new Uint8Array(4);
+        assert.equal(api.fill(buf), 4);
+        assert.deepEqual([...buf], [1, 2, 3, 4]);
+
+        const shared = FfiBufferUint8.allocate(api, 2);
+        assert.equal(api.fill(shared.asTypedArray()), 2);
+        assert.deepEqual([...shared.asTypedArray()], [1, 2]);
+        shared.drop();
+
+        const samples = new Float32Array([1.0, -0.5]);
+        api.gain(samples, 2.0);
+        assert.deepEqual([...samples], [2.0, -1.0]);
+    ),
+    (
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        fill(buf: Uint8Array): number;
+
+        gain(samples: Float32Array, factor: number): void;
+    })
+}
+
+compile_pass! {
+    args_mut_slice_view,
+    "fn view_addr(buf: &mut [u8]) -> usize;
+    fn buffer_addr(buf: &buffer<u8>) -> usize;",
+    (
+        pub fn view_addr(buf: &mut [u8]) -> usize {
+            buf.as_ptr() as usize
+        }
+
+        pub fn buffer_addr(buf: &[u8]) -> usize {
+            buf.as_ptr() as usize
+        }
+    ),
+    (
+        let mut buf = vec![0u8; 2];
+        assert_eq!(__view_addr(buf.as_mut_ptr() as _, buf.len() as _) as usize, buf.as_ptr() as usize);
+    ),
+    (
+        final shared = FfiBufferUint8.allocate(api, 2);
+        assert(api.viewAddr(shared.asTypedList()) == api.bufferAddr(shared));
+        assert(api.viewAddr(Uint8List(2)) != api.bufferAddr(shared));
+        final view = shared.asTypedList();
+        shared.drop();
+        try {
+            api.viewAddr(view);
+            assert(false);
+        } on StateError {
+        }
+    ),
+    (
+        const shared = FfiBufferUint8.allocate(api, 2);
+        assert.equal(api.viewAddr(shared.asTypedArray()), api.bufferAddr(shared));
+        assert.notEqual(api.viewAddr(new Uint8Array(2)), api.bufferAddr(shared));
+        shared.drop();
+    ),
+    (
+    export class FfiBufferUint8 {
+        static allocate(api: Api, length: number): FfiBufferUint8;
+
+        asTypedArray(): Uint8Array;
+
+        drop(): void;
+    }
+
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        viewAddr(buf: Uint8Array): number;
+
+        bufferAddr(buf: FfiBufferUint8): number;
+    })
+}
+
 compile_pass! {
     js_sanitize_identifiers,
     "fn fun(package: i32, await: bool);",
@@ -712,3 +1119,161 @@ compile_pass! {
         fallible(): void;
     })
 }*/
+
+#[test]
+fn signature_mismatch() {
+    let stderr = ffi_gen::test_runner::compile_fail_rust(
+        "fn hello_world();\nfn add(a: u8, b: u8) -> u8;\nfn greet(name: &string) -> string;",
+        genco::quote!(
+            pub fn hello_world() {}
+
+            pub fn greet(name: String) -> String {
+                name
+            }
+
+            pub fn add(a: u8, b: String) -> u8 {
+                a
+            }
+        ),
+    )
+    .unwrap();
+    assert!(
+        stderr.contains("api.rsh:2: expected signature `fn add(a: u8, b: u8) -> u8` for `add`"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("is not implemented for fn item `fn(u8, String) -> u8 {add}`"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(
+            "api.rsh:3: expected signature `fn greet(name: &string) -> string` for `greet`"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        !stderr.contains("expected signature `fn hello_world()"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn borrowed_arg_mismatch() {
+    let stderr = ffi_gen::test_runner::compile_fail_rust(
+        "fn create() -> Greeter;\nobject Greeter {\n    fn greet(other: &Greeter, names: &[string]) -> string;\n}",
+        genco::quote!(
+            pub fn create() -> Greeter {
+                Greeter
+            }
+
+            pub struct Greeter;
+
+            impl Greeter {
+                pub fn greet(&self, other: Greeter, names: Vec<String>) -> String {
+                    names.join(", ")
+                }
+            }
+        ),
+    )
+    .unwrap();
+    assert!(
+        stderr.contains(
+            "api.rsh:3: expected signature `fn greet(other: &Greeter, names: &[string]) -> string` for `Greeter::greet`"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(
+            "is not implemented for fn item `for<'a> fn(&'a Greeter, Greeter, Vec<String>) -> String {Greeter::greet}`"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        !stderr.contains("expected signature `fn create()"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn bounded_mismatch() {
+    let stderr = ffi_gen::test_runner::compile_fail_rust(
+        "fn names() -> Iterator<string>;\nfn parse(s: &string) -> Result<u32>;\nfn sum(data: &buffer<u32>) -> u32;\nfn counter() -> Counter;\nobject Counter {\n    fn inc(by: u32);\n}",
+        genco::quote!(
+            pub fn names() -> Vec<u32> {
+                vec![]
+            }
+
+            pub fn parse(s: &str) -> Result<u32, ()> {
+                s.parse().map_err(|_| ())
+            }
+
+            pub fn sum(data: &[u8]) -> u32 {
+                data.len() as u32
+            }
+
+            pub fn counter() -> Counter {
+                Counter(0)
+            }
+
+            pub struct Counter(u64);
+
+            impl Counter {
+                pub fn inc(&mut self, by: u64) {
+                    self.0 += by;
+                }
+            }
+        ),
+    )
+    .unwrap();
+    for expected in [
+        "api.rsh:1: expected signature `fn names() -> Iterator<string>` for `names`",
+        "api.rsh:2: expected signature `fn parse(s: &string) -> Result<u32>` for `parse`",
+        "api.rsh:3: expected signature `fn sum(data: &buffer<u32>) -> u32` for `sum`",
+    ] {
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+    assert!(
+        !stderr.contains("for `Counter::inc`") && !stderr.contains("for `counter`"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn pointer_sized_native32() {
+    ffi_gen::test_runner::compile_pass_rust_abi(
+        r#"fn split(n: usize) -> (usize, isize);
+        fn negate(b: &[usize]) -> Vec<isize>;
+        fn sum(b: &buffer<usize>) -> usize;
+        fn fill(b: &mut [isize]);"#,
+        ffi_gen::Abi::Native32,
+        "wasm32-unknown-unknown",
+        genco::quote!(
+            pub fn split(n: usize) -> (usize, isize) {
+                (n / 2, -((n % 2) as isize))
+            }
+
+            pub fn negate(b: &[usize]) -> Vec<isize> {
+                b.iter().map(|n| -(*n as isize)).collect()
+            }
+
+            pub fn sum(b: &[usize]) -> usize {
+                b.iter().sum()
+            }
+
+            pub fn fill(b: &mut [isize]) {
+                for (i, n) in b.iter_mut().enumerate() {
+                    *n = -(i as isize);
+                }
+            }
+        ),
+    )
+    .unwrap();
+}
@@ -0,0 +1,257 @@
+use ffi_gen::compile_pass_go;
+
+compile_pass_go! {
+    multiple_returns,
+    r#"fn positive(num: i64) -> Result<i64>;
+    fn check_positive(num: i64) -> Result<()>;
+    fn unwrap_or_len(res: Result<u32>) -> u32;
+    fn non_zero(num: i64) -> Option<i64>;
+    fn split(n: u32) -> (u32, f64);
+    "#,
+    (
+        pub fn positive(num: i64) -> Result<i64, &'static str> {
+            if num > 0 {
+                Ok(num)
+            } else {
+                Err("not positive")
+            }
+        }
+
+        pub fn check_positive(num: i64) -> Result<(), &'static str> {
+            positive(num).map(|_| ())
+        }
+
+        pub fn unwrap_or_len(res: Result<u32, String>) -> u32 {
+            match res {
+                Ok(n) => n,
+                Err(err) => err.len() as u32,
+            }
+        }
+
+        pub fn non_zero(num: i64) -> Option<i64> {
+            if num == 0 {
+                None
+            } else {
+                Some(num)
+            }
+        }
+
+        pub fn split(n: u32) -> (u32, f64) {
+            (n / 2, n as f64 / 2.0)
+        }
+    ),
+    (
+        if value, err := api.Positive(7); err != nil || value != 7 {
+            panic("positive(7) failed")
+        }
+        _, err := api.Positive(0)
+        check(err != nil && err.Error() == "not positive")
+        ffiErr, ok := err.(*api.FfiError)
+        check(ok && ffiErr.Message == "not positive")
+        check(api.CheckPositive(1) == nil && api.CheckPositive(-1) != nil)
+        check(api.UnwrapOrLen(api.FfiResult[uint32]{Value: 42}) == 42)
+        check(api.UnwrapOrLen(api.FfiResult[uint32]{Err: &api.FfiError{Message: "four"}}) == 4)
+        check(api.NonZero(0) == nil && *api.NonZero(-3) == -3)
+        half, exact := api.Split(5)
+        check(half == 2 && exact == 2.5)
+    )
+}
+
+compile_pass_go! {
+    close,
+    r#"object Counter {
+        static fn new(start: u32) -> Counter;
+        fn inc(by: u32);
+        fn get() -> u32;
+    }
+    fn dropped() -> u32;
+    "#,
+    (
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+        pub struct Counter(std::cell::Cell<u32>);
+
+        impl Counter {
+            pub fn new(start: u32) -> Self {
+                Self(std::cell::Cell::new(start))
+            }
+
+            pub fn inc(&self, by: u32) {
+                self.0.set(self.0.get() + by);
+            }
+
+            pub fn get(&self) -> u32 {
+                self.0.get()
+            }
+        }
+
+        impl Drop for Counter {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        pub fn dropped() -> u32 {
+            DROPPED.load(Ordering::SeqCst)
+        }
+    ),
+    (
+        func() {
+            counter := api.NewCounter(40)
+            defer counter.Close()
+            counter.Inc(2)
+            check(counter.Get() == 42)
+        }()
+        check(api.Dropped() == 1)
+        closed := api.NewCounter(0)
+        closed.Close()
+        closed.Close()
+        check(api.Dropped() == 2)
+        recovered := func() (recovered any) {
+            defer func() {
+                recovered = recover()
+            }()
+            closed.Get()
+            return nil
+        }()
+        check(recovered == "use after move")
+    )
+}
+
+compile_pass_go! {
+    channels,
+    r#"fn delayed(value: u32) -> Future<Result<u32>>;
+    fn values(values: &[u32]) -> Stream<u32>;
+    "#,
+    (
+        use crate::api::Stream;
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Poll};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        struct Delayed(Arc<Mutex<bool>>, Option<u32>);
+
+        impl Future for Delayed {
+            type Output = u32;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+                if *self.0.lock().unwrap() {
+                    return Poll::Ready(self.1.take().unwrap());
+                }
+                let ready = self.0.clone();
+                let waker = cx.waker().clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(10));
+                    *ready.lock().unwrap() = true;
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+        }
+
+        pub async fn delayed(value: u32) -> Result<u32, &'static str> {
+            match Delayed(Arc::new(Mutex::new(false)), Some(value)).await {
+                0 => Err("is zero"),
+                value => Ok(value),
+            }
+        }
+
+        struct Values(Vec<u32>);
+
+        impl Stream for Values {
+            type Item = u32;
+
+            fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+                Poll::Ready(self.0.pop())
+            }
+        }
+
+        pub fn values(values: &[u32]) -> impl Stream<Item = u32> {
+            Values(values.iter().rev().copied().collect())
+        }
+    ),
+    (
+        first := api.Delayed(1)
+        second := api.Delayed(2)
+        var sum uint32
+        for i := 0; i < 2; i++ {
+            select {
+            case res := <-first:
+                check(res.Err == nil)
+                sum += res.Value
+            case res := <-second:
+                check(res.Err == nil)
+                sum += res.Value
+            }
+        }
+        check(sum == 3)
+        res := <-api.Delayed(0)
+        check(res.Err != nil && res.Err.Error() == "is zero")
+        var items []uint32
+        for item := range api.Values([]uint32{42, 99}) {
+            items = append(items, item)
+        }
+        check(equal(items, []uint32{42, 99}))
+    )
+}
+
+compile_pass_go! {
+    slices,
+    r#"object Item {
+        fn id() -> u32;
+    }
+    fn names() -> Iterator<string>;
+    fn items(n: u32) -> Vec<Item>;
+    fn fill(buf: &mut [u8]);
+    fn counting(n: usize) -> buffer<u16>;
+    "#,
+    (
+        pub struct Item(u32);
+
+        impl Item {
+            pub fn id(&self) -> u32 {
+                self.0
+            }
+        }
+
+        pub fn names() -> impl Iterator<Item = String> + Send + 'static {
+            vec!["hello".to_string(), "world".to_string()].into_iter()
+        }
+
+        pub fn items(n: u32) -> Vec<Item> {
+            (0..n).map(Item).collect()
+        }
+
+        pub fn fill(buf: &mut [u8]) {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = i as u8 * 2;
+            }
+        }
+
+        pub fn counting(n: usize) -> api::FfiBuffer<u16> {
+            api::FfiBuffer::new((0..n).map(|n| n as u16).collect())
+        }
+    ),
+    (
+        var names []string
+        for name := range api.Names() {
+            names = append(names, name)
+        }
+        check(equal(names, []string{"hello", "world"}))
+        list := api.Items(3)
+        defer list.Close()
+        first := list.Remove(0)
+        list.Insert(2, first)
+        check(list.Len() == 3 && list.Get(2).Id() == 0)
+        buf := make([]uint8, 4)
+        api.Fill(buf[:3])
+        check(equal(buf, []uint8{0, 2, 4, 0}))
+        counted := api.Counting(4)
+        defer counted.Close()
+        check(equal(counted.Elements(), []uint16{0, 1, 2, 3}))
+    )
+}
@@ -0,0 +1,251 @@
+use ffi_gen::compile_pass_kotlin;
+
+compile_pass_kotlin! {
+    exceptions,
+    r#"fn positive(num: i64) -> Result<i64>;
+    fn unwrap_or_len(res: Result<u32>) -> u32;
+    fn non_zero(num: i64) -> Option<i64>;
+    fn split(n: u32) -> (u32, f64);
+    "#,
+    (
+        pub fn positive(num: i64) -> Result<i64, &'static str> {
+            if num > 0 {
+                Ok(num)
+            } else {
+                Err("not positive")
+            }
+        }
+
+        pub fn unwrap_or_len(res: Result<u32, String>) -> u32 {
+            match res {
+                Ok(n) => n,
+                Err(err) => err.len() as u32,
+            }
+        }
+
+        pub fn non_zero(num: i64) -> Option<i64> {
+            if num == 0 {
+                None
+            } else {
+                Some(num)
+            }
+        }
+
+        pub fn split(n: u32) -> (u32, f64) {
+            (n / 2, n as f64 / 2.0)
+        }
+    ),
+    (
+        check(positive(7) == 7L)
+        val failed = runCatching { positive(0) }.exceptionOrNull()
+        check(failed is FfiError && failed.message == "not positive")
+        check(unwrapOrLen(Result.success(42u)) == 42u)
+        check(unwrapOrLen(Result.failure(FfiError("four"))) == 4u)
+        check((nonZero(0) ?: 1L) == 1L)
+        check(nonZero(-3) == -3L)
+        val (half, exact) = split(5u)
+        check(half == 2u && exact == 2.5)
+    )
+}
+
+compile_pass_kotlin! {
+    closeable,
+    r#"object Counter {
+        static fn new(start: u32) -> Counter;
+        fn inc(by: u32);
+        fn get() -> u32;
+    }
+    fn dropped() -> u32;
+    "#,
+    (
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+        pub struct Counter(std::cell::Cell<u32>);
+
+        impl Counter {
+            pub fn new(start: u32) -> Self {
+                Self(std::cell::Cell::new(start))
+            }
+
+            pub fn inc(&self, by: u32) {
+                self.0.set(self.0.get() + by);
+            }
+
+            pub fn get(&self) -> u32 {
+                self.0.get()
+            }
+        }
+
+        impl Drop for Counter {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        pub fn dropped() -> u32 {
+            DROPPED.load(Ordering::SeqCst)
+        }
+    ),
+    (
+        val total = Counter.new(40u).use { counter ->
+            counter.inc(2u)
+            counter.get()
+        }
+        check(total == 42u)
+        check(dropped() == 1u)
+        val closed = Counter.new(0u)
+        closed.close()
+        closed.close()
+        check(dropped() == 2u)
+        check(runCatching { closed.get() }.isFailure)
+        repeat(100) { Counter.new(it.toUInt()) }
+        withTimeout(10000) {
+            while (dropped() < 102u) {
+                System.gc()
+                delay(10)
+            }
+        }
+    )
+}
+
+compile_pass_kotlin! {
+    coroutines,
+    r#"fn delayed(value: u32) -> Future<Result<u32>>;
+    fn values(values: &[u32]) -> Stream<u32>;
+    "#,
+    (
+        use crate::api::Stream;
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Poll};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        struct Delayed(Arc<Mutex<bool>>, Option<u32>);
+
+        impl Future for Delayed {
+            type Output = u32;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+                if *self.0.lock().unwrap() {
+                    return Poll::Ready(self.1.take().unwrap());
+                }
+                let ready = self.0.clone();
+                let waker = cx.waker().clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(10));
+                    *ready.lock().unwrap() = true;
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+        }
+
+        pub async fn delayed(value: u32) -> Result<u32, &'static str> {
+            match Delayed(Arc::new(Mutex::new(false)), Some(value)).await {
+                0 => Err("is zero"),
+                value => Ok(value),
+            }
+        }
+
+        struct Values(Vec<u32>);
+
+        impl Stream for Values {
+            type Item = u32;
+
+            fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+                Poll::Ready(self.0.pop())
+            }
+        }
+
+        pub fn values(values: &[u32]) -> impl Stream<Item = u32> {
+            Values(values.iter().rev().copied().collect())
+        }
+    ),
+    (
+        val both = listOf(async { delayed(1u) }, async { delayed(2u) }).awaitAll()
+        check(both == listOf(1u, 2u))
+        check(withTimeout(5000) { delayed(3u) } == 3u)
+        try {
+            delayed(0u)
+            error("delayed should throw")
+        } catch (err: FfiError) {
+            check(err.message == "is zero")
+        }
+        val flow: Flow<UInt> = values(listOf(1u, 2u, 3u, 4u))
+        check(flow.filter { it % 2u == 0u }.map { it * 10u }.toList() == listOf(20u, 40u))
+        check(values(listOf(42u, 99u)).first() == 42u)
+        check(values(listOf()).toList().isEmpty())
+    )
+}
+
+compile_pass_kotlin! {
+    collections,
+    r#"object Item {
+        fn id() -> u32;
+    }
+    fn names() -> Iterator<string>;
+    fn items(n: u32) -> Vec<Item>;
+    fn fill(buf: &mut [u8]);
+    fn counting(n: usize) -> buffer<u16>;
+    "#,
+    (
+        pub struct Item(u32);
+
+        impl Item {
+            pub fn id(&self) -> u32 {
+                self.0
+            }
+        }
+
+        pub fn names() -> impl Iterator<Item = String> + Send + 'static {
+            vec!["hello".to_string(), "world".to_string()].into_iter()
+        }
+
+        pub fn items(n: u32) -> Vec<Item> {
+            (0..n).map(Item).collect()
+        }
+
+        pub fn fill(buf: &mut [u8]) {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = i as u8 * 2;
+            }
+        }
+
+        pub fn counting(n: usize) -> api::FfiBuffer<u16> {
+            api::FfiBuffer::new((0..n).map(|n| n as u16).collect())
+        }
+    ),
+    (
+        check(names().asSequence().map { it.uppercase() }.toList() == listOf("HELLO", "WORLD"))
+        val list = items(3u)
+        check(list.map { item -> item.id() } == listOf(0u, 1u, 2u))
+        val first = list.removeAt(0)
+        list.add(first)
+        check(list.last().id() == 0u)
+        val buf = mutableListOf<UByte>(0u, 0u, 0u, 0u)
+        fill(buf)
+        check(buf == listOf<UByte>(0u, 2u, 4u, 6u))
+        val counted = counting(4uL).elements
+        check(List(counted.limit()) { i -> counted.get(i).toInt() } == listOf(0, 1, 2, 3))
+    )
+}
+
+compile_pass_kotlin! {
+    tuples,
+    "fn digits(n: u32) -> (u8, u8, u8, u8);",
+    (
+        pub fn digits(n: u32) -> (u8, u8, u8, u8) {
+            let digit = |i: u32| (n / 10u32.pow(i) % 10) as u8;
+            (digit(3), digit(2), digit(1), digit(0))
+        }
+    ),
+    (
+        check(digits(1234u) == Tuple4<UByte, UByte, UByte, UByte>(1u, 2u, 3u, 4u))
+        val (thousands, _, _, ones) = digits(1234u)
+        check(thousands.toInt() == 1 && ones.toInt() == 4)
+        check(digits(1234u).fourth.toInt() == 4)
+    )
+}
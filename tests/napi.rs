@@ -0,0 +1,229 @@
+use ffi_gen::compile_pass_napi;
+
+compile_pass_napi! {
+    args_usize_ret_isize,
+    "fn sub(a: usize, b: usize) -> isize;",
+    (
+        pub fn sub(a: usize, b: usize) -> isize {
+            a as isize - b as isize
+        }
+    ),
+    ( assert.equal(api.sub(2, 44), -42); )
+}
+
+compile_pass_napi! {
+    args_string,
+    r#"fn len(s: string) -> usize;
+    fn hello(name: &string) -> string;"#,
+    (
+        pub fn len(s: String) -> usize {
+            s.len()
+        }
+
+        pub fn hello(name: &str) -> String {
+            format!("hello {}", name)
+        }
+    ),
+    (
+        assert.equal(api.len("hello world"), 11);
+        assert.equal(api.hello("world"), "hello world");
+        assert.equal(api.hello(""), "hello ");
+    )
+}
+
+compile_pass_napi! {
+    args_slice,
+    r#"fn sum(b: &buffer<usize>) -> usize;
+    fn fill(b: &mut [u64]);
+    fn total_len(strs: &[string]) -> usize;
+    fn evens(n: u32) -> Vec<u32>;"#,
+    (
+        pub fn sum(b: &[usize]) -> usize {
+            b.iter().sum()
+        }
+
+        pub fn fill(b: &mut [u64]) {
+            for (i, n) in b.iter_mut().enumerate() {
+                *n = i as u64;
+            }
+        }
+
+        pub fn total_len(strs: &[&str]) -> usize {
+            strs.iter().map(|s| s.len()).sum()
+        }
+
+        pub fn evens(n: u32) -> Vec<u32> {
+            (0..n).map(|i| i * 2).collect()
+        }
+    ),
+    (
+        const buf = FfiBufferUintPtr.allocate(api, 3);
+        buf.asTypedArray().set([1n, 2n, 39n]);
+        assert.equal(api.sum(buf), 42);
+        buf.drop();
+
+        const nums = new BigUint64Array(3);
+        api.fill(nums);
+        assert.equal(nums[2], 2n);
+
+        assert.equal(api.totalLen(["ab", "cde", ""]), 5);
+        assert.deepEqual(api.evens(3), [0, 2, 4]);
+        assert.deepEqual(api.evens(0), []);
+    )
+}
+
+compile_pass_napi! {
+    options_and_results,
+    r#"fn non_zero(num: i64) -> Option<i64>;
+    fn positive(num: i64) -> Result<i64>;
+    fn split(n: u32) -> (u32, f64);"#,
+    (
+        pub fn non_zero(num: i64) -> Option<i64> {
+            if num == 0 {
+                None
+            } else {
+                Some(num)
+            }
+        }
+
+        pub fn positive(num: i64) -> Result<i64, &'static str> {
+            if num > 0 {
+                Ok(num)
+            } else {
+                Err("not positive")
+            }
+        }
+
+        pub fn split(n: u32) -> (u32, f64) {
+            (n / 2, n as f64 / 2.0)
+        }
+    ),
+    (
+        assert.equal(api.nonZero(0n), null);
+        assert.equal(api.nonZero(-3n), -3n);
+        assert.equal(api.positive(7n), 7n);
+        assert.throws(() => api.positive(0n), (err) => err === "not positive");
+        assert.deepEqual(api.split(5), [2, 2.5]);
+    )
+}
+
+compile_pass_napi! {
+    object,
+    r#"object Counter {
+        fn increment(by: usize);
+        fn count() -> usize;
+    }
+    fn counter() -> Counter;"#,
+    (
+        #[derive(Default)]
+        pub struct Counter(usize);
+
+        impl Counter {
+            pub fn increment(&mut self, by: usize) {
+                self.0 += by;
+            }
+
+            pub fn count(&self) -> usize {
+                self.0
+            }
+        }
+
+        pub fn counter() -> Counter {
+            Counter::default()
+        }
+    ),
+    (
+        const counter = api.counter();
+        counter.increment(40);
+        counter.increment(2);
+        assert.equal(counter.count(), 42);
+        counter.drop();
+    )
+}
+
+compile_pass_napi! {
+    iterator,
+    "fn values() -> Iterator<string>;",
+    (
+        pub fn values() -> impl Iterator<Item = String> + Send + 'static {
+            vec!["hello".to_string(), "world".to_string()].into_iter()
+        }
+    ),
+    ( assert.deepEqual(Array.from(api.values()), ["hello", "world"]); )
+}
+
+compile_pass_napi! {
+    threaded_future,
+    "fn delayed(value: u32) -> Future<Result<u32>>;",
+    (
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Poll};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        struct Delayed(Arc<Mutex<bool>>, Option<u32>);
+
+        impl Future for Delayed {
+            type Output = u32;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+                if *self.0.lock().unwrap() {
+                    return Poll::Ready(self.1.take().unwrap());
+                }
+                let ready = self.0.clone();
+                let waker = cx.waker().clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(10));
+                    *ready.lock().unwrap() = true;
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+        }
+
+        pub async fn delayed(value: u32) -> Result<u32, &'static str> {
+            match Delayed(Arc::new(Mutex::new(false)), Some(value)).await {
+                0 => Err("is zero"),
+                value => Ok(value),
+            }
+        }
+    ),
+    (
+        // the pending futures alone keep node alive
+        assert.equal(await api.delayed(42), 42);
+        await assert.rejects(api.delayed(0), (err) => err === "is zero");
+        assert.deepEqual(await Promise.all([api.delayed(1), api.delayed(2)]), [1, 2]);
+    )
+}
+
+compile_pass_napi! {
+    stream,
+    "fn create(values: &[u32]) -> Stream<u32>;",
+    (
+        use crate::api::Stream;
+        use core::pin::Pin;
+        use core::task::{Context, Poll};
+
+        struct TestStream(Vec<u32>);
+
+        impl Stream for TestStream {
+            type Item = u32;
+
+            fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+                Poll::Ready(self.0.pop())
+            }
+        }
+
+        pub fn create(values: &[u32]) -> impl Stream<Item = u32> {
+            TestStream(values.iter().rev().copied().collect())
+        }
+    ),
+    (
+        const values = [];
+        for await (const value of api.create([42, 99])) {
+            values.push(value);
+        }
+        assert.deepEqual(values, [42, 99]);
+    )
+}
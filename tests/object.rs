@@ -97,6 +97,362 @@ compile_pass! {
 
 }
 
+compile_pass! {
+    rust_raw_identifiers,
+    r#"
+    fn type(loop: u8) -> Keywords;
+    object Keywords {
+        fn match(value: u8) -> bool;
+    }
+    "#,
+    (
+        pub fn r#type(r#loop: u8) -> Keywords {
+            Keywords(r#loop)
+        }
+
+        pub struct Keywords(u8);
+
+        impl Keywords {
+            pub fn r#match(&self, value: u8) -> bool {
+                self.0 == value
+            }
+        }
+    ),
+    (
+        let boxed = __type(42);
+        assert_eq!(__Keywords_match(boxed, 42), 1);
+        assert_eq!(__Keywords_match(boxed, 0), 0);
+        drop_box_Keywords(0, boxed as _);
+    ),
+    (
+        final keywords = api.type(42);
+        assert(keywords.match(42));
+        assert(!keywords.match(0));
+    ),
+    (
+        const keywords = api.type(42);
+        assert.equal(keywords.match(42), true);
+        assert.equal(keywords.match(0), false);
+        keywords.drop();
+    ),
+    (
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        type(loop: number): Keywords;
+    }
+
+    export class Keywords {
+        match(value: number): boolean;
+
+        drop(): void;
+    })
+}
+
+compile_pass! {
+    borrowed_returns,
+    r#"
+    fn client(port: u32) -> Client;
+    object Settings {
+        fn port() -> u32;
+    }
+    object Client {
+        fn settings() -> &Settings;
+        fn ports() -> &Iterator<u32>;
+    }
+    "#,
+    (
+        pub fn client(port: u32) -> Client {
+            Client {
+                settings: Settings { port },
+                ports: api::FfiIter::new(vec![port, port + 1]),
+            }
+        }
+
+        pub struct Settings {
+            port: u32,
+        }
+
+        impl Settings {
+            pub fn port(&self) -> u32 {
+                self.port
+            }
+        }
+
+        pub struct Client {
+            settings: Settings,
+            ports: api::FfiIter<u32>,
+        }
+
+        impl Client {
+            pub fn settings(&self) -> &Settings {
+                &self.settings
+            }
+
+            pub fn ports(&mut self) -> &mut api::FfiIter<u32> {
+                &mut self.ports
+            }
+        }
+    ),
+    (
+        let client = __client(8080);
+        let settings = __Client_settings(client);
+        assert_eq!(__Settings_port(settings), 8080);
+        let ports = __Client_ports(client);
+        let next = __Client_ports_iter_next(ports);
+        assert_eq!((next.ret0, next.ret1), (1, 8080));
+        let next = __Client_ports_iter_next(ports);
+        assert_eq!((next.ret0, next.ret1), (1, 8081));
+        let next = __Client_ports_iter_next(ports);
+        assert_eq!(next.ret0, 0);
+        drop_box_Client(0, client as _);
+    ),
+    (
+        final client = api.client(8080);
+        final settings = client.settings();
+        assert(settings.port() == 8080);
+        assert(client.ports().toList().length == 2);
+        client.drop();
+        try {
+            settings.port();
+            assert(false);
+        } on StateError {
+        }
+    ),
+    (
+        const client = api.client(8080);
+        const settings = client.settings();
+        assert.equal(settings.port(), 8080);
+        assert.deepEqual([...client.ports()], [8080, 8081]);
+        client.drop();
+        assert.throws(() => settings.port(), /owner was dropped/);
+    ),
+    (
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        client(port: number): Client;
+    }
+
+    export class Settings {
+        port(): number;
+
+        drop(): void;
+    }
+
+    export class Client {
+        settings(): Settings;
+
+        ports(): Iterable<number>;
+
+        drop(): void;
+    })
+}
+
+compile_pass! {
+    nested_borrows,
+    r#"
+    fn client(port: u32) -> Client;
+    object Limits {
+        fn max() -> u32;
+    }
+    object Settings {
+        fn limits() -> &Limits;
+    }
+    object Client {
+        fn settings() -> &Settings;
+    }
+    "#,
+    (
+        pub fn client(port: u32) -> Client {
+            Client {
+                settings: Settings {
+                    limits: Limits { max: port },
+                },
+            }
+        }
+
+        pub struct Limits {
+            max: u32,
+        }
+
+        impl Limits {
+            pub fn max(&self) -> u32 {
+                self.max
+            }
+        }
+
+        pub struct Settings {
+            limits: Limits,
+        }
+
+        impl Settings {
+            pub fn limits(&self) -> &Limits {
+                &self.limits
+            }
+        }
+
+        pub struct Client {
+            settings: Settings,
+        }
+
+        impl Client {
+            pub fn settings(&self) -> &Settings {
+                &self.settings
+            }
+        }
+    ),
+    (
+        let client = __client(8080);
+        let settings = __Client_settings(client);
+        let limits = __Settings_limits(settings);
+        assert_eq!(__Limits_max(limits), 8080);
+        drop_box_Client(0, client as _);
+    ),
+    (
+        final client = api.client(8080);
+        final limits = client.settings().limits();
+        assert(limits.max() == 8080);
+        client.drop();
+        try {
+            limits.max();
+            assert(false);
+        } on StateError {
+        }
+    ),
+    (
+        const client = api.client(8080);
+        const limits = client.settings().limits();
+        assert.equal(limits.max(), 8080);
+        client.drop();
+        assert.throws(() => limits.max(), /owner was dropped/);
+    ),
+    (
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        client(port: number): Client;
+    }
+
+    export class Limits {
+        max(): number;
+
+        drop(): void;
+    }
+
+    export class Settings {
+        limits(): Limits;
+
+        drop(): void;
+    }
+
+    export class Client {
+        settings(): Settings;
+
+        drop(): void;
+    })
+}
+
+compile_pass! {
+    slice_args,
+    r#"
+    fn find(names: &[string], needle: &string) -> i32;
+    fn chars(names: &[string]) -> u32;
+    fn counter(count: u32) -> Counter;
+    fn total(counters: &[Counter]) -> u32;
+    object Counter {
+        fn count() -> u32;
+    }
+    "#,
+    (
+        pub fn find(names: &[&str], needle: &str) -> i32 {
+            names.iter().position(|name| *name == needle).map(|i| i as i32).unwrap_or(-1)
+        }
+
+        pub fn chars(names: &[&str]) -> u32 {
+            names.iter().map(|name| name.chars().count() as u32).sum()
+        }
+
+        pub fn total(counters: &[&Counter]) -> u32 {
+            counters.iter().map(|counter| counter.count).sum()
+        }
+
+        pub fn counter(count: u32) -> Counter {
+            Counter { count }
+        }
+
+        pub struct Counter {
+            count: u32,
+        }
+
+        impl Counter {
+            pub fn count(&self) -> u32 {
+                self.count
+            }
+        }
+    ),
+    (
+        let names = [2, 2, u32::from_ne_bytes(*b"ab\xc3\xa9")];
+        let needle = "é";
+        assert_eq!(__find(names.as_ptr() as _, 2, needle.as_ptr() as _, needle.len() as _), 1);
+        assert_eq!(__chars(names.as_ptr() as _, 2), 3);
+
+        let counters = vec![__counter(2), __counter(40)];
+        assert_eq!(__total(counters.as_ptr() as _, 2), 42);
+        assert_eq!(__total(counters.as_ptr() as _, 1), 2);
+        for counter in counters {
+            drop_box_Counter(0, counter as _);
+        }
+    ),
+    (
+        assert(api.find(["ab", "é", "c"], "c") == 2);
+        assert(api.find([], "c") == -1);
+        assert(api.chars(["ab", "é"]) == 3);
+
+        final counters = [api.counter(2), api.counter(40)];
+        assert(api.total(counters) == 42);
+        assert(counters[1].count() == 40);
+        counters.forEach((counter) => counter.drop());
+    ),
+    (
+        assert.equal(api.find(["ab", "é", "c"], "c"), 2);
+        assert.equal(api.find([], "c"), -1);
+        assert.equal(api.chars(["ab", "é"]), 3);
+
+        const counters = [api.counter(2), api.counter(40)];
+        assert.equal(api.total(counters), 42);
+        assert.equal(counters[1].count(), 40);
+        counters.forEach((counter) => counter.drop());
+        assert.throws(() => api.total(counters), /use after free/);
+    ),
+    (
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        find(names: Array<string>, needle: string): number;
+
+        chars(names: Array<string>): number;
+
+        counter(count: number): Counter;
+
+        total(counters: Array<Counter>): number;
+    }
+
+    export class Counter {
+        count(): number;
+
+        drop(): void;
+    })
+}
+
 compile_pass! {
     iterator,
     r#"fn vec_str() -> Iterator<string>;
@@ -686,6 +1042,65 @@ fn get_shapes() -> Vec<Shape>;
     )
 }
 
+compile_pass_no_js! {
+    enum_slice_args,
+    "
+    object Vector2 {
+        fn x() -> u64;
+    }
+
+    enum Shape {
+        Square(Vector2),
+        None
+    }
+
+    fn square(x: u64) -> Shape;
+    fn nothing() -> Shape;
+    fn count_squares(shapes: &[Shape]) -> u32;
+    ",
+    (
+        #[derive(Clone, Copy)]
+        pub struct Vector2 {
+            x: u64,
+        }
+
+        impl Vector2 {
+            pub fn x(&self) -> u64 {
+                self.x
+            }
+        }
+
+        #[derive(Clone, Copy)]
+        pub enum Shape {
+            Square(Vector2),
+            None,
+        }
+
+        pub fn square(x: u64) -> Shape {
+            Shape::Square(Vector2 { x })
+        }
+
+        pub fn nothing() -> Shape {
+            Shape::None
+        }
+
+        pub fn count_squares(shapes: &[&Shape]) -> u32 {
+            shapes.iter().filter(|shape| matches!(shape, Shape::Square(_))).count() as u32
+        }
+    ),
+    (
+        let shapes = vec![__square(2), __nothing(), __square(3)];
+        assert_eq!(__count_squares(shapes.as_ptr() as _, 3), 2);
+        assert_eq!(__count_squares(shapes.as_ptr() as _, 0), 0);
+    ),
+    (
+        final shapes = [api.square(2), api.nothing(), api.square(3)];
+        assert(api.countSquares(shapes) == 2);
+        assert(api.countSquares([]) == 0);
+        assert((shapes[2].inner as Vector2).x() == 3);
+    )
+}
+
 compile_pass_no_js! {
     future_vec_string,
     "\
@@ -765,3 +1180,88 @@ compile_pass_no_js! {
         assert(api.sumList(list) == 40);
     )
 }
+
+compile_pass! {
+    prefixed_symbols,
+    r#"
+    //! @prefix greeter
+    fn sum(b: &buffer<u32>) -> u32;
+    fn counter() -> Counter;
+    object Counter {
+        fn count() -> u32;
+    }
+    "#,
+    (
+        pub fn sum(b: &[u32]) -> u32 {
+            b.iter().sum()
+        }
+
+        pub struct Counter;
+
+        impl Counter {
+            pub fn count(&self) -> u32 {
+                42
+            }
+        }
+
+        pub fn counter() -> Counter {
+            Counter
+        }
+    ),
+    (
+        unsafe {
+            let buf = greeter___ffi_buffer_new(8, 4);
+            let addr = greeter___ffi_buffer_address(buf as _) as *mut u32;
+            std::slice::from_raw_parts_mut(addr, 2).copy_from_slice(&[40, 2]);
+            assert_eq!(greeter___sum(buf as _), 42);
+            greeter_drop_box_FfiBuffer(0, buf);
+        }
+        let boxed = greeter___counter();
+        assert_eq!(greeter___Counter_count(boxed), 42);
+        greeter_drop_box_Counter(0, boxed as _);
+    ),
+    (
+        final buf = FfiBufferUint32.allocate(api, 2);
+        buf.asTypedList().setAll(0, [40, 2]);
+        assert(api.sum(buf) == 42);
+        buf.drop();
+
+        final counter = api.counter();
+        assert(counter.count() == 42);
+        counter.drop();
+    ),
+    (
+        const buf = FfiBufferUint32.allocate(api, 2);
+        buf.asTypedArray().set([40, 2]);
+        assert.equal(api.sum(buf), 42);
+        buf.drop();
+
+        const counter = api.counter();
+        assert.equal(counter.count(), 42);
+        counter.drop();
+    ),
+    (
+    export class FfiBufferUint32 {
+        static allocate(api: Api, length: number): FfiBufferUint32;
+
+        asTypedArray(): Uint32Array;
+
+        drop(): void;
+    }
+
+    export class Api {
+        constructor();
+
+        fetch(url, imports): Promise<void>;
+
+        sum(b: FfiBufferUint32): number;
+
+        counter(): Counter;
+    }
+
+    export class Counter {
+        count(): number;
+
+        drop(): void;
+    })
+}
@@ -0,0 +1,337 @@
+use ffi_gen::compile_pass_python;
+
+compile_pass_python! {
+    exceptions,
+    r#"fn positive(num: i64) -> Result<i64>;
+    fn unwrap_or_len(res: Result<u32>) -> u32;
+    fn non_zero(num: i64) -> Option<i64>;
+    fn present(flag: bool) -> Option<()>;
+    "#,
+    (
+        pub fn positive(num: i64) -> Result<i64, &'static str> {
+            if num > 0 {
+                Ok(num)
+            } else {
+                Err("not positive")
+            }
+        }
+
+        pub fn unwrap_or_len(res: Result<u32, String>) -> u32 {
+            match res {
+                Ok(n) => n,
+                Err(err) => err.len() as u32,
+            }
+        }
+
+        pub fn non_zero(num: i64) -> Option<i64> {
+            if num == 0 {
+                None
+            } else {
+                Some(num)
+            }
+        }
+
+        pub fn present(flag: bool) -> Option<()> {
+            if flag {
+                Some(())
+            } else {
+                None
+            }
+        }
+    ),
+    (
+        assert api.positive(7) == 7
+        try:
+            api.positive(0)
+            assert False
+        except FfiError as err:
+            assert str(err) == "not positive"
+        assert api.unwrap_or_len(FfiResult.ok(42)) == 42
+        assert api.unwrap_or_len(FfiResult.err("four")) == 4
+        assert api.non_zero(0) is None
+        assert api.non_zero(-3) == -3
+        assert api.present(True) == ()
+        assert api.present(False) is None
+    )
+}
+
+compile_pass_python! {
+    context_managers,
+    r#"object Counter {
+        static fn new(start: u32) -> Counter;
+        fn inc(by: u32);
+        fn get() -> u32;
+    }
+    fn dropped() -> u32;
+    "#,
+    (
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+        pub struct Counter(std::cell::Cell<u32>);
+
+        impl Counter {
+            pub fn new(start: u32) -> Self {
+                Self(std::cell::Cell::new(start))
+            }
+
+            pub fn inc(&self, by: u32) {
+                self.0.set(self.0.get() + by);
+            }
+
+            pub fn get(&self) -> u32 {
+                self.0.get()
+            }
+        }
+
+        impl Drop for Counter {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        pub fn dropped() -> u32 {
+            DROPPED.load(Ordering::SeqCst)
+        }
+    ),
+    (
+        with Counter.new(api, 40) as counter:
+            counter.inc(2)
+            assert counter.get() == 42
+        assert api.dropped() == 1
+        try:
+            counter.get()
+            assert False
+        except RuntimeError:
+            pass
+        other = Counter.new(api, 0)
+        del other
+        assert api.dropped() == 2
+    )
+}
+
+compile_pass_python! {
+    asyncio,
+    r#"fn delayed(value: u32) -> Future<Result<u32>>;
+    fn values(values: &[u32]) -> Stream<u32>;
+    "#,
+    (
+        use crate::api::Stream;
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Poll};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        struct Delayed(Arc<Mutex<bool>>, Option<u32>);
+
+        impl Future for Delayed {
+            type Output = u32;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+                if *self.0.lock().unwrap() {
+                    return Poll::Ready(self.1.take().unwrap());
+                }
+                let ready = self.0.clone();
+                let waker = cx.waker().clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(10));
+                    *ready.lock().unwrap() = true;
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+        }
+
+        pub async fn delayed(value: u32) -> Result<u32, &'static str> {
+            match Delayed(Arc::new(Mutex::new(false)), Some(value)).await {
+                0 => Err("is zero"),
+                value => Ok(value),
+            }
+        }
+
+        struct Values(Vec<u32>);
+
+        impl Stream for Values {
+            type Item = u32;
+
+            fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+                Poll::Ready(self.0.pop())
+            }
+        }
+
+        pub fn values(values: &[u32]) -> impl Stream<Item = u32> {
+            Values(values.iter().rev().copied().collect())
+        }
+    ),
+    (
+        assert await asyncio.gather(api.delayed(1), api.delayed(2)) == [1, 2]
+        try:
+            await api.delayed(0)
+            assert False
+        except FfiError as err:
+            assert str(err) == "is zero"
+        assert [value async for value in api.values([42, 99])] == [42, 99]
+    )
+}
+
+compile_pass_python! {
+    sequences,
+    r#"object Item {
+        fn id() -> u32;
+    }
+    fn names() -> Iterator<string>;
+    fn items(n: u32) -> Vec<Item>;
+    fn words() -> Vec<string>;
+    fn fill(buf: &mut [u8]);
+    "#,
+    (
+        pub struct Item(u32);
+
+        impl Item {
+            pub fn id(&self) -> u32 {
+                self.0
+            }
+        }
+
+        pub fn names() -> impl Iterator<Item = String> + Send + 'static {
+            vec!["hello".to_string(), "world".to_string()].into_iter()
+        }
+
+        pub fn items(n: u32) -> Vec<Item> {
+            (0..n).map(Item).collect()
+        }
+
+        pub fn words() -> Vec<String> {
+            vec!["hello".to_string(), "world".to_string()]
+        }
+
+        pub fn fill(buf: &mut [u8]) {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = i as u8 * 2;
+            }
+        }
+    ),
+    (
+        names = api.names()
+        assert next(names) == "hello"
+        assert list(names) == ["world"]
+        items = api.items(3)
+        assert len(items) == 3
+        assert items[-1].id() == 2
+        first = items.remove(0)
+        assert first.id() == 0 and len(items) == 2
+        items.insert(0, first)
+        assert [item.id() for item in items] == [0, 1, 2]
+        assert [str(word) for word in api.words()] == ["hello", "world"]
+        buf = bytearray(4)
+        api.fill(buf)
+        assert list(buf) == [0, 2, 4, 6]
+    )
+}
+
+#[test]
+fn shared_runtime() {
+    ffi_gen::test_runner::compile_pass_python_shared(
+        &[
+            (
+                "base",
+                r#"//! @prefix base
+                fn hello(name: &string) -> string;
+                "#,
+                genco::quote! {
+                    pub fn hello(name: &str) -> String {
+                        format!("hello {}", name)
+                    }
+                },
+            ),
+            (
+                "text",
+                r#"//! @prefix text
+                //! @runtime extern base
+                fn shout(text: &string) -> string;
+                fn words(text: &string) -> Vec<string>;
+                "#,
+                genco::quote! {
+                    pub fn shout(text: &str) -> String {
+                        text.to_uppercase()
+                    }
+
+                    pub fn words(text: &str) -> Vec<String> {
+                        text.split(' ').map(str::to_string).collect()
+                    }
+                },
+            ),
+        ],
+        genco::quote! {
+            base_api = base.Api()
+            text_api = text.Api()
+            assert base_api.hello("world") == "hello world"
+            assert text_api.shout(base_api.hello("world")) == "HELLO WORLD"
+            assert [str(word) for word in text_api.words("a b")] == ["a", "b"]
+            assert hasattr(base_api._lib, "base_allocate")
+            assert not hasattr(base_api._lib, "text_allocate")
+            assert not hasattr(base_api._lib, "text___ffi_buffer_new")
+        },
+    )
+    .unwrap();
+}
+
+compile_pass_python! {
+    renamed,
+    r#"#[rename = "Greeting"]
+    object Greeter {
+        #[rename = "create"]
+        static fn new(name: &string) -> Greeter;
+        #[rename = "kind"]
+        fn type() -> Type;
+        fn greet() -> string;
+    }
+    #[rename = "Kind"]
+    enum Type {
+        Formal,
+        Casual
+    }
+    #[rename = "greetings"]
+    fn list(names: &[string]) -> Vec<Greeter>;
+    "#,
+    (
+        pub struct Greeter(String);
+
+        impl Greeter {
+            pub fn new(name: &str) -> Self {
+                Self(name.to_string())
+            }
+
+            pub fn r#type(&self) -> Type {
+                match self.0.as_str() {
+                    "friend" => Type::Casual,
+                    _ => Type::Formal,
+                }
+            }
+
+            pub fn greet(&self) -> String {
+                format!("hello {}", self.0)
+            }
+        }
+
+        #[derive(Clone)]
+        pub enum Type {
+            Formal,
+            Casual,
+        }
+
+        pub fn list(names: &[&str]) -> Vec<Greeter> {
+            names.iter().map(|name| Greeter::new(name)).collect()
+        }
+    ),
+    (
+        greeting = Greeting.create(api, "world")
+        assert greeting.greet() == "hello world"
+        assert greeting.kind().tag == KindTag.Formal
+        assert Greeting.create(api, "friend").kind().tag == KindTag.Casual
+        assert [greeting.greet() for greeting in api.greetings(["a", "b"])] == ["hello a", "hello b"]
+        assert not hasattr(api, "list")
+    )
+}
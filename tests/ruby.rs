@@ -0,0 +1,237 @@
+use ffi_gen::compile_pass_ruby;
+
+compile_pass_ruby! {
+    exceptions,
+    r#"fn positive(num: i64) -> Result<i64>;
+    fn unwrap_or_len(res: Result<u32>) -> u32;
+    fn non_zero(num: i64) -> Option<i64>;
+    fn split(n: u32) -> (u32, f64);
+    "#,
+    (
+        pub fn positive(num: i64) -> Result<i64, &'static str> {
+            if num > 0 {
+                Ok(num)
+            } else {
+                Err("not positive")
+            }
+        }
+
+        pub fn unwrap_or_len(res: Result<u32, String>) -> u32 {
+            match res {
+                Ok(n) => n,
+                Err(err) => err.len() as u32,
+            }
+        }
+
+        pub fn non_zero(num: i64) -> Option<i64> {
+            if num == 0 {
+                None
+            } else {
+                Some(num)
+            }
+        }
+
+        pub fn split(n: u32) -> (u32, f64) {
+            (n / 2, n as f64 / 2.0)
+        }
+    ),
+    (
+        check(Api.positive(7) == 7)
+        begin
+            Api.positive(0)
+            raise "positive should raise"
+        rescue StandardError => err
+            check(err.is_a?(Api::FfiError) && err.message == "not positive")
+        end
+        check((Api.positive(-1) rescue :failed) == :failed)
+        check(Api.unwrap_or_len(Api::FfiResult.ok(42)) == 42)
+        check(Api.unwrap_or_len(Api::FfiResult.err("four")) == 4)
+        check(Api.non_zero(0).nil? && Api.non_zero(-3) == -3)
+        half, exact = Api.split(5)
+        check(half == 2 && exact == 2.5)
+    )
+}
+
+compile_pass_ruby! {
+    auto_pointers,
+    r#"object Counter {
+        static fn new(start: u32) -> Counter;
+        fn inc(by: u32);
+        fn get() -> u32;
+    }
+    fn dropped() -> u32;
+    "#,
+    (
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+        pub struct Counter(std::cell::Cell<u32>);
+
+        impl Counter {
+            pub fn new(start: u32) -> Self {
+                Self(std::cell::Cell::new(start))
+            }
+
+            pub fn inc(&self, by: u32) {
+                self.0.set(self.0.get() + by);
+            }
+
+            pub fn get(&self) -> u32 {
+                self.0.get()
+            }
+        }
+
+        impl Drop for Counter {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        pub fn dropped() -> u32 {
+            DROPPED.load(Ordering::SeqCst)
+        }
+    ),
+    (
+        counter = Api::Counter.new(40)
+        counter.inc(2)
+        check(counter.get == 42)
+        counter.drop
+        counter.drop
+        check(Api.dropped == 1)
+        begin
+            counter.get
+            raise "get should raise"
+        rescue RuntimeError => err
+            check(err.message == "use after free")
+        end
+        100.times { |i| Api::Counter.new(i) }
+        100.times do
+            break if Api.dropped == 101
+            GC.start(full_mark: true, immediate_sweep: true)
+            sleep(0.01)
+        end
+        check(Api.dropped > 1)
+    )
+}
+
+compile_pass_ruby! {
+    enumerables,
+    r#"fn names() -> Iterator<string>;
+    fn delayed(value: u32) -> Future<Result<u32>>;
+    fn values(values: &[u32]) -> Stream<u32>;
+    "#,
+    (
+        use crate::api::Stream;
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Poll};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        pub fn names() -> impl Iterator<Item = String> + Send + 'static {
+            vec!["hello".to_string(), "world".to_string()].into_iter()
+        }
+
+        struct Delayed(Arc<Mutex<bool>>, Option<u32>);
+
+        impl Future for Delayed {
+            type Output = u32;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+                if *self.0.lock().unwrap() {
+                    return Poll::Ready(self.1.take().unwrap());
+                }
+                let ready = self.0.clone();
+                let waker = cx.waker().clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(10));
+                    *ready.lock().unwrap() = true;
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+        }
+
+        pub async fn delayed(value: u32) -> Result<u32, &'static str> {
+            match Delayed(Arc::new(Mutex::new(false)), Some(value)).await {
+                0 => Err("is zero"),
+                value => Ok(value),
+            }
+        }
+
+        struct Values(Vec<u32>);
+
+        impl Stream for Values {
+            type Item = u32;
+
+            fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+                Poll::Ready(self.0.pop())
+            }
+        }
+
+        pub fn values(values: &[u32]) -> impl Stream<Item = u32> {
+            Values(values.iter().rev().copied().collect())
+        }
+    ),
+    (
+        check(Api.names.map(&:upcase) == ["HELLO", "WORLD"])
+        check(Api.names.first == "hello")
+        check([Api.delayed(1), Api.delayed(2)].map(&:value) == [1, 2])
+        begin
+            Api.delayed(0).value
+            raise "delayed should raise"
+        rescue Api::FfiError => err
+            check(err.message == "is zero")
+        end
+        check(Api.values([1, 2, 3, 4]).select(&:even?) == [2, 4])
+        check(Api.values([42, 99]).each_with_index.to_a == [[42, 0], [99, 1]])
+        check(Api.values([1, 2, 3]).reduce(:+) == 6)
+    )
+}
+
+compile_pass_ruby! {
+    arrays,
+    r#"object Item {
+        fn id() -> u32;
+    }
+    fn items(n: u32) -> Vec<Item>;
+    fn fill(buf: &mut [u8]);
+    fn counting(n: usize) -> buffer<u16>;
+    "#,
+    (
+        pub struct Item(u32);
+
+        impl Item {
+            pub fn id(&self) -> u32 {
+                self.0
+            }
+        }
+
+        pub fn items(n: u32) -> Vec<Item> {
+            (0..n).map(Item).collect()
+        }
+
+        pub fn fill(buf: &mut [u8]) {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = i as u8 * 2;
+            }
+        }
+
+        pub fn counting(n: usize) -> api::FfiBuffer<u16> {
+            api::FfiBuffer::new((0..n).map(|n| n as u16).collect())
+        }
+    ),
+    (
+        list = Api.items(3)
+        check(list.map(&:id) == [0, 1, 2])
+        first = list.delete_at(0)
+        list.push(first)
+        check(list.length == 3 && list[2].id == 0)
+        check(list.min_by(&:id).id == 0)
+        buf = [0, 0, 0, 0]
+        Api.fill(buf)
+        check(buf == [0, 2, 4, 6])
+        check(Api.counting(4).to_a == [0, 1, 2, 3])
+    )
+}
@@ -0,0 +1,232 @@
+use ffi_gen::compile_pass_swift;
+
+compile_pass_swift! {
+    throws,
+    r#"fn positive(num: i64) -> Result<i64>;
+    fn unwrap_or_len(res: Result<u32>) -> u32;
+    fn non_zero(num: i64) -> Option<i64>;
+    fn split(n: u32) -> (u32, f64);
+    "#,
+    (
+        pub fn positive(num: i64) -> Result<i64, &'static str> {
+            if num > 0 {
+                Ok(num)
+            } else {
+                Err("not positive")
+            }
+        }
+
+        pub fn unwrap_or_len(res: Result<u32, String>) -> u32 {
+            match res {
+                Ok(n) => n,
+                Err(err) => err.len() as u32,
+            }
+        }
+
+        pub fn non_zero(num: i64) -> Option<i64> {
+            if num == 0 {
+                None
+            } else {
+                Some(num)
+            }
+        }
+
+        pub fn split(n: u32) -> (u32, f64) {
+            (n / 2, n as f64 / 2.0)
+        }
+    ),
+    (
+        let positiveNum = try positive(num: 7)
+        precondition(positiveNum == 7)
+        do {
+            _ = try positive(num: 0)
+            preconditionFailure()
+        } catch let err as FfiError {
+            precondition(err.message == "not positive")
+        }
+        precondition((try? positive(num: -1)) == nil)
+        precondition(unwrapOrLen(res: .success(42)) == 42)
+        precondition(unwrapOrLen(res: .failure(FfiError("four"))) == 4)
+        guard let nonZeroNum = nonZero(num: -3) else {
+            preconditionFailure()
+        }
+        precondition(nonZeroNum == -3 && nonZero(num: 0) == nil)
+        let (half, exact) = split(n: 5)
+        precondition(half == 2 && exact == 2.5)
+    )
+}
+
+compile_pass_swift! {
+    deinit,
+    r#"object Counter {
+        static fn new(start: u32) -> Counter;
+        fn inc(by: u32);
+        fn get() -> u32;
+    }
+    fn dropped() -> u32;
+    "#,
+    (
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+        pub struct Counter(std::cell::Cell<u32>);
+
+        impl Counter {
+            pub fn new(start: u32) -> Self {
+                Self(std::cell::Cell::new(start))
+            }
+
+            pub fn inc(&self, by: u32) {
+                self.0.set(self.0.get() + by);
+            }
+
+            pub fn get(&self) -> u32 {
+                self.0.get()
+            }
+        }
+
+        impl Drop for Counter {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        pub fn dropped() -> u32 {
+            DROPPED.load(Ordering::SeqCst)
+        }
+    ),
+    (
+        var counter: Counter? = Counter.new(start: 40)
+        let alias = counter
+        counter?.inc(by: 2)
+        counter = nil
+        precondition(dropped() == 0)
+        precondition(alias?.get() == 42)
+        withExtendedLifetime(alias) {}
+        func scoped() {
+            let temporary = Counter.new(start: 0)
+            temporary.inc(by: 1)
+        }
+        scoped()
+        precondition(dropped() == 1)
+    )
+}
+
+compile_pass_swift! {
+    async_await,
+    r#"fn delayed(value: u32) -> Future<Result<u32>>;
+    fn values(values: &[u32]) -> Stream<u32>;
+    "#,
+    (
+        use crate::api::Stream;
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Poll};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        struct Delayed(Arc<Mutex<bool>>, Option<u32>);
+
+        impl Future for Delayed {
+            type Output = u32;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+                if *self.0.lock().unwrap() {
+                    return Poll::Ready(self.1.take().unwrap());
+                }
+                let ready = self.0.clone();
+                let waker = cx.waker().clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(10));
+                    *ready.lock().unwrap() = true;
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+        }
+
+        pub async fn delayed(value: u32) -> Result<u32, &'static str> {
+            match Delayed(Arc::new(Mutex::new(false)), Some(value)).await {
+                0 => Err("is zero"),
+                value => Ok(value),
+            }
+        }
+
+        struct Values(Vec<u32>);
+
+        impl Stream for Values {
+            type Item = u32;
+
+            fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+                Poll::Ready(self.0.pop())
+            }
+        }
+
+        pub fn values(values: &[u32]) -> impl Stream<Item = u32> {
+            Values(values.iter().rev().copied().collect())
+        }
+    ),
+    (
+        func gather() async throws -> [UInt32] {
+            async let first = delayed(value: 1)
+            async let second = delayed(value: 2)
+            return try await [first, second]
+        }
+        let gathered = try await gather()
+        precondition(gathered == [1, 2])
+        do {
+            _ = try await delayed(value: 0)
+            preconditionFailure()
+        } catch let err as FfiError {
+            precondition(err.message == "is zero")
+        }
+        let stream: AsyncThrowingStream<UInt32, Error> = values(values: [42, 99])
+        var items: [UInt32] = []
+        for try await item in stream {
+            items.append(item)
+        }
+        precondition(items == [42, 99])
+        let summed = try await values(values: [1, 2, 3]).reduce(0, +)
+        precondition(summed == 6)
+    )
+}
+
+compile_pass_swift! {
+    sequences,
+    r#"fn names() -> Iterator<string>;
+    fn evens(n: u32) -> Vec<u32>;
+    fn fill(buf: &mut [u8]);
+    fn counting(n: usize) -> buffer<u16>;
+    "#,
+    (
+        pub fn names() -> impl Iterator<Item = String> + Send + 'static {
+            vec!["hello".to_string(), "world".to_string()].into_iter()
+        }
+
+        pub fn evens(n: u32) -> Vec<u32> {
+            (0..n).map(|i| i * 2).collect()
+        }
+
+        pub fn fill(buf: &mut [u8]) {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = i as u8 * 2;
+            }
+        }
+
+        pub fn counting(n: usize) -> api::FfiBuffer<u16> {
+            api::FfiBuffer::new((0..n).map(|n| n as u16).collect())
+        }
+    ),
+    (
+        precondition(names().map { name in name.uppercased() } == ["HELLO", "WORLD"])
+        precondition(evens(n: 3) == [0, 2, 4])
+        var buf: [UInt8] = [0, 0, 0, 0]
+        fill(buf: &buf)
+        precondition(buf == [0, 2, 4, 6])
+        let counted = counting(n: 4)
+        withExtendedLifetime(counted) {
+            precondition(Array(counted.elements) == [0, 1, 2, 3])
+        }
+    )
+}
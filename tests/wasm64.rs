@@ -0,0 +1,103 @@
+use ffi_gen::compile_pass_wasm64;
+
+compile_pass_wasm64! {
+    args_usize_ret_isize,
+    "fn sub(a: usize, b: usize) -> isize;",
+    (
+        pub fn sub(a: usize, b: usize) -> isize {
+            a as isize - b as isize
+        }
+    ),
+    [
+        "exports.__sub(BigInt(tmp1),BigInt(tmp3),)",
+        "const tmp6 = Number(tmp4);",
+        "sub(a: number, b: number): number;",
+    ],
+    ( assert.equal(api.sub(2, 44), -42); )
+}
+
+compile_pass_wasm64! {
+    args_string,
+    "fn len(s: string) -> usize;",
+    (
+        pub fn len(s: String) -> usize {
+            s.len()
+        }
+    ),
+    [
+        "exports.__len(BigInt(tmp1),BigInt(tmp2),BigInt(tmp3),)",
+        "len(s: string): number;",
+    ],
+    ( assert.equal(api.len("hello world"), 11); )
+}
+
+compile_pass_wasm64! {
+    args_slice,
+    r#"fn sum(b: &buffer<usize>) -> usize;
+    fn fill(b: &mut [u64]);"#,
+    (
+        pub fn sum(b: &[usize]) -> usize {
+            b.iter().sum()
+        }
+
+        pub fn fill(b: &mut [u64]) {
+            for (i, n) in b.iter_mut().enumerate() {
+                *n = i as u64;
+            }
+        }
+    ),
+    [
+        "exports.__sum(BigInt(tmp1),)",
+        "sum(b: FfiBufferUintPtr): number;",
+        "fill(b: BigUint64Array): void;",
+    ],
+    (
+        const buf = FfiBufferUintPtr.allocate(api, 3);
+        buf.asTypedArray().set([1n, 2n, 39n]);
+        assert.equal(api.sum(buf), 42);
+        buf.drop();
+
+        const nums = new BigUint64Array(3);
+        api.fill(nums);
+        assert.equal(nums[2], 2n);
+    )
+}
+
+compile_pass_wasm64! {
+    object,
+    r#"object Counter {
+        fn increment(by: usize);
+        fn count() -> usize;
+    }
+    fn counter() -> Counter;"#,
+    (
+        #[derive(Default)]
+        pub struct Counter(std::cell::Cell<usize>);
+
+        impl Counter {
+            pub fn increment(&self, by: usize) {
+                self.0.set(self.0.get() + by);
+            }
+
+            pub fn count(&self) -> usize {
+                self.0.get()
+            }
+        }
+
+        pub fn counter() -> Counter {
+            Counter::default()
+        }
+    ),
+    [
+        "const tmp2 = Number(tmp0);",
+        "exports.__Counter_increment(BigInt(tmp0),BigInt(tmp2),)",
+        "increment(by: number): void;",
+    ],
+    (
+        const counter = api.counter();
+        counter.increment(40);
+        counter.increment(2);
+        assert.equal(counter.count(), 42);
+        counter.drop();
+    )
+}